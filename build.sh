@@ -16,6 +16,7 @@ cargo clean
 cargo build --target x86_64-unknown-linux-gnu --profile=$PROFILE
 cargo build --target aarch64-unknown-linux-gnu --profile=$PROFILE
 cargo build --target wasm32-unknown-unknown --profile=$PROFILE
+cargo build --target wasm32-wasip1 --profile=$PROFILE
 cargo ndk --target arm64-v8a build --profile=$PROFILE
 cargo ndk --target armeabi-v7a build --profile=$PROFILE
 cargo ndk --target x86 build --profile=$PROFILE
@@ -57,9 +58,14 @@ mkdir -p dist/web/
 cp -a js/dist/ dist/web/
 cp js/package.json dist/web
 
+mkdir -p dist/wasi/
+
+cp target/wasm32-wasip1/$PROFILE_PATH/vodozemac.wasm dist/wasi/
+
 mkdir -p dist/bundles
 
 (pushd dist/shared; zip -r ../bundles/shared.jar .; popd)
 (pushd dist/android-shared; zip -r ../bundles/android.jar .; popd)
 (pushd dist/static; zip -r ../bundles/static.jar .; popd)
 (pushd dist/web; tar cvf ../bundles/web.tar.gz .; popd)
+(pushd dist/wasi; tar cvf ../bundles/wasi.tar.gz .; popd)
@@ -0,0 +1,1005 @@
+// SPDX-FileCopyrightText: 2025 Felix Hilgers <contact@fhilgers.com>
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Declarations of the exported C ABI as a foreign caller sees it, together
+//! with an allocator which counts the live allocations of the current thread
+//! and can look for secrets in the memory it frees.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+use std::mem::MaybeUninit;
+
+// Only link the library, everything is called through the declarations below.
+extern crate vodozemac as _;
+
+pub struct CountingAllocator;
+
+thread_local! {
+    static LIVE_ALLOCATIONS: Cell<isize> = const { Cell::new(0) };
+    static WATCHED: Cell<Option<&'static [u8]>> = const { Cell::new(None) };
+    static WATCHED_FREES: Cell<usize> = const { Cell::new(0) };
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        LIVE_ALLOCATIONS.set(LIVE_ALLOCATIONS.get() + 1);
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        LIVE_ALLOCATIONS.set(LIVE_ALLOCATIONS.get() - 1);
+        if let Some(watched) = WATCHED.get() {
+            let freed = unsafe { std::slice::from_raw_parts(ptr, layout.size()) };
+            if freed.windows(watched.len()).any(|window| window == watched) {
+                WATCHED_FREES.set(WATCHED_FREES.get() + 1);
+            }
+        }
+        unsafe { System.dealloc(ptr, layout) }
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+/// Runs `f` and asserts that it freed everything it allocated.
+pub fn assert_no_leaks(f: impl FnOnce()) {
+    // `thread_rng` allocates its state on first use and keeps it until the
+    // thread exits, make sure that happens outside of the measurement.
+    unsafe { vodozemac_sas_sas_free(vodozemac_sas_sas_new()) };
+
+    let before = LIVE_ALLOCATIONS.get();
+    f();
+    let after = LIVE_ALLOCATIONS.get();
+
+    assert_eq!(before, after, "{} allocations were leaked", after - before);
+}
+
+/// Runs `f` and returns how many of the allocations it freed still contained
+/// `secret`.
+pub fn count_freed_copies(secret: &'static [u8], f: impl FnOnce()) -> usize {
+    WATCHED_FREES.set(0);
+    WATCHED.set(Some(secret));
+    f();
+    WATCHED.set(None);
+    WATCHED_FREES.get()
+}
+
+macro_rules! opaque {
+    ($($name:ident),* $(,)?) => {
+        $(
+            #[repr(C)]
+            pub struct $name {
+                _private: [u8; 0],
+            }
+        )*
+    };
+}
+
+opaque!(
+    Account,
+    Session,
+    TrackedSession,
+    SessionKeys,
+    Message,
+    OlmSessionConfig,
+    GroupSession,
+    InboundGroupSession,
+    TrackedInboundGroupSession,
+    SessionKey,
+    ExportedSessionKey,
+    MegolmMessage,
+    MegolmSessionConfig,
+    Sas,
+    EstablishedSas,
+    SasBytes,
+    Mac,
+    Verification,
+    Curve25519PublicKey,
+    Ed25519PublicKey,
+    Ed25519Signature,
+);
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct CSlice<T> {
+    pub ptr: *mut T,
+    pub len: usize,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct CErrorStr {
+    pub message: CSlice<u8>,
+    pub class: usize,
+    pub code: usize,
+    pub message_index: usize,
+}
+
+pub type CSecretSlice = CSlice<u8>;
+
+#[repr(C, usize)]
+#[derive(Clone, Copy, Debug)]
+pub enum CResult<T, E> {
+    Ok(T),
+    Err(E),
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct ZST {
+    _private: [u8; 0],
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct IdentityKeys {
+    pub ed25519: *mut Ed25519PublicKey,
+    pub curve25519: *mut Curve25519PublicKey,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct InboundCreationResult {
+    pub plaintext: CSecretSlice,
+    pub session: *mut Session,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct OneTimeKeyGenerationResult {
+    pub created: CSlice<*mut Curve25519PublicKey>,
+    pub removed: CSlice<*mut Curve25519PublicKey>,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct DehydratedDeviceResult {
+    pub ciphertext: CSlice<u8>,
+    pub nonce: CSlice<u8>,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct DecryptedMessage {
+    pub plaintext: CSecretSlice,
+    pub message_index: usize,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct AuthenticatedMessage {
+    pub plaintext: CSecretSlice,
+    pub message_index: usize,
+    pub status: usize,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct RekeyedPickles {
+    pub pickles: CSlice<u8>,
+    pub lengths: CSlice<u32>,
+}
+
+pub type BatchDecryptionResult = CResult<DecryptedMessage, CErrorStr>;
+
+#[repr(C, usize)]
+#[derive(Clone, Copy, Debug)]
+pub enum OlmMessage {
+    Normal {
+        message: *mut Message,
+    },
+    PreKey {
+        message: *mut Message,
+        session_keys: *mut SessionKeys,
+    },
+}
+
+unsafe extern "C" {
+    pub fn alloc(size: usize, align: usize) -> *mut u8;
+    pub fn dealloc(ptr: *mut u8, size: usize, align: usize);
+    pub fn vodozemac_abi_layout(result: *mut CSlice<u8>);
+    pub fn vodozemac_abi_version() -> u32;
+    pub fn vodozemac_version_string(result: *mut CSlice<u8>);
+    pub fn vodozemac_features() -> u32;
+    pub fn vodozemac_secret_slice_free(ptr: *mut u8, len: usize);
+
+    pub fn vodozemac_pickle_key_pbkdf2_sha256(
+        result: *mut CResult<CSecretSlice, CErrorStr>,
+        passphrase_ptr: *const u8,
+        passphrase_len: u32,
+        salt_ptr: *const u8,
+        salt_len: u32,
+        iterations: u32,
+    );
+    pub fn vodozemac_pickle_key_argon2id(
+        result: *mut CResult<CSecretSlice, CErrorStr>,
+        passphrase_ptr: *const u8,
+        passphrase_len: u32,
+        salt_ptr: *const u8,
+        salt_len: u32,
+        memory_kib: u32,
+        iterations: u32,
+        parallelism: u32,
+    );
+    pub fn vodozemac_olm_account_rekey_pickles(
+        result: *mut CResult<RekeyedPickles, CErrorStr>,
+        pickles_ptr: *const u8,
+        pickles_len: u32,
+        pickle_lengths_ptr: *const u32,
+        pickle_lengths_len: u32,
+        old_key: *const [u8; 32],
+        new_key: *const [u8; 32],
+    );
+    pub fn vodozemac_megolm_group_session_rekey_pickles(
+        result: *mut CResult<RekeyedPickles, CErrorStr>,
+        pickles_ptr: *const u8,
+        pickles_len: u32,
+        pickle_lengths_ptr: *const u32,
+        pickle_lengths_len: u32,
+        old_key: *const [u8; 32],
+        new_key: *const [u8; 32],
+    );
+
+    pub fn vodozemac_curve25519_public_key_from_bytes(
+        bytes: *const [u8; 32],
+    ) -> *mut Curve25519PublicKey;
+    pub fn vodozemac_curve25519_public_key_to_bytes(
+        key: *const Curve25519PublicKey,
+        bytes: *mut [u8; 32],
+    );
+    pub fn vodozemac_curve25519_public_key_calculate_commitment(
+        result: *mut CResult<CSlice<u8>, CErrorStr>,
+        key: *const Curve25519PublicKey,
+        start_content_ptr: *const u8,
+        start_content_len: u32,
+    );
+    pub fn vodozemac_curve25519_public_key_verify_commitment(
+        result: *mut CResult<ZST, CErrorStr>,
+        key: *const Curve25519PublicKey,
+        start_content_ptr: *const u8,
+        start_content_len: u32,
+        commitment_ptr: *const u8,
+        commitment_len: u32,
+    );
+    pub fn vodozemac_curve25519_public_key_free(key: *mut Curve25519PublicKey);
+    pub fn vodozemac_ed25519_public_key_from_bytes(bytes: *const [u8; 32])
+    -> *mut Ed25519PublicKey;
+    pub fn vodozemac_ed25519_public_key_to_bytes(
+        key: *const Ed25519PublicKey,
+        bytes: *mut [u8; 32],
+    );
+    pub fn vodozemac_ed25519_public_key_verify(
+        result: *mut CResult<ZST, CErrorStr>,
+        key: *const Ed25519PublicKey,
+        message_ptr: *const u8,
+        message_len: u32,
+        signature: *const Ed25519Signature,
+    );
+    pub fn vodozemac_ed25519_public_key_free(key: *mut Ed25519PublicKey);
+    pub fn vodozemac_ed25519_signature_from_bytes(bytes: *const [u8; 64]) -> *mut Ed25519Signature;
+    pub fn vodozemac_ed25519_signature_to_bytes(
+        signature: *const Ed25519Signature,
+        bytes: *mut [u8; 64],
+    );
+    pub fn vodozemac_ed25519_signature_free(signature: *mut Ed25519Signature);
+
+    pub fn vodozemac_olm_account_new() -> *mut Account;
+    pub fn vodozemac_olm_account_free(account: *mut Account);
+    pub fn vodozemac_olm_account_identity_keys(result: *mut IdentityKeys, account: *const Account);
+    pub fn vodozemac_olm_account_ed25519_key(account: *const Account) -> *mut Ed25519PublicKey;
+    pub fn vodozemac_olm_account_curve25519_key(
+        account: *const Account,
+    ) -> *mut Curve25519PublicKey;
+    pub fn vodozemac_olm_account_sign(
+        account: *const Account,
+        message_ptr: *const u8,
+        message_len: u32,
+    ) -> *mut Ed25519Signature;
+    pub fn vodozemac_olm_account_generate_one_time_keys(
+        result: *mut OneTimeKeyGenerationResult,
+        account: *const Account,
+        count: u32,
+    );
+    pub fn vodozemac_olm_account_one_time_keys(
+        result: *mut CSlice<*mut Curve25519PublicKey>,
+        account: *const Account,
+    );
+    pub fn vodozemac_olm_account_stored_one_time_key_count(account: *const Account) -> u32;
+    pub fn vodozemac_olm_account_generate_fallback_key(
+        account: *const Account,
+    ) -> *mut Curve25519PublicKey;
+    pub fn vodozemac_olm_account_fallback_key(account: *const Account) -> *mut Curve25519PublicKey;
+    pub fn vodozemac_olm_account_mark_keys_as_published(account: *const Account);
+    pub fn vodozemac_olm_account_forget_fallback_key(account: *const Account) -> u32;
+    pub fn vodozemac_olm_account_one_time_key_target(account: *const Account) -> u32;
+    pub fn vodozemac_olm_account_replenish_keys(
+        result: *mut CResult<CSlice<u8>, CErrorStr>,
+        account: *const Account,
+        user_id_ptr: *const u8,
+        user_id_len: u32,
+        device_id_ptr: *const u8,
+        device_id_len: u32,
+        server_count: u32,
+        fallback_key_unused: u32,
+        fallback_key_rotated_at: u64,
+        now: u64,
+    );
+    pub fn vodozemac_olm_account_create_outbound_session(
+        account: *const Account,
+        session_config: *const OlmSessionConfig,
+        identity_key: *const Curve25519PublicKey,
+        one_time_key: *const Curve25519PublicKey,
+    ) -> *mut Session;
+    pub fn vodozemac_olm_account_create_inbound_session(
+        result: *mut CResult<InboundCreationResult, CErrorStr>,
+        account: *const Account,
+        their_identity_key: *const Curve25519PublicKey,
+        message: *const Message,
+        session_keys: *const SessionKeys,
+    );
+    pub fn vodozemac_olm_account_pickle(
+        result: *mut CSlice<u8>,
+        account: *const Account,
+        pickle_key: *const [u8; 32],
+    );
+    pub fn vodozemac_olm_account_from_pickle(
+        result: *mut CResult<*mut Account, CErrorStr>,
+        ciphertext_ptr: *const u8,
+        ciphertext_len: u32,
+        pickle_key: *const [u8; 32],
+    );
+    pub fn vodozemac_olm_account_to_dehydrated_device(
+        result: *mut CResult<DehydratedDeviceResult, CErrorStr>,
+        account: *const Account,
+        key: *const [u8; 32],
+    );
+    pub fn vodozemac_olm_account_from_dehydrated_device(
+        result: *mut CResult<*mut Account, CErrorStr>,
+        ciphertext_ptr: *const u8,
+        ciphertext_len: u32,
+        nonce_ptr: *const u8,
+        nonce_len: u32,
+        key: *const [u8; 32],
+    );
+
+    pub fn vodozemac_olm_account_new_dehydrated(one_time_keys: u32) -> *mut Account;
+    pub fn vodozemac_olm_account_dehydrated_device_request(
+        result: *mut CResult<CSlice<u8>, CErrorStr>,
+        account: *const Account,
+        user_id_ptr: *const u8,
+        user_id_len: u32,
+        device_id_ptr: *const u8,
+        device_id_len: u32,
+        display_name_ptr: *const u8,
+        display_name_len: u32,
+        pickle_key: *const [u8; 32],
+    );
+    pub fn vodozemac_olm_account_from_dehydrated_device_data(
+        result: *mut CResult<*mut Account, CErrorStr>,
+        device_data_ptr: *const u8,
+        device_data_len: u32,
+        pickle_key: *const [u8; 32],
+    );
+    pub fn vodozemac_olm_account_process_dehydrated_events(
+        result: *mut CResult<CSecretSlice, CErrorStr>,
+        account: *const Account,
+        user_id_ptr: *const u8,
+        user_id_len: u32,
+        events_ptr: *const u8,
+        events_len: u32,
+    );
+
+    pub fn vodozemac_olm_session_config_version_1() -> *mut OlmSessionConfig;
+    pub fn vodozemac_olm_session_config_version_2() -> *mut OlmSessionConfig;
+    pub fn vodozemac_olm_session_config_version(session_config: *const OlmSessionConfig) -> u32;
+    pub fn vodozemac_olm_session_config_from_algorithm(
+        algorithm_ptr: *const u8,
+        algorithm_len: u32,
+    ) -> *mut OlmSessionConfig;
+    pub fn vodozemac_olm_session_config_algorithm(
+        result: *mut CSlice<u8>,
+        session_config: *const OlmSessionConfig,
+    );
+    pub fn vodozemac_olm_session_config_equals(
+        session_config: *const OlmSessionConfig,
+        other: *const OlmSessionConfig,
+    ) -> u32;
+    pub fn vodozemac_olm_session_config_supports_full_mac(
+        session_config: *const OlmSessionConfig,
+    ) -> u32;
+    pub fn vodozemac_olm_session_config_free(session_config: *mut OlmSessionConfig);
+
+    pub fn vodozemac_olm_session_free(session: *mut Session);
+    pub fn vodozemac_olm_session_session_id(result: *mut CSlice<u8>, session: *const Session);
+    pub fn vodozemac_olm_session_session_keys(session: *const Session) -> *mut SessionKeys;
+    pub fn vodozemac_olm_session_has_received_message(session: *const Session) -> u32;
+    pub fn vodozemac_olm_session_encrypt(
+        result: *mut OlmMessage,
+        session: *const Session,
+        plaintext_ptr: *const u8,
+        plaintext_len: u32,
+    );
+    pub fn vodozemac_olm_session_decrypt(
+        result: *mut CResult<CSecretSlice, CErrorStr>,
+        session: *const Session,
+        message: *const Message,
+        session_keys: *const SessionKeys,
+    );
+    pub fn vodozemac_olm_session_pickle(
+        result: *mut CSlice<u8>,
+        session: *const Session,
+        pickle_key: *const [u8; 32],
+    );
+    pub fn vodozemac_olm_session_from_pickle(
+        result: *mut CResult<*mut Session, CErrorStr>,
+        ciphertext_ptr: *const u8,
+        ciphertext_len: u32,
+        pickle_key: *const [u8; 32],
+    );
+
+    pub fn vodozemac_olm_tracked_session_new(
+        session: *const Session,
+        created_at: u64,
+    ) -> *mut TrackedSession;
+    pub fn vodozemac_olm_tracked_session_free(tracked: *mut TrackedSession);
+    pub fn vodozemac_olm_tracked_session_session(tracked: *const TrackedSession) -> *mut Session;
+    pub fn vodozemac_olm_tracked_session_encrypt(
+        result: *mut OlmMessage,
+        tracked: *const TrackedSession,
+        plaintext_ptr: *const u8,
+        plaintext_len: u32,
+        now: u64,
+    );
+    pub fn vodozemac_olm_tracked_session_decrypt(
+        result: *mut CResult<CSecretSlice, CErrorStr>,
+        tracked: *const TrackedSession,
+        message: *const Message,
+        session_keys: *const SessionKeys,
+        now: u64,
+    );
+    pub fn vodozemac_olm_tracked_session_record_failure(tracked: *const TrackedSession);
+    pub fn vodozemac_olm_tracked_session_created_at(tracked: *const TrackedSession) -> u64;
+    pub fn vodozemac_olm_tracked_session_last_encrypted_at(tracked: *const TrackedSession) -> u64;
+    pub fn vodozemac_olm_tracked_session_last_decrypted_at(tracked: *const TrackedSession) -> u64;
+    pub fn vodozemac_olm_tracked_session_consecutive_failures(
+        tracked: *const TrackedSession,
+    ) -> u32;
+    pub fn vodozemac_olm_tracked_session_is_wedged(tracked: *const TrackedSession) -> u32;
+    pub fn vodozemac_olm_tracked_session_should_replace(
+        tracked: *const TrackedSession,
+        now: u64,
+    ) -> u32;
+    pub fn vodozemac_olm_tracked_session_pickle(
+        result: *mut CSlice<u8>,
+        tracked: *const TrackedSession,
+        pickle_key: *const [u8; 32],
+    );
+    pub fn vodozemac_olm_tracked_session_from_pickle(
+        result: *mut CResult<*mut TrackedSession, CErrorStr>,
+        ciphertext_ptr: *const u8,
+        ciphertext_len: u32,
+        pickle_key: *const [u8; 32],
+    );
+
+    pub fn vodozemac_olm_session_keys_free(session_keys: *mut SessionKeys);
+    pub fn vodozemac_olm_session_keys_session_id(
+        result: *mut CSlice<u8>,
+        session_keys: *const SessionKeys,
+    );
+
+    pub fn vodozemac_olm_message_free(message: *mut Message);
+    pub fn vodozemac_olm_message_chain_index(message: *const Message) -> u64;
+    pub fn vodozemac_olm_message_to_bytes(
+        result: *mut CSlice<u8>,
+        message: *const Message,
+        session_keys: *const SessionKeys,
+    );
+    pub fn vodozemac_olm_message_from_bytes(
+        result: *mut CResult<OlmMessage, CErrorStr>,
+        message_type: u32,
+        bytes_ptr: *const u8,
+        bytes_len: u32,
+    );
+
+    pub fn vodozemac_megolm_session_config_version_1() -> *mut MegolmSessionConfig;
+    pub fn vodozemac_megolm_session_config_version_2() -> *mut MegolmSessionConfig;
+    pub fn vodozemac_megolm_session_config_version(
+        session_config: *const MegolmSessionConfig,
+    ) -> u32;
+    pub fn vodozemac_megolm_session_config_from_algorithm(
+        algorithm_ptr: *const u8,
+        algorithm_len: u32,
+    ) -> *mut MegolmSessionConfig;
+    pub fn vodozemac_megolm_session_config_algorithm(
+        result: *mut CSlice<u8>,
+        session_config: *const MegolmSessionConfig,
+    );
+    pub fn vodozemac_megolm_session_config_equals(
+        session_config: *const MegolmSessionConfig,
+        other: *const MegolmSessionConfig,
+    ) -> u32;
+    pub fn vodozemac_megolm_session_config_supports_full_mac(
+        session_config: *const MegolmSessionConfig,
+    ) -> u32;
+    pub fn vodozemac_megolm_session_config_free(session_config: *mut MegolmSessionConfig);
+
+    pub fn vodozemac_megolm_group_session_new(
+        config: *const MegolmSessionConfig,
+    ) -> *mut GroupSession;
+    pub fn vodozemac_megolm_group_session_free(group_session: *mut GroupSession);
+    pub fn vodozemac_megolm_group_session_session_key(
+        group_session: *const GroupSession,
+    ) -> *mut SessionKey;
+    pub fn vodozemac_megolm_group_session_session_id(
+        result: *mut CSlice<u8>,
+        group_session: *const GroupSession,
+    );
+    pub fn vodozemac_megolm_group_session_message_index(group_session: *const GroupSession) -> u32;
+    pub fn vodozemac_megolm_group_session_encrypt(
+        group_session: *const GroupSession,
+        plaintext_ptr: *const u8,
+        plaintext_len: u32,
+    ) -> *mut MegolmMessage;
+    pub fn vodozemac_megolm_group_session_pickle(
+        result: *mut CSlice<u8>,
+        group_session: *const GroupSession,
+        pickle_key: *const [u8; 32],
+    );
+    pub fn vodozemac_megolm_group_session_from_pickle(
+        result: *mut CResult<*mut GroupSession, CErrorStr>,
+        ciphertext_ptr: *const u8,
+        ciphertext_len: u32,
+        pickle_key: *const [u8; 32],
+    );
+
+    pub fn vodozemac_megolm_session_key_free(session_key: *mut SessionKey);
+    pub fn vodozemac_megolm_session_key_to_bytes(
+        result: *mut CSecretSlice,
+        session_key: *const SessionKey,
+    );
+    pub fn vodozemac_megolm_session_key_from_bytes(
+        result: *mut CResult<*mut SessionKey, CErrorStr>,
+        bytes_ptr: *const u8,
+        bytes_len: u32,
+    );
+
+    pub fn vodozemac_megolm_exported_session_key_free(session_key: *mut ExportedSessionKey);
+
+    pub fn vodozemac_megolm_inbound_group_session_new(
+        key: *const SessionKey,
+        session_config: *const MegolmSessionConfig,
+    ) -> *mut InboundGroupSession;
+    pub fn vodozemac_megolm_inbound_group_session_import(
+        session_key: *const ExportedSessionKey,
+        session_config: *const MegolmSessionConfig,
+    ) -> *mut InboundGroupSession;
+    pub fn vodozemac_megolm_inbound_group_session_free(
+        inbound_group_session: *mut InboundGroupSession,
+    );
+    pub fn vodozemac_megolm_inbound_group_session_session_id(
+        result: *mut CSlice<u8>,
+        inbound_group_session: *const InboundGroupSession,
+    );
+    pub fn vodozemac_megolm_inbound_group_session_first_known_index(
+        inbound_group_session: *const InboundGroupSession,
+    ) -> u32;
+    pub fn vodozemac_megolm_inbound_group_session_export_at(
+        inbound_group_session: *const InboundGroupSession,
+        index: u32,
+    ) -> *mut ExportedSessionKey;
+    pub fn vodozemac_megolm_inbound_group_session_advance_to(
+        inbound_group_session: *const InboundGroupSession,
+        index: u32,
+    ) -> u32;
+    pub fn vodozemac_megolm_inbound_group_session_decrypt(
+        result: *mut CResult<DecryptedMessage, CErrorStr>,
+        inbound_group_session: *const InboundGroupSession,
+        message: *const MegolmMessage,
+    );
+    pub fn vodozemac_megolm_inbound_group_session_signing_key(
+        inbound_group_session: *const InboundGroupSession,
+    ) -> *mut Ed25519PublicKey;
+    pub fn vodozemac_megolm_inbound_group_session_decrypt_batch(
+        result: *mut CResult<CSlice<BatchDecryptionResult>, CErrorStr>,
+        sessions_ptr: *const *const InboundGroupSession,
+        sessions_len: u32,
+        session_indices_ptr: *const u32,
+        session_indices_len: u32,
+        messages_ptr: *const u8,
+        messages_len: u32,
+        message_lengths_ptr: *const u32,
+        message_lengths_len: u32,
+    );
+    pub fn vodozemac_megolm_inbound_group_session_decrypt_batch_free(
+        results: *mut BatchDecryptionResult,
+        length: u32,
+    );
+    pub fn vodozemac_megolm_inbound_group_session_pickle(
+        result: *mut CSlice<u8>,
+        inbound_group_session: *const InboundGroupSession,
+        pickle_key: *const [u8; 32],
+    );
+    pub fn vodozemac_megolm_inbound_group_session_from_pickle(
+        result: *mut CResult<*mut InboundGroupSession, CErrorStr>,
+        ciphertext_ptr: *const u8,
+        ciphertext_len: u32,
+        pickle_key: *const [u8; 32],
+    );
+
+    pub fn vodozemac_megolm_tracked_inbound_group_session_new(
+        result: *mut CResult<*mut TrackedInboundGroupSession, CErrorStr>,
+        session: *const InboundGroupSession,
+        sender_key: *const Curve25519PublicKey,
+        claimed_ed25519_key: *const Ed25519PublicKey,
+        forwarding_chain_ptr: *const *const Curve25519PublicKey,
+        forwarding_chain_len: u32,
+        room_id_ptr: *const u8,
+        room_id_len: u32,
+        imported: u32,
+    );
+    pub fn vodozemac_megolm_tracked_inbound_group_session_free(
+        tracked: *mut TrackedInboundGroupSession,
+    );
+    pub fn vodozemac_megolm_tracked_inbound_group_session_session(
+        tracked: *const TrackedInboundGroupSession,
+    ) -> *mut InboundGroupSession;
+    pub fn vodozemac_megolm_tracked_inbound_group_session_sender_key(
+        tracked: *const TrackedInboundGroupSession,
+    ) -> *mut Curve25519PublicKey;
+    pub fn vodozemac_megolm_tracked_inbound_group_session_claimed_ed25519_key(
+        tracked: *const TrackedInboundGroupSession,
+    ) -> *mut Ed25519PublicKey;
+    pub fn vodozemac_megolm_tracked_inbound_group_session_forwarding_chain(
+        result: *mut CSlice<*mut Curve25519PublicKey>,
+        tracked: *const TrackedInboundGroupSession,
+    );
+    pub fn vodozemac_megolm_tracked_inbound_group_session_room_id(
+        result: *mut CSlice<u8>,
+        tracked: *const TrackedInboundGroupSession,
+    );
+    pub fn vodozemac_megolm_tracked_inbound_group_session_imported(
+        tracked: *const TrackedInboundGroupSession,
+    ) -> u32;
+    pub fn vodozemac_megolm_tracked_inbound_group_session_decrypt(
+        result: *mut CResult<DecryptedMessage, CErrorStr>,
+        tracked: *const TrackedInboundGroupSession,
+        message: *const MegolmMessage,
+    );
+    pub fn vodozemac_megolm_tracked_inbound_group_session_decrypt_and_authenticate(
+        result: *mut CResult<AuthenticatedMessage, CErrorStr>,
+        tracked: *const TrackedInboundGroupSession,
+        message: *const MegolmMessage,
+        device_key: *const Ed25519PublicKey,
+    );
+    pub fn vodozemac_megolm_tracked_inbound_group_session_compare(
+        tracked: *const TrackedInboundGroupSession,
+        other: *const TrackedInboundGroupSession,
+    ) -> u32;
+    pub fn vodozemac_megolm_tracked_inbound_group_session_merge(
+        tracked: *const TrackedInboundGroupSession,
+        other: *const TrackedInboundGroupSession,
+    ) -> *mut TrackedInboundGroupSession;
+    pub fn vodozemac_megolm_tracked_inbound_group_session_pickle(
+        result: *mut CSlice<u8>,
+        tracked: *const TrackedInboundGroupSession,
+        pickle_key: *const [u8; 32],
+    );
+    pub fn vodozemac_megolm_tracked_inbound_group_session_from_pickle(
+        result: *mut CResult<*mut TrackedInboundGroupSession, CErrorStr>,
+        ciphertext_ptr: *const u8,
+        ciphertext_len: u32,
+        pickle_key: *const [u8; 32],
+    );
+
+    pub fn vodozemac_megolm_message_free(message: *mut MegolmMessage);
+    pub fn vodozemac_megolm_message_index(message: *const MegolmMessage) -> u32;
+    pub fn vodozemac_megolm_message_to_bytes(
+        result: *mut CSlice<u8>,
+        message: *const MegolmMessage,
+    );
+    pub fn vodozemac_megolm_message_from_bytes(
+        result: *mut CResult<*mut MegolmMessage, CErrorStr>,
+        bytes_ptr: *const u8,
+        bytes_len: u32,
+    );
+
+    pub fn vodozemac_sas_sas_new() -> *mut Sas;
+    pub fn vodozemac_sas_sas_free(sas: *mut Sas);
+    pub fn vodozemac_sas_sas_public_key(sas: *const Sas) -> *mut Curve25519PublicKey;
+    pub fn vodozemac_sas_sas_is_used(sas: *const Sas) -> u32;
+    pub fn vodozemac_sas_sas_diffie_hellman(
+        result: *mut CResult<*mut EstablishedSas, CErrorStr>,
+        sas: *const Sas,
+        their_public_key: *const Curve25519PublicKey,
+    );
+    pub fn vodozemac_sas_sas_diffie_hellman_with_raw(
+        result: *mut CResult<*mut EstablishedSas, CErrorStr>,
+        sas: *const Sas,
+        their_public_key_ptr: *const u8,
+        their_public_key_len: u32,
+    );
+    pub fn vodozemac_sas_established_sas_free(sas: *mut EstablishedSas);
+    pub fn vodozemac_sas_established_sas_bytes(
+        sas: *const EstablishedSas,
+        info_ptr: *const u8,
+        info_len: u32,
+    ) -> *mut SasBytes;
+    pub fn vodozemac_sas_established_sas_calculate_mac(
+        sas: *const EstablishedSas,
+        input_ptr: *const u8,
+        input_len: u32,
+        info_ptr: *const u8,
+        info_len: u32,
+    ) -> *mut Mac;
+    pub fn vodozemac_sas_established_sas_verify_mac(
+        sas: *const EstablishedSas,
+        input_ptr: *const u8,
+        input_len: u32,
+        info_ptr: *const u8,
+        info_len: u32,
+        tag: *const Mac,
+    ) -> u32;
+    pub fn vodozemac_sas_established_sas_calculate_mac_invalid_base64(
+        result: *mut CResult<CSlice<u8>, CErrorStr>,
+        sas: *const EstablishedSas,
+        input_ptr: *const u8,
+        input_len: u32,
+        info_ptr: *const u8,
+        info_len: u32,
+    );
+    pub fn vodozemac_sas_established_sas_calculate_mac_with_method(
+        result: *mut CResult<CSlice<u8>, CErrorStr>,
+        sas: *const EstablishedSas,
+        method: u32,
+        input_ptr: *const u8,
+        input_len: u32,
+        info_ptr: *const u8,
+        info_len: u32,
+    );
+    pub fn vodozemac_sas_established_sas_verify_mac_with_method(
+        result: *mut CResult<ZST, CErrorStr>,
+        sas: *const EstablishedSas,
+        method: u32,
+        input_ptr: *const u8,
+        input_len: u32,
+        info_ptr: *const u8,
+        info_len: u32,
+        tag_ptr: *const u8,
+        tag_len: u32,
+    );
+    pub fn vodozemac_sas_established_sas_our_public_key(
+        sas: *const EstablishedSas,
+    ) -> *mut Curve25519PublicKey;
+    pub fn vodozemac_sas_established_sas_calculate_commitment(
+        result: *mut CResult<CSlice<u8>, CErrorStr>,
+        sas: *const EstablishedSas,
+        start_content_ptr: *const u8,
+        start_content_len: u32,
+    );
+    pub fn vodozemac_sas_established_sas_verify_commitment(
+        result: *mut CResult<ZST, CErrorStr>,
+        sas: *const EstablishedSas,
+        start_content_ptr: *const u8,
+        start_content_len: u32,
+        commitment_ptr: *const u8,
+        commitment_len: u32,
+    );
+    pub fn vodozemac_sas_sas_bytes_free(sas_bytes: *mut SasBytes);
+    pub fn vodozemac_sas_sas_bytes_emoji_indices(
+        sas_bytes: *const SasBytes,
+        emoji_indices_out: *mut [u8; 7],
+    );
+    pub fn vodozemac_sas_sas_bytes_emojis(
+        result: *mut CSlice<u8>,
+        sas_bytes: *const SasBytes,
+        locale_ptr: *const u8,
+        locale_len: u32,
+    );
+    pub fn vodozemac_sas_sas_bytes_decimals(
+        sas_bytes: *const SasBytes,
+        decimals_out: *mut [u16; 3],
+    );
+    pub fn vodozemac_sas_sas_bytes_as_bytes(sas_bytes: *const SasBytes, bytes_out: *mut [u8; 6]);
+    pub fn vodozemac_sas_mac_from_slice(bytes: *const [u8; 32]) -> *mut Mac;
+    pub fn vodozemac_sas_mac_free(mac: *mut Mac);
+    pub fn vodozemac_sas_mac_as_bytes(mac: *const Mac, bytes_out: *mut [u8; 32]);
+
+    pub fn vodozemac_sas_verification_new(
+        result: *mut CResult<*mut Verification, CErrorStr>,
+        our_user_id_ptr: *const u8,
+        our_user_id_len: u32,
+        our_device_id_ptr: *const u8,
+        our_device_id_len: u32,
+        their_user_id_ptr: *const u8,
+        their_user_id_len: u32,
+        their_device_id_ptr: *const u8,
+        their_device_id_len: u32,
+        transaction_id_ptr: *const u8,
+        transaction_id_len: u32,
+    );
+    pub fn vodozemac_sas_verification_free(verification: *mut Verification);
+    pub fn vodozemac_sas_verification_state(verification: *const Verification) -> u32;
+    pub fn vodozemac_sas_verification_start(
+        result: *mut CResult<CSlice<u8>, CErrorStr>,
+        verification: *const Verification,
+    );
+    pub fn vodozemac_sas_verification_accept(
+        result: *mut CResult<CSlice<u8>, CErrorStr>,
+        verification: *const Verification,
+    );
+    pub fn vodozemac_sas_verification_receive(
+        result: *mut CResult<CSlice<u8>, CErrorStr>,
+        verification: *const Verification,
+        event_type_ptr: *const u8,
+        event_type_len: u32,
+        content_ptr: *const u8,
+        content_len: u32,
+    );
+    pub fn vodozemac_sas_verification_short_auth_string(
+        verification: *const Verification,
+    ) -> *mut SasBytes;
+    pub fn vodozemac_sas_verification_supports_emoji(verification: *const Verification) -> u32;
+    pub fn vodozemac_sas_verification_confirm(
+        result: *mut CResult<CSlice<u8>, CErrorStr>,
+        verification: *const Verification,
+        our_keys_ptr: *const u8,
+        our_keys_len: u32,
+        their_keys_ptr: *const u8,
+        their_keys_len: u32,
+    );
+    pub fn vodozemac_sas_verification_cancel(
+        result: *mut CResult<CSlice<u8>, CErrorStr>,
+        verification: *const Verification,
+        code_ptr: *const u8,
+        code_len: u32,
+    );
+    pub fn vodozemac_sas_verification_cancel_code(
+        result: *mut CSlice<u8>,
+        verification: *const Verification,
+    );
+    pub fn vodozemac_sas_verification_verified_keys(
+        result: *mut CSlice<u8>,
+        verification: *const Verification,
+    );
+}
+
+#[cfg(feature = "deterministic-rng")]
+unsafe extern "C" {
+    pub fn vodozemac_rng_set_seed(seed: *const [u8; 32]) -> u32;
+}
+
+#[cfg(feature = "dangerous-json-pickles")]
+unsafe extern "C" {
+    pub fn vodozemac_olm_account_dangerous_json_pickle(
+        result: *mut CSecretSlice,
+        account: *const Account,
+    );
+    pub fn vodozemac_olm_account_dangerous_from_json_pickle(
+        result: *mut CResult<*mut Account, CErrorStr>,
+        json_ptr: *const u8,
+        json_len: u32,
+    );
+    pub fn vodozemac_olm_session_dangerous_json_pickle(
+        result: *mut CSecretSlice,
+        session: *const Session,
+    );
+    pub fn vodozemac_olm_session_dangerous_from_json_pickle(
+        result: *mut CResult<*mut Session, CErrorStr>,
+        json_ptr: *const u8,
+        json_len: u32,
+    );
+    pub fn vodozemac_megolm_group_session_dangerous_json_pickle(
+        result: *mut CSecretSlice,
+        group_session: *const GroupSession,
+    );
+    pub fn vodozemac_megolm_group_session_dangerous_from_json_pickle(
+        result: *mut CResult<*mut GroupSession, CErrorStr>,
+        json_ptr: *const u8,
+        json_len: u32,
+    );
+    pub fn vodozemac_megolm_inbound_group_session_dangerous_json_pickle(
+        result: *mut CSecretSlice,
+        inbound_group_session: *const InboundGroupSession,
+    );
+    pub fn vodozemac_megolm_inbound_group_session_dangerous_from_json_pickle(
+        result: *mut CResult<*mut InboundGroupSession, CErrorStr>,
+        json_ptr: *const u8,
+        json_len: u32,
+    );
+}
+
+/// Calls a function which returns its result through a `#[sret]` pointer.
+pub fn sret<T>(f: impl FnOnce(*mut T)) -> T {
+    let mut result = MaybeUninit::uninit();
+    f(result.as_mut_ptr());
+    unsafe { result.assume_init() }
+}
+
+/// Copies the bytes out of `slice` and frees it.
+///
+/// # Safety
+///
+/// `slice` has to be a byte slice returned by the library.
+pub unsafe fn take_bytes(slice: CSlice<u8>) -> Vec<u8> {
+    let bytes = unsafe { std::slice::from_raw_parts(slice.ptr, slice.len) }.to_vec();
+    unsafe { dealloc(slice.ptr, slice.len, 1) };
+    bytes
+}
+
+/// Copies the bytes out of `slice` and wipes it.
+///
+/// # Safety
+///
+/// `slice` has to be a secret slice returned by the library.
+pub unsafe fn take_secret(slice: CSecretSlice) -> Vec<u8> {
+    let bytes = unsafe { std::slice::from_raw_parts(slice.ptr, slice.len) }.to_vec();
+    unsafe { vodozemac_secret_slice_free(slice.ptr, slice.len) };
+    bytes
+}
+
+/// Copies the pointers out of `slice` and frees it, but not its elements.
+///
+/// # Safety
+///
+/// `slice` has to be a pointer slice returned by the library.
+pub unsafe fn take_ptrs<T>(slice: CSlice<*mut T>) -> Vec<*mut T> {
+    let ptrs = unsafe { std::slice::from_raw_parts(slice.ptr, slice.len) }.to_vec();
+    let size = size_of::<*mut T>();
+    unsafe { dealloc(slice.ptr.cast(), slice.len * size, size) };
+    ptrs
+}
+
+pub fn take_error(error: CErrorStr) -> String {
+    String::from_utf8(unsafe { take_bytes(error.message) }).expect("error messages are valid UTF-8")
+}
+
+/// Unwraps `result`, panicking with the freed error message.
+pub fn unwrap<T>(result: CResult<T, CErrorStr>) -> T {
+    match result {
+        CResult::Ok(value) => value,
+        CResult::Err(error) => panic!("unexpected error: {}", take_error(error)),
+    }
+}
+
+/// Unwraps the error of `result`, returning the freed error message.
+pub fn unwrap_err<T>(result: CResult<T, CErrorStr>) -> String {
+    match result {
+        CResult::Ok(_) => panic!("expected an error"),
+        CResult::Err(error) => take_error(error),
+    }
+}
+
+/// Frees both halves of an [`OlmMessage`].
+pub fn free_olm_message(message: OlmMessage) {
+    match message {
+        OlmMessage::Normal { message } => unsafe { vodozemac_olm_message_free(message) },
+        OlmMessage::PreKey {
+            message,
+            session_keys,
+        } => unsafe {
+            vodozemac_olm_message_free(message);
+            vodozemac_olm_session_keys_free(session_keys);
+        },
+    }
+}
+
+pub const PICKLE_KEY: [u8; 32] = [7; 32];
+
+/// # Safety
+///
+/// `key` has to be a live key returned by the library.
+pub unsafe fn curve25519_bytes(key: *const Curve25519PublicKey) -> [u8; 32] {
+    let mut bytes = [0; 32];
+    unsafe { vodozemac_curve25519_public_key_to_bytes(key, &mut bytes) };
+    bytes
+}
+
+/// # Safety
+///
+/// `key` has to be a live key returned by the library.
+pub unsafe fn ed25519_bytes(key: *const Ed25519PublicKey) -> [u8; 32] {
+    let mut bytes = [0; 32];
+    unsafe { vodozemac_ed25519_public_key_to_bytes(key, &mut bytes) };
+    bytes
+}
@@ -0,0 +1,316 @@
+// SPDX-FileCopyrightText: 2025 Felix Hilgers <contact@fhilgers.com>
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! The JNI bindings return `#[sret]` values as a `long[]` built with
+//! `AsUsize`, which has to match the layout seen by C callers word for word.
+
+use ffi_tests::*;
+use serde_json::Value;
+use std::ffi::c_void;
+use std::ptr::NonNull;
+use vodozemac::AsUsize;
+use vodozemac::olm::{account, session};
+use vodozemac::slices;
+
+/// Reinterprets `value` as the library type `L` and checks that flattening
+/// it yields the words at the start of its memory.
+fn assert_flattened<L: AsUsize, T>(value: &T) {
+    assert_eq!(size_of::<L>(), size_of::<T>());
+    assert_eq!(align_of::<L>(), align_of::<T>());
+
+    let flattened: Vec<usize> = unsafe { &*(value as *const T as *const L) }
+        .as_usize()
+        .collect();
+    let words = unsafe {
+        std::slice::from_raw_parts(
+            value as *const T as *const usize,
+            size_of::<T>() / size_of::<usize>(),
+        )
+    };
+
+    assert_eq!(flattened[..], words[..flattened.len()]);
+}
+
+type LibResult<T> = vodozemac::CResult<T, slices::CErrorStr>;
+
+#[test]
+fn layouts() {
+    assert_eq!(size_of::<CSlice<u8>>(), 2 * size_of::<usize>());
+    assert_eq!(size_of::<CErrorStr>(), 5 * size_of::<usize>());
+    assert_eq!(size_of::<CResult<ZST, CErrorStr>>(), 6 * size_of::<usize>());
+    assert_eq!(
+        size_of::<CResult<*mut Account, CErrorStr>>(),
+        6 * size_of::<usize>()
+    );
+    assert_eq!(size_of::<OlmMessage>(), 3 * size_of::<usize>());
+
+    assert_eq!(
+        size_of::<LibResult<vodozemac::ZST>>(),
+        size_of::<CResult<ZST, CErrorStr>>()
+    );
+    assert_eq!(size_of::<slices::CSlice<u8>>(), size_of::<CSlice<u8>>());
+    assert_eq!(size_of::<slices::CSecretSlice>(), size_of::<CSecretSlice>());
+    assert_eq!(size_of::<session::OlmMessage>(), size_of::<OlmMessage>());
+    assert_eq!(
+        size_of::<account::IdentityKeys>(),
+        size_of::<IdentityKeys>()
+    );
+    assert_eq!(
+        size_of::<account::InboundCreationResult>(),
+        size_of::<InboundCreationResult>()
+    );
+    assert_eq!(
+        size_of::<account::OneTimeKeyGenerationResult>(),
+        size_of::<OneTimeKeyGenerationResult>()
+    );
+    assert_eq!(
+        size_of::<account::DehydratedDeviceResult>(),
+        size_of::<DehydratedDeviceResult>()
+    );
+}
+
+#[test]
+fn version() {
+    assert_no_leaks(|| unsafe {
+        assert_eq!(vodozemac_abi_version(), vodozemac::ABI_VERSION);
+        assert_eq!(
+            vodozemac_features() & vodozemac::FEATURE_DETERMINISTIC_RNG != 0,
+            cfg!(feature = "deterministic-rng")
+        );
+
+        let version = take_bytes(sret(|result| vodozemac_version_string(result)));
+        let version = String::from_utf8(version).unwrap();
+        assert!(version.starts_with("libvodozemac "), "{version}");
+        assert!(version.contains("(vodozemac 0."), "{version}");
+        assert!(
+            version.ends_with(&format!("ABI version {})", vodozemac::ABI_VERSION)),
+            "{version}"
+        );
+    });
+}
+
+/// The seed only reaches vodozemac where getrandom accepts a custom backend.
+#[cfg(feature = "deterministic-rng")]
+#[test]
+fn rng_seed() {
+    let seeded = unsafe { vodozemac_rng_set_seed(&[1; 32]) };
+    assert_eq!(seeded, vodozemac::rng::SEEDABLE as u32);
+}
+
+/// The exported layout description matches the declarations above.
+#[test]
+fn layout_description() {
+    let json = unsafe { take_bytes(sret(|result| vodozemac_abi_layout(result))) };
+    let layout: Value = serde_json::from_slice(&json).expect("the layout is valid JSON");
+
+    assert_eq!(layout["word"], size_of::<usize>());
+
+    let types = layout["types"].as_array().unwrap();
+    let find = |name: &str| {
+        types
+            .iter()
+            .find(|ty| ty["name"] == name)
+            .unwrap_or_else(|| panic!("{name} is described"))
+    };
+    let offsets = |fields: &Value| {
+        fields
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|field| {
+                (
+                    field["name"].as_str().unwrap().to_owned(),
+                    field["offset"].as_u64().unwrap() as usize,
+                )
+            })
+            .collect::<Vec<_>>()
+    };
+    macro_rules! assert_struct {
+        ($ty:ident { $($field:ident),* }) => {
+            let described = find(stringify!($ty));
+            assert_eq!(described["size"], size_of::<$ty>());
+            assert_eq!(
+                offsets(&described["fields"]),
+                [$((stringify!($field).to_owned(), std::mem::offset_of!($ty, $field))),*]
+            );
+        };
+    }
+
+    assert_struct!(IdentityKeys {
+        ed25519,
+        curve25519
+    });
+    assert_struct!(InboundCreationResult { plaintext, session });
+    assert_struct!(OneTimeKeyGenerationResult { created, removed });
+    assert_struct!(DehydratedDeviceResult { ciphertext, nonce });
+    assert_struct!(DecryptedMessage {
+        plaintext,
+        message_index
+    });
+    assert_struct!(AuthenticatedMessage {
+        plaintext,
+        message_index,
+        status
+    });
+    assert_struct!(CErrorStr {
+        message,
+        class,
+        code,
+        message_index
+    });
+    assert_struct!(RekeyedPickles { pickles, lengths });
+
+    let message = find("OlmMessage");
+    assert_eq!(message["size"], size_of::<OlmMessage>());
+    let pre_key = &message["variants"][1];
+    assert_eq!(pre_key["name"], "PreKey");
+    assert_eq!(pre_key["tag"], 1);
+    let word = size_of::<usize>();
+    assert_eq!(
+        offsets(&pre_key["fields"]),
+        [
+            ("message".to_owned(), word),
+            ("session_keys".to_owned(), 2 * word)
+        ]
+    );
+}
+
+#[test]
+fn results() {
+    assert_no_leaks(|| unsafe {
+        let account = vodozemac_olm_account_new();
+        let pickle = take_bytes(sret(|result| {
+            vodozemac_olm_account_pickle(result, account, &PICKLE_KEY)
+        }));
+
+        let ok = sret(|result| {
+            vodozemac_olm_account_from_pickle(
+                result,
+                pickle.as_ptr(),
+                pickle.len() as _,
+                &PICKLE_KEY,
+            )
+        });
+        assert_flattened::<LibResult<NonNull<c_void>>, _>(&ok);
+        vodozemac_olm_account_free(unwrap(ok));
+
+        let err = sret(|result| {
+            vodozemac_olm_account_from_pickle(result, pickle.as_ptr(), pickle.len() as _, &[0; 32])
+        });
+        assert_flattened::<LibResult<NonNull<c_void>>, _>(&err);
+        let CResult::Err(error) = err else {
+            panic!("the pickle key is wrong");
+        };
+        // `ExceptionClass::Pickle` and `PICKLE_DECRYPTION`.
+        assert_eq!(
+            (error.class, error.code, error.message_index),
+            (2, 1, usize::MAX)
+        );
+        take_error(error);
+
+        let key = vodozemac_olm_account_ed25519_key(account);
+        let message = b"message";
+        let signature = vodozemac_olm_account_sign(account, message.as_ptr(), message.len() as _);
+        let verified = sret(|result| {
+            vodozemac_ed25519_public_key_verify(
+                result,
+                key,
+                message.as_ptr(),
+                message.len() as _,
+                signature,
+            )
+        });
+        assert_flattened::<LibResult<vodozemac::ZST>, _>(&verified);
+        unwrap(verified);
+
+        vodozemac_ed25519_signature_free(signature);
+        vodozemac_ed25519_public_key_free(key);
+        vodozemac_olm_account_free(account);
+    });
+}
+
+#[test]
+fn olm_structs() {
+    assert_no_leaks(|| unsafe {
+        let alice = vodozemac_olm_account_new();
+        let bob = vodozemac_olm_account_new();
+
+        let identity_keys = sret(|result| vodozemac_olm_account_identity_keys(result, alice));
+        assert_flattened::<account::IdentityKeys, _>(&identity_keys);
+        vodozemac_ed25519_public_key_free(identity_keys.ed25519);
+        vodozemac_curve25519_public_key_free(identity_keys.curve25519);
+
+        let generated = sret(|result| vodozemac_olm_account_generate_one_time_keys(result, bob, 2));
+        assert_flattened::<account::OneTimeKeyGenerationResult, _>(&generated);
+        let one_time_keys = take_ptrs(generated.created);
+        take_ptrs(generated.removed);
+
+        let device =
+            sret(|result| vodozemac_olm_account_to_dehydrated_device(result, alice, &PICKLE_KEY));
+        assert_flattened::<LibResult<account::DehydratedDeviceResult>, _>(&device);
+        let device = unwrap(device);
+        take_bytes(device.ciphertext);
+        take_bytes(device.nonce);
+
+        let alice_key = vodozemac_olm_account_curve25519_key(alice);
+        let bob_key = vodozemac_olm_account_curve25519_key(bob);
+        let config = vodozemac_olm_session_config_version_1();
+        let outbound =
+            vodozemac_olm_account_create_outbound_session(alice, config, bob_key, one_time_keys[0]);
+
+        let plaintext = b"flattened";
+        let pre_key = sret(|result| {
+            vodozemac_olm_session_encrypt(
+                result,
+                outbound,
+                plaintext.as_ptr(),
+                plaintext.len() as _,
+            )
+        });
+        assert_flattened::<session::OlmMessage<'static>, _>(&pre_key);
+        let OlmMessage::PreKey {
+            message,
+            session_keys,
+        } = pre_key
+        else {
+            panic!("the first message has to be a pre-key message");
+        };
+
+        let created = sret(|result| {
+            vodozemac_olm_account_create_inbound_session(
+                result,
+                bob,
+                alice_key,
+                message,
+                session_keys,
+            )
+        });
+        assert_flattened::<LibResult<account::InboundCreationResult>, _>(&created);
+        let created = unwrap(created);
+        take_secret(created.plaintext);
+
+        let normal = sret(|result| {
+            vodozemac_olm_session_encrypt(
+                result,
+                created.session,
+                plaintext.as_ptr(),
+                plaintext.len() as _,
+            )
+        });
+        assert_flattened::<session::OlmMessage<'static>, _>(&normal);
+
+        free_olm_message(normal);
+        free_olm_message(pre_key);
+        vodozemac_olm_session_free(created.session);
+        vodozemac_olm_session_free(outbound);
+        vodozemac_olm_session_config_free(config);
+        vodozemac_curve25519_public_key_free(alice_key);
+        vodozemac_curve25519_public_key_free(bob_key);
+        for key in one_time_keys {
+            vodozemac_curve25519_public_key_free(key);
+        }
+        vodozemac_olm_account_free(alice);
+        vodozemac_olm_account_free(bob);
+    });
+}
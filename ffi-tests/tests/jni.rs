@@ -0,0 +1,773 @@
+// SPDX-FileCopyrightText: 2025 Felix Hilgers <contact@fhilgers.com>
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Checks the JNI bindings without an Android device: the signatures are
+//! compared against the Rust wrapper types, and the natives are registered in
+//! a JVM started inside the test process on stub `*BindingsKt` classes.
+//!
+//! The JVM test is skipped if no JDK can be found through `JAVA_HOME` or a
+//! `javac` on the `PATH`.
+
+use jni::objects::{JClass, JObject, JThrowable, JValue};
+use jni::sys::{JNI_OK, JNI_VERSION_1_8, JavaVMInitArgs, JavaVMOption, jint, jlong};
+use jni::{JNIEnv, JavaVM};
+use std::collections::HashSet;
+use std::ffi::{CString, c_char, c_int, c_void};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::{env, fs, ptr};
+use vodozemac::exceptions::ExceptionClass;
+use vodozemac::{ABI_VERSION, DEFAULT_JNI_PREFIX, jni_modules};
+
+/// Bindings built against a newer version of the library.
+const STALE_PREFIX: &str = "org/example/stale";
+
+/// Bindings renamed by the application, whose `AbiKt` was stripped.
+const CUSTOM_PREFIX: &str = "org/example/custom";
+
+/// The words of a `CResult<T, CErrorStr>` returned through `#[sret]`, the tag
+/// followed by the largest variant.
+const RESULT_WORDS: jint = 6;
+
+#[test]
+fn signatures() {
+    let mut errors = Vec::new();
+
+    for module in jni_modules() {
+        let mut names = HashSet::new();
+        for method in module.methods {
+            if let Err(error) = method.validate() {
+                errors.push(format!("{}: {error}", module.class));
+            }
+            if !names.insert(method.name) {
+                errors.push(format!(
+                    "{}: {} is registered twice",
+                    module.class, method.name
+                ));
+            }
+        }
+    }
+
+    assert!(errors.is_empty(), "{}", errors.join("\n"));
+}
+
+#[test]
+fn jvm() {
+    let Some(java_home) = java_home() else {
+        eprintln!("skipping, no JDK found");
+        return;
+    };
+
+    let out_dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("jni");
+    let _ = fs::remove_dir_all(&out_dir);
+    let classes = out_dir.join("classes");
+    let sources = write_stubs(&out_dir.join("src"));
+
+    let status = Command::new(java_home.join("bin").join("javac"))
+        .arg("-d")
+        .arg(&classes)
+        .args(&sources)
+        .status()
+        .expect("javac can be started");
+    assert!(status.success(), "the stubs have to compile");
+
+    let vm = create_vm(&java_home, &classes);
+    let mut env = vm.attach_current_thread().expect("thread can be attached");
+
+    // Every step runs in its own local frame, `-Xcheck:jni` warns once more
+    // than 32 local references are alive.
+    let steps = [
+        register,
+        abi_version,
+        short_auth_string,
+        mac_round_trip,
+        direct_buffers,
+        copied_arrays,
+        custom_prefix,
+        typed_exception,
+    ];
+    for step in steps {
+        env.with_local_frame(32, |env| {
+            step(env);
+            Ok::<_, jni::errors::Error>(())
+        })
+        .unwrap();
+    }
+}
+
+fn register(env: &mut JNIEnv) {
+    let errors = vodozemac::register_natives(env, DEFAULT_JNI_PREFIX);
+    assert!(errors.is_empty(), "{}", errors.join("\n"));
+}
+
+/// Bindings declaring a different ABI version are rejected with an
+/// `UnsatisfiedLinkError`.
+fn abi_version(env: &mut JNIEnv) {
+    assert!(vodozemac::has_bindings(env, DEFAULT_JNI_PREFIX));
+    assert!(!vodozemac::has_bindings(env, "org/example/missing"));
+
+    assert_eq!(
+        vodozemac::check_abi_version(env, DEFAULT_JNI_PREFIX),
+        Ok(())
+    );
+
+    let prefix = CString::new(STALE_PREFIX).unwrap();
+    let result = unsafe { vodozemac::vodozemac_register_natives(env.get_raw(), prefix.as_ptr()) };
+    assert_eq!(result, -1);
+
+    let exception = env.exception_occurred().unwrap();
+    env.exception_clear().unwrap();
+    assert!(
+        env.is_instance_of(&exception, "java/lang/UnsatisfiedLinkError")
+            .unwrap()
+    );
+
+    let message = env
+        .call_method(&exception, "getMessage", "()Ljava/lang/String;", &[])
+        .and_then(|message| message.l())
+        .unwrap();
+    let message: String = env.get_string(&message.into()).unwrap().into();
+    assert!(
+        message.contains(&format!("ABI version {}", ABI_VERSION + 1)),
+        "{message}"
+    );
+}
+
+/// Both sides of a SAS agree on the emoji and decimals read back from the
+/// `byte[]` and `short[]` out-parameters.
+fn short_auth_string(env: &mut JNIEnv) {
+    let sas = bindings(env, "sas/SasBindingsKt");
+    let established = bindings(env, "sas/EstablishedSasBindingsKt");
+    let sas_bytes = bindings(env, "sas/SasBytesBindingsKt");
+    let keys = bindings(env, "KeyBindingsKt");
+
+    let alice = call(env, &sas, "vodozemac_sas_sas_new", "()J", &[])
+        .j()
+        .unwrap();
+    let bob = call(env, &sas, "vodozemac_sas_sas_new", "()J", &[])
+        .j()
+        .unwrap();
+
+    let sig = "(J)J";
+    let alice_key = call(
+        env,
+        &sas,
+        "vodozemac_sas_sas_public_key",
+        sig,
+        &[alice.into()],
+    );
+    let bob_key = call(
+        env,
+        &sas,
+        "vodozemac_sas_sas_public_key",
+        sig,
+        &[bob.into()],
+    );
+    let (alice_key, bob_key) = (alice_key.j().unwrap(), bob_key.j().unwrap());
+
+    // The result is written to the array as the tag followed by the handle.
+    let sig = "(Ljava/lang/Object;JJ)V";
+    let name = "vodozemac_sas_sas_diffie_hellman";
+    let mut established_sas = |sas_handle: jlong, key: jlong| {
+        let result = env.new_long_array(RESULT_WORDS).unwrap();
+        call(
+            env,
+            &sas,
+            name,
+            sig,
+            &[(&result).into(), sas_handle.into(), key.into()],
+        );
+        let mut words = [0; 3];
+        env.get_long_array_region(&result, 0, &mut words).unwrap();
+        assert_eq!(words[0], 0);
+        words[1]
+    };
+    let alice_established = established_sas(alice, bob_key);
+    let bob_established = established_sas(bob, alice_key);
+
+    let mut results = Vec::new();
+    for established_sas in [alice_established, bob_established] {
+        assert_ne!(established_sas, 0);
+
+        let info = b"MATRIX_KEY_VERIFICATION_SAS";
+        let info_array = env.byte_array_from_slice(info).unwrap();
+        let bytes = call(
+            env,
+            &established,
+            "vodozemac_sas_established_sas_bytes",
+            "(JLjava/lang/Object;I)J",
+            &[
+                established_sas.into(),
+                (&info_array).into(),
+                (info.len() as jint).into(),
+            ],
+        )
+        .j()
+        .unwrap();
+
+        let emoji_array = env.new_byte_array(7).unwrap();
+        let sig = "(JLjava/lang/Object;)V";
+        let name = "vodozemac_sas_sas_bytes_emoji_indices";
+        call(
+            env,
+            &sas_bytes,
+            name,
+            sig,
+            &[bytes.into(), (&emoji_array).into()],
+        );
+        let mut emoji = [0; 7];
+        env.get_byte_array_region(&emoji_array, 0, &mut emoji)
+            .unwrap();
+
+        let decimals_array = env.new_short_array(3).unwrap();
+        let name = "vodozemac_sas_sas_bytes_decimals";
+        call(
+            env,
+            &sas_bytes,
+            name,
+            sig,
+            &[bytes.into(), (&decimals_array).into()],
+        );
+        let mut decimals = [0; 3];
+        env.get_short_array_region(&decimals_array, 0, &mut decimals)
+            .unwrap();
+
+        assert!(emoji.iter().all(|&index| (0..64).contains(&index)));
+        assert!(
+            decimals
+                .iter()
+                .all(|&decimal| (1000..=9191).contains(&decimal))
+        );
+        results.push((emoji, decimals));
+
+        call(
+            env,
+            &sas_bytes,
+            "vodozemac_sas_sas_bytes_free",
+            "(J)V",
+            &[bytes.into()],
+        );
+        let name = "vodozemac_sas_established_sas_free";
+        call(env, &established, name, "(J)V", &[established_sas.into()]);
+    }
+    assert_eq!(results[0], results[1]);
+
+    for key in [alice_key, bob_key] {
+        let name = "vodozemac_curve25519_public_key_free";
+        call(env, &keys, name, "(J)V", &[key.into()]);
+    }
+    for sas_handle in [alice, bob] {
+        call(
+            env,
+            &sas,
+            "vodozemac_sas_sas_free",
+            "(J)V",
+            &[sas_handle.into()],
+        );
+    }
+}
+
+/// Fixed size arrays are copied in and out of the Java arrays.
+fn mac_round_trip(env: &mut JNIEnv) {
+    let mac = bindings(env, "sas/MacBindingsKt");
+
+    let bytes: Vec<u8> = (0..32).collect();
+    let input = env.byte_array_from_slice(&bytes).unwrap();
+    let handle = call(
+        env,
+        &mac,
+        "vodozemac_sas_mac_from_slice",
+        "(Ljava/lang/Object;)J",
+        &[(&input).into()],
+    )
+    .j()
+    .unwrap();
+
+    let output = env.new_byte_array(32).unwrap();
+    call(
+        env,
+        &mac,
+        "vodozemac_sas_mac_as_bytes",
+        "(JLjava/lang/Object;)V",
+        &[handle.into(), (&output).into()],
+    );
+    let mut copied = [0; 32];
+    env.get_byte_array_region(&output, 0, &mut copied).unwrap();
+    assert_eq!(copied.map(|byte| byte as u8)[..], bytes[..]);
+
+    call(
+        env,
+        &mac,
+        "vodozemac_sas_mac_free",
+        "(J)V",
+        &[handle.into()],
+    );
+}
+
+/// Direct buffers are read from their position up to their limit, sizes are
+/// checked before they are used.
+fn direct_buffers(env: &mut JNIEnv) {
+    let sas = bindings(env, "sas/SasBindingsKt");
+    let keys = bindings(env, "KeyBindingsKt");
+    let slices = bindings(env, "SliceBindingsKt");
+
+    let sas_handle = call(env, &sas, "vodozemac_sas_sas_new", "()J", &[])
+        .j()
+        .unwrap();
+    let key = call(
+        env,
+        &sas,
+        "vodozemac_sas_sas_public_key",
+        "(J)J",
+        &[sas_handle.into()],
+    )
+    .j()
+    .unwrap();
+
+    let start = br#"{"method":"m.sas.v1"}"#;
+    let start_array = env.byte_array_from_slice(start).unwrap();
+    let result = env.new_long_array(RESULT_WORDS).unwrap();
+    call(
+        env,
+        &keys,
+        "vodozemac_curve25519_public_key_calculate_commitment",
+        "(Ljava/lang/Object;JLjava/lang/Object;I)V",
+        &[
+            (&result).into(),
+            key.into(),
+            (&start_array).into(),
+            (start.len() as jint).into(),
+        ],
+    );
+    let mut words = [0; 3];
+    env.get_long_array_region(&result, 0, &mut words).unwrap();
+    assert_eq!(words[0], 0);
+    let commitment = env.new_byte_array(words[2] as jint).unwrap();
+    call(
+        env,
+        &slices,
+        "copy_nonoverlapping",
+        "(JLjava/lang/Object;I)V",
+        &[
+            words[1].into(),
+            (&commitment).into(),
+            (words[2] as jint).into(),
+        ],
+    );
+    call(
+        env,
+        &slices,
+        "dealloc",
+        "(JII)V",
+        &[words[1].into(), (words[2] as jint).into(), 1.into()],
+    );
+
+    // The start content follows a prefix which the position skips.
+    let mut backing = b"xyz".to_vec();
+    backing.extend_from_slice(start);
+    let buffer =
+        unsafe { env.new_direct_byte_buffer(backing.as_mut_ptr(), backing.len()) }.unwrap();
+    env.call_method(&buffer, "position", "(I)Ljava/nio/Buffer;", &[3.into()])
+        .unwrap();
+
+    let verify = |env: &mut JNIEnv, length: usize| {
+        let result = env.new_long_array(RESULT_WORDS).unwrap();
+        env.call_static_method(
+            &keys,
+            "vodozemac_curve25519_public_key_verify_commitment",
+            "(Ljava/lang/Object;JLjava/lang/Object;ILjava/lang/Object;I)V",
+            &[
+                (&result).into(),
+                key.into(),
+                (&buffer).into(),
+                (length as jint).into(),
+                (&commitment).into(),
+                (env.get_array_length(&commitment).unwrap()).into(),
+            ],
+        )
+        .map(drop)
+    };
+    verify(env, start.len()).unwrap();
+
+    // Bytes past the limit are not read.
+    env.call_method(
+        &buffer,
+        "limit",
+        "(I)Ljava/nio/Buffer;",
+        &[(backing.len() as jint - 1).into()],
+    )
+    .unwrap();
+    verify(env, start.len()).expect_err("the limit cuts the content short");
+    let exception = env.exception_occurred().unwrap();
+    env.exception_clear().unwrap();
+    assert!(
+        env.is_instance_of(&exception, "java/lang/IndexOutOfBoundsException")
+            .unwrap()
+    );
+
+    env.call_static_method(
+        &slices,
+        "new_direct_byte_buffer",
+        "(JI)Ljava/nio/ByteBuffer;",
+        &[words[1].into(), (-1).into()],
+    )
+    .expect_err("the size is negative");
+    let exception = env.exception_occurred().unwrap();
+    env.exception_clear().unwrap();
+    assert!(
+        env.is_instance_of(&exception, "java/lang/IllegalArgumentException")
+            .unwrap()
+    );
+
+    let name = "vodozemac_curve25519_public_key_free";
+    call(env, &keys, name, "(J)V", &[key.into()]);
+    call(
+        env,
+        &sas,
+        "vodozemac_sas_sas_free",
+        "(J)V",
+        &[sas_handle.into()],
+    );
+}
+
+/// `#[copy]` arguments are copied out of a `byte[]` instead of being pinned,
+/// direct buffers are still read in place.
+fn copied_arrays(env: &mut JNIEnv) {
+    let pickle = bindings(env, "PickleBindingsKt");
+    let slices = bindings(env, "SliceBindingsKt");
+
+    let passphrase = b"correct horse battery staple";
+    let salt = b"vodozemac salt 1";
+    let iterations = 100_000;
+    let expected = vodozemac::pickle::pbkdf2_sha256(passphrase, salt, iterations).unwrap();
+
+    let mut backing = passphrase.to_vec();
+    let direct =
+        unsafe { env.new_direct_byte_buffer(backing.as_mut_ptr(), backing.len()) }.unwrap();
+    let array = env.byte_array_from_slice(passphrase).unwrap();
+    let salt_array = env.byte_array_from_slice(salt).unwrap();
+
+    let passphrase_objects: [&JObject; 2] = [&array, &direct];
+    for passphrase_object in passphrase_objects {
+        let result = env.new_long_array(RESULT_WORDS).unwrap();
+        call(
+            env,
+            &pickle,
+            "vodozemac_pickle_key_pbkdf2_sha256",
+            "(Ljava/lang/Object;Ljava/lang/Object;ILjava/lang/Object;II)V",
+            &[
+                (&result).into(),
+                passphrase_object.into(),
+                (passphrase.len() as jint).into(),
+                (&salt_array).into(),
+                (salt.len() as jint).into(),
+                (iterations as jint).into(),
+            ],
+        );
+        let mut words = [0; 3];
+        env.get_long_array_region(&result, 0, &mut words).unwrap();
+        assert_eq!(words[0], 0);
+
+        let key = env.new_byte_array(words[2] as jint).unwrap();
+        call(
+            env,
+            &slices,
+            "copy_nonoverlapping",
+            "(JLjava/lang/Object;I)V",
+            &[words[1].into(), (&key).into(), (words[2] as jint).into()],
+        );
+        call(
+            env,
+            &slices,
+            "vodozemac_secret_slice_free",
+            "(JJ)V",
+            &[words[1].into(), words[2].into()],
+        );
+        assert_eq!(env.convert_byte_array(&key).unwrap(), &expected[..]);
+    }
+
+    // Too short arrays are still refused before anything is copied.
+    let result = env.new_long_array(RESULT_WORDS).unwrap();
+    env.call_static_method(
+        &pickle,
+        "vodozemac_pickle_key_pbkdf2_sha256",
+        "(Ljava/lang/Object;Ljava/lang/Object;ILjava/lang/Object;II)V",
+        &[
+            (&result).into(),
+            (&array).into(),
+            (passphrase.len() as jint + 1).into(),
+            (&salt_array).into(),
+            (salt.len() as jint).into(),
+            (iterations as jint).into(),
+        ],
+    )
+    .expect_err("the passphrase is shorter than its length");
+    let exception = env.exception_occurred().unwrap();
+    env.exception_clear().unwrap();
+    assert!(
+        env.is_instance_of(&exception, "java/lang/IndexOutOfBoundsException")
+            .unwrap()
+    );
+}
+
+/// Bindings registered after `JNI_OnLoad` throw their own exception classes,
+/// while the default bindings keep throwing theirs.
+fn custom_prefix(env: &mut JNIEnv) {
+    let errors = vodozemac::register_natives(env, CUSTOM_PREFIX);
+    assert_eq!(errors.len(), 1, "{}", errors.join("\n"));
+    assert!(errors[0].contains("AbiKt"), "{}", errors[0]);
+
+    let account = env
+        .find_class(format!("{CUSTOM_PREFIX}/bindings/olm/AccountBindingsKt"))
+        .unwrap();
+    let exception = from_invalid_pickle(env, &account);
+
+    let class = format!("{CUSTOM_PREFIX}/{}", ExceptionClass::Pickle.name());
+    assert!(env.is_instance_of(&exception, class).unwrap());
+}
+
+/// Functions marked with `#[throws]` throw the exception class of the error.
+fn typed_exception(env: &mut JNIEnv) {
+    let account = bindings(env, "olm/AccountBindingsKt");
+    let exception = from_invalid_pickle(env, &account);
+
+    let class = format!("{DEFAULT_JNI_PREFIX}/{}", ExceptionClass::Pickle.name());
+    assert!(env.is_instance_of(&exception, class).unwrap());
+}
+
+/// Unpickles an account from garbage with the bindings `account` and returns
+/// the thrown exception.
+fn from_invalid_pickle<'local>(env: &mut JNIEnv<'local>, account: &JClass) -> JThrowable<'local> {
+    let result = env.new_long_array(RESULT_WORDS).unwrap();
+    let pickle = b"not a pickle";
+    let pickle_array = env.byte_array_from_slice(pickle).unwrap();
+    let key = env.new_byte_array(32).unwrap();
+
+    let error = env
+        .call_static_method(
+            account,
+            "vodozemac_olm_account_from_pickle",
+            "(Ljava/lang/Object;Ljava/lang/Object;ILjava/lang/Object;)V",
+            &[
+                (&result).into(),
+                (&pickle_array).into(),
+                (pickle.len() as jint).into(),
+                (&key).into(),
+            ],
+        )
+        .expect_err("the pickle is invalid");
+    assert!(matches!(error, jni::errors::Error::JavaException));
+
+    let exception = env.exception_occurred().unwrap();
+    env.exception_clear().unwrap();
+    exception
+}
+
+fn bindings<'local>(env: &mut JNIEnv<'local>, class: &str) -> JClass<'local> {
+    env.find_class(format!("{DEFAULT_JNI_PREFIX}/bindings/{class}"))
+        .unwrap_or_else(|error| panic!("{class} can not be found: {error}"))
+}
+
+fn call<'local>(
+    env: &mut JNIEnv<'local>,
+    class: &JClass,
+    name: &str,
+    sig: &str,
+    args: &[JValue],
+) -> jni::objects::JValueOwned<'local> {
+    env.call_static_method(class, name, sig, args)
+        .unwrap_or_else(|error| panic!("{name} failed: {error}"))
+}
+
+/// Writes a class with a `static native` declaration for every registered
+/// method, plus the exception classes, and returns the written files.
+fn write_stubs(dir: &Path) -> Vec<PathBuf> {
+    let mut files = Vec::new();
+
+    for prefix in [DEFAULT_JNI_PREFIX, CUSTOM_PREFIX] {
+        files.extend(write_prefix_stubs(dir, prefix));
+    }
+
+    for (prefix, version) in [
+        (DEFAULT_JNI_PREFIX, ABI_VERSION),
+        (STALE_PREFIX, ABI_VERSION + 1),
+    ] {
+        files.push(write_class(
+            dir,
+            &format!("{prefix}/bindings").replace('/', "."),
+            "AbiKt",
+            &format!("public final class AbiKt {{\n    public static final int ABI_VERSION = {version};\n}}\n"),
+        ));
+    }
+
+    files
+}
+
+/// Writes the bindings and exception classes under `prefix`.
+fn write_prefix_stubs(dir: &Path, prefix: &str) -> Vec<PathBuf> {
+    let package = prefix.replace('/', ".");
+    let mut files = Vec::new();
+
+    for module in jni_modules() {
+        let path = format!("{prefix}/bindings/{}", module.class);
+        let (class_package, class) = path.rsplit_once('/').unwrap();
+
+        let methods = module.methods.iter().map(|method| {
+            let (params, ret) = parse_signature(method.sig);
+            let params = params
+                .iter()
+                .enumerate()
+                .map(|(i, param)| format!("{param} arg{i}"))
+                .collect::<Vec<_>>();
+
+            format!(
+                "    public static native {ret} {}({});\n",
+                method.name,
+                params.join(", ")
+            )
+        });
+
+        files.push(write_class(
+            dir,
+            &class_package.replace('/', "."),
+            class,
+            &format!(
+                "public final class {class} {{\n{}}}\n",
+                methods.collect::<String>()
+            ),
+        ));
+    }
+
+    for exception in ExceptionClass::ALL {
+        let class = exception.name();
+        files.push(write_class(
+            dir,
+            &package,
+            class,
+            &format!(
+                "public class {class} extends RuntimeException {{\n    \
+                 public {class}(String message, int code, long messageIndex) {{\n        \
+                 super(message);\n    \
+                 }}\n\
+                 }}\n"
+            ),
+        ));
+    }
+
+    files
+}
+
+fn write_class(dir: &Path, package: &str, class: &str, body: &str) -> PathBuf {
+    let path = dir
+        .join(package.replace('.', "/"))
+        .join(format!("{class}.java"));
+    fs::create_dir_all(path.parent().unwrap()).unwrap();
+    fs::write(&path, format!("package {package};\n\n{body}")).unwrap();
+    path
+}
+
+/// Splits a JNI method signature into the Java parameter and return types.
+fn parse_signature(sig: &str) -> (Vec<String>, String) {
+    let (params, ret) = sig
+        .strip_prefix('(')
+        .and_then(|sig| sig.split_once(')'))
+        .unwrap_or_else(|| panic!("malformed signature {sig}"));
+
+    let mut types = Vec::new();
+    let mut rest = params;
+    while !rest.is_empty() {
+        let len = if rest.starts_with('L') {
+            rest.find(';')
+                .unwrap_or_else(|| panic!("malformed signature {sig}"))
+                + 1
+        } else {
+            1
+        };
+        types.push(java_type(&rest[..len]));
+        rest = &rest[len..];
+    }
+
+    (types, java_type(ret))
+}
+
+fn java_type(descriptor: &str) -> String {
+    match descriptor {
+        "V" => "void".to_owned(),
+        "B" => "byte".to_owned(),
+        "S" => "short".to_owned(),
+        "I" => "int".to_owned(),
+        "J" => "long".to_owned(),
+        _ => descriptor
+            .strip_prefix('L')
+            .and_then(|class| class.strip_suffix(';'))
+            .unwrap_or_else(|| panic!("unsupported descriptor {descriptor}"))
+            .replace('/', "."),
+    }
+}
+
+fn java_home() -> Option<PathBuf> {
+    if let Some(home) = env::var_os("JAVA_HOME") {
+        return Some(home.into());
+    }
+
+    let javac = env::split_paths(&env::var_os("PATH")?)
+        .map(|dir| dir.join("javac"))
+        .find(|path| path.is_file())?;
+
+    Some(javac.canonicalize().ok()?.parent()?.parent()?.to_owned())
+}
+
+unsafe extern "C" {
+    fn dlopen(filename: *const c_char, flags: c_int) -> *mut c_void;
+    fn dlsym(handle: *mut c_void, symbol: *const c_char) -> *mut c_void;
+}
+
+const RTLD_NOW: c_int = 2;
+
+type CreateJavaVm =
+    unsafe extern "system" fn(*mut *mut jni::sys::JavaVM, *mut *mut c_void, *mut c_void) -> jint;
+
+/// Loads `libjvm` from `java_home` and starts a VM with `-Xcheck:jni`, which
+/// aborts on array accesses with the wrong element type.
+fn create_vm(java_home: &Path, class_path: &Path) -> JavaVM {
+    let library = java_home.join("lib").join("server").join(format!(
+        "{}jvm{}",
+        env::consts::DLL_PREFIX,
+        env::consts::DLL_SUFFIX
+    ));
+    let library = CString::new(library.to_str().unwrap()).unwrap();
+
+    let create: CreateJavaVm = unsafe {
+        let handle = dlopen(library.as_ptr(), RTLD_NOW);
+        assert!(!handle.is_null(), "{library:?} can not be loaded");
+        let symbol = dlsym(handle, c"JNI_CreateJavaVM".as_ptr());
+        assert!(!symbol.is_null(), "JNI_CreateJavaVM is missing");
+        std::mem::transmute::<*mut c_void, CreateJavaVm>(symbol)
+    };
+
+    let options = [
+        CString::new(format!("-Djava.class.path={}", class_path.display())).unwrap(),
+        CString::new("-Xcheck:jni").unwrap(),
+    ];
+    let mut options = options
+        .iter()
+        .map(|option| JavaVMOption {
+            optionString: option.as_ptr() as _,
+            extraInfo: ptr::null_mut(),
+        })
+        .collect::<Vec<_>>();
+    let mut args = JavaVMInitArgs {
+        version: JNI_VERSION_1_8,
+        nOptions: options.len() as _,
+        options: options.as_mut_ptr(),
+        ignoreUnrecognized: 0,
+    };
+
+    let mut vm = ptr::null_mut();
+    let mut env = ptr::null_mut();
+    let status = unsafe { create(&mut vm, &mut env, &mut args as *mut _ as _) };
+    assert_eq!(status, JNI_OK, "the JVM can not be created");
+
+    unsafe { JavaVM::from_raw(vm) }.unwrap()
+}
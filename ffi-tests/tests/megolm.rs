@@ -0,0 +1,576 @@
+// SPDX-FileCopyrightText: 2025 Felix Hilgers <contact@fhilgers.com>
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD_NO_PAD;
+use ffi_tests::*;
+use std::ptr;
+
+// The `DECRYPTION_*` codes, see `exceptions.rs`.
+const INVALID_MAC_LENGTH: usize = 2;
+const UNKNOWN_MESSAGE_INDEX: usize = 4;
+const INVALID_MESSAGE: usize = 5;
+
+// The values of `AuthenticatedMessage::status`.
+const VERIFIED: usize = 0;
+const UNKNOWN_DEVICE: usize = 1;
+const MISMATCHED: usize = 2;
+
+const MEGOLM_V1: &[u8] = b"m.megolm.v1.aes-sha2";
+const MEGOLM_V2: &[u8] = b"org.matrix.vodozemac.megolm.v2";
+
+struct Pair {
+    config: *mut MegolmSessionConfig,
+    outbound: *mut GroupSession,
+    inbound: *mut InboundGroupSession,
+}
+
+impl Pair {
+    fn new() -> Self {
+        unsafe {
+            let config = vodozemac_megolm_session_config_version_1();
+            let outbound = vodozemac_megolm_group_session_new(config);
+
+            let session_key = vodozemac_megolm_group_session_session_key(outbound);
+            let bytes = take_secret(sret(|result| {
+                vodozemac_megolm_session_key_to_bytes(result, session_key)
+            }));
+            let parsed = unwrap(sret(|result| {
+                vodozemac_megolm_session_key_from_bytes(result, bytes.as_ptr(), bytes.len() as _)
+            }));
+            let inbound = vodozemac_megolm_inbound_group_session_new(parsed, config);
+
+            vodozemac_megolm_session_key_free(parsed);
+            vodozemac_megolm_session_key_free(session_key);
+
+            Self {
+                config,
+                outbound,
+                inbound,
+            }
+        }
+    }
+
+    fn encrypt(&self, plaintext: &[u8]) -> *mut MegolmMessage {
+        unsafe {
+            vodozemac_megolm_group_session_encrypt(
+                self.outbound,
+                plaintext.as_ptr(),
+                plaintext.len() as _,
+            )
+        }
+    }
+}
+
+impl Drop for Pair {
+    fn drop(&mut self) {
+        unsafe {
+            vodozemac_megolm_inbound_group_session_free(self.inbound);
+            vodozemac_megolm_group_session_free(self.outbound);
+            vodozemac_megolm_session_config_free(self.config);
+        }
+    }
+}
+
+#[test]
+fn share_and_decrypt() {
+    assert_no_leaks(|| unsafe {
+        let pair = Pair::new();
+
+        let outbound_id = take_bytes(sret(|result| {
+            vodozemac_megolm_group_session_session_id(result, pair.outbound)
+        }));
+        let inbound_id = take_bytes(sret(|result| {
+            vodozemac_megolm_inbound_group_session_session_id(result, pair.inbound)
+        }));
+        assert_eq!(outbound_id, inbound_id);
+
+        for index in 0..3 {
+            assert_eq!(
+                vodozemac_megolm_group_session_message_index(pair.outbound),
+                index
+            );
+
+            let message = pair.encrypt(b"Hello group");
+            assert_eq!(vodozemac_megolm_message_index(message), index);
+
+            let bytes = take_bytes(sret(|result| {
+                vodozemac_megolm_message_to_bytes(result, message)
+            }));
+            let parsed = unwrap(sret(|result| {
+                vodozemac_megolm_message_from_bytes(result, bytes.as_ptr(), bytes.len() as _)
+            }));
+
+            let decrypted = unwrap(sret(|result| {
+                vodozemac_megolm_inbound_group_session_decrypt(result, pair.inbound, parsed)
+            }));
+            assert_eq!(decrypted.message_index, index as usize);
+            assert_eq!(take_secret(decrypted.plaintext), b"Hello group");
+
+            vodozemac_megolm_message_free(parsed);
+            vodozemac_megolm_message_free(message);
+        }
+    });
+}
+
+#[test]
+fn decrypt_and_authenticate() {
+    assert_no_leaks(|| unsafe {
+        let pair = Pair::new();
+        let other = Pair::new();
+
+        let signing_key = vodozemac_megolm_inbound_group_session_signing_key(pair.inbound);
+        let session_id = take_bytes(sret(|result| {
+            vodozemac_megolm_inbound_group_session_session_id(result, pair.inbound)
+        }));
+        assert_eq!(
+            STANDARD_NO_PAD
+                .encode(ed25519_bytes(signing_key))
+                .as_bytes(),
+            session_id
+        );
+
+        // Any Ed25519 key stands in for the key of the sending device.
+        let device_key = vodozemac_megolm_inbound_group_session_signing_key(other.inbound);
+        let sender_key = vodozemac_curve25519_public_key_from_bytes(&[1; 32]);
+        let forwarding_chain: &[*const Curve25519PublicKey] = &[];
+        let room_id = b"!room:example.org";
+        let tracked = unwrap(sret(|result| {
+            vodozemac_megolm_tracked_inbound_group_session_new(
+                result,
+                pair.inbound,
+                sender_key,
+                device_key,
+                forwarding_chain.as_ptr(),
+                forwarding_chain.len() as _,
+                room_id.as_ptr(),
+                room_id.len() as _,
+                0,
+            )
+        }));
+
+        // The claimed device key counts, not the signing key of the session.
+        for (expected, status) in [
+            (device_key, VERIFIED),
+            (ptr::null_mut(), UNKNOWN_DEVICE),
+            (signing_key, MISMATCHED),
+        ] {
+            let message = pair.encrypt(b"Who sent this?");
+            let authenticated = unwrap(sret(|result| {
+                vodozemac_megolm_tracked_inbound_group_session_decrypt_and_authenticate(
+                    result, tracked, message, expected,
+                )
+            }));
+            assert_eq!(authenticated.status, status);
+            assert_eq!(take_secret(authenticated.plaintext), b"Who sent this?");
+            vodozemac_megolm_message_free(message);
+        }
+
+        // Messages of another session fail the signature check.
+        let message = other.encrypt(b"Not for this session");
+        let error = unwrap_err(sret(|result| {
+            vodozemac_megolm_tracked_inbound_group_session_decrypt_and_authenticate(
+                result, tracked, message, device_key,
+            )
+        }));
+        assert!(!error.is_empty());
+
+        vodozemac_megolm_message_free(message);
+        vodozemac_megolm_tracked_inbound_group_session_free(tracked);
+        vodozemac_curve25519_public_key_free(sender_key);
+        vodozemac_ed25519_public_key_free(device_key);
+        vodozemac_ed25519_public_key_free(signing_key);
+    });
+}
+
+#[test]
+fn plaintexts_are_wiped() {
+    const SECRET: &[u8] = b"Nobody else may read this";
+
+    assert_no_leaks(|| unsafe {
+        let pair = Pair::new();
+        let decrypt = || {
+            let message = pair.encrypt(SECRET);
+            let decrypted = unwrap(sret(|result| {
+                vodozemac_megolm_inbound_group_session_decrypt(result, pair.inbound, message)
+            }));
+            vodozemac_megolm_message_free(message);
+            decrypted.plaintext
+        };
+
+        let plaintext = decrypt();
+        let copies = count_freed_copies(SECRET, || {
+            vodozemac_secret_slice_free(plaintext.ptr, plaintext.len);
+        });
+        assert_eq!(copies, 0);
+
+        // Freeing it like any other slice leaves the plaintext behind.
+        let plaintext = decrypt();
+        let copies = count_freed_copies(SECRET, || {
+            dealloc(plaintext.ptr, plaintext.len, 1);
+        });
+        assert_eq!(copies, 1);
+    });
+}
+
+#[test]
+fn session_config_from_algorithm() {
+    assert_no_leaks(|| unsafe {
+        let v1 = vodozemac_megolm_session_config_from_algorithm(
+            MEGOLM_V1.as_ptr(),
+            MEGOLM_V1.len() as _,
+        );
+        let v2 = vodozemac_megolm_session_config_from_algorithm(
+            MEGOLM_V2.as_ptr(),
+            MEGOLM_V2.len() as _,
+        );
+        let unknown = b"m.megolm.v3.aes-sha2";
+        assert!(
+            vodozemac_megolm_session_config_from_algorithm(unknown.as_ptr(), unknown.len() as _)
+                .is_null()
+        );
+
+        assert_eq!(vodozemac_megolm_session_config_version(v1), 1);
+        assert_eq!(vodozemac_megolm_session_config_version(v2), 2);
+        for (config, algorithm) in [(v1, MEGOLM_V1), (v2, MEGOLM_V2)] {
+            let name = take_bytes(sret(|result| {
+                vodozemac_megolm_session_config_algorithm(result, config)
+            }));
+            assert_eq!(name, algorithm);
+        }
+
+        let version_1 = vodozemac_megolm_session_config_version_1();
+        assert_eq!(vodozemac_megolm_session_config_equals(v1, version_1), 1);
+        assert_eq!(vodozemac_megolm_session_config_equals(v1, v2), 0);
+        assert_eq!(vodozemac_megolm_session_config_supports_full_mac(v1), 0);
+        assert_eq!(vodozemac_megolm_session_config_supports_full_mac(v2), 1);
+
+        // Sessions reject messages encrypted with the other version.
+        for (outbound_config, inbound_config) in [(v2, v1), (v1, v2)] {
+            let outbound = vodozemac_megolm_group_session_new(outbound_config);
+            let session_key = vodozemac_megolm_group_session_session_key(outbound);
+            let inbound = vodozemac_megolm_inbound_group_session_new(session_key, inbound_config);
+            let message = vodozemac_megolm_group_session_encrypt(outbound, b"v2".as_ptr(), 2);
+            let CResult::Err(error) = sret(|result| {
+                vodozemac_megolm_inbound_group_session_decrypt(result, inbound, message)
+            }) else {
+                panic!("the message was encrypted with the other version");
+            };
+            assert_eq!(error.code, INVALID_MAC_LENGTH);
+            take_error(error);
+
+            vodozemac_megolm_message_free(message);
+            vodozemac_megolm_inbound_group_session_free(inbound);
+            vodozemac_megolm_session_key_free(session_key);
+            vodozemac_megolm_group_session_free(outbound);
+        }
+        vodozemac_megolm_session_config_free(version_1);
+        vodozemac_megolm_session_config_free(v2);
+        vodozemac_megolm_session_config_free(v1);
+    });
+}
+
+#[test]
+fn unknown_message_index() {
+    assert_no_leaks(|| unsafe {
+        let pair = Pair::new();
+        let message = pair.encrypt(b"Too late");
+
+        assert_eq!(
+            vodozemac_megolm_inbound_group_session_advance_to(pair.inbound, 1),
+            1
+        );
+        assert_eq!(
+            vodozemac_megolm_inbound_group_session_first_known_index(pair.inbound),
+            1
+        );
+
+        let result = sret(|result| {
+            vodozemac_megolm_inbound_group_session_decrypt(result, pair.inbound, message)
+        });
+        assert!(!unwrap_err(result).is_empty());
+
+        vodozemac_megolm_message_free(message);
+    });
+}
+
+#[test]
+fn export_and_import() {
+    assert_no_leaks(|| unsafe {
+        let pair = Pair::new();
+        let first = pair.encrypt(b"First");
+        let second = pair.encrypt(b"Second");
+
+        let exported = vodozemac_megolm_inbound_group_session_export_at(pair.inbound, 1);
+        assert!(!exported.is_null());
+        let imported = vodozemac_megolm_inbound_group_session_import(exported, pair.config);
+        assert_eq!(
+            vodozemac_megolm_inbound_group_session_first_known_index(imported),
+            1
+        );
+
+        let result =
+            sret(|result| vodozemac_megolm_inbound_group_session_decrypt(result, imported, first));
+        assert!(!unwrap_err(result).is_empty());
+
+        let decrypted = unwrap(sret(|result| {
+            vodozemac_megolm_inbound_group_session_decrypt(result, imported, second)
+        }));
+        assert_eq!(take_secret(decrypted.plaintext), b"Second");
+
+        vodozemac_megolm_inbound_group_session_free(imported);
+        vodozemac_megolm_exported_session_key_free(exported);
+        vodozemac_megolm_message_free(second);
+        vodozemac_megolm_message_free(first);
+    });
+}
+
+#[test]
+fn tracked_sessions() {
+    assert_no_leaks(|| unsafe {
+        let pair = Pair::new();
+        let first = pair.encrypt(b"First");
+        let second = pair.encrypt(b"Second");
+
+        let sender_key = vodozemac_curve25519_public_key_from_bytes(&[1; 32]);
+        let forwarder = vodozemac_curve25519_public_key_from_bytes(&[2; 32]);
+        let claimed_key = vodozemac_megolm_inbound_group_session_signing_key(pair.inbound);
+        let room_id = b"!room:example.org";
+        let try_track = |session,
+                         claimed_key,
+                         forwarding_chain: &[*const Curve25519PublicKey],
+                         room_id: &[u8],
+                         imported| {
+            sret(|result| {
+                vodozemac_megolm_tracked_inbound_group_session_new(
+                    result,
+                    session,
+                    sender_key,
+                    claimed_key,
+                    forwarding_chain.as_ptr(),
+                    forwarding_chain.len() as _,
+                    room_id.as_ptr(),
+                    room_id.len() as _,
+                    imported,
+                )
+            })
+        };
+        let track =
+            |session, forwarding_chain: &[*const Curve25519PublicKey], room_id, imported| {
+                unwrap(try_track(
+                    session,
+                    claimed_key,
+                    forwarding_chain,
+                    room_id,
+                    imported,
+                ))
+            };
+
+        let exported = vodozemac_megolm_inbound_group_session_export_at(pair.inbound, 1);
+        let imported = vodozemac_megolm_inbound_group_session_import(exported, pair.config);
+        let direct = track(pair.inbound, &[], room_id, 0);
+        let forwarded = track(imported, &[forwarder], room_id, 1);
+
+        // The metadata survives the pickle.
+        let pickle = take_bytes(sret(|result| {
+            vodozemac_megolm_tracked_inbound_group_session_pickle(result, forwarded, &PICKLE_KEY)
+        }));
+        let restored = unwrap(sret(|result| {
+            vodozemac_megolm_tracked_inbound_group_session_from_pickle(
+                result,
+                pickle.as_ptr(),
+                pickle.len() as _,
+                &PICKLE_KEY,
+            )
+        }));
+        let restored_room = take_bytes(sret(|result| {
+            vodozemac_megolm_tracked_inbound_group_session_room_id(result, restored)
+        }));
+        assert_eq!(restored_room, room_id);
+        assert_eq!(
+            vodozemac_megolm_tracked_inbound_group_session_imported(restored),
+            1
+        );
+        let restored_sender = vodozemac_megolm_tracked_inbound_group_session_sender_key(restored);
+        assert_eq!(curve25519_bytes(restored_sender), [1; 32]);
+        let restored_claimed =
+            vodozemac_megolm_tracked_inbound_group_session_claimed_ed25519_key(restored);
+        assert_eq!(ed25519_bytes(restored_claimed), ed25519_bytes(claimed_key));
+        let chain = take_ptrs(sret(|result| {
+            vodozemac_megolm_tracked_inbound_group_session_forwarding_chain(result, restored)
+        }));
+        assert_eq!(chain.len(), 1);
+        assert_eq!(curve25519_bytes(chain[0]), [2; 32]);
+
+        let decrypted = unwrap(sret(|result| {
+            vodozemac_megolm_tracked_inbound_group_session_decrypt(result, restored, second)
+        }));
+        assert_eq!(take_secret(decrypted.plaintext), b"Second");
+        unwrap_err(sret(|result| {
+            vodozemac_megolm_tracked_inbound_group_session_decrypt(result, restored, first)
+        }));
+
+        // Better
+        assert_eq!(
+            vodozemac_megolm_tracked_inbound_group_session_compare(direct, restored),
+            1
+        );
+
+        // The merged session keeps the ratchet and the origin of the direct copy.
+        let merged = vodozemac_megolm_tracked_inbound_group_session_merge(restored, direct);
+        assert!(!merged.is_null());
+        assert_eq!(
+            vodozemac_megolm_tracked_inbound_group_session_imported(merged),
+            0
+        );
+        assert!(
+            take_ptrs(sret(|result| {
+                vodozemac_megolm_tracked_inbound_group_session_forwarding_chain(result, merged)
+            }))
+            .is_empty()
+        );
+        let merged_session = vodozemac_megolm_tracked_inbound_group_session_session(merged);
+        assert_eq!(
+            vodozemac_megolm_inbound_group_session_first_known_index(merged_session),
+            0
+        );
+
+        let other_room = track(pair.inbound, &[], b"!other:example.org", 0);
+        assert!(vodozemac_megolm_tracked_inbound_group_session_merge(direct, other_room).is_null());
+
+        // Copies claimed by different devices are not merged either.
+        let other = Pair::new();
+        let other_claimed_key = vodozemac_megolm_inbound_group_session_signing_key(other.inbound);
+        let other_device = unwrap(try_track(pair.inbound, other_claimed_key, &[], room_id, 0));
+        assert!(
+            vodozemac_megolm_tracked_inbound_group_session_merge(direct, other_device).is_null()
+        );
+
+        let error = unwrap_err(try_track(pair.inbound, claimed_key, &[], b"\xff", 0));
+        assert!(error.contains("utf-8"));
+
+        for key in chain {
+            vodozemac_curve25519_public_key_free(key);
+        }
+        vodozemac_megolm_tracked_inbound_group_session_free(other_device);
+        vodozemac_ed25519_public_key_free(other_claimed_key);
+        vodozemac_megolm_tracked_inbound_group_session_free(other_room);
+        vodozemac_megolm_inbound_group_session_free(merged_session);
+        vodozemac_megolm_tracked_inbound_group_session_free(merged);
+        vodozemac_ed25519_public_key_free(restored_claimed);
+        vodozemac_curve25519_public_key_free(restored_sender);
+        vodozemac_megolm_tracked_inbound_group_session_free(restored);
+        vodozemac_megolm_tracked_inbound_group_session_free(forwarded);
+        vodozemac_megolm_tracked_inbound_group_session_free(direct);
+        vodozemac_megolm_inbound_group_session_free(imported);
+        vodozemac_megolm_exported_session_key_free(exported);
+        vodozemac_ed25519_public_key_free(claimed_key);
+        vodozemac_curve25519_public_key_free(forwarder);
+        vodozemac_curve25519_public_key_free(sender_key);
+        vodozemac_megolm_message_free(second);
+        vodozemac_megolm_message_free(first);
+    });
+}
+
+#[test]
+fn decrypt_batch() {
+    assert_no_leaks(|| unsafe {
+        let pair = Pair::new();
+
+        let mut messages = Vec::new();
+        let mut message_lengths = Vec::new();
+        for plaintext in [b"zero".as_slice(), b"one", b"two"] {
+            let message = pair.encrypt(plaintext);
+            let bytes = take_bytes(sret(|result| {
+                vodozemac_megolm_message_to_bytes(result, message)
+            }));
+            message_lengths.push(bytes.len() as u32);
+            messages.extend(bytes);
+            vodozemac_megolm_message_free(message);
+        }
+        message_lengths.push(3);
+        messages.extend([1, 2, 3]);
+
+        assert_eq!(
+            vodozemac_megolm_inbound_group_session_advance_to(pair.inbound, 1),
+            1
+        );
+
+        let sessions = [pair.inbound as *const InboundGroupSession];
+        let decrypt_batch = |session_indices: &[u32], message_lengths: &[u32]| {
+            sret(|result| {
+                vodozemac_megolm_inbound_group_session_decrypt_batch(
+                    result,
+                    sessions.as_ptr(),
+                    sessions.len() as _,
+                    session_indices.as_ptr(),
+                    session_indices.len() as _,
+                    messages.as_ptr(),
+                    messages.len() as _,
+                    message_lengths.as_ptr(),
+                    message_lengths.len() as _,
+                )
+            })
+        };
+
+        // Lengths which do not describe the buffer fail the whole batch.
+        assert!(!unwrap_err(decrypt_batch(&[0; 3], &message_lengths)).is_empty());
+        let mut too_long = message_lengths.clone();
+        too_long[3] += 1;
+        assert!(!unwrap_err(decrypt_batch(&[0; 4], &too_long)).is_empty());
+        let mut overflowing = message_lengths.clone();
+        overflowing[2] = u32::MAX;
+        overflowing[3] = u32::MAX;
+        assert!(!unwrap_err(decrypt_batch(&[0; 4], &overflowing)).is_empty());
+
+        let results = unwrap(decrypt_batch(&[0; 4], &message_lengths));
+        assert_eq!(results.len, 4);
+
+        let entries = std::slice::from_raw_parts(results.ptr, results.len);
+        let CResult::Err(failure) = entries[0] else {
+            panic!("message 0 precedes the first known index");
+        };
+        assert_eq!(failure.code, UNKNOWN_MESSAGE_INDEX);
+        assert_eq!(failure.message_index, 0);
+
+        for (index, plaintext) in [(1, b"one"), (2, b"two")] {
+            let CResult::Ok(decrypted) = entries[index] else {
+                panic!("message {index} should decrypt");
+            };
+            assert_eq!(decrypted.message_index, index);
+            assert_eq!(
+                std::slice::from_raw_parts(decrypted.plaintext.ptr, decrypted.plaintext.len),
+                plaintext
+            );
+        }
+
+        let CResult::Err(failure) = entries[3] else {
+            panic!("message 3 is not a megolm message");
+        };
+        assert_eq!(failure.code, INVALID_MESSAGE);
+        assert_eq!(failure.message_index, usize::MAX);
+
+        vodozemac_megolm_inbound_group_session_decrypt_batch_free(results.ptr, results.len as _);
+    });
+}
+
+#[test]
+fn invalid_messages_and_keys() {
+    assert_no_leaks(|| unsafe {
+        let garbage = [0u8; 16];
+
+        let result = sret(|result| {
+            vodozemac_megolm_message_from_bytes(result, garbage.as_ptr(), garbage.len() as _)
+        });
+        assert!(!unwrap_err(result).is_empty());
+
+        let result = sret(|result| {
+            vodozemac_megolm_session_key_from_bytes(result, garbage.as_ptr(), garbage.len() as _)
+        });
+        assert!(!unwrap_err(result).is_empty());
+
+        let result = sret(|result| vodozemac_megolm_message_from_bytes(result, ptr::null(), 0));
+        assert!(!unwrap_err(result).is_empty());
+    });
+}
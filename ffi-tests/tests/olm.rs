@@ -0,0 +1,931 @@
+// SPDX-FileCopyrightText: 2025 Felix Hilgers <contact@fhilgers.com>
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD_NO_PAD;
+use ffi_tests::*;
+use serde_json::{Value, json};
+use std::ptr;
+use vodozemac::olm::key_pool::FALLBACK_KEY_GRACE_PERIOD_MS;
+
+struct Pair {
+    alice: *mut Account,
+    bob: *mut Account,
+    alice_session: *mut Session,
+    bob_session: *mut Session,
+}
+
+impl Pair {
+    /// Alice creates an outbound session to Bob, Bob creates the inbound
+    /// session from the first pre-key message.
+    fn new() -> Self {
+        unsafe {
+            let alice = vodozemac_olm_account_new();
+            let bob = vodozemac_olm_account_new();
+
+            let generated =
+                sret(|result| vodozemac_olm_account_generate_one_time_keys(result, bob, 1));
+            assert_eq!(generated.created.len, 1);
+            assert_eq!(generated.removed.len, 0);
+            for key in take_ptrs(generated.created) {
+                vodozemac_curve25519_public_key_free(key);
+            }
+            take_ptrs(generated.removed);
+
+            let one_time_keys = take_ptrs(sret(|result| {
+                vodozemac_olm_account_one_time_keys(result, bob)
+            }));
+            assert_eq!(one_time_keys.len(), 1);
+            vodozemac_olm_account_mark_keys_as_published(bob);
+
+            let bob_identity_key = vodozemac_olm_account_curve25519_key(bob);
+            let alice_identity_key = vodozemac_olm_account_curve25519_key(alice);
+            let config = vodozemac_olm_session_config_version_1();
+
+            let alice_session = vodozemac_olm_account_create_outbound_session(
+                alice,
+                config,
+                bob_identity_key,
+                one_time_keys[0],
+            );
+
+            let plaintext = b"It's a secret to everybody";
+            let message = sret(|result| {
+                vodozemac_olm_session_encrypt(
+                    result,
+                    alice_session,
+                    plaintext.as_ptr(),
+                    plaintext.len() as _,
+                )
+            });
+            let OlmMessage::PreKey {
+                message: pre_key,
+                session_keys,
+            } = message
+            else {
+                panic!("the first message has to be a pre-key message");
+            };
+
+            let created = unwrap(sret(|result| {
+                vodozemac_olm_account_create_inbound_session(
+                    result,
+                    bob,
+                    alice_identity_key,
+                    pre_key,
+                    session_keys,
+                )
+            }));
+            assert_eq!(take_secret(created.plaintext), plaintext);
+            assert_eq!(vodozemac_olm_account_stored_one_time_key_count(bob), 0);
+
+            free_olm_message(message);
+            vodozemac_olm_session_config_free(config);
+            vodozemac_curve25519_public_key_free(alice_identity_key);
+            vodozemac_curve25519_public_key_free(bob_identity_key);
+            for key in one_time_keys {
+                vodozemac_curve25519_public_key_free(key);
+            }
+
+            Self {
+                alice,
+                bob,
+                alice_session,
+                bob_session: created.session,
+            }
+        }
+    }
+}
+
+impl Drop for Pair {
+    fn drop(&mut self) {
+        unsafe {
+            vodozemac_olm_session_free(self.alice_session);
+            vodozemac_olm_session_free(self.bob_session);
+            vodozemac_olm_account_free(self.alice);
+            vodozemac_olm_account_free(self.bob);
+        }
+    }
+}
+
+#[test]
+fn identity_keys() {
+    assert_no_leaks(|| unsafe {
+        let account = vodozemac_olm_account_new();
+
+        let keys = sret(|result| vodozemac_olm_account_identity_keys(result, account));
+        let ed25519 = vodozemac_olm_account_ed25519_key(account);
+        let curve25519 = vodozemac_olm_account_curve25519_key(account);
+
+        assert_eq!(ed25519_bytes(keys.ed25519), ed25519_bytes(ed25519));
+        assert_eq!(
+            curve25519_bytes(keys.curve25519),
+            curve25519_bytes(curve25519)
+        );
+
+        vodozemac_ed25519_public_key_free(keys.ed25519);
+        vodozemac_curve25519_public_key_free(keys.curve25519);
+        vodozemac_ed25519_public_key_free(ed25519);
+        vodozemac_curve25519_public_key_free(curve25519);
+        vodozemac_olm_account_free(account);
+    });
+}
+
+#[test]
+fn sign_and_verify() {
+    assert_no_leaks(|| unsafe {
+        let account = vodozemac_olm_account_new();
+        let key = vodozemac_olm_account_ed25519_key(account);
+        let message = b"signed";
+        let signature = vodozemac_olm_account_sign(account, message.as_ptr(), message.len() as _);
+
+        let result = sret(|result| {
+            vodozemac_ed25519_public_key_verify(
+                result,
+                key,
+                message.as_ptr(),
+                message.len() as _,
+                signature,
+            )
+        });
+        unwrap(result);
+
+        let forged = b"forged";
+        let result = sret(|result| {
+            vodozemac_ed25519_public_key_verify(
+                result,
+                key,
+                forged.as_ptr(),
+                forged.len() as _,
+                signature,
+            )
+        });
+        assert!(!unwrap_err(result).is_empty());
+
+        let mut bytes = [0; 64];
+        vodozemac_ed25519_signature_to_bytes(signature, &mut bytes);
+        let copy = vodozemac_ed25519_signature_from_bytes(&bytes);
+        let result = sret(|result| {
+            vodozemac_ed25519_public_key_verify(
+                result,
+                key,
+                message.as_ptr(),
+                message.len() as _,
+                copy,
+            )
+        });
+        unwrap(result);
+
+        vodozemac_ed25519_signature_free(copy);
+        vodozemac_ed25519_signature_free(signature);
+        vodozemac_ed25519_public_key_free(key);
+        vodozemac_olm_account_free(account);
+    });
+}
+
+#[test]
+fn fallback_key() {
+    assert_no_leaks(|| unsafe {
+        let account = vodozemac_olm_account_new();
+        assert!(vodozemac_olm_account_fallback_key(account).is_null());
+
+        let generated = vodozemac_olm_account_generate_fallback_key(account);
+        assert!(generated.is_null());
+
+        let fallback_key = vodozemac_olm_account_fallback_key(account);
+        assert!(!fallback_key.is_null());
+
+        vodozemac_curve25519_public_key_free(fallback_key);
+        vodozemac_olm_account_free(account);
+    });
+}
+
+/// Runs `vodozemac_olm_account_replenish_keys` for `user_id` and the device
+/// `DEVICE`.
+unsafe fn try_replenish(
+    account: *const Account,
+    user_id: &[u8],
+    server_count: u32,
+    fallback_key_unused: bool,
+    rotated_at: u64,
+    now: u64,
+) -> CResult<CSlice<u8>, CErrorStr> {
+    let device_id = "DEVICE";
+    sret(|result| unsafe {
+        vodozemac_olm_account_replenish_keys(
+            result,
+            account,
+            user_id.as_ptr(),
+            user_id.len() as _,
+            device_id.as_ptr(),
+            device_id.len() as _,
+            server_count,
+            fallback_key_unused.into(),
+            rotated_at,
+            now,
+        )
+    })
+}
+
+/// Runs `vodozemac_olm_account_replenish_keys` and parses the JSON.
+unsafe fn replenish(
+    account: *const Account,
+    server_count: u32,
+    fallback_key_unused: bool,
+    rotated_at: u64,
+    now: u64,
+) -> Value {
+    let user_id = b"@alice:example.org";
+    let body = unsafe {
+        take_bytes(unwrap(try_replenish(
+            account,
+            user_id,
+            server_count,
+            fallback_key_unused,
+            rotated_at,
+            now,
+        )))
+    };
+    serde_json::from_slice(&body).unwrap()
+}
+
+#[test]
+fn replenish_keys() {
+    assert_no_leaks(|| unsafe {
+        let account = vodozemac_olm_account_new();
+        assert_eq!(vodozemac_olm_account_one_time_key_target(account), 25);
+
+        let body = replenish(account, 0, false, 0, 1000);
+        let one_time_keys = body["one_time_keys"].as_object().unwrap();
+        assert_eq!(one_time_keys.len(), 25);
+        assert_eq!(body["fallback_keys"].as_object().unwrap().len(), 1);
+        assert_eq!(body["fallback_key_rotated_at"], 1000);
+        let (key_id, key) = one_time_keys.iter().next().unwrap();
+        assert!(key_id.starts_with("signed_curve25519:"));
+        assert!(key["signatures"]["@alice:example.org"]["ed25519:DEVICE"].is_string());
+
+        // Without an upload the same keys come back.
+        assert_eq!(replenish(account, 0, false, 1000, 2000), body);
+
+        vodozemac_olm_account_mark_keys_as_published(account);
+        let body = replenish(account, 25, true, 1000, 3000);
+        assert!(body["one_time_keys"].as_object().unwrap().is_empty());
+        assert!(body["fallback_keys"].as_object().unwrap().is_empty());
+
+        // Claimed keys are replaced, a used fallback key is rotated.
+        let body = replenish(account, 20, false, 1000, 4000);
+        assert_eq!(body["one_time_keys"].as_object().unwrap().len(), 5);
+        assert_eq!(body["fallback_keys"].as_object().unwrap().len(), 1);
+        assert_eq!(body["fallback_key_rotated_at"], 4000);
+        vodozemac_olm_account_mark_keys_as_published(account);
+
+        // The previous fallback key is forgotten after the grace period.
+        replenish(account, 25, true, 4000, 4000 + FALLBACK_KEY_GRACE_PERIOD_MS);
+        assert_eq!(vodozemac_olm_account_forget_fallback_key(account), 0);
+
+        let error = unwrap_err(try_replenish(account, b"\xff", 25, true, 0, 5000));
+        assert!(error.contains("utf-8"));
+
+        vodozemac_olm_account_free(account);
+    });
+}
+
+#[test]
+fn session_config_from_algorithm() {
+    assert_no_leaks(|| unsafe {
+        let algorithm = b"m.olm.v1.curve25519-aes-sha2";
+        let config =
+            vodozemac_olm_session_config_from_algorithm(algorithm.as_ptr(), algorithm.len() as _);
+        assert_eq!(vodozemac_olm_session_config_version(config), 1);
+        let name = take_bytes(sret(|result| {
+            vodozemac_olm_session_config_algorithm(result, config)
+        }));
+        assert_eq!(name, algorithm);
+
+        let version_1 = vodozemac_olm_session_config_version_1();
+        let version_2 = vodozemac_olm_session_config_version_2();
+        assert_eq!(vodozemac_olm_session_config_equals(config, version_1), 1);
+        assert_eq!(vodozemac_olm_session_config_equals(config, version_2), 0);
+        assert_eq!(vodozemac_olm_session_config_supports_full_mac(config), 0);
+        assert_eq!(vodozemac_olm_session_config_supports_full_mac(version_2), 1);
+
+        let v2 = b"org.matrix.vodozemac.olm.v2";
+        let name = take_bytes(sret(|result| {
+            vodozemac_olm_session_config_algorithm(result, version_2)
+        }));
+        assert_eq!(name, v2);
+        let config_2 = vodozemac_olm_session_config_from_algorithm(v2.as_ptr(), v2.len() as _);
+        assert_eq!(vodozemac_olm_session_config_version(config_2), 2);
+        vodozemac_olm_session_config_free(config_2);
+
+        // Neither a typo nor a made up name in the spec namespace is accepted.
+        for unknown in [
+            b"m.olm.v1.curve25519-aes-sha3".as_slice(),
+            b"m.olm.v2.curve25519-aes-sha2",
+        ] {
+            assert!(
+                vodozemac_olm_session_config_from_algorithm(unknown.as_ptr(), unknown.len() as _)
+                    .is_null()
+            );
+        }
+
+        vodozemac_olm_session_config_free(version_2);
+        vodozemac_olm_session_config_free(version_1);
+        vodozemac_olm_session_config_free(config);
+    });
+}
+
+#[test]
+fn conversation() {
+    assert_no_leaks(|| unsafe {
+        let pair = Pair::new();
+
+        let alice_id = take_bytes(sret(|result| {
+            vodozemac_olm_session_session_id(result, pair.alice_session)
+        }));
+        let bob_id = take_bytes(sret(|result| {
+            vodozemac_olm_session_session_id(result, pair.bob_session)
+        }));
+        assert_eq!(alice_id, bob_id);
+        assert_eq!(
+            vodozemac_olm_session_has_received_message(pair.bob_session),
+            1
+        );
+
+        let plaintext = b"Reply";
+        let message = sret(|result| {
+            vodozemac_olm_session_encrypt(
+                result,
+                pair.bob_session,
+                plaintext.as_ptr(),
+                plaintext.len() as _,
+            )
+        });
+        let OlmMessage::Normal { message: normal } = message else {
+            panic!("replies are normal messages");
+        };
+
+        let decrypted = sret(|result| {
+            vodozemac_olm_session_decrypt(result, pair.alice_session, normal, ptr::null())
+        });
+        assert_eq!(take_secret(unwrap(decrypted)), plaintext);
+
+        // The message key has been used up, decrypting the message again fails.
+        let replayed = sret(|result| {
+            vodozemac_olm_session_decrypt(result, pair.alice_session, normal, ptr::null())
+        });
+        assert!(!unwrap_err(replayed).is_empty());
+
+        free_olm_message(message);
+    });
+}
+
+/// Encrypts with `tracked` and decrypts with `other` at `now`.
+unsafe fn send(
+    tracked: *const TrackedSession,
+    other: *const TrackedSession,
+    now: u64,
+) -> CResult<CSecretSlice, CErrorStr> {
+    unsafe {
+        let plaintext = b"Are you still there?";
+        let message = sret(|result| {
+            vodozemac_olm_tracked_session_encrypt(
+                result,
+                tracked,
+                plaintext.as_ptr(),
+                plaintext.len() as _,
+                now,
+            )
+        });
+        let (message_ptr, session_keys) = match message {
+            OlmMessage::Normal { message } => (message, ptr::null()),
+            OlmMessage::PreKey {
+                message,
+                session_keys,
+            } => (message, session_keys.cast_const()),
+        };
+        let decrypted = sret(|result| {
+            vodozemac_olm_tracked_session_decrypt(result, other, message_ptr, session_keys, now)
+        });
+        free_olm_message(message);
+        decrypted
+    }
+}
+
+#[test]
+fn wedged_sessions() {
+    const HOUR: u64 = 60 * 60 * 1000;
+
+    assert_no_leaks(|| unsafe {
+        let pair = Pair::new();
+        let stranger = Pair::new();
+
+        let alice = vodozemac_olm_tracked_session_new(pair.alice_session, 1000);
+        let bob = vodozemac_olm_tracked_session_new(pair.bob_session, 1000);
+        let unrelated = vodozemac_olm_tracked_session_new(stranger.bob_session, 1000);
+
+        take_secret(unwrap(send(bob, alice, 2000)));
+        assert_eq!(vodozemac_olm_tracked_session_created_at(alice), 1000);
+        assert_eq!(vodozemac_olm_tracked_session_last_encrypted_at(bob), 2000);
+        assert_eq!(vodozemac_olm_tracked_session_last_decrypted_at(alice), 2000);
+        assert_eq!(vodozemac_olm_tracked_session_last_decrypted_at(bob), 0);
+
+        // Messages of another session fail without counting against this one.
+        unwrap_err(send(unrelated, alice, 3000));
+        assert_eq!(vodozemac_olm_tracked_session_consecutive_failures(alice), 0);
+
+        // Failures the caller attributes to the session count until it is wedged.
+        for failures in 1..=3 {
+            assert_eq!(vodozemac_olm_tracked_session_is_wedged(alice), 0);
+            vodozemac_olm_tracked_session_record_failure(alice);
+            assert_eq!(
+                vodozemac_olm_tracked_session_consecutive_failures(alice),
+                failures
+            );
+        }
+        assert_eq!(vodozemac_olm_tracked_session_is_wedged(alice), 1);
+        assert_eq!(
+            vodozemac_olm_tracked_session_should_replace(alice, 1000 + HOUR - 1),
+            0
+        );
+        assert_eq!(
+            vodozemac_olm_tracked_session_should_replace(alice, 1000 + HOUR),
+            1
+        );
+
+        // The history survives the pickle.
+        let pickle = take_bytes(sret(|result| {
+            vodozemac_olm_tracked_session_pickle(result, alice, &PICKLE_KEY)
+        }));
+        let restored = unwrap(sret(|result| {
+            vodozemac_olm_tracked_session_from_pickle(
+                result,
+                pickle.as_ptr(),
+                pickle.len() as _,
+                &PICKLE_KEY,
+            )
+        }));
+        assert_eq!(vodozemac_olm_tracked_session_created_at(restored), 1000);
+        assert_eq!(
+            vodozemac_olm_tracked_session_last_decrypted_at(restored),
+            2000
+        );
+        assert_eq!(
+            vodozemac_olm_tracked_session_consecutive_failures(restored),
+            3
+        );
+
+        // A working message unwedges the session.
+        take_secret(unwrap(send(bob, restored, 4000)));
+        assert_eq!(
+            vodozemac_olm_tracked_session_consecutive_failures(restored),
+            0
+        );
+        assert_eq!(vodozemac_olm_tracked_session_is_wedged(restored), 0);
+
+        let session = vodozemac_olm_tracked_session_session(restored);
+        assert_eq!(vodozemac_olm_session_has_received_message(session), 1);
+
+        // Pre-key messages name their session, only those of this one count.
+        let encrypt = |session| {
+            let plaintext = b"Hello again";
+            let message = sret(|result| {
+                vodozemac_olm_session_encrypt(
+                    result,
+                    session,
+                    plaintext.as_ptr(),
+                    plaintext.len() as _,
+                )
+            });
+            let OlmMessage::PreKey {
+                message,
+                session_keys,
+            } = message
+            else {
+                panic!("the outbound session has not received a message yet");
+            };
+            (message, session_keys)
+        };
+        let (ours, our_keys) = encrypt(pair.alice_session);
+        let (theirs, their_keys) = encrypt(stranger.alice_session);
+        for (message, keys, failures) in [(theirs, their_keys, 0), (theirs, our_keys, 1)] {
+            unwrap_err(sret(|result| {
+                vodozemac_olm_tracked_session_decrypt(result, bob, message, keys, 5000)
+            }));
+            assert_eq!(
+                vodozemac_olm_tracked_session_consecutive_failures(bob),
+                failures
+            );
+        }
+        free_olm_message(OlmMessage::PreKey {
+            message: ours,
+            session_keys: our_keys,
+        });
+        free_olm_message(OlmMessage::PreKey {
+            message: theirs,
+            session_keys: their_keys,
+        });
+
+        vodozemac_olm_session_free(session);
+        vodozemac_olm_tracked_session_free(restored);
+        vodozemac_olm_tracked_session_free(unrelated);
+        vodozemac_olm_tracked_session_free(bob);
+        vodozemac_olm_tracked_session_free(alice);
+    });
+}
+
+#[test]
+fn message_round_trip() {
+    assert_no_leaks(|| unsafe {
+        let pair = Pair::new();
+
+        let plaintext = b"Second";
+        let message = sret(|result| {
+            vodozemac_olm_session_encrypt(
+                result,
+                pair.alice_session,
+                plaintext.as_ptr(),
+                plaintext.len() as _,
+            )
+        });
+        let OlmMessage::PreKey {
+            message: pre_key,
+            session_keys,
+        } = message
+        else {
+            panic!("alice has not received a message yet");
+        };
+        assert_eq!(vodozemac_olm_message_chain_index(pre_key), 1);
+
+        let bytes = take_bytes(sret(|result| {
+            vodozemac_olm_message_to_bytes(result, pre_key, session_keys)
+        }));
+        let parsed = unwrap(sret(|result| {
+            vodozemac_olm_message_from_bytes(result, 0, bytes.as_ptr(), bytes.len() as _)
+        }));
+        let OlmMessage::PreKey {
+            message: parsed_pre_key,
+            session_keys: parsed_session_keys,
+        } = parsed
+        else {
+            panic!("message type 0 is a pre-key message");
+        };
+
+        let expected = take_bytes(sret(|result| {
+            vodozemac_olm_session_keys_session_id(result, session_keys)
+        }));
+        let actual = take_bytes(sret(|result| {
+            vodozemac_olm_session_keys_session_id(result, parsed_session_keys)
+        }));
+        assert_eq!(expected, actual);
+
+        let decrypted = sret(|result| {
+            vodozemac_olm_session_decrypt(
+                result,
+                pair.bob_session,
+                parsed_pre_key,
+                parsed_session_keys,
+            )
+        });
+        assert_eq!(take_secret(unwrap(decrypted)), plaintext);
+
+        let garbage = [0u8; 3];
+        let invalid = sret(|result| {
+            vodozemac_olm_message_from_bytes(result, 1, garbage.as_ptr(), garbage.len() as _)
+        });
+        assert!(!unwrap_err(invalid).is_empty());
+
+        free_olm_message(message);
+        free_olm_message(parsed);
+    });
+}
+
+const USER_ID: &str = "@alice:example.org";
+
+fn base64(bytes: &[u8]) -> String {
+    STANDARD_NO_PAD.encode(bytes)
+}
+
+fn unbase64<const N: usize>(value: &Value) -> [u8; N] {
+    let bytes = STANDARD_NO_PAD
+        .decode(value.as_str().expect("keys are strings"))
+        .expect("valid base64");
+    bytes.try_into().expect("unexpected key length")
+}
+
+/// Matrix' canonical JSON, with the keys sorted explicitly so that the check
+/// does not depend on the key order of `serde_json`.
+fn canonical_json(value: &Value) -> String {
+    match value {
+        Value::Object(object) => {
+            let mut keys = object.keys().collect::<Vec<_>>();
+            keys.sort();
+            let entries = keys
+                .into_iter()
+                .map(|key| {
+                    format!(
+                        "{}:{}",
+                        Value::from(key.as_str()),
+                        canonical_json(&object[key])
+                    )
+                })
+                .collect::<Vec<_>>();
+            format!("{{{}}}", entries.join(","))
+        }
+        Value::Array(values) => {
+            let values = values.iter().map(canonical_json).collect::<Vec<_>>();
+            format!("[{}]", values.join(","))
+        }
+        value => value.to_string(),
+    }
+}
+
+/// Checks the signature of `USER_ID`'s `device_id` over the canonical JSON of
+/// `object`.
+unsafe fn assert_signed(object: &Value, device_id: &str, key: *const Ed25519PublicKey) {
+    let mut unsigned = object.clone();
+    let signatures = unsigned
+        .as_object_mut()
+        .unwrap()
+        .remove("signatures")
+        .expect("the object is signed");
+    let signature: [u8; 64] = unbase64(&signatures[USER_ID][format!("ed25519:{device_id}")]);
+
+    let canonical = canonical_json(&unsigned);
+    unsafe {
+        let signature = vodozemac_ed25519_signature_from_bytes(&signature);
+        unwrap(sret(|result| {
+            vodozemac_ed25519_public_key_verify(
+                result,
+                key,
+                canonical.as_ptr(),
+                canonical.len() as _,
+                signature,
+            )
+        }));
+        vodozemac_ed25519_signature_free(signature);
+    }
+}
+
+#[test]
+fn dehydrated_device() {
+    assert_no_leaks(|| unsafe {
+        let device = vodozemac_olm_account_new_dehydrated(5);
+        let identity_keys = sret(|result| vodozemac_olm_account_identity_keys(result, device));
+        let device_id = base64(&curve25519_bytes(identity_keys.curve25519));
+        let ed25519_key = base64(&ed25519_bytes(identity_keys.ed25519));
+        let display_name = "Dehydrated device";
+
+        let request = take_bytes(unwrap(sret(|result| {
+            vodozemac_olm_account_dehydrated_device_request(
+                result,
+                device,
+                USER_ID.as_ptr(),
+                USER_ID.len() as _,
+                device_id.as_ptr(),
+                device_id.len() as _,
+                display_name.as_ptr(),
+                display_name.len() as _,
+                &PICKLE_KEY,
+            )
+        })));
+        let request: Value = serde_json::from_slice(&request).expect("valid JSON");
+        assert_eq!(request["device_id"], device_id.as_str());
+        assert_eq!(request["initial_device_display_name"], display_name);
+        assert_eq!(request["device_data"]["algorithm"], "org.matrix.msc3814.v2");
+
+        let device_keys = &request["device_keys"];
+        assert_eq!(device_keys["dehydrated"], true);
+        assert_eq!(device_keys["user_id"], USER_ID);
+        assert_eq!(
+            device_keys["keys"][format!("ed25519:{device_id}")],
+            ed25519_key.as_str()
+        );
+        assert_signed(device_keys, &device_id, identity_keys.ed25519);
+
+        let one_time_keys = request["one_time_keys"].as_object().unwrap();
+        let fallback_keys = request["fallback_keys"].as_object().unwrap();
+        assert_eq!(one_time_keys.len(), 5);
+        assert_eq!(fallback_keys.len(), 1);
+        for key in one_time_keys.values().chain(fallback_keys.values()) {
+            assert_signed(key, &device_id, identity_keys.ed25519);
+        }
+
+        let invalid = b"@alice:\xff";
+        let error = unwrap_err(sret(|result| {
+            vodozemac_olm_account_dehydrated_device_request(
+                result,
+                device,
+                invalid.as_ptr(),
+                invalid.len() as _,
+                device_id.as_ptr(),
+                device_id.len() as _,
+                display_name.as_ptr(),
+                display_name.len() as _,
+                &PICKLE_KEY,
+            )
+        }));
+        assert!(error.contains("utf-8"), "{error}");
+        vodozemac_olm_account_free(device);
+
+        // Another device of the user shares a room key with the dehydrated
+        // device while it is offline.
+        let alice = vodozemac_olm_account_new();
+        let alice_keys = sret(|result| vodozemac_olm_account_identity_keys(result, alice));
+        let one_time_key = vodozemac_curve25519_public_key_from_bytes(&unbase64(
+            &one_time_keys.values().next().unwrap()["key"],
+        ));
+        let config = vodozemac_olm_session_config_version_1();
+        let session = vodozemac_olm_account_create_outbound_session(
+            alice,
+            config,
+            identity_keys.curve25519,
+            one_time_key,
+        );
+
+        let megolm_config = vodozemac_megolm_session_config_version_1();
+        let group_session = vodozemac_megolm_group_session_new(megolm_config);
+        let session_id = take_bytes(sret(|result| {
+            vodozemac_megolm_group_session_session_id(result, group_session)
+        }));
+        let session_id = String::from_utf8(session_id).unwrap();
+        let key = vodozemac_megolm_group_session_session_key(group_session);
+        let session_key = base64(&take_secret(sret(|result| {
+            vodozemac_megolm_session_key_to_bytes(result, key)
+        })));
+        vodozemac_megolm_session_key_free(key);
+
+        let room_key = json!({
+            "type": "m.room_key",
+            "sender": USER_ID,
+            "recipient": USER_ID,
+            "recipient_keys": { "ed25519": ed25519_key },
+            "keys": { "ed25519": base64(&ed25519_bytes(alice_keys.ed25519)) },
+            "content": {
+                "algorithm": "m.megolm.v1.aes-sha2",
+                "room_id": "!room:example.org",
+                "session_id": session_id,
+                "session_key": session_key,
+            },
+        })
+        .to_string();
+        let message = sret(|result| {
+            vodozemac_olm_session_encrypt(result, session, room_key.as_ptr(), room_key.len() as _)
+        });
+        let OlmMessage::PreKey {
+            message: pre_key,
+            session_keys,
+        } = message
+        else {
+            panic!("the first message has to be a pre-key message");
+        };
+        let body = base64(&take_bytes(sret(|result| {
+            vodozemac_olm_message_to_bytes(result, pre_key, session_keys)
+        })));
+
+        let sender_key = base64(&curve25519_bytes(alice_keys.curve25519));
+        let events = json!([
+            {
+                "type": "m.room.encrypted",
+                "sender": USER_ID,
+                "content": {
+                    "algorithm": "m.olm.v1.curve25519-aes-sha2",
+                    "sender_key": sender_key,
+                    "ciphertext": { &device_id: { "type": 0, "body": body } },
+                },
+            },
+            { "type": "m.room.encrypted", "sender": USER_ID, "content": {} },
+        ])
+        .to_string();
+
+        // Another device of the user rehydrates the device and fetches the
+        // events that were sent to it.
+        let device_data = request["device_data"].to_string();
+        let rehydrated = unwrap(sret(|result| {
+            vodozemac_olm_account_from_dehydrated_device_data(
+                result,
+                device_data.as_ptr(),
+                device_data.len() as _,
+                &PICKLE_KEY,
+            )
+        }));
+        let processed = take_secret(unwrap(sret(|result| {
+            vodozemac_olm_account_process_dehydrated_events(
+                result,
+                rehydrated,
+                USER_ID.as_ptr(),
+                USER_ID.len() as _,
+                events.as_ptr(),
+                events.len() as _,
+            )
+        })));
+        let processed: Value = serde_json::from_slice(&processed).expect("valid JSON");
+
+        assert_eq!(
+            processed["room_keys"],
+            json!([{
+                "algorithm": "m.megolm.v1.aes-sha2",
+                "room_id": "!room:example.org",
+                "session_id": session_id,
+                "session_key": session_key,
+                "sender": USER_ID,
+                "sender_key": sender_key,
+                "sender_claimed_keys": {
+                    "ed25519": base64(&ed25519_bytes(alice_keys.ed25519)),
+                },
+            }])
+        );
+        assert_eq!(processed["failures"].as_array().unwrap().len(), 1);
+        assert_eq!(processed["failures"][0]["index"], 1);
+
+        let mut unsupported = request["device_data"].clone();
+        unsupported["algorithm"] = "org.matrix.msc2697.v2".into();
+        let unsupported = unsupported.to_string();
+        let error = unwrap_err(sret(|result| {
+            vodozemac_olm_account_from_dehydrated_device_data(
+                result,
+                unsupported.as_ptr(),
+                unsupported.len() as _,
+                &PICKLE_KEY,
+            )
+        }));
+        assert!(error.contains("org.matrix.msc2697.v2"));
+
+        free_olm_message(message);
+        vodozemac_olm_account_free(rehydrated);
+        vodozemac_megolm_group_session_free(group_session);
+        vodozemac_megolm_session_config_free(megolm_config);
+        vodozemac_olm_session_free(session);
+        vodozemac_olm_session_config_free(config);
+        vodozemac_curve25519_public_key_free(one_time_key);
+        vodozemac_ed25519_public_key_free(alice_keys.ed25519);
+        vodozemac_curve25519_public_key_free(alice_keys.curve25519);
+        vodozemac_olm_account_free(alice);
+        vodozemac_ed25519_public_key_free(identity_keys.ed25519);
+        vodozemac_curve25519_public_key_free(identity_keys.curve25519);
+    });
+}
+
+/// The key the device data fixtures are encrypted with.
+const DEVICE_DATA_KEY: [u8; 32] = [7; 32];
+/// The Curve25519 key of the account in the device data fixtures.
+const DEVICE_DATA_CURVE25519: &str = "5HDEycCNXYLxwaoSMGDucEWabjNZmWTPLYiF9ULCkQg";
+
+/// `device_data` as matrix-rust-sdk uploads it, created with vodozemac 0.9.
+fn device_data_fixtures() -> [Value; 2] {
+    [
+        json!({
+            "algorithm": "org.matrix.msc3814.v2",
+            "device_pickle": "86fNqfihe4PN9tlbom1dmLytGW1vjrtEtS11vrFhHiFP5eCqu9GtZqbcBWocEf3PKDMN/OY/\
+                4x3NfBYLEGIaR0db2Qy7ojYJhnxautb3RfzmMuQ0A+vOXaMaFaHWcwPwJAwezMWicnxG3/RpJdyFQQTSAx9qa4\
+                fDzKAOJYgrac4eE0SY7nFawQyuLBgov6qjTqqXPuMkkIfQVY8rv460DGIFACcekWSnIkIIX2B4VRZBkK/eRAeD\
+                u90",
+            "nonce": "v/HKN0tynHc1zeh7",
+        }),
+        json!({
+            "algorithm": "org.matrix.msc3814.v1.olm",
+            "device_pickle": "A3/Vj1/ETFA1enkLOk8Cl2HAB8uq/VPuf9iLDWW1JKmYbeIRTiR/9aWjQ84ok3rQMuR28zTc\
+                RpHnK8j1rGIREfZ/j+1lKJy/0Naxd4+Dn+HS40/JqLNPrUpFsWrC/iFo2Msg0Iq1Z1vatFgxPu6VRzEi5T4aS3\
+                KdnPmviw467WprhZ7ro+VP3Vo2bgOqFMnP0bnU1D5gFdgQfdBmYiF/wQJTvDySbQd/ZUL7dd350FwoJbvCWwND\
+                DRR88cty4qQjamZ6uWgrewpvYWlR5tOmzHxnmxDiVAPlTgvmf+ktMJOVtq8mFvpoUQAF27H9CbV+eY0V9s2/ZE\
+                xx/UIQPg6AcNKX3V2aWnbVfqIsuCwH+eqkrIZJdfz92ZC1BZv5uaplbp7KBkdkhixq6RJtkq8pV/hqSGrxvNRd\
+                Y5qlaEjfxl58rewUK0L7SexIOgJvh1SMya/vvguKdGD+oGPMbBKhwTopGX0Dw5pKmkoeHbUPkOKwy2XTzsRrT3\
+                ohXB6TuUicVrfPEPKYrO4",
+        }),
+    ]
+}
+
+fn from_device_data(device_data: &Value) -> CResult<*mut Account, CErrorStr> {
+    let device_data = device_data.to_string();
+    sret(|result| unsafe {
+        vodozemac_olm_account_from_dehydrated_device_data(
+            result,
+            device_data.as_ptr(),
+            device_data.len() as _,
+            &DEVICE_DATA_KEY,
+        )
+    })
+}
+
+#[test]
+fn dehydrated_device_data_formats() {
+    assert_no_leaks(|| unsafe {
+        for device_data in device_data_fixtures() {
+            let account = unwrap(from_device_data(&device_data));
+            let curve25519 = vodozemac_olm_account_curve25519_key(account);
+            assert_eq!(
+                base64(&curve25519_bytes(curve25519)),
+                DEVICE_DATA_CURVE25519
+            );
+
+            vodozemac_curve25519_public_key_free(curve25519);
+            vodozemac_olm_account_free(account);
+        }
+
+        // The current format needs its nonce.
+        let [mut without_nonce, _] = device_data_fixtures();
+        without_nonce.as_object_mut().unwrap().remove("nonce");
+        let error = unwrap_err(from_device_data(&without_nonce));
+        assert!(error.contains("nonce"));
+    });
+}
@@ -0,0 +1,499 @@
+// SPDX-FileCopyrightText: 2025 Felix Hilgers <contact@fhilgers.com>
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use ffi_tests::*;
+
+const WRONG_KEY: [u8; 32] = [8; 32];
+
+#[test]
+fn account() {
+    assert_no_leaks(|| unsafe {
+        let account = vodozemac_olm_account_new();
+        let pickle = take_bytes(sret(|result| {
+            vodozemac_olm_account_pickle(result, account, &PICKLE_KEY)
+        }));
+
+        let restored = unwrap(sret(|result| {
+            vodozemac_olm_account_from_pickle(
+                result,
+                pickle.as_ptr(),
+                pickle.len() as _,
+                &PICKLE_KEY,
+            )
+        }));
+
+        let expected = vodozemac_olm_account_curve25519_key(account);
+        let actual = vodozemac_olm_account_curve25519_key(restored);
+        assert_eq!(curve25519_bytes(expected), curve25519_bytes(actual));
+
+        let result = sret(|result| {
+            vodozemac_olm_account_from_pickle(
+                result,
+                pickle.as_ptr(),
+                pickle.len() as _,
+                &WRONG_KEY,
+            )
+        });
+        assert!(!unwrap_err(result).is_empty());
+
+        vodozemac_curve25519_public_key_free(expected);
+        vodozemac_curve25519_public_key_free(actual);
+        vodozemac_olm_account_free(restored);
+        vodozemac_olm_account_free(account);
+    });
+}
+
+#[test]
+fn dehydrated_device() {
+    assert_no_leaks(|| unsafe {
+        let account = vodozemac_olm_account_new();
+        let device = unwrap(sret(|result| {
+            vodozemac_olm_account_to_dehydrated_device(result, account, &PICKLE_KEY)
+        }));
+        let ciphertext = take_bytes(device.ciphertext);
+        let nonce = take_bytes(device.nonce);
+
+        let restored = unwrap(sret(|result| {
+            vodozemac_olm_account_from_dehydrated_device(
+                result,
+                ciphertext.as_ptr(),
+                ciphertext.len() as _,
+                nonce.as_ptr(),
+                nonce.len() as _,
+                &PICKLE_KEY,
+            )
+        }));
+
+        let expected = vodozemac_olm_account_ed25519_key(account);
+        let actual = vodozemac_olm_account_ed25519_key(restored);
+        assert_eq!(ed25519_bytes(expected), ed25519_bytes(actual));
+
+        let result = sret(|result| {
+            vodozemac_olm_account_from_dehydrated_device(
+                result,
+                ciphertext.as_ptr(),
+                ciphertext.len() as _,
+                nonce.as_ptr(),
+                nonce.len() as _,
+                &WRONG_KEY,
+            )
+        });
+        assert!(!unwrap_err(result).is_empty());
+
+        vodozemac_ed25519_public_key_free(expected);
+        vodozemac_ed25519_public_key_free(actual);
+        vodozemac_olm_account_free(restored);
+        vodozemac_olm_account_free(account);
+    });
+}
+
+#[test]
+fn session() {
+    assert_no_leaks(|| unsafe {
+        let alice = vodozemac_olm_account_new();
+        let bob = vodozemac_olm_account_new();
+        let generated = sret(|result| vodozemac_olm_account_generate_one_time_keys(result, bob, 1));
+        let one_time_keys = take_ptrs(generated.created);
+        take_ptrs(generated.removed);
+
+        let identity_key = vodozemac_olm_account_curve25519_key(bob);
+        let config = vodozemac_olm_session_config_version_2();
+        let session = vodozemac_olm_account_create_outbound_session(
+            alice,
+            config,
+            identity_key,
+            one_time_keys[0],
+        );
+
+        let pickle = take_bytes(sret(|result| {
+            vodozemac_olm_session_pickle(result, session, &PICKLE_KEY)
+        }));
+        let restored = unwrap(sret(|result| {
+            vodozemac_olm_session_from_pickle(
+                result,
+                pickle.as_ptr(),
+                pickle.len() as _,
+                &PICKLE_KEY,
+            )
+        }));
+
+        let expected = take_bytes(sret(|result| {
+            vodozemac_olm_session_session_id(result, session)
+        }));
+        let actual = take_bytes(sret(|result| {
+            vodozemac_olm_session_session_id(result, restored)
+        }));
+        assert_eq!(expected, actual);
+
+        let result = sret(|result| {
+            vodozemac_olm_session_from_pickle(
+                result,
+                pickle.as_ptr(),
+                pickle.len() as _,
+                &WRONG_KEY,
+            )
+        });
+        assert!(!unwrap_err(result).is_empty());
+
+        vodozemac_olm_session_free(restored);
+        vodozemac_olm_session_free(session);
+        vodozemac_olm_session_config_free(config);
+        vodozemac_curve25519_public_key_free(identity_key);
+        for key in one_time_keys {
+            vodozemac_curve25519_public_key_free(key);
+        }
+        vodozemac_olm_account_free(alice);
+        vodozemac_olm_account_free(bob);
+    });
+}
+
+#[test]
+fn group_sessions() {
+    assert_no_leaks(|| unsafe {
+        let config = vodozemac_megolm_session_config_version_2();
+        let outbound = vodozemac_megolm_group_session_new(config);
+        let session_key = vodozemac_megolm_group_session_session_key(outbound);
+        let inbound = vodozemac_megolm_inbound_group_session_new(session_key, config);
+
+        let pickle = take_bytes(sret(|result| {
+            vodozemac_megolm_group_session_pickle(result, outbound, &PICKLE_KEY)
+        }));
+        let restored_outbound = unwrap(sret(|result| {
+            vodozemac_megolm_group_session_from_pickle(
+                result,
+                pickle.as_ptr(),
+                pickle.len() as _,
+                &PICKLE_KEY,
+            )
+        }));
+        let result = sret(|result| {
+            vodozemac_megolm_group_session_from_pickle(
+                result,
+                pickle.as_ptr(),
+                pickle.len() as _,
+                &WRONG_KEY,
+            )
+        });
+        assert!(!unwrap_err(result).is_empty());
+
+        let pickle = take_bytes(sret(|result| {
+            vodozemac_megolm_inbound_group_session_pickle(result, inbound, &PICKLE_KEY)
+        }));
+        let restored_inbound = unwrap(sret(|result| {
+            vodozemac_megolm_inbound_group_session_from_pickle(
+                result,
+                pickle.as_ptr(),
+                pickle.len() as _,
+                &PICKLE_KEY,
+            )
+        }));
+
+        // The restored sessions still talk to each other.
+        let plaintext = b"Pickled";
+        let message = vodozemac_megolm_group_session_encrypt(
+            restored_outbound,
+            plaintext.as_ptr(),
+            plaintext.len() as _,
+        );
+        let decrypted = unwrap(sret(|result| {
+            vodozemac_megolm_inbound_group_session_decrypt(result, restored_inbound, message)
+        }));
+        assert_eq!(take_secret(decrypted.plaintext), plaintext);
+
+        vodozemac_megolm_message_free(message);
+        vodozemac_megolm_inbound_group_session_free(restored_inbound);
+        vodozemac_megolm_group_session_free(restored_outbound);
+        vodozemac_megolm_inbound_group_session_free(inbound);
+        vodozemac_megolm_session_key_free(session_key);
+        vodozemac_megolm_group_session_free(outbound);
+        vodozemac_megolm_session_config_free(config);
+    });
+}
+
+const PASSPHRASE: &[u8] = b"correct horse battery staple";
+const SALT: &[u8] = b"vodozemac salt 1";
+
+#[test]
+fn pbkdf2_sha256() {
+    assert_no_leaks(|| unsafe {
+        let derive = |salt: &[u8], iterations| {
+            sret(|result| {
+                vodozemac_pickle_key_pbkdf2_sha256(
+                    result,
+                    PASSPHRASE.as_ptr(),
+                    PASSPHRASE.len() as _,
+                    salt.as_ptr(),
+                    salt.len() as _,
+                    iterations,
+                )
+            })
+        };
+
+        // Computed with Python's `hashlib.pbkdf2_hmac`.
+        let expected = "d50e4c71f0099ee38f372a56b4d16a37e2ab6e5118c5c4b8c545b786609b0dc7";
+        let key = take_secret(unwrap(derive(SALT, 100_000)));
+        let hex = key.iter().map(|b| format!("{b:02x}")).collect::<String>();
+        assert_eq!(hex, expected);
+
+        assert!(unwrap_err(derive(b"short salt", 100_000)).contains("salt"));
+        assert!(unwrap_err(derive(SALT, 1000)).contains("iterations"));
+    });
+}
+
+#[test]
+fn argon2id() {
+    assert_no_leaks(|| unsafe {
+        let derive_with = |salt: &[u8], memory_kib, iterations, parallelism| {
+            sret(|result| {
+                vodozemac_pickle_key_argon2id(
+                    result,
+                    PASSPHRASE.as_ptr(),
+                    PASSPHRASE.len() as _,
+                    salt.as_ptr(),
+                    salt.len() as _,
+                    memory_kib,
+                    iterations,
+                    parallelism,
+                )
+            })
+        };
+        let derive = |salt: &[u8], memory_kib| derive_with(salt, memory_kib, 2, 1);
+
+        let key = take_secret(unwrap(derive(SALT, 19456)));
+        assert_eq!(key.len(), 32);
+        assert_eq!(key, take_secret(unwrap(derive(SALT, 19456))));
+        assert_ne!(key, take_secret(unwrap(derive(b"vodozemac salt 2", 19456))));
+
+        // The derived key can be used to pickle.
+        let key: [u8; 32] = key.try_into().unwrap();
+        let account = vodozemac_olm_account_new();
+        let pickle = take_bytes(sret(|result| {
+            vodozemac_olm_account_pickle(result, account, &key)
+        }));
+        let restored = unwrap(sret(|result| {
+            vodozemac_olm_account_from_pickle(result, pickle.as_ptr(), pickle.len() as _, &key)
+        }));
+
+        assert!(unwrap_err(derive(b"short salt", 19456)).contains("salt"));
+
+        // Below the OWASP minimum of 19 MiB and two iterations, and more memory
+        // than a phone can spare.
+        assert!(unwrap_err(derive(SALT, 19455)).contains("memory"));
+        assert!(unwrap_err(derive(SALT, 1024 * 1024 + 1)).contains("memory"));
+        assert!(unwrap_err(derive_with(SALT, 19456, 1, 1)).contains("iterations"));
+        assert!(unwrap_err(derive_with(SALT, 19456, 2, 0)).contains("Argon2"));
+
+        vodozemac_olm_account_free(restored);
+        vodozemac_olm_account_free(account);
+    });
+}
+
+/// Splits and frees the result of a `*_rekey_pickles` function.
+unsafe fn take_rekeyed(rekeyed: RekeyedPickles) -> Vec<Vec<u8>> {
+    let mut pickles = unsafe { take_bytes(rekeyed.pickles) }.into_iter();
+    let lengths = unsafe { std::slice::from_raw_parts(rekeyed.lengths.ptr, rekeyed.lengths.len) };
+    let split = lengths
+        .iter()
+        .map(|&length| pickles.by_ref().take(length as usize).collect())
+        .collect();
+    unsafe { dealloc(rekeyed.lengths.ptr.cast(), rekeyed.lengths.len * 4, 4) };
+    split
+}
+
+#[test]
+fn rekey() {
+    assert_no_leaks(|| unsafe {
+        let accounts = [vodozemac_olm_account_new(), vodozemac_olm_account_new()];
+        let pickles = accounts.map(|account| {
+            take_bytes(sret(|result| {
+                vodozemac_olm_account_pickle(result, account, &PICKLE_KEY)
+            }))
+        });
+        let concatenated = pickles.concat();
+        let lengths = pickles.each_ref().map(|pickle| pickle.len() as u32);
+
+        let rekey = |old_key, lengths: &[u32]| {
+            sret(|result| {
+                vodozemac_olm_account_rekey_pickles(
+                    result,
+                    concatenated.as_ptr(),
+                    concatenated.len() as _,
+                    lengths.as_ptr(),
+                    lengths.len() as _,
+                    old_key,
+                    &WRONG_KEY,
+                )
+            })
+        };
+
+        let rekeyed = take_rekeyed(unwrap(rekey(&PICKLE_KEY, &lengths)));
+        assert_eq!(rekeyed.len(), accounts.len());
+        for (account, pickle) in accounts.into_iter().zip(rekeyed) {
+            let restored = unwrap(sret(|result| {
+                vodozemac_olm_account_from_pickle(
+                    result,
+                    pickle.as_ptr(),
+                    pickle.len() as _,
+                    &WRONG_KEY,
+                )
+            }));
+            let expected = vodozemac_olm_account_ed25519_key(account);
+            let actual = vodozemac_olm_account_ed25519_key(restored);
+            assert_eq!(ed25519_bytes(expected), ed25519_bytes(actual));
+
+            vodozemac_ed25519_public_key_free(expected);
+            vodozemac_ed25519_public_key_free(actual);
+            vodozemac_olm_account_free(restored);
+        }
+
+        assert!(unwrap_err(rekey(&WRONG_KEY, &lengths)).starts_with("pickle 0: "));
+
+        // Lengths which do not describe the buffer are rejected instead of
+        // being sliced.
+        let too_long = [lengths[0], lengths[1] + 1];
+        assert!(!unwrap_err(rekey(&PICKLE_KEY, &too_long)).is_empty());
+        let overflowing = [u32::MAX, u32::MAX, lengths[0]];
+        assert!(!unwrap_err(rekey(&PICKLE_KEY, &overflowing)).is_empty());
+
+        // Pickles of other types are rejected.
+        let config = vodozemac_megolm_session_config_version_2();
+        let session = vodozemac_megolm_group_session_new(config);
+        let group_pickle = take_bytes(sret(|result| {
+            vodozemac_megolm_group_session_pickle(result, session, &PICKLE_KEY)
+        }));
+        let mixed = [pickles[0].as_slice(), &group_pickle].concat();
+        let mixed_lengths = [lengths[0], group_pickle.len() as u32];
+        let result = sret(|result| {
+            vodozemac_megolm_group_session_rekey_pickles(
+                result,
+                mixed.as_ptr(),
+                mixed.len() as _,
+                mixed_lengths.as_ptr(),
+                mixed_lengths.len() as _,
+                &PICKLE_KEY,
+                &WRONG_KEY,
+            )
+        });
+        assert!(unwrap_err(result).starts_with("pickle 0: "));
+
+        vodozemac_megolm_group_session_free(session);
+        vodozemac_megolm_session_config_free(config);
+        for account in accounts {
+            vodozemac_olm_account_free(account);
+        }
+    });
+}
+
+/// Exports `value` as JSON, checks that it is valid JSON and imports it again.
+#[cfg(feature = "dangerous-json-pickles")]
+unsafe fn json_round_trip<T>(
+    value: *const T,
+    export: unsafe extern "C" fn(*mut CSecretSlice, *const T),
+    import: unsafe extern "C" fn(*mut CResult<*mut T, CErrorStr>, *const u8, u32),
+) -> *mut T {
+    let json = unsafe { take_secret(sret(|result| export(result, value))) };
+    let parsed: serde_json::Value = serde_json::from_slice(&json).expect("valid JSON");
+    assert!(parsed.is_object());
+
+    let result = sret(|result| unsafe { import(result, b"{}".as_ptr(), 2) });
+    assert!(!unwrap_err(result).is_empty());
+
+    unwrap(sret(|result| unsafe {
+        import(result, json.as_ptr(), json.len() as _)
+    }))
+}
+
+#[test]
+#[cfg(feature = "dangerous-json-pickles")]
+fn json() {
+    assert_no_leaks(|| unsafe {
+        assert_ne!(
+            vodozemac_features() & vodozemac::FEATURE_DANGEROUS_JSON_PICKLES,
+            0
+        );
+
+        let alice = vodozemac_olm_account_new();
+        let bob = vodozemac_olm_account_new();
+        let restored = json_round_trip(
+            alice,
+            vodozemac_olm_account_dangerous_json_pickle,
+            vodozemac_olm_account_dangerous_from_json_pickle,
+        );
+        let expected = vodozemac_olm_account_curve25519_key(alice);
+        let actual = vodozemac_olm_account_curve25519_key(restored);
+        assert_eq!(curve25519_bytes(expected), curve25519_bytes(actual));
+
+        let generated = sret(|result| vodozemac_olm_account_generate_one_time_keys(result, bob, 1));
+        let one_time_keys = take_ptrs(generated.created);
+        take_ptrs(generated.removed);
+        let identity_key = vodozemac_olm_account_curve25519_key(bob);
+        let olm_config = vodozemac_olm_session_config_version_2();
+        let session = vodozemac_olm_account_create_outbound_session(
+            alice,
+            olm_config,
+            identity_key,
+            one_time_keys[0],
+        );
+        let restored_session = json_round_trip(
+            session,
+            vodozemac_olm_session_dangerous_json_pickle,
+            vodozemac_olm_session_dangerous_from_json_pickle,
+        );
+        assert_eq!(
+            take_bytes(sret(|result| vodozemac_olm_session_session_id(
+                result, session
+            ))),
+            take_bytes(sret(|result| vodozemac_olm_session_session_id(
+                result,
+                restored_session
+            )))
+        );
+
+        let megolm_config = vodozemac_megolm_session_config_version_2();
+        let outbound = vodozemac_megolm_group_session_new(megolm_config);
+        let session_key = vodozemac_megolm_group_session_session_key(outbound);
+        let inbound = vodozemac_megolm_inbound_group_session_new(session_key, megolm_config);
+        let restored_outbound = json_round_trip(
+            outbound,
+            vodozemac_megolm_group_session_dangerous_json_pickle,
+            vodozemac_megolm_group_session_dangerous_from_json_pickle,
+        );
+        let restored_inbound = json_round_trip(
+            inbound,
+            vodozemac_megolm_inbound_group_session_dangerous_json_pickle,
+            vodozemac_megolm_inbound_group_session_dangerous_from_json_pickle,
+        );
+
+        let plaintext = b"Migrated";
+        let message = vodozemac_megolm_group_session_encrypt(
+            restored_outbound,
+            plaintext.as_ptr(),
+            plaintext.len() as _,
+        );
+        let decrypted = unwrap(sret(|result| {
+            vodozemac_megolm_inbound_group_session_decrypt(result, restored_inbound, message)
+        }));
+        assert_eq!(take_secret(decrypted.plaintext), plaintext);
+
+        vodozemac_megolm_message_free(message);
+        vodozemac_megolm_inbound_group_session_free(restored_inbound);
+        vodozemac_megolm_inbound_group_session_free(inbound);
+        vodozemac_megolm_group_session_free(restored_outbound);
+        vodozemac_megolm_group_session_free(outbound);
+        vodozemac_megolm_session_key_free(session_key);
+        vodozemac_megolm_session_config_free(megolm_config);
+        vodozemac_olm_session_free(restored_session);
+        vodozemac_olm_session_free(session);
+        vodozemac_olm_session_config_free(olm_config);
+        vodozemac_curve25519_public_key_free(identity_key);
+        for key in one_time_keys {
+            vodozemac_curve25519_public_key_free(key);
+        }
+        vodozemac_curve25519_public_key_free(expected);
+        vodozemac_curve25519_public_key_free(actual);
+        vodozemac_olm_account_free(restored);
+        vodozemac_olm_account_free(alice);
+        vodozemac_olm_account_free(bob);
+    });
+}
@@ -0,0 +1,783 @@
+// SPDX-FileCopyrightText: 2025 Felix Hilgers <contact@fhilgers.com>
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD_NO_PAD;
+use ffi_tests::*;
+use serde_json::{Value, json};
+
+/// Runs the key agreement of both sides.
+fn establish() -> (*mut EstablishedSas, *mut EstablishedSas) {
+    unsafe {
+        let alice = vodozemac_sas_sas_new();
+        let bob = vodozemac_sas_sas_new();
+
+        let alice_key = vodozemac_sas_sas_public_key(alice);
+        let bob_key = vodozemac_sas_sas_public_key(bob);
+
+        let alice_established = unwrap(sret(|result| {
+            vodozemac_sas_sas_diffie_hellman(result, alice, bob_key)
+        }));
+        let bob_established = unwrap(sret(|result| {
+            vodozemac_sas_sas_diffie_hellman(result, bob, alice_key)
+        }));
+
+        vodozemac_curve25519_public_key_free(alice_key);
+        vodozemac_curve25519_public_key_free(bob_key);
+        vodozemac_sas_sas_free(alice);
+        vodozemac_sas_sas_free(bob);
+
+        (alice_established, bob_established)
+    }
+}
+
+fn short_auth_string(sas: *const EstablishedSas, info: &[u8]) -> ([u8; 7], [u16; 3]) {
+    unsafe {
+        let bytes = vodozemac_sas_established_sas_bytes(sas, info.as_ptr(), info.len() as _);
+
+        let mut emoji_indices = [0; 7];
+        let mut decimals = [0; 3];
+        vodozemac_sas_sas_bytes_emoji_indices(bytes, &mut emoji_indices);
+        vodozemac_sas_sas_bytes_decimals(bytes, &mut decimals);
+
+        vodozemac_sas_sas_bytes_free(bytes);
+
+        (emoji_indices, decimals)
+    }
+}
+
+#[test]
+fn both_sides_agree() {
+    assert_no_leaks(|| unsafe {
+        let (alice, bob) = establish();
+
+        let info = b"MATRIX_KEY_VERIFICATION_SAS";
+        let (alice_emoji, alice_decimals) = short_auth_string(alice, info);
+        let (bob_emoji, bob_decimals) = short_auth_string(bob, info);
+
+        assert_eq!(alice_emoji, bob_emoji);
+        assert_eq!(alice_decimals, bob_decimals);
+        assert!(alice_emoji.iter().all(|&index| index < 64));
+        assert!(
+            alice_decimals
+                .iter()
+                .all(|&decimal| (1000..=9191).contains(&decimal))
+        );
+
+        vodozemac_sas_established_sas_free(alice);
+        vodozemac_sas_established_sas_free(bob);
+    });
+}
+
+#[test]
+fn emojis() {
+    let table: Value = serde_json::from_str(include_str!("../../src/sas/sas-emoji.json")).unwrap();
+
+    let (alice, bob) = establish();
+    let info = b"MATRIX_KEY_VERIFICATION_SAS";
+    let emojis = |bytes, locale: &[u8]| unsafe {
+        let emojis = take_bytes(sret(|result| {
+            vodozemac_sas_sas_bytes_emojis(result, bytes, locale.as_ptr(), locale.len() as _)
+        }));
+        serde_json::from_slice::<Vec<Value>>(&emojis).unwrap()
+    };
+
+    // The table is parsed on first use and kept for the rest of the process.
+    unsafe {
+        let bytes = vodozemac_sas_established_sas_bytes(alice, info.as_ptr(), info.len() as _);
+        emojis(bytes, b"en");
+        vodozemac_sas_sas_bytes_free(bytes);
+    }
+
+    assert_no_leaks(|| unsafe {
+        let bytes = vodozemac_sas_established_sas_bytes(alice, info.as_ptr(), info.len() as _);
+        let mut indices = [0; 7];
+        vodozemac_sas_sas_bytes_emoji_indices(bytes, &mut indices);
+
+        let descriptions = |locale: &[u8]| {
+            emojis(bytes, locale)
+                .iter()
+                .map(|emoji| emoji["description"].as_str().unwrap().to_owned())
+                .collect::<Vec<_>>()
+        };
+        let english = descriptions(b"en");
+        let german = descriptions(b"de");
+        for (description, index) in german.iter().zip(indices) {
+            assert_eq!(
+                table[index as usize]["translated_descriptions"]["de"],
+                **description
+            );
+        }
+        assert_eq!(descriptions(b"de-AT"), german);
+        assert_eq!(descriptions(b"pt-BR"), descriptions(b"pt"));
+        assert_eq!(descriptions(b"pt_BR"), descriptions(b"pt"));
+        assert_eq!(descriptions(b"xx"), english);
+        assert_eq!(descriptions(b"\xff"), english);
+
+        for locale in ["en", "de-AT", "pt_BR", ""] {
+            let emojis = emojis(bytes, locale.as_bytes());
+            assert_eq!(emojis.len(), 7);
+
+            for (emoji, index) in emojis.iter().zip(indices) {
+                let entry = &table[index as usize];
+                let translations = &entry["translated_descriptions"];
+                let language = locale.split(['-', '_']).next().unwrap();
+                let description = translations
+                    .get(locale.replace('-', "_"))
+                    .or_else(|| translations.get(language))
+                    .unwrap_or(&entry["description"]);
+
+                assert_eq!(emoji["emoji"], entry["emoji"]);
+                assert_eq!(&emoji["description"], description);
+            }
+        }
+
+        vodozemac_sas_sas_bytes_free(bytes);
+    });
+
+    unsafe {
+        vodozemac_sas_established_sas_free(alice);
+        vodozemac_sas_established_sas_free(bob);
+    }
+}
+
+#[test]
+fn mac_verification() {
+    assert_no_leaks(|| unsafe {
+        let (alice, bob) = establish();
+
+        let input = b"ed25519:DEVICEID";
+        let info = b"MATRIX_KEY_VERIFICATION_MAC";
+        let mac = vodozemac_sas_established_sas_calculate_mac(
+            alice,
+            input.as_ptr(),
+            input.len() as _,
+            info.as_ptr(),
+            info.len() as _,
+        );
+
+        let verify = |input: &[u8], tag| {
+            vodozemac_sas_established_sas_verify_mac(
+                bob,
+                input.as_ptr(),
+                input.len() as _,
+                info.as_ptr(),
+                info.len() as _,
+                tag,
+            )
+        };
+        assert_eq!(verify(input, mac), 1);
+        assert_eq!(verify(b"ed25519:OTHER", mac), 0);
+
+        let mut bytes = [0; 32];
+        vodozemac_sas_mac_as_bytes(mac, &mut bytes);
+        bytes[0] ^= 1;
+        let tampered = vodozemac_sas_mac_from_slice(&bytes);
+        assert_eq!(verify(input, tampered), 0);
+
+        vodozemac_sas_mac_free(tampered);
+        vodozemac_sas_mac_free(mac);
+        vodozemac_sas_established_sas_free(alice);
+        vodozemac_sas_established_sas_free(bob);
+    });
+}
+
+const MAC_METHOD_HKDF_HMAC_SHA256_V2: u32 = 0;
+const MAC_METHOD_HKDF_HMAC_SHA256: u32 = 1;
+
+#[test]
+fn mac_methods() {
+    assert_no_leaks(|| unsafe {
+        let (alice, bob) = establish();
+        let input = b"ed25519:DEVICEID";
+        let info = b"MATRIX_KEY_VERIFICATION_MAC";
+
+        let calculate = |method| {
+            sret(|result| {
+                vodozemac_sas_established_sas_calculate_mac_with_method(
+                    result,
+                    alice,
+                    method,
+                    input.as_ptr(),
+                    input.len() as _,
+                    info.as_ptr(),
+                    info.len() as _,
+                )
+            })
+        };
+        let verify = |method, tag: &[u8]| {
+            sret(|result| {
+                vodozemac_sas_established_sas_verify_mac_with_method(
+                    result,
+                    bob,
+                    method,
+                    input.as_ptr(),
+                    input.len() as _,
+                    info.as_ptr(),
+                    info.len() as _,
+                    tag.as_ptr(),
+                    tag.len() as _,
+                )
+            })
+        };
+
+        let v2 = take_bytes(unwrap(calculate(MAC_METHOD_HKDF_HMAC_SHA256_V2)));
+        let mac = vodozemac_sas_established_sas_calculate_mac(
+            alice,
+            input.as_ptr(),
+            input.len() as _,
+            info.as_ptr(),
+            info.len() as _,
+        );
+        let mut bytes = [0; 32];
+        vodozemac_sas_mac_as_bytes(mac, &mut bytes);
+        assert_eq!(v2, STANDARD_NO_PAD.encode(bytes).as_bytes());
+
+        let legacy = take_bytes(unwrap(calculate(MAC_METHOD_HKDF_HMAC_SHA256)));
+        let calculate_invalid_base64 = |input: &[u8]| {
+            sret(|result| {
+                vodozemac_sas_established_sas_calculate_mac_invalid_base64(
+                    result,
+                    alice,
+                    input.as_ptr(),
+                    input.len() as _,
+                    info.as_ptr(),
+                    info.len() as _,
+                )
+            })
+        };
+        let invalid_base64 = take_bytes(unwrap(calculate_invalid_base64(input)));
+        assert_eq!(legacy, invalid_base64);
+        assert_ne!(legacy, v2);
+
+        unwrap(verify(MAC_METHOD_HKDF_HMAC_SHA256_V2, &v2));
+        unwrap(verify(MAC_METHOD_HKDF_HMAC_SHA256, &legacy));
+        unwrap_err(verify(MAC_METHOD_HKDF_HMAC_SHA256, &v2));
+        unwrap_err(verify(MAC_METHOD_HKDF_HMAC_SHA256_V2, &legacy));
+        unwrap_err(verify(MAC_METHOD_HKDF_HMAC_SHA256, &legacy[1..]));
+
+        let error = unwrap_err(calculate(2));
+        assert!(error.contains("unknown MAC method 2"));
+        let error = unwrap_err(verify(2, &v2));
+        assert!(error.contains("unknown MAC method 2"));
+        let error = unwrap_err(calculate_invalid_base64(b"\xff"));
+        assert!(error.contains("utf-8"));
+
+        vodozemac_sas_mac_free(mac);
+        vodozemac_sas_established_sas_free(alice);
+        vodozemac_sas_established_sas_free(bob);
+    });
+}
+
+#[test]
+fn diffie_hellman_with_raw() {
+    assert_no_leaks(|| unsafe {
+        let alice = vodozemac_sas_sas_new();
+        let bob = vodozemac_sas_sas_new();
+        let alice_key = vodozemac_sas_sas_public_key(alice);
+        let bob_key = vodozemac_sas_sas_public_key(bob);
+
+        let raw = STANDARD_NO_PAD.encode(curve25519_bytes(bob_key));
+        let alice_established = unwrap(sret(|result| {
+            vodozemac_sas_sas_diffie_hellman_with_raw(result, alice, raw.as_ptr(), raw.len() as _)
+        }));
+        let bob_established = unwrap(sret(|result| {
+            vodozemac_sas_sas_diffie_hellman(result, bob, alice_key)
+        }));
+
+        let info = b"MATRIX_KEY_VERIFICATION_SAS";
+        assert_eq!(
+            short_auth_string(alice_established, info),
+            short_auth_string(bob_established, info)
+        );
+
+        let invalid = vodozemac_sas_sas_new();
+        let garbage = b"not a key";
+        unwrap_err(sret(|result| {
+            vodozemac_sas_sas_diffie_hellman_with_raw(
+                result,
+                invalid,
+                garbage.as_ptr(),
+                garbage.len() as _,
+            )
+        }));
+        assert_eq!(vodozemac_sas_sas_is_used(invalid), 0);
+
+        vodozemac_sas_sas_free(invalid);
+        vodozemac_sas_established_sas_free(alice_established);
+        vodozemac_sas_established_sas_free(bob_established);
+        vodozemac_curve25519_public_key_free(alice_key);
+        vodozemac_curve25519_public_key_free(bob_key);
+        vodozemac_sas_sas_free(alice);
+        vodozemac_sas_sas_free(bob);
+    });
+}
+
+#[test]
+fn reuse_after_diffie_hellman() {
+    assert_no_leaks(|| unsafe {
+        let alice = vodozemac_sas_sas_new();
+        let bob = vodozemac_sas_sas_new();
+        let alice_key = vodozemac_sas_sas_public_key(alice);
+        let bob_key = vodozemac_sas_sas_public_key(bob);
+
+        // A key of small order is rejected without using up the secret.
+        let zero = [0u8; 32];
+        let zero_key = vodozemac_curve25519_public_key_from_bytes(&zero);
+        let error = unwrap_err(sret(|result| {
+            vodozemac_sas_sas_diffie_hellman(result, alice, zero_key)
+        }));
+        assert!(!error.is_empty());
+        assert_eq!(vodozemac_sas_sas_is_used(alice), 0);
+
+        let established = unwrap(sret(|result| {
+            vodozemac_sas_sas_diffie_hellman(result, alice, bob_key)
+        }));
+        assert_eq!(vodozemac_sas_sas_is_used(alice), 1);
+
+        // The public key is still readable, a second exchange fails.
+        let key_again = vodozemac_sas_sas_public_key(alice);
+        assert_eq!(curve25519_bytes(key_again), curve25519_bytes(alice_key));
+        let error = unwrap_err(sret(|result| {
+            vodozemac_sas_sas_diffie_hellman(result, alice, bob_key)
+        }));
+        assert!(error.contains("already"));
+
+        vodozemac_sas_established_sas_free(established);
+        vodozemac_curve25519_public_key_free(key_again);
+        vodozemac_curve25519_public_key_free(zero_key);
+        vodozemac_curve25519_public_key_free(alice_key);
+        vodozemac_curve25519_public_key_free(bob_key);
+        vodozemac_sas_sas_free(alice);
+        vodozemac_sas_sas_free(bob);
+    });
+}
+
+#[test]
+fn commitment() {
+    assert_no_leaks(|| unsafe {
+        let (alice, bob) = establish();
+
+        let start = br#"{"method":"m.sas.v1","hashes":["sha256"],"transaction_id":"txn1"}"#;
+        // The same content as another client might serialize it.
+        let reordered =
+            br#"{ "transaction_id": "txn1", "hashes": [ "sha256" ], "method": "m.sas.v1" }"#;
+
+        let commitment = take_bytes(unwrap(sret(|result| {
+            vodozemac_sas_established_sas_calculate_commitment(
+                result,
+                bob,
+                start.as_ptr(),
+                start.len() as _,
+            )
+        })));
+
+        let bob_key = vodozemac_sas_established_sas_our_public_key(bob);
+        let from_key = take_bytes(unwrap(sret(|result| {
+            vodozemac_curve25519_public_key_calculate_commitment(
+                result,
+                bob_key,
+                reordered.as_ptr(),
+                reordered.len() as _,
+            )
+        })));
+        assert_eq!(commitment, from_key);
+
+        let verify = |start: &[u8], commitment: &[u8]| {
+            sret(|result| {
+                vodozemac_sas_established_sas_verify_commitment(
+                    result,
+                    alice,
+                    start.as_ptr(),
+                    start.len() as _,
+                    commitment.as_ptr(),
+                    commitment.len() as _,
+                )
+            })
+        };
+        unwrap(verify(reordered, &commitment));
+        let other = br#"{"method":"m.sas.v1","hashes":["sha256"],"transaction_id":"txn2"}"#;
+        assert!(unwrap_err(verify(other, &commitment)).contains("does not match"));
+        assert!(unwrap_err(verify(b"{", &commitment)).contains("invalid start content"));
+
+        // Alice's own key is not the one Bob committed to.
+        let alice_key = vodozemac_sas_established_sas_our_public_key(alice);
+        let result = sret(|result| {
+            vodozemac_curve25519_public_key_verify_commitment(
+                result,
+                alice_key,
+                start.as_ptr(),
+                start.len() as _,
+                commitment.as_ptr(),
+                commitment.len() as _,
+            )
+        });
+        assert!(!unwrap_err(result).is_empty());
+
+        vodozemac_curve25519_public_key_free(alice_key);
+        vodozemac_curve25519_public_key_free(bob_key);
+        vodozemac_sas_established_sas_free(alice);
+        vodozemac_sas_established_sas_free(bob);
+    });
+}
+
+#[test]
+fn commitment_canonical_json() {
+    assert_no_leaks(|| unsafe {
+        let key = vodozemac_curve25519_public_key_from_bytes(&[1; 32]);
+        let start = br#"{"transaction_id":"txn1","method":"m.sas.v1","hashes":["sha256"]}"#;
+
+        // SHA-256 over the base64 key followed by
+        // {"hashes":["sha256"],"method":"m.sas.v1","transaction_id":"txn1"}.
+        let commitment = take_bytes(unwrap(sret(|result| {
+            vodozemac_curve25519_public_key_calculate_commitment(
+                result,
+                key,
+                start.as_ptr(),
+                start.len() as _,
+            )
+        })));
+        assert_eq!(commitment, b"nuCRWG2u3/JU710e/pJdyfb839uEVRHdYYv3sOWD4yE");
+
+        vodozemac_curve25519_public_key_free(key);
+    });
+}
+
+const CREATED: u32 = 0;
+const STARTED: u32 = 1;
+const CANCELLED: u32 = 7;
+
+fn new_verification(
+    ours: (&[u8], &[u8]),
+    theirs: (&[u8], &[u8]),
+) -> CResult<*mut Verification, CErrorStr> {
+    let transaction_id = b"txn1";
+    sret(|result| unsafe {
+        vodozemac_sas_verification_new(
+            result,
+            ours.0.as_ptr(),
+            ours.0.len() as _,
+            ours.1.as_ptr(),
+            ours.1.len() as _,
+            theirs.0.as_ptr(),
+            theirs.0.len() as _,
+            theirs.1.as_ptr(),
+            theirs.1.len() as _,
+            transaction_id.as_ptr(),
+            transaction_id.len() as _,
+        )
+    })
+}
+
+fn verification(ours: (&str, &str), theirs: (&str, &str)) -> *mut Verification {
+    unwrap(new_verification(
+        (ours.0.as_bytes(), ours.1.as_bytes()),
+        (theirs.0.as_bytes(), theirs.1.as_bytes()),
+    ))
+}
+
+fn events(json: CSlice<u8>) -> Vec<Value> {
+    let json = unsafe { take_bytes(json) };
+    serde_json::from_slice(&json).expect("the events are a JSON array")
+}
+
+/// Hands `events` to `to` and returns its replies.
+fn deliver(to: *const Verification, events: &[Value]) -> Vec<Value> {
+    events
+        .iter()
+        .flat_map(|event| {
+            let event_type = event["type"].as_str().unwrap();
+            let content = event["content"].to_string();
+            self::events(unwrap(sret(|result| unsafe {
+                vodozemac_sas_verification_receive(
+                    result,
+                    to,
+                    event_type.as_ptr(),
+                    event_type.len() as _,
+                    content.as_ptr(),
+                    content.len() as _,
+                )
+            })))
+        })
+        .collect()
+}
+
+fn types(events: &[Value]) -> Vec<&str> {
+    events
+        .iter()
+        .map(|event| event["type"].as_str().unwrap())
+        .collect()
+}
+
+fn cancel_code(verification: *const Verification) -> String {
+    let code = unsafe {
+        take_bytes(sret(|result| {
+            vodozemac_sas_verification_cancel_code(result, verification)
+        }))
+    };
+    String::from_utf8(code).unwrap()
+}
+
+fn sas_bytes(verification: *const Verification) -> Option<[u8; 6]> {
+    unsafe {
+        let bytes = vodozemac_sas_verification_short_auth_string(verification);
+        if bytes.is_null() {
+            return None;
+        }
+        let mut out = [0; 6];
+        vodozemac_sas_sas_bytes_as_bytes(bytes, &mut out);
+        vodozemac_sas_sas_bytes_free(bytes);
+        Some(out)
+    }
+}
+
+fn confirm(verification: *const Verification, ours: &Value, theirs: &Value) -> Vec<Value> {
+    let (ours, theirs) = (ours.to_string(), theirs.to_string());
+    events(unwrap(sret(|result| unsafe {
+        vodozemac_sas_verification_confirm(
+            result,
+            verification,
+            ours.as_ptr(),
+            ours.len() as _,
+            theirs.as_ptr(),
+            theirs.len() as _,
+        )
+    })))
+}
+
+/// Runs the flow up to the point where both sides show the short
+/// authentication string.
+fn exchange_keys(alice: *const Verification, bob: *const Verification) {
+    unsafe {
+        let start = events(unwrap(sret(|result| {
+            vodozemac_sas_verification_start(result, alice)
+        })));
+        assert_eq!(types(&start), ["m.key.verification.start"]);
+        assert_eq!(deliver(bob, &start), Vec::<Value>::new());
+        assert!(sas_bytes(bob).is_none());
+
+        let accept = events(unwrap(sret(|result| {
+            vodozemac_sas_verification_accept(result, bob)
+        })));
+        assert_eq!(types(&accept), ["m.key.verification.accept"]);
+
+        let alice_key = deliver(alice, &accept);
+        assert_eq!(types(&alice_key), ["m.key.verification.key"]);
+        let bob_key = deliver(bob, &alice_key);
+        assert_eq!(types(&bob_key), ["m.key.verification.key"]);
+        assert_eq!(deliver(alice, &bob_key), Vec::<Value>::new());
+    }
+}
+
+#[test]
+fn verification_flow() {
+    assert_no_leaks(|| unsafe {
+        let alice = verification(("@alice:example.org", "ALICE"), ("@bob:example.org", "BOB"));
+        let bob = verification(("@bob:example.org", "BOB"), ("@alice:example.org", "ALICE"));
+
+        exchange_keys(alice, bob);
+        assert_eq!(vodozemac_sas_verification_state(alice), 3);
+        assert_eq!(vodozemac_sas_verification_supports_emoji(alice), 1);
+        let alice_sas = sas_bytes(alice).expect("the keys were exchanged");
+        assert_eq!(Some(alice_sas), sas_bytes(bob));
+
+        let alice_keys = json!({ "ed25519:ALICE": "YWxpY2UncyBkZXZpY2Uga2V5" });
+        let bob_keys = json!({
+            "ed25519:BOB": "Ym9iJ3MgZGV2aWNlIGtleQ",
+            "ed25519:MASTER": "Ym9iJ3MgbWFzdGVyIGtleQ",
+        });
+
+        // Bob confirms first, his MAC is only checked once Alice confirms too.
+        let bob_mac = confirm(bob, &bob_keys, &alice_keys);
+        assert_eq!(types(&bob_mac), ["m.key.verification.mac"]);
+        assert_eq!(deliver(alice, &bob_mac), Vec::<Value>::new());
+
+        // Alice does not know Bob's master key yet.
+        let alice_known = json!({ "ed25519:BOB": "Ym9iJ3MgZGV2aWNlIGtleQ" });
+        let alice_mac = confirm(alice, &alice_keys, &alice_known);
+        assert_eq!(
+            types(&alice_mac),
+            ["m.key.verification.mac", "m.key.verification.done"]
+        );
+
+        let bob_done = deliver(bob, &alice_mac);
+        assert_eq!(types(&bob_done), ["m.key.verification.done"]);
+        assert_eq!(deliver(alice, &bob_done), Vec::<Value>::new());
+        assert_eq!(vodozemac_sas_verification_state(alice), 6);
+        assert_eq!(vodozemac_sas_verification_state(bob), 6);
+
+        let verified = take_bytes(sret(|result| {
+            vodozemac_sas_verification_verified_keys(result, alice)
+        }));
+        assert_eq!(verified, br#"["ed25519:BOB"]"#);
+
+        vodozemac_sas_verification_free(alice);
+        vodozemac_sas_verification_free(bob);
+    });
+}
+
+#[test]
+fn legacy_mac_negotiation() {
+    assert_no_leaks(|| unsafe {
+        let bob = verification(("@bob:example.org", "BOB"), ("@alice:example.org", "ALICE"));
+
+        let start = json!([{
+            "type": "m.key.verification.start",
+            "content": {
+                "transaction_id": "txn1",
+                "from_device": "ALICE",
+                "method": "m.sas.v1",
+                "key_agreement_protocols": ["curve25519-hkdf-sha256"],
+                "hashes": ["sha256"],
+                "message_authentication_codes": ["hkdf-hmac-sha256"],
+                "short_authentication_string": ["decimal"],
+            },
+        }]);
+        assert_eq!(deliver(bob, start.as_array().unwrap()), Vec::<Value>::new());
+
+        let accept = events(unwrap(sret(|result| {
+            vodozemac_sas_verification_accept(result, bob)
+        })));
+        let content = &accept[0]["content"];
+        assert_eq!(content["message_authentication_code"], "hkdf-hmac-sha256");
+        assert_eq!(content["short_authentication_string"], json!(["decimal"]));
+        assert_eq!(vodozemac_sas_verification_supports_emoji(bob), 0);
+
+        vodozemac_sas_verification_free(bob);
+    });
+}
+
+#[test]
+fn verification_cancellation() {
+    assert_no_leaks(|| unsafe {
+        let alice = verification(("@alice:example.org", "ALICE"), ("@bob:example.org", "BOB"));
+        let bob = verification(("@bob:example.org", "BOB"), ("@alice:example.org", "ALICE"));
+
+        // A MAC before the keys were exchanged is out of order.
+        let start = events(unwrap(sret(|result| {
+            vodozemac_sas_verification_start(result, alice)
+        })));
+        deliver(bob, &start);
+        let mac = json!([{
+            "type": "m.key.verification.mac",
+            "content": { "transaction_id": "txn1", "mac": {}, "keys": "" },
+        }]);
+        let cancel = deliver(bob, mac.as_array().unwrap());
+        assert_eq!(types(&cancel), ["m.key.verification.cancel"]);
+        assert_eq!(cancel_code(bob), "m.unexpected_message");
+        assert_eq!(vodozemac_sas_verification_state(bob), CANCELLED);
+
+        assert_eq!(deliver(alice, &cancel), Vec::<Value>::new());
+        assert_eq!(cancel_code(alice), "m.unexpected_message");
+        let error = unwrap_err(sret(|result| {
+            vodozemac_sas_verification_accept(result, alice)
+        }));
+        assert!(error.contains("accept"));
+
+        vodozemac_sas_verification_free(alice);
+        vodozemac_sas_verification_free(bob);
+    });
+}
+
+#[test]
+fn other_transactions() {
+    assert_no_leaks(|| unsafe {
+        let alice = verification(("@alice:example.org", "ALICE"), ("@bob:example.org", "BOB"));
+        let bob = verification(("@bob:example.org", "BOB"), ("@alice:example.org", "ALICE"));
+
+        let mut start = events(unwrap(sret(|result| {
+            vodozemac_sas_verification_start(result, alice)
+        })));
+        start[0]["content"]["transaction_id"] = "txn2".into();
+        assert_eq!(deliver(bob, &start), Vec::<Value>::new());
+        assert_eq!(vodozemac_sas_verification_state(bob), CREATED);
+
+        let cancel = json!([{
+            "type": "m.key.verification.cancel",
+            "content": { "transaction_id": "txn2", "code": "m.user", "reason": "" },
+        }]);
+        assert_eq!(
+            deliver(alice, cancel.as_array().unwrap()),
+            Vec::<Value>::new()
+        );
+        assert_eq!(vodozemac_sas_verification_state(alice), STARTED);
+        assert_eq!(cancel_code(alice), "");
+
+        vodozemac_sas_verification_free(alice);
+        vodozemac_sas_verification_free(bob);
+    });
+}
+
+#[test]
+fn verification_invalid_utf8() {
+    assert_no_leaks(|| unsafe {
+        let invalid = b"\xff".as_slice();
+        let error = unwrap_err(new_verification(
+            (b"@alice:example.org", invalid),
+            (b"@bob:example.org", b"BOB"),
+        ));
+        assert!(error.contains("utf-8"));
+
+        let alice = verification(("@alice:example.org", "ALICE"), ("@bob:example.org", "BOB"));
+        let content = br#"{"transaction_id":"txn1"}"#;
+        let error = unwrap_err(sret(|result| {
+            vodozemac_sas_verification_receive(
+                result,
+                alice,
+                invalid.as_ptr(),
+                invalid.len() as _,
+                content.as_ptr(),
+                content.len() as _,
+            )
+        }));
+        assert!(error.contains("utf-8"));
+
+        let error = unwrap_err(sret(|result| {
+            vodozemac_sas_verification_cancel(result, alice, invalid.as_ptr(), invalid.len() as _)
+        }));
+        assert!(error.contains("utf-8"));
+        assert_eq!(vodozemac_sas_verification_state(alice), CREATED);
+
+        vodozemac_sas_verification_free(alice);
+    });
+}
+
+#[test]
+fn mismatched_commitment() {
+    assert_no_leaks(|| unsafe {
+        let alice = verification(("@alice:example.org", "ALICE"), ("@bob:example.org", "BOB"));
+        let bob = verification(("@bob:example.org", "BOB"), ("@alice:example.org", "ALICE"));
+        let mallory = verification(("@bob:example.org", "BOB"), ("@alice:example.org", "ALICE"));
+
+        let start = events(unwrap(sret(|result| {
+            vodozemac_sas_verification_start(result, alice)
+        })));
+        deliver(bob, &start);
+        deliver(mallory, &start);
+        let accept = events(unwrap(sret(|result| {
+            vodozemac_sas_verification_accept(result, bob)
+        })));
+        events(unwrap(sret(|result| {
+            vodozemac_sas_verification_accept(result, mallory)
+        })));
+
+        // Mallory answers with a key Bob did not commit to.
+        let alice_key = deliver(alice, &accept);
+        let mallory_key = deliver(mallory, &alice_key);
+        let cancel = deliver(alice, &mallory_key);
+        assert_eq!(types(&cancel), ["m.key.verification.cancel"]);
+        assert_eq!(cancel_code(alice), "m.mismatched_commitment");
+        assert!(sas_bytes(alice).is_none());
+
+        let code = "m.user";
+        let cancel = events(unwrap(sret(|result| {
+            vodozemac_sas_verification_cancel(result, bob, code.as_ptr(), code.len() as _)
+        })));
+        assert_eq!(cancel[0]["content"]["code"], code);
+
+        vodozemac_sas_verification_free(alice);
+        vodozemac_sas_verification_free(bob);
+        vodozemac_sas_verification_free(mallory);
+    });
+}
@@ -0,0 +1,279 @@
+// SPDX-FileCopyrightText: 2025 Felix Hilgers <contact@fhilgers.com>
+//
+// SPDX-License-Identifier: Apache-2.0
+
+// Generates the JS wrapper classes and their typings from the descriptors
+// which `#[ffi]` stores in the `vodozemac_ffi` custom section.
+//
+// Every `*_free` function introduces a class, all other functions are
+// attached to the class with the longest matching prefix. Functions taking a
+// reference to the class as their first argument become instance methods, all
+// others become static methods. Functions whose types can not be represented
+// in JS fail the generation, unless they are listed in IGNORED_FUNCTIONS.
+//
+// The layouts of the `#[repr(C)]` types are read from `vodozemac_abi_layout`
+// and embedded into the generated code.
+
+import { BUILTIN_TYPES, isSupported, parseType, readLayout } from "./src/runtime.mjs";
+
+const MODULES = ["olm", "megolm", "sas"];
+
+// Functions with a `_free` suffix which do not free a class.
+const IGNORED_CLASSES = [
+  "vodozemac_byte_slice",
+  "vodozemac_ptr_slice",
+  "vodozemac_megolm_inbound_group_session_decrypt_batch",
+];
+
+// Functions which are not needed in JS.
+const IGNORED_FUNCTIONS = [
+  // The runtime frees the results while reading them.
+  "vodozemac_megolm_inbound_group_session_decrypt_batch_free",
+];
+
+const camel = (name) => name.replace(/_([a-z0-9])/g, (_, c) => c.toUpperCase());
+const pascal = (name) => camel(name).replace(/^./, (c) => c.toUpperCase());
+
+const readDescriptors = (module) => {
+  const decoder = new TextDecoder();
+  return WebAssembly.Module.customSections(module, "vodozemac_ffi")
+    .flatMap((section) => decoder.decode(section).split("\n"))
+    .filter((line) => line.length > 0)
+    .map((line) => JSON.parse(line));
+};
+
+const collectClasses = (descriptors) =>
+  descriptors
+    .filter(({ name, args }) => name.endsWith("_free") && args.length === 1)
+    .map((free) => ({ free, prefix: free.name.slice(0, -"_free".length) }))
+    .filter(({ prefix }) => !IGNORED_CLASSES.includes(prefix))
+    .map(({ free, prefix }) => {
+      const path = prefix.slice("vodozemac_".length).split("_");
+      const module = MODULES.includes(path[0]) ? path.shift() : null;
+      const name = pascal(path.join("_"));
+
+      return {
+        prefix,
+        module,
+        name,
+        ident: module === null ? name : pascal(module) + name,
+        rustType: parseType(free.args[0].ty).params[0].name,
+        free,
+        methods: [],
+      };
+    });
+
+// Rewrites the Rust type names in `ty` to the identifiers of their classes,
+// preferring classes from the module of `owner`.
+const resolveTypes = (ty, owner, classes) =>
+  ty.replace(/\b[A-Z]\w*\b/g, (word) => {
+    const candidates = classes.filter(({ rustType }) => rustType === word);
+    const resolved = candidates.find(({ module }) => module === owner.module) ?? candidates[0];
+    return resolved?.ident ?? word;
+  });
+
+// Resolves the field types of the layout like the types of the functions
+// returning them.
+const resolveLayout = (exports, descriptors, classes) =>
+  readLayout(exports, (name, ty) => {
+    const returning = descriptors.find(({ ret }) => new RegExp(`\\b${name}\\b`).test(ret));
+    const owner = classes.find(({ prefix }) => returning?.name.startsWith(prefix + "_"));
+    return resolveTypes(ty, owner ?? { module: null }, classes);
+  });
+
+const collectMethods = (descriptors, classes, layout) => {
+  const idents = new Set(classes.map(({ ident }) => ident));
+  const frees = new Set(classes.map(({ free }) => free.name));
+
+  for (const descriptor of descriptors) {
+    if (frees.has(descriptor.name) || IGNORED_FUNCTIONS.includes(descriptor.name)) continue;
+
+    const owner = classes
+      .filter(({ prefix }) => descriptor.name.startsWith(prefix + "_"))
+      .reduce((best, cls) => (best && best.prefix.length > cls.prefix.length ? best : cls), null);
+    if (owner === null) continue;
+
+    const resolved = {
+      ...descriptor,
+      args: descriptor.args.map((arg) => ({ ...arg, ty: resolveTypes(arg.ty, owner, classes) })),
+      ret: resolveTypes(descriptor.ret, owner, classes),
+    };
+
+    const unsupported = [
+      ...resolved.args
+        .filter(({ ty }) => !isSupported(parseType(ty), idents, true, layout))
+        .map(({ ty }) => ty),
+      ...(isSupported(parseType(resolved.ret), idents, false, layout) ? [] : [resolved.ret]),
+    ];
+    if (unsupported.length > 0) {
+      const types = unsupported.join(", ");
+      throw new Error(`${descriptor.name} uses types which are not supported in JS: ${types}`);
+    }
+
+    const [first] = resolved.args;
+    owner.methods.push({
+      descriptor: resolved,
+      name: camel(descriptor.name.slice(owner.prefix.length + 1)),
+      instance: first !== undefined && first.ty === `&${owner.ident}`,
+    });
+  }
+};
+
+const emitCode = (classes, layout) => {
+  const lines = [
+    `const __rt = new Runtime(instance.exports, ${JSON.stringify(layout)});`,
+    `const __ffi = ${JSON.stringify(
+      Object.fromEntries(
+        classes.flatMap(({ methods }) =>
+          methods.map(({ descriptor }) => [descriptor.name, descriptor]),
+        ),
+      ),
+    )};`,
+  ];
+
+  for (const cls of classes) {
+    lines.push(`class ${cls.ident} extends Handle {`);
+    for (const { descriptor, name, instance } of cls.methods) {
+      const params = descriptor.args
+        .slice(instance ? 1 : 0)
+        .filter(({ ty }) => !ty.startsWith("&mut"))
+        .map(({ name }) => camel(name));
+      lines.push(
+        `  ${instance ? "" : "static "}${name}(${params.join(", ")}) {`,
+        `    return __rt.invoke(__ffi.${descriptor.name}, ${instance ? "this" : "null"}, [${params.join(", ")}]);`,
+        "  }",
+      );
+    }
+    lines.push(
+      "}",
+      `${cls.ident}.__free = instance.exports.${cls.free.name};`,
+      `${cls.ident}.__registry = __rt.registry(${cls.ident}.__free);`,
+    );
+  }
+
+  lines.push(`__rt.classes = { ${classes.map(({ ident }) => ident).join(", ")} };`);
+
+  for (const module of MODULES) {
+    const members = classes
+      .filter((cls) => cls.module === module)
+      .map(({ name, ident }) => `${name}: ${ident}`);
+    lines.push(`export const ${module} = { ${members.join(", ")} };`);
+  }
+
+  const roots = classes.filter(({ module }) => module === null).map(({ ident }) => ident);
+  lines.push(`export { VodozemacError, ${roots.join(", ")} };`);
+
+  return lines.join("\n") + "\n";
+};
+
+const emitTypings = (classes, layout) => {
+  const qualified = new Map(
+    classes.map(({ module, name, ident }) => [ident, module === null ? name : `${module}.${name}`]),
+  );
+  const classType = (ty) => qualified.get(ty.name);
+
+  const tsType = (ty, argument, top = false) => {
+    const [param] = ty.params;
+    switch (ty.name) {
+      case "&":
+        if (param.name === "[]") {
+          const [elem] = param.params;
+          if (elem.name === "u8") return "Uint8Array | string";
+          if (elem.name === "u32") return "number[]";
+          return `${classType(elem.params[0])}[]`;
+        }
+        if (param.name === "[;]") return "Uint8Array | string";
+        return classType(param);
+      case "NonNull":
+        return classType(param);
+      case "Option":
+        return `${tsType(param, argument)} | null`;
+      case "CSlice":
+        return param.name === "u8" ? "Uint8Array" : `Array<${tsType(param, argument)}>`;
+      case "CSecretSlice":
+        return "Uint8Array";
+      case "CResult": {
+        const [ok, err] = ty.params;
+        if (top && err.name === "CErrorStr") return tsType(ok, argument);
+        return `{ ok: ${tsType(ok, argument)} } | { error: ${tsType(err, argument)} }`;
+      }
+      case "CErrorStr":
+        return "VodozemacError";
+      case "u64":
+        return argument ? "bigint | number" : "bigint";
+      case "ZST":
+      case "()":
+        return "void";
+      default:
+        if (ty.name in layout.structs || ty.name in layout.enums) return ty.name;
+        return "number";
+    }
+  };
+
+  const fields = (fields) =>
+    fields.map(([name, field]) => `${name}: ${tsType(parseType(field), false)}`).join("; ");
+
+  const returnType = ({ args, ret }) => {
+    if (ret !== "()") return tsType(parseType(ret), false, true);
+    const outputs = args
+      .map(({ ty }) => parseType(ty))
+      .filter(({ name }) => name === "&mut")
+      .map(({ params: [array] }) => (array.params[0].name === "u8" ? "Uint8Array" : "Uint16Array"));
+    if (outputs.length === 0) return "void";
+    return outputs.length === 1 ? outputs[0] : `[${outputs.join(", ")}]`;
+  };
+
+  const declareClass = (cls, indent) => {
+    const keyword = indent === "" ? "export declare class" : "export class";
+    const lines = [`${indent}${keyword} ${cls.name} {`, `${indent}  private constructor();`];
+    for (const { descriptor, name, instance } of cls.methods) {
+      const params = descriptor.args
+        .slice(instance ? 1 : 0)
+        .filter(({ ty }) => !ty.startsWith("&mut"))
+        .map(({ name, ty }) => `${camel(name)}: ${tsType(parseType(ty), true)}`);
+      lines.push(
+        `${indent}  ${instance ? "" : "static "}${name}(${params.join(", ")}): ${returnType(descriptor)};`,
+      );
+    }
+    lines.push(`${indent}  free(): void;`, `${indent}}`);
+    return lines;
+  };
+
+  const lines = [
+    "export declare const instance: WebAssembly.Instance;",
+    "export declare class VodozemacError extends Error {",
+    "  readonly errorClass: string;",
+    "  readonly code: number;",
+    "  readonly messageIndex: number | null;",
+    "}",
+    ...Object.entries(layout.structs)
+      .filter(([name]) => !BUILTIN_TYPES.includes(name))
+      .map(([name, { fields: layout }]) => `export interface ${name} { ${fields(layout)} }`),
+    ...Object.entries(layout.enums).map(
+      ([name, { variants }]) =>
+        `export type ${name} = ${variants
+          .map((layout, variant) => `{ variant: ${variant}; ${fields(layout)} }`)
+          .join(" | ")};`,
+    ),
+    ...classes.filter(({ module }) => module === null).flatMap((cls) => declareClass(cls, "")),
+  ];
+
+  for (const module of MODULES) {
+    lines.push(`export declare namespace ${module} {`);
+    for (const cls of classes.filter((cls) => cls.module === module)) {
+      lines.push(...declareClass(cls, "  "));
+    }
+    lines.push("}");
+  }
+
+  return lines.join("\n") + "\n";
+};
+
+export const generate = (module, instance) => {
+  const descriptors = readDescriptors(module);
+  const classes = collectClasses(descriptors);
+  const layout = resolveLayout(instance.exports, descriptors, classes);
+  collectMethods(descriptors, classes, layout);
+
+  return { code: emitCode(classes, layout), typings: emitTypings(classes, layout) };
+};
@@ -8,22 +8,33 @@ import replace from "@rollup/plugin-replace";
 
 import fs from "fs/promises";
 
+import { generate } from "./generate.mjs";
+
 const appendExportsPlugin = (wasmPath) => {
   return {
     name: "append-exports",
-    async transform(code) {
+    async transform(code, id) {
+      if (!id.endsWith("src/index.mjs")) return null;
+
       const wasmBuffer = await fs.readFile(wasmPath);
-      const module = await WebAssembly.instantiate(wasmBuffer, {
+      const module = await WebAssembly.compile(wasmBuffer);
+      const instance = await WebAssembly.instantiate(module, {
         env: { extern_rng: () => {} },
       });
 
-      console.log(Object.entries(module.instance.exports));
-      const exports = Object.keys(module.instance.exports)
+      const exports = Object.keys(instance.exports)
         .map((key) => `export const ${key} = instance.exports.${key};`)
         .join("\n");
 
+      const { code: wrappers, typings } = generate(module, instance);
+      this.emitFile({
+        type: "asset",
+        fileName: "vodozemac.d.ts",
+        source: typings,
+      });
+
       return {
-        code: code + exports,
+        code: code + exports + "\n" + wrappers,
       };
     },
   };
@@ -4,6 +4,8 @@
 
 import wasm from "vodozemac.wasm";
 
+import { Runtime, Handle, VodozemacError } from "./runtime.mjs";
+
 const { instance } = await wasm({
   env: {
     extern_rng: (ptr, len) => {
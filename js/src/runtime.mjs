@@ -0,0 +1,429 @@
+// SPDX-FileCopyrightText: 2025 Felix Hilgers <contact@fhilgers.com>
+//
+// SPDX-License-Identifier: Apache-2.0
+
+// Helpers used by the wrapper classes generated by generate.mjs. The wrappers
+// describe every call with the descriptor emitted by `#[ffi]`, this file turns
+// the Rust types of those descriptors into JS values. All sizes assume wasm32,
+// where pointers and usize are 4 bytes wide.
+
+const WORD = 4;
+
+const encoder = new TextEncoder();
+const decoder = new TextDecoder();
+
+// Types with a dedicated representation in JS, their layout is only used for
+// their size.
+export const BUILTIN_TYPES = ["CSecretSlice", "CErrorStr"];
+
+const camel = (name) => name.replace(/_([a-z0-9])/g, (_, c) => c.toUpperCase());
+
+// Types as written in the layout description, without lifetimes and spaces.
+const normalizeType = (ty) => ty.replace(/'\w+/g, "").replace(/\s+/g, "");
+
+// Reads the description of the `#[repr(C)]` types returned through `#[sret]`
+// from `vodozemac_abi_layout`. Generic types are skipped, `resolve` rewrites
+// the Rust type of every field of the named type.
+export const readLayout = (exports, resolve = (name, ty) => ty) => {
+  const ptr = exports.alloc(2 * WORD, WORD);
+  exports.vodozemac_abi_layout(ptr);
+  const view = new DataView(exports.memory.buffer);
+  const [data, len] = [view.getUint32(ptr, true), view.getUint32(ptr + WORD, true)];
+  const json = JSON.parse(decoder.decode(new Uint8Array(exports.memory.buffer, data, len)));
+  exports.dealloc(data, len, 1);
+  exports.dealloc(ptr, 2 * WORD, WORD);
+
+  if (json.word !== WORD) {
+    throw new Error(`expected a wasm32 module with ${WORD} byte words, got ${json.word}`);
+  }
+
+  const fields = (name, fields) =>
+    fields.map((field) => [
+      camel(field.name),
+      resolve(name, normalizeType(field.type)),
+      field.offset,
+    ]);
+  const layout = { structs: {}, enums: {} };
+  for (const ty of json.types.filter(({ name }) => !name.includes("<"))) {
+    if (ty.fields !== undefined) {
+      layout.structs[ty.name] = { size: ty.size, fields: fields(ty.name, ty.fields) };
+    } else {
+      layout.enums[ty.name] = {
+        size: ty.size,
+        variants: ty.variants.map((variant) => fields(ty.name, variant.fields)),
+      };
+    }
+  }
+  return layout;
+};
+
+export const ALIASES = {
+  BatchDecryptionResult: "CResult<DecryptedMessage,CErrorStr>",
+};
+
+// Parses a type as written by `#[ffi]`, e.g. `CResult<NonNull<Account>,CErrorStr>`.
+export const parseType = (ty) => {
+  if (ty in ALIASES) return parseType(ALIASES[ty]);
+  if (ty.startsWith("&mut")) return { name: "&mut", params: [parseType(ty.slice(4))] };
+  if (ty.startsWith("&")) return { name: "&", params: [parseType(ty.slice(1))] };
+  if (ty.startsWith("*")) return { name: "*", params: [] };
+  if (ty.startsWith("[")) {
+    const [elem, len] = splitTopLevel(ty.slice(1, -1), ";");
+    return len === undefined
+      ? { name: "[]", params: [parseType(elem)] }
+      : { name: "[;]", params: [parseType(elem)], len: Number(len) };
+  }
+  const open = ty.indexOf("<");
+  if (open === -1) return { name: ty, params: [] };
+  return {
+    name: ty.slice(0, open),
+    params: splitTopLevel(ty.slice(open + 1, -1), ",").map(parseType),
+  };
+};
+
+const splitTopLevel = (ty, separator) => {
+  const parts = [];
+  let depth = 0;
+  let start = 0;
+  for (let i = 0; i < ty.length; i++) {
+    if (ty[i] === "<" || ty[i] === "[") depth++;
+    else if (ty[i] === ">" || ty[i] === "]") depth--;
+    else if (ty[i] === separator && depth === 0) {
+      parts.push(ty.slice(start, i));
+      start = i + 1;
+    }
+  }
+  parts.push(ty.slice(start));
+  return parts;
+};
+
+const INTEGERS = ["u8", "u16", "u32", "usize", "i32"];
+
+export const sizeOf = (ty, layout) => {
+  switch (ty.name) {
+    case "()":
+    case "ZST":
+      return 0;
+    case "u64":
+      return 8;
+    case "CSlice":
+      return 2 * WORD;
+    case "CResult":
+      return WORD + Math.max(...ty.params.map((param) => sizeOf(param, layout)));
+    default:
+      return (layout.structs[ty.name] ?? layout.enums[ty.name])?.size ?? WORD;
+  }
+};
+
+// Whether `ty` can be passed to (`argument`) or returned from a function.
+export const isSupported = (ty, classes, argument, layout) => {
+  const isClass = (ty) => classes.has(ty.name);
+  const [param] = ty.params;
+  switch (ty.name) {
+    case "&":
+      if (!argument) return isClass(param);
+      if (param.name === "[]") {
+        const [elem] = param.params;
+        return ["u8", "u32"].includes(elem.name) || (elem.name === "&" && isClass(elem.params[0]));
+      }
+      if (param.name === "[;]") return param.params[0].name === "u8";
+      return isClass(param);
+    case "&mut":
+      return argument && param.name === "[;]" && ["u8", "u16"].includes(param.params[0].name);
+    case "NonNull":
+      return !argument && isClass(param);
+    case "Option":
+      return isSupported(param, classes, argument, layout) && param.name !== "Option";
+    case "CSlice":
+      return !argument && (param.name === "u8" || isSupported(param, classes, false, layout));
+    case "CResult":
+      return !argument && ty.params.every((param) => isSupported(param, classes, false, layout));
+    case "()":
+    case "ZST":
+    case "CSecretSlice":
+      return !argument;
+    case "u64":
+    case "CErrorStr":
+      return true;
+    default:
+      return (
+        INTEGERS.includes(ty.name) ||
+        (!argument && (ty.name in layout.structs || ty.name in layout.enums))
+      );
+  }
+};
+
+// The names of `ExceptionClass`, indexed by `CErrorStr::class`.
+const ERROR_CLASSES = ["Vodozemac", "Decryption", "Pickle", "Key", "Signature"];
+
+// `NO_MESSAGE_INDEX` on wasm32.
+const NO_MESSAGE_INDEX = 0xffffffff;
+
+// `errorClass` and `code` identify the error like the exception classes and
+// codes of the JNI bindings, `messageIndex` is null unless the error refers
+// to a message.
+export class VodozemacError extends Error {
+  constructor(message, errorClass = "Vodozemac", code = 0, messageIndex = null) {
+    super(message);
+    this.name = "VodozemacError";
+    this.errorClass = errorClass;
+    this.code = code;
+    this.messageIndex = messageIndex;
+  }
+}
+
+export class Runtime {
+  constructor(exports, layout) {
+    this.exports = exports;
+    this.layout = layout;
+  }
+
+  get view() {
+    return new DataView(this.exports.memory.buffer);
+  }
+
+  alloc(size, align = 1) {
+    return this.exports.alloc(size, align);
+  }
+
+  dealloc(ptr, size, align = 1) {
+    this.exports.dealloc(ptr, size, align);
+  }
+
+  bytes(value) {
+    return typeof value === "string" ? encoder.encode(value) : value;
+  }
+
+  // Copies `values` into wasm memory, returns the pointer and the number of
+  // elements. Every element is `size` bytes wide and written by `write`.
+  passArray(values, size, write) {
+    const ptr = this.alloc(values.length * size, size);
+    const view = this.view;
+    for (let i = 0; i < values.length; i++) {
+      write(view, ptr + i * size, values[i]);
+    }
+    return [ptr, values.length];
+  }
+
+  passBytes(value, length) {
+    const bytes = this.bytes(value);
+    if (length !== undefined && bytes.length !== length) {
+      throw new RangeError(`expected ${length} bytes, got ${bytes.length}`);
+    }
+    const ptr = this.alloc(bytes.length);
+    new Uint8Array(this.exports.memory.buffer, ptr, bytes.length).set(bytes);
+    return [ptr, bytes.length];
+  }
+
+  takeBytes(ptr, length) {
+    const bytes = new Uint8Array(this.exports.memory.buffer, ptr, length).slice();
+    this.dealloc(ptr, length);
+    return bytes;
+  }
+
+  // Like `takeBytes`, but the library wipes the memory before freeing it.
+  takeSecret(ptr, length) {
+    const bytes = new Uint8Array(this.exports.memory.buffer, ptr, length).slice();
+    this.exports.vodozemac_secret_slice_free(ptr, length);
+    return bytes;
+  }
+
+  takeString(ptr, length) {
+    return decoder.decode(this.takeBytes(ptr, length));
+  }
+
+  registry(free) {
+    return new FinalizationRegistry((ptr) => free(ptr));
+  }
+
+  // Calls the function described by `descriptor`. `self` is passed as the
+  // first argument of instance methods, `&mut [T; N]` arguments are not taken
+  // from `args` but returned if the function itself does not return anything.
+  invoke(descriptor, self, args) {
+    const cleanup = [];
+    const outputs = [];
+    const call = [];
+    const inputs = (self === null ? args : [self, ...args])[Symbol.iterator]();
+
+    try {
+      for (const arg of descriptor.args) {
+        const ty = parseType(arg.ty);
+        const value = ty.name === "&mut" ? undefined : inputs.next().value;
+        this.pass(ty, value, call, cleanup, outputs);
+      }
+
+      const ret = parseType(descriptor.ret);
+      const fn = this.exports[descriptor.name];
+      let result;
+      if (descriptor.sret) {
+        const size = sizeOf(ret, this.layout);
+        const ptr = this.alloc(size, WORD);
+        cleanup.push(() => this.dealloc(ptr, size, WORD));
+        fn(ptr, ...call);
+        result = this.read(ret, ptr, true);
+      } else {
+        result = this.convert(ret, fn(...call));
+      }
+
+      if (ret.name !== "()") return result;
+      const values = outputs.map((read) => read());
+      return values.length > 1 ? values : values[0];
+    } finally {
+      cleanup.forEach((free) => free());
+    }
+  }
+
+  pass(ty, value, call, cleanup, outputs) {
+    const [param] = ty.params;
+    switch (ty.name) {
+      case "Option":
+        if (value === null || value === undefined) call.push(0);
+        else this.pass(param, value, call, cleanup, outputs);
+        return;
+      case "&":
+        if (param.name === "[]") {
+          const [ptr, len] = this.passSlice(param.params[0], value);
+          const size = param.params[0].name === "u8" ? 1 : WORD;
+          cleanup.push(() => this.dealloc(ptr, len * size, size));
+          call.push(ptr, len);
+        } else if (param.name === "[;]") {
+          // Fixed size arrays are keys, wipe them before they are freed.
+          const [ptr, len] = this.passBytes(value, param.len);
+          cleanup.push(() => {
+            new Uint8Array(this.exports.memory.buffer, ptr, len).fill(0);
+            this.dealloc(ptr, len);
+          });
+          call.push(ptr);
+        } else {
+          call.push(value.ptr);
+        }
+        return;
+      case "&mut": {
+        const array = param.params[0].name === "u8" ? Uint8Array : Uint16Array;
+        const size = param.len * array.BYTES_PER_ELEMENT;
+        const ptr = this.alloc(size, array.BYTES_PER_ELEMENT);
+        cleanup.push(() => this.dealloc(ptr, size, array.BYTES_PER_ELEMENT));
+        outputs.push(() => new array(this.exports.memory.buffer, ptr, param.len).slice());
+        call.push(ptr);
+        return;
+      }
+      case "u64":
+        call.push(BigInt(value));
+        return;
+      default:
+        call.push(value);
+    }
+  }
+
+  passSlice(elem, value) {
+    if (elem.name === "u8") return this.passBytes(value);
+    if (elem.name === "u32") {
+      return this.passArray(value, WORD, (view, offset, x) => view.setUint32(offset, x, true));
+    }
+    return this.passArray(value, WORD, (view, offset, x) => view.setUint32(offset, x.ptr, true));
+  }
+
+  wrap(ty, ptr) {
+    return this.classes[ty.name].__wrap(ptr);
+  }
+
+  convert(ty, value) {
+    switch (ty.name) {
+      case "NonNull":
+        return this.wrap(ty.params[0], value);
+      case "Option":
+        return value === 0 ? null : this.convert(ty.params[0], value);
+      default:
+        return value;
+    }
+  }
+
+  // Reads a value of type `ty` from wasm memory, taking ownership of all
+  // allocations it refers to. Errors of a top level `CResult` are thrown.
+  read(ty, ptr, throws = false) {
+    const word = (offset = 0) => this.view.getUint32(ptr + offset, true);
+    const [param] = ty.params;
+    switch (ty.name) {
+      case "u32":
+      case "usize":
+        return word();
+      case "&":
+      case "NonNull":
+        return this.wrap(param, word());
+      case "Option":
+        return word() === 0 ? null : this.read(param, ptr);
+      case "CErrorStr": {
+        const [errorClass, code, messageIndex] = [word(2 * WORD), word(3 * WORD), word(4 * WORD)];
+        return new VodozemacError(
+          this.takeString(word(), word(WORD)),
+          ERROR_CLASSES[errorClass] ?? "Vodozemac",
+          code,
+          messageIndex === NO_MESSAGE_INDEX ? null : messageIndex,
+        );
+      }
+      case "CSecretSlice":
+        return this.takeSecret(word(), word(WORD));
+      case "CSlice": {
+        const [data, len] = [word(), word(WORD)];
+        if (param.name === "u8") return this.takeBytes(data, len);
+        const size = sizeOf(param, this.layout);
+        const values = Array.from({ length: len }, (_, i) => this.read(param, data + i * size));
+        this.dealloc(data, len * size, WORD);
+        return values;
+      }
+      case "CResult": {
+        const [ok, err] = ty.params;
+        if (word() === 0) {
+          const value = this.read(ok, ptr + WORD);
+          return throws ? value : { ok: value };
+        }
+        const error = this.read(err, ptr + WORD);
+        if (throws) throw error;
+        return { error };
+      }
+      case "ZST":
+        return undefined;
+      default:
+        if (ty.name in this.layout.enums) {
+          const variant = word();
+          return { variant, ...this.readFields(this.layout.enums[ty.name].variants[variant], ptr) };
+        }
+        return this.readFields(this.layout.structs[ty.name].fields, ptr);
+    }
+  }
+
+  // Reads the fields of a struct or enum variant starting at `ptr`, their
+  // offsets include the tag of enums.
+  readFields(fields, ptr) {
+    const result = {};
+    for (const [name, field, offset] of fields) {
+      result[name] = this.read(parseType(field), ptr + offset);
+    }
+    return result;
+  }
+}
+
+// Base class of all handle types, `free` releases the native object early,
+// otherwise it is released once the wrapper is garbage collected.
+export class Handle {
+  static __wrap(ptr) {
+    const handle = Object.create(this.prototype);
+    handle.__ptr = ptr;
+    this.__registry.register(handle, ptr, handle);
+    return handle;
+  }
+
+  get ptr() {
+    if (!this.__ptr) {
+      throw new VodozemacError(`${this.constructor.name} has already been freed`);
+    }
+    return this.__ptr;
+  }
+
+  free() {
+    const ptr = this.ptr;
+    this.__ptr = 0;
+    this.constructor.__registry.unregister(this);
+    this.constructor.__free(ptr);
+  }
+}
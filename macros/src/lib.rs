@@ -3,9 +3,12 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use proc_macro::TokenStream;
-use quote::{quote, format_ident};
-use syn::{parse_macro_input, ItemFn, FnArg, Pat, Type, TypeReference, Error, PatType, TypeSlice, PatIdent, ReturnType, TypePath, GenericArgument, TypeArray};
+use quote::{format_ident, quote};
 use syn::spanned::Spanned;
+use syn::{
+    Error, FnArg, GenericArgument, ItemFn, Pat, PatIdent, PatType, ReturnType, Type, TypeArray,
+    TypePath, TypeReference, TypeSlice, parse_macro_input,
+};
 
 #[proc_macro_attribute]
 pub fn ffi(_attr: TokenStream, item: TokenStream) -> TokenStream {
@@ -15,8 +18,8 @@ pub fn ffi(_attr: TokenStream, item: TokenStream) -> TokenStream {
     let vis = &input_fn.vis;
     let output = &input_fn.sig.output;
     let output_ty_toks = match output {
-        ReturnType::Type(_, ty) => quote!{ #ty },
-        ReturnType::Default => quote! { () }
+        ReturnType::Type(_, ty) => quote! { #ty },
+        ReturnType::Default => quote! { () },
     };
 
     let mut new_args = Vec::new();
@@ -24,16 +27,36 @@ pub fn ffi(_attr: TokenStream, item: TokenStream) -> TokenStream {
     let mut call_args = Vec::new();
     let mut needs_unsafe = false;
 
-    let sret = if input_fn.attrs.iter().any(|attr| attr.path().is_ident("sret")) {
+    let sret = if input_fn
+        .attrs
+        .iter()
+        .any(|attr| attr.path().is_ident("sret"))
+    {
         let ReturnType::Type(_, ty) = output else {
-            return Error::new(output.span(), "#[sret] requires a non default return value").into_compile_error().into();
+            return Error::new(output.span(), "#[sret] requires a non default return value")
+                .into_compile_error()
+                .into();
         };
         Some(quote! { result: &mut ::core::mem::MaybeUninit<#ty> })
-    } else { None };
+    } else {
+        None
+    };
+
+    let throws = input_fn
+        .attrs
+        .iter()
+        .any(|attr| attr.path().is_ident("throws"));
+    if throws && sret.is_none() {
+        return Error::new(input_fn.sig.span(), "#[throws] requires #[sret]")
+            .into_compile_error()
+            .into();
+    }
 
     for argument in &input_fn.sig.inputs {
         let FnArg::Typed(PatType { attrs, ty, pat, .. }) = argument else {
-            return Error::new(argument.span(), "#[ffi] can not be used on methods").into_compile_error().into();
+            return Error::new(argument.span(), "#[ffi] can not be used on methods")
+                .into_compile_error()
+                .into();
         };
         let should_expand = attrs.iter().any(|attr| attr.path().is_ident("expand"));
 
@@ -41,20 +64,29 @@ pub fn ffi(_attr: TokenStream, item: TokenStream) -> TokenStream {
             new_args.push(quote! { #pat: #ty });
             inner_args.push(quote! { #pat: #ty });
             call_args.push(quote! { #pat });
-            continue
+            continue;
         }
 
         needs_unsafe = true;
 
-        let Type::Reference(TypeReference { elem, mutability, .. }) = &**ty else {
-            return Error::new(ty.span(), "#[expand] can only be used on slices").into_compile_error().into();
+        let Type::Reference(TypeReference {
+            elem, mutability, ..
+        }) = &**ty
+        else {
+            return Error::new(ty.span(), "#[expand] can only be used on slices")
+                .into_compile_error()
+                .into();
         };
-        let Type::Slice(TypeSlice { elem, .. } ) = &**elem else {
-            return Error::new(ty.span(), "#[expand] can only be used on slices").into_compile_error().into();
+        let Type::Slice(TypeSlice { elem, .. }) = &**elem else {
+            return Error::new(ty.span(), "#[expand] can only be used on slices")
+                .into_compile_error()
+                .into();
         };
 
         let Pat::Ident(PatIdent { ident, .. }) = &**pat else {
-            return Error::new(ty.span(), "#[expand] can only be used on named types").into_compile_error().into();
+            return Error::new(ty.span(), "#[expand] can only be used on named types")
+                .into_compile_error()
+                .into();
         };
 
         let ptr_ident = format_ident!("{}_ptr", ident);
@@ -106,7 +138,7 @@ pub fn ffi(_attr: TokenStream, item: TokenStream) -> TokenStream {
         None
     };
 
-    let inner_fn = quote!{
+    let inner_fn = quote! {
         fn #inner_name(#(#inner_args),*) #output #block
     };
 
@@ -119,7 +151,7 @@ pub fn ffi(_attr: TokenStream, item: TokenStream) -> TokenStream {
                     result.write(#inner_name(#(#call_args),*));
                 }
             }
-        },
+        }
         None => {
             quote! {
                 #[unsafe(no_mangle)]
@@ -133,79 +165,167 @@ pub fn ffi(_attr: TokenStream, item: TokenStream) -> TokenStream {
 
     let jni_name = format_ident!("{}_JNI", fn_name.to_string().to_uppercase());
 
-
     let mut pre_statements = Vec::new();
     let mut post_statements = Vec::new();
+    let mut critical_statements = Vec::new();
+    let mut release_statements = Vec::new();
     let mut jni_signature = String::new();
     let mut jni_args = Vec::new();
+    let mut jni_param_types = Vec::new();
     let mut jni_call_args = Vec::new();
     let mut jni_raw_call_args = Vec::new();
 
     for argument in &input_fn.sig.inputs {
-        let FnArg::Typed(PatType { ty, pat, .. }) = argument else {
-            return Error::new(argument.span(), "#[ffi] can not be used on methods").into_compile_error().into();
+        let FnArg::Typed(PatType { attrs, ty, pat, .. }) = argument else {
+            return Error::new(argument.span(), "#[ffi] can not be used on methods")
+                .into_compile_error()
+                .into();
         };
+        let copy = attrs.iter().any(|attr| attr.path().is_ident("copy"));
+        if copy && !is_byte_slice(ty) {
+            return Error::new(ty.span(), "#[copy] can only be used on byte slices")
+                .into_compile_error()
+                .into();
+        }
 
-        if let Type::Reference(TypeReference { elem, mutability, .. }) = &**ty {
+        if let Type::Reference(TypeReference {
+            elem, mutability, ..
+        }) = &**ty
+        {
             if let Type::Slice(TypeSlice { elem, .. }) = &**elem {
                 let Pat::Ident(PatIdent { ident, .. }) = &**pat else {
-                    return Error::new(ty.span(), "#[expand] can only be used on named types").into_compile_error().into();
+                    return Error::new(ty.span(), "#[expand] can only be used on named types")
+                        .into_compile_error()
+                        .into();
                 };
-                let Type::Path(TypePath { path, .. }) = &**elem else {
-                    return Error::new(elem.span(), "#[ffi] only supports primitive slices").into_compile_error().into();
+                let mutability_mode = if mutability.is_some() {
+                    quote! { ::jni::objects::ReleaseMode::CopyBack }
+                } else {
+                    quote! { ::jni::objects::ReleaseMode::NoCopyBack }
                 };
-                if path.segments.len() != 1 {
-                    return Error::new(path.span(), "#[ffi] only supports primitive slices").into_compile_error().into();
+                let elem_name = match &**elem {
+                    Type::Reference(_) => "NonNull".to_string(),
+                    Type::Path(TypePath { path, .. }) => {
+                        if path.segments.len() != 1 {
+                            return Error::new(
+                                path.span(),
+                                "#[ffi] only supports primitive slices",
+                            )
+                            .into_compile_error()
+                            .into();
+                        }
+                        path.segments
+                            .get(0)
+                            .expect("first elem to exist after size check")
+                            .ident
+                            .to_string()
+                    }
+                    _ => {
+                        return Error::new(elem.span(), "#[ffi] only supports primitive slices")
+                            .into_compile_error()
+                            .into();
+                    }
+                };
+
+                let size_ident = format_ident!("{}_len", ident);
+
+                // Byte slices are passed without copying, either from a direct
+                // `java.nio.ByteBuffer` or by pinning the `byte[]` right before
+                // the call. `#[copy]` copies a `byte[]` instead, for functions
+                // that run too long to hold up the garbage collector.
+                if matches!(elem_name.as_str(), "i8" | "u8") {
+                    let pinned_ident = format_ident!("{}_pinned", ident);
+                    pre_statements.push(quote! { let mut #ident = crate::JniBytes::new(env, #ident, #size_ident)?; });
+                    if copy {
+                        pre_statements.push(quote! { let #pinned_ident = #ident.copy(env, #size_ident, #mutability_mode)?; });
+                    } else {
+                        critical_statements.push(
+                            quote! { let #pinned_ident = unsafe { #ident.pin(#mutability_mode)? }; },
+                        );
+                    }
+                    release_statements.push(quote! { drop(#ident); });
+                    jni_call_args.push(quote! { #pinned_ident as _, #size_ident });
+                    jni_raw_call_args.push(quote! { #ident, #size_ident });
+                    jni_args.push(quote! { #ident: ::jni::objects::JObject, #size_ident: u32 });
+                    jni_param_types.push(quote! { ::jni::objects::JObject });
+                    jni_param_types.push(quote! { u32 });
+                    jni_signature.push_str("Ljava/lang/Object;");
+                    jni_signature.push('I');
+                    continue;
                 }
-                let segment = path.segments.get(0).expect("first elem to exist after size check");
 
-                let arg_type = match segment.ident.to_string().as_str() {
-                    "i8" | "u8" => quote! { ::jni::objects::JByteArray },
-                    "i16" | "u16" => quote! { ::jni::objects::JShortArray },
-                    "i32" | "u32" => quote! { ::jni::objects::JIntArray },
-                    "i64" | "u64" => quote! { ::jni::objects::JLongArray },
-                    "isize" | "usize" => quote! { ::jni::objects::JLongArray },
+                let (arg_type, region_fn) = match elem_name.as_str() {
+                    "i16" | "u16" => (
+                        quote! { ::jni::objects::JShortArray },
+                        quote! { crate::get_short_array_region },
+                    ),
+                    "i32" | "u32" => (
+                        quote! { ::jni::objects::JIntArray },
+                        quote! { crate::get_int_array_region },
+                    ),
+                    "i64" | "u64" => (
+                        quote! { ::jni::objects::JLongArray },
+                        quote! { crate::get_long_array_region },
+                    ),
+                    "isize" | "usize" | "NonNull" => (
+                        quote! { ::jni::objects::JLongArray },
+                        quote! { crate::get_ptr_array_region },
+                    ),
                     _ => {
-                        return Error::new(ident.span(), "#[ffi] only supports primitive slices").into_compile_error().into();
+                        return Error::new(ident.span(), "#[ffi] only supports primitive slices")
+                            .into_compile_error()
+                            .into();
                     }
                 };
 
-                let size_ident = format_ident!("{}_len", ident);
-                pre_statements.push(quote! { let #ident = crate::get_byte_array_region(env, &#ident, 0, #size_ident)?; });
-                jni_call_args.push(quote! { #ident.as_ptr(), #size_ident });
+                pre_statements
+                    .push(quote! { let #ident = #region_fn(env, &#ident, 0, #size_ident)?; });
+                jni_call_args.push(quote! { #ident.as_ptr() as _, #size_ident });
                 jni_raw_call_args.push(quote! { #ident, #size_ident });
                 jni_args.push(quote! { #ident: #arg_type, #size_ident: u32 });
+                jni_param_types.push(arg_type);
+                jni_param_types.push(quote! { u32 });
                 jni_signature.push_str("Ljava/lang/Object;");
-                jni_signature.push_str("I");
+                jni_signature.push('I');
                 continue;
             } else if let Type::Array(TypeArray { elem, len, .. }) = &**elem {
                 let Pat::Ident(PatIdent { ident, .. }) = &**pat else {
-                    return Error::new(ty.span(), "#[expand] can only be used on named types").into_compile_error().into();
+                    return Error::new(ty.span(), "#[expand] can only be used on named types")
+                        .into_compile_error()
+                        .into();
                 };
                 let Type::Path(TypePath { path, .. }) = &**elem else {
-                    return Error::new(elem.span(), "#[ffi] only supports primitive slices").into_compile_error().into();
+                    return Error::new(elem.span(), "#[ffi] only supports primitive slices")
+                        .into_compile_error()
+                        .into();
                 };
                 if path.segments.len() != 1 {
-                    return Error::new(path.span(), "#[ffi] only supports primitive slices").into_compile_error().into();
+                    return Error::new(path.span(), "#[ffi] only supports primitive slices")
+                        .into_compile_error()
+                        .into();
                 }
-                let segment = path.segments.get(0).expect("first elem to exist after size check");
+                let segment = path
+                    .segments
+                    .get(0)
+                    .expect("first elem to exist after size check");
 
                 let native_ident = format_ident!("{ident}_native");
 
                 let arg_type = match segment.ident.to_string().as_str() {
                     "i8" | "u8" => {
                         if mutability.is_some() {
-                            pre_statements.push(quote! { let mut #native_ident = [0u8; #len]; });
-                            jni_call_args.push(quote! { &mut #native_ident });
+                            // Byte arrays may hold secrets, the copies are wiped when they go out of scope.
+                            pre_statements.push(quote! { let mut #native_ident = ::zeroize::Zeroizing::new([0u8; #len]); });
+                            jni_call_args.push(quote! { &mut *#native_ident });
                             post_statements.push(quote! {
                                 env.set_byte_array_region(#ident, 0, unsafe { &*(&#native_ident[..] as *const [u8] as *const [i8]) })?;
                             });
                         } else {
                             pre_statements.push(quote! { let #native_ident = crate::get_byte_array_region_const::<#len>(env, &#ident, 0)?; });
-                            jni_call_args.push(quote! { &#native_ident });
+                            jni_call_args.push(quote! { &*#native_ident });
                         }
                         quote! { ::jni::objects::JByteArray }
-                    },
+                    }
                     "i16" | "u16" => {
                         if mutability.is_some() {
                             pre_statements.push(quote! { let mut #native_ident = [0u16; #len]; });
@@ -218,38 +338,46 @@ pub fn ffi(_attr: TokenStream, item: TokenStream) -> TokenStream {
                             jni_call_args.push(quote! { &#native_ident });
                         }
                         quote! { ::jni::objects::JShortArray }
-                    },
+                    }
                     "i32" | "u32" => {
                         if mutability.is_some() {
                             pre_statements.push(quote! { let mut #native_ident = [0u32; #len]; });
                             jni_call_args.push(quote! { &mut #native_ident });
                             post_statements.push(quote! {
-                                env.set_short_array_region(#ident, 0, unsafe { &*(&#native_ident[..] as *const [u32] as *const [i32]) })?;
+                                env.set_int_array_region(#ident, 0, unsafe { &*(&#native_ident[..] as *const [u32] as *const [i32]) })?;
                             });
                         } else {
                             pre_statements.push(quote! { let #native_ident = crate::get_int_array_region_const::<#len>(env, &#ident, 0)?; });
                             jni_call_args.push(quote! { &#native_ident });
                         }
                         quote! { ::jni::objects::JIntArray }
-                    },
+                    }
                     _ => {
-                        return Error::new(ident.span(), "#[ffi] only supports primitive slices").into_compile_error().into();
+                        return Error::new(ident.span(), "#[ffi] only supports primitive slices")
+                            .into_compile_error()
+                            .into();
                     }
                 };
                 jni_raw_call_args.push(quote! { #ident });
                 jni_args.push(quote! { #ident: #arg_type });
+                jni_param_types.push(arg_type);
                 jni_signature.push_str("Ljava/lang/Object;");
                 continue;
             } else {
-                jni_signature.push_str("J");
+                jni_signature.push('J');
             }
         } else if let Type::Ptr(_) = &**ty {
-            jni_signature.push_str("J");
+            jni_signature.push('J');
         } else if let Type::Path(TypePath { path, .. }) = &**ty {
             if path.segments.len() != 1 {
-                return Error::new(path.span(), "#[ffi] does not support fully specified paths").into_compile_error().into();
+                return Error::new(path.span(), "#[ffi] does not support fully specified paths")
+                    .into_compile_error()
+                    .into();
             }
-            let segment = path.segments.get(0).expect("first elem to exist after size check");
+            let segment = path
+                .segments
+                .get(0)
+                .expect("first elem to exist after size check");
 
             let sig = match segment.ident.to_string().as_str() {
                 "i8" | "u8" => "B",
@@ -260,67 +388,92 @@ pub fn ffi(_attr: TokenStream, item: TokenStream) -> TokenStream {
                 "NonNull" => "J",
                 "Option" => {
                     let syn::PathArguments::AngleBracketed(ref args) = segment.arguments else {
-                        return Error::new(segment.ident.span(), "#[ffi] does not support this type").into_compile_error().into();
+                        return Error::new(
+                            segment.ident.span(),
+                            "#[ffi] does not support this type",
+                        )
+                        .into_compile_error()
+                        .into();
                     };
                     let GenericArgument::Type(ty) = args.args.iter().next().unwrap() else {
-                        return Error::new(args.span(), "#[ffi] does not support this type").into_compile_error().into();
+                        return Error::new(args.span(), "#[ffi] does not support this type")
+                            .into_compile_error()
+                            .into();
                     };
 
                     match ty {
                         Type::Path(TypePath { path, .. }) => {
                             if path.segments.len() != 1 {
-                                return Error::new(path.span(), "#[ffi] does not support this type").into_compile_error().into();
+                                return Error::new(
+                                    path.span(),
+                                    "#[ffi] does not support this type",
+                                )
+                                .into_compile_error()
+                                .into();
                             }
 
                             let segment = path.segments.iter().next().unwrap();
                             if segment.ident.to_string().as_str() == "NonNull" {
                                 "J"
                             } else {
-                                return Error::new(segment.ident.span(), "#[ffi] does not support this type").into_compile_error().into();
+                                return Error::new(
+                                    segment.ident.span(),
+                                    "#[ffi] does not support this type",
+                                )
+                                .into_compile_error()
+                                .into();
                             }
                         }
-                        Type::Reference(_) => {
-                            "J"
-                        }
+                        Type::Reference(_) => "J",
                         _ => {
-                            return Error::new(ty.span(), "#[ffi] does not support this type").into_compile_error().into();
+                            return Error::new(ty.span(), "#[ffi] does not support this type")
+                                .into_compile_error()
+                                .into();
                         }
                     }
-
-                },
+                }
                 _ => {
-                    return Error::new(segment.ident.span(), "#[ffi] does not support this type").into_compile_error().into();
+                    return Error::new(segment.ident.span(), "#[ffi] does not support this type")
+                        .into_compile_error()
+                        .into();
                 }
             };
 
             jni_signature.push_str(sig);
         } else {
-            return Error::new(ty.span(), "#[ffi] does not support this type").into_compile_error().into();
+            return Error::new(ty.span(), "#[ffi] does not support this type")
+                .into_compile_error()
+                .into();
         }
 
         jni_raw_call_args.push(quote! { #pat });
         jni_call_args.push(quote! { #pat });
         jni_args.push(quote! { #pat: #ty });
+        jni_param_types.push(quote! { #ty });
     }
 
-
     let jni_output = match &sret {
-        Some(_) => quote!{ -> ::jni::errors::Result<()> },
-        None => quote! { -> ::jni::errors::Result<#output_ty_toks> }
+        Some(_) => quote! { -> ::jni::errors::Result<()> },
+        None => quote! { -> ::jni::errors::Result<#output_ty_toks> },
     };
 
     let wrapped_jni_output = match &sret {
         Some(_) => Some(quote! { -> () }),
-        None => Some(quote !{ -> #output_ty_toks }),
+        None => Some(quote! { -> #output_ty_toks }),
     };
 
-    let sret_arg = match sret {
-        Some(_) => Some(quote!{ result: ::jni::objects::JLongArray, }),
-        None => None,
-    };
-    let sret_arg_call = match sret {
-        Some(_) => Some(quote! {result,}),
-        None => None,
+    let sret_arg = sret
+        .as_ref()
+        .map(|_| quote! { result: ::jni::objects::JLongArray, });
+    let sret_arg_call = sret.as_ref().map(|_| quote! {result,});
+    let jni_param_types = sret
+        .as_ref()
+        .map(|_| quote! { ::jni::objects::JLongArray })
+        .into_iter()
+        .chain(jni_param_types);
+    let jni_ret_type = match &sret {
+        Some(_) => quote! { () },
+        None => output_ty_toks.clone(),
     };
 
     let jni_ret_signature = match sret {
@@ -328,36 +481,66 @@ pub fn ffi(_attr: TokenStream, item: TokenStream) -> TokenStream {
         None => match output {
             ReturnType::Default => "V",
             ReturnType::Type(_, ty) => match &**ty {
-                Type::Path(TypePath { path, ..}) => match path.segments.iter().next().unwrap().ident.to_string().as_str() {
+                Type::Path(TypePath { path, .. }) => match path
+                    .segments
+                    .iter()
+                    .next()
+                    .unwrap()
+                    .ident
+                    .to_string()
+                    .as_str()
+                {
                     "Option" | "NonNull" => "J",
                     "u32" | "i32" => "I",
                     "u64" | "i64" => "J",
                     "usize" | "isize" => "J",
                     "u16" | "i16" => "S",
                     "u8" | "i8" => "B",
-                    _ => return Error::new(path.span(), "unsupported").into_compile_error().into()
-                }
+                    _ => {
+                        return Error::new(path.span(), "unsupported")
+                            .into_compile_error()
+                            .into();
+                    }
+                },
                 Type::Ptr(_) => "J",
-                _ => return Error::new(ty.span(), "unsupported").into_compile_error().into()
-            }
-        }
+                _ => {
+                    return Error::new(ty.span(), "unsupported")
+                        .into_compile_error()
+                        .into();
+                }
+            },
+        },
     };
 
     let jni_first_arg = match sret {
         Some(_) => "Ljava/lang/Object;",
-        None => ""
+        None => "",
     };
 
+    // Errors of functions marked with #[throws] are thrown as typed exceptions
+    // instead of being returned to the caller.
+    let throw_error = throws.then(|| {
+        quote! {
+            if let crate::CResult::Err(error) = c_result {
+                return crate::exceptions::throw(env, class, error);
+            }
+        }
+    });
+
     let sret_fn_call = match sret {
         Some(_) => quote! {
             #(#pre_statements)*
 
             use crate::{AsUsize, CollectIntoArray};
-            let lvalues = {
+            let c_result = {
                 let mut result = ::core::mem::MaybeUninit::uninit();
+                #(#critical_statements)*
                 unsafe { #fn_name(&mut result, #(#jni_call_args),*); }
+                #(#release_statements)*
                 unsafe { result.assume_init() }
-            }
+            };
+            #throw_error
+            let lvalues = c_result
                 .as_usize()
                 .map(::jni::sys::jlong::try_from)
                 .map(::core::result::Result::unwrap)
@@ -369,10 +552,12 @@ pub fn ffi(_attr: TokenStream, item: TokenStream) -> TokenStream {
         },
         None => quote! {
             #(#pre_statements)*
+            #(#critical_statements)*
             let full_result = unsafe { #fn_name(#(#jni_call_args),*) };
+            #(#release_statements)*
             #(#post_statements)*
             Ok(full_result)
-        }
+        },
     };
 
     let jni_signature = format!("({jni_first_arg}{jni_signature}){jni_ret_signature}");
@@ -416,18 +601,97 @@ pub fn ffi(_attr: TokenStream, item: TokenStream) -> TokenStream {
                 name: #raw_fn_name,
                 sig: #jni_signature,
                 fn_ptr: inner_wrapped as _,
+                params: &[#(<#jni_param_types as crate::JniType>::SIGNATURE),*],
+                ret: <#jni_ret_type as crate::JniType>::SIGNATURE,
             }
         };
     };
 
+    // Every function is described in the `vodozemac_ffi` custom section of the
+    // wasm module, js/generate.mjs turns these descriptions into wrapper
+    // classes.
+    let descriptor_args = input_fn
+        .sig
+        .inputs
+        .iter()
+        .map(|argument| {
+            let FnArg::Typed(PatType { attrs, ty, pat, .. }) = argument else {
+                unreachable!()
+            };
+            let expand = attrs.iter().any(|attr| attr.path().is_ident("expand"));
+            format!(
+                r#"{{"name":"{}","ty":"{}","expand":{expand}}}"#,
+                quote!(#pat),
+                type_string(&quote!(#ty).to_string()),
+            )
+        })
+        .collect::<Vec<_>>();
+    let descriptor = format!(
+        "{{\"name\":\"{raw_fn_name}\",\"args\":[{}],\"ret\":\"{}\",\"sret\":{},\"throws\":{throws}}}\n",
+        descriptor_args.join(","),
+        type_string(&output_ty_toks.to_string()),
+        sret.is_some(),
+    );
+    let descriptor_bytes = syn::LitByteStr::new(descriptor.as_bytes(), fn_name.span());
+    let descriptor_len = descriptor.len();
+
+    let wasm_descriptor = quote! {
+        #[cfg(target_arch = "wasm32")]
+        const _: () = {
+            #[used]
+            #[unsafe(link_section = "vodozemac_ffi")]
+            static DESCRIPTOR: [u8; #descriptor_len] = *#descriptor_bytes;
+        };
+    };
+
+    // The JNI bindings copy `#[sret]` results into a `long[]` word by word.
+    let layout_assertion = sret.as_ref().map(|_| {
+        quote! {
+            const _: () = crate::abi::assert_words::<#output_ty_toks>();
+        }
+    });
+
     let full_expansion = quote! {
         #expanded
         #jni
+        #wasm_descriptor
+        #layout_assertion
     };
 
     full_expansion.into()
 }
 
+fn is_byte_slice(ty: &Type) -> bool {
+    let Type::Reference(TypeReference { elem, .. }) = ty else {
+        return false;
+    };
+    let Type::Slice(TypeSlice { elem, .. }) = &**elem else {
+        return false;
+    };
+    matches!(&**elem, Type::Path(TypePath { path, .. }) if path.is_ident("u8") || path.is_ident("i8"))
+}
+
+/// Removes lifetimes and whitespace that is not needed to separate words from
+/// a stringified type, e.g. `OlmMessage < 'static >` becomes `OlmMessage`.
+fn type_string(ty: &str) -> String {
+    let mut result = String::with_capacity(ty.len());
+    let mut tokens = ty.split_whitespace().peekable();
+
+    while let Some(token) = tokens.next() {
+        if !token.starts_with('\'') {
+            let is_word = |c: char| c.is_alphanumeric() || c == '_';
+            if result.ends_with(is_word) && token.starts_with(is_word) {
+                result.push(' ');
+            }
+            result.push_str(token);
+        } else if tokens.peek() == Some(&",") {
+            tokens.next();
+        }
+    }
+
+    result.replace("<>", "")
+}
+
 /*
 
 
@@ -484,4 +748,4 @@ fn vodozemac_megolm_message_from_bytes_jni(class: &str) -> NativeMethod {
         fn_ptr: inner_wrapped as _,
     }
 }
- */
\ No newline at end of file
+ */
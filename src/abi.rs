@@ -0,0 +1,263 @@
+// SPDX-FileCopyrightText: 2025 Felix Hilgers <contact@fhilgers.com>
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Layout of the `#[repr(C)]` types returned through `#[sret]`.
+//!
+//! The JNI bindings copy these values into a `long[]` word by word, so every
+//! one of them has to consist only of pointer-sized words. `#[ffi]` checks
+//! this at compile time for each `#[sret]` return type, the description
+//! returned by [`crate::vodozemac_abi_layout`] lets foreign generators read
+//! the fields without hardcoding offsets.
+
+use crate::AsUsize;
+use crate::megolm::inbound_group_session::DecryptedMessage;
+use crate::megolm::tracked_inbound_group_session::AuthenticatedMessage;
+use crate::olm::account::{
+    DehydratedDeviceResult, IdentityKeys, InboundCreationResult, OneTimeKeyGenerationResult,
+};
+use crate::olm::session::OlmMessage;
+use crate::pickle::RekeyedPickles;
+use crate::slices::{CErrorStr, CSecretSlice, CSlice};
+use std::fmt::Write;
+
+pub const WORD: usize = size_of::<usize>();
+
+/// Fails the build if `T` can not be copied into a `long[]` of
+/// `size_of::<T>() / WORD` elements by its [`AsUsize`] implementation.
+pub const fn assert_words<T: AsUsize>() {
+    assert!(
+        size_of::<T>().is_multiple_of(WORD),
+        "#[sret] types must consist of pointer-sized words"
+    );
+    assert!(
+        size_of::<T>() == 0 || align_of::<T>() == align_of::<usize>(),
+        "#[sret] types must be pointer aligned"
+    );
+    assert!(
+        T::WORDS == size_of::<T>() / WORD,
+        "AsUsize has to flatten every word of the type"
+    );
+}
+
+pub struct FieldLayout {
+    pub name: &'static str,
+    pub ty: &'static str,
+    pub offset: usize,
+    pub size: Option<usize>,
+}
+
+pub struct VariantLayout {
+    pub name: &'static str,
+    pub tag: usize,
+    pub fields: &'static [FieldLayout],
+}
+
+pub enum Fields {
+    Struct(&'static [FieldLayout]),
+    /// A `#[repr(C, usize)]` enum, the tag is the first word.
+    Enum(&'static [VariantLayout]),
+}
+
+/// The layout of a type, `size` is `None` for generic types whose size
+/// depends on their parameters.
+pub struct TypeLayout {
+    pub name: &'static str,
+    pub size: Option<usize>,
+    pub align: usize,
+    pub fields: Fields,
+}
+
+pub trait Layout {
+    const LAYOUT: TypeLayout;
+}
+
+/// Computes the fields of an enum variant, they follow the tag without padding
+/// because every field is made of whole words.
+pub const fn variant_fields<const N: usize>(
+    fields: [(&'static str, &'static str, usize); N],
+) -> [FieldLayout; N] {
+    let mut layouts = [const {
+        FieldLayout {
+            name: "",
+            ty: "",
+            offset: 0,
+            size: None,
+        }
+    }; N];
+
+    let mut offset = WORD;
+    let mut i = 0;
+    while i < N {
+        let (name, ty, size) = fields[i];
+        layouts[i] = FieldLayout {
+            name,
+            ty,
+            offset,
+            size: Some(size),
+        };
+        offset += size;
+        i += 1;
+    }
+
+    layouts
+}
+
+const C_SLICE: TypeLayout = TypeLayout {
+    name: "CSlice<T>",
+    size: Some(size_of::<CSlice<u8>>()),
+    align: align_of::<CSlice<u8>>(),
+    fields: Fields::Struct(&[
+        FieldLayout {
+            name: "ptr",
+            ty: "NonNull<T>",
+            offset: 0,
+            size: Some(WORD),
+        },
+        FieldLayout {
+            name: "len",
+            ty: "usize",
+            offset: WORD,
+            size: Some(WORD),
+        },
+    ]),
+};
+
+/// Laid out like `CSlice<u8>`, but freed with
+/// [`crate::vodozemac_secret_slice_free`].
+const C_SECRET_SLICE: TypeLayout = TypeLayout {
+    name: "CSecretSlice",
+    size: Some(size_of::<CSecretSlice>()),
+    align: align_of::<CSecretSlice>(),
+    fields: Fields::Struct(&[
+        FieldLayout {
+            name: "ptr",
+            ty: "NonNull<u8>",
+            offset: 0,
+            size: Some(WORD),
+        },
+        FieldLayout {
+            name: "len",
+            ty: "usize",
+            offset: WORD,
+            size: Some(WORD),
+        },
+    ]),
+};
+
+const C_RESULT: TypeLayout = TypeLayout {
+    name: "CResult<T, E>",
+    size: None,
+    align: WORD,
+    fields: Fields::Enum(&[
+        VariantLayout {
+            name: "Ok",
+            tag: 0,
+            fields: &[FieldLayout {
+                name: "0",
+                ty: "T",
+                offset: WORD,
+                size: None,
+            }],
+        },
+        VariantLayout {
+            name: "Err",
+            tag: 1,
+            fields: &[FieldLayout {
+                name: "0",
+                ty: "E",
+                offset: WORD,
+                size: None,
+            }],
+        },
+    ]),
+};
+
+const LAYOUTS: &[TypeLayout] = &[
+    C_SLICE,
+    C_SECRET_SLICE,
+    C_RESULT,
+    CErrorStr::LAYOUT,
+    IdentityKeys::LAYOUT,
+    InboundCreationResult::LAYOUT,
+    OneTimeKeyGenerationResult::LAYOUT,
+    DehydratedDeviceResult::LAYOUT,
+    OlmMessage::LAYOUT,
+    DecryptedMessage::LAYOUT,
+    AuthenticatedMessage::LAYOUT,
+    RekeyedPickles::LAYOUT,
+];
+
+fn write_fields(json: &mut String, fields: &[FieldLayout]) {
+    json.push('[');
+    for (i, field) in fields.iter().enumerate() {
+        if i > 0 {
+            json.push(',');
+        }
+        let size = field
+            .size
+            .map_or("null".to_owned(), |size| size.to_string());
+        let _ = write!(
+            json,
+            r#"{{"name":"{}","type":"{}","offset":{},"size":{size}}}"#,
+            field.name, field.ty, field.offset
+        );
+    }
+    json.push(']');
+}
+
+/// Describes every `#[repr(C)]` type returned through `#[sret]` as JSON:
+///
+/// ```json
+/// {"word":8,"types":[{"name":"IdentityKeys","size":16,"align":8,"fields":[
+///   {"name":"ed25519","type":"NonNull<Ed25519PublicKey>","offset":0,"size":8},
+///   ...]},
+///  {"name":"OlmMessage","size":24,"align":8,"variants":[
+///   {"name":"Normal","tag":0,"fields":[...]}, ...]}]}
+/// ```
+///
+/// Sizes are `null` where they depend on type parameters.
+pub fn layout_json() -> String {
+    let mut json = format!(r#"{{"word":{WORD},"types":["#);
+
+    for (i, layout) in LAYOUTS.iter().enumerate() {
+        if i > 0 {
+            json.push(',');
+        }
+        let size = layout
+            .size
+            .map_or("null".to_owned(), |size| size.to_string());
+        let _ = write!(
+            json,
+            r#"{{"name":"{}","size":{size},"align":{},"#,
+            layout.name, layout.align
+        );
+
+        match layout.fields {
+            Fields::Struct(fields) => {
+                json.push_str(r#""fields":"#);
+                write_fields(&mut json, fields);
+            }
+            Fields::Enum(variants) => {
+                json.push_str(r#""variants":["#);
+                for (i, variant) in variants.iter().enumerate() {
+                    if i > 0 {
+                        json.push(',');
+                    }
+                    let _ = write!(
+                        json,
+                        r#"{{"name":"{}","tag":{},"fields":"#,
+                        variant.name, variant.tag
+                    );
+                    write_fields(&mut json, variant.fields);
+                    json.push('}');
+                }
+                json.push(']');
+            }
+        }
+        json.push('}');
+    }
+
+    json.push_str("]}");
+    json
+}
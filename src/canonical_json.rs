@@ -0,0 +1,30 @@
+// SPDX-FileCopyrightText: 2025 Felix Hilgers <contact@fhilgers.com>
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use serde_json::Value;
+
+/// Serializes `value` as the canonical JSON which Matrix signs and hashes: compact and
+/// with the keys of every object sorted. The keys are sorted here because
+/// `serde_json` keeps them in insertion order once any crate in the build
+/// enables its `preserve_order` feature.
+pub(crate) fn canonical_json(value: &Value) -> String {
+    match value {
+        Value::Object(object) => {
+            let mut entries = object.iter().collect::<Vec<_>>();
+            entries.sort_unstable_by_key(|&(key, _)| key);
+            let entries = entries
+                .into_iter()
+                .map(|(key, value)| {
+                    format!("{}:{}", Value::from(key.as_str()), canonical_json(value))
+                })
+                .collect::<Vec<_>>();
+            format!("{{{}}}", entries.join(","))
+        }
+        Value::Array(values) => {
+            let values = values.iter().map(canonical_json).collect::<Vec<_>>();
+            format!("[{}]", values.join(","))
+        }
+        value => value.to_string(),
+    }
+}
@@ -0,0 +1,342 @@
+// SPDX-FileCopyrightText: 2025 Felix Hilgers <contact@fhilgers.com>
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::olm::dehydrated_device::DeviceDataError;
+use crate::pickle::KdfError;
+use crate::sas::sas::DiffieHellmanError;
+use crate::sas::verification::CommitmentError;
+use crate::slices::{CErrorStr, CSlice};
+use jni::JNIEnv;
+use jni::objects::{GlobalRef, JClass, JString, JThrowable};
+use parking_lot::Mutex;
+use std::error::Error;
+use std::ptr::slice_from_raw_parts_mut;
+use vodozemac::megolm::SessionKeyDecodeError;
+use vodozemac::olm::SessionCreationError;
+use vodozemac::{
+    Base64DecodeError, DecodeError, DehydratedDeviceError, KeyError, LibolmPickleError,
+    PickleError, SignatureError, megolm, olm,
+};
+
+pub const DECRYPTION_SIGNATURE: i32 = 0;
+pub const DECRYPTION_INVALID_MAC: i32 = 1;
+pub const DECRYPTION_INVALID_MAC_LENGTH: i32 = 2;
+pub const DECRYPTION_INVALID_PADDING: i32 = 3;
+pub const DECRYPTION_UNKNOWN_MESSAGE_INDEX: i32 = 4;
+pub const DECRYPTION_INVALID_MESSAGE: i32 = 5;
+pub const DECRYPTION_INVALID_SESSION_INDEX: i32 = 6;
+pub const DECRYPTION_MISSING_MESSAGE_KEY: i32 = 7;
+pub const DECRYPTION_TOO_BIG_MESSAGE_GAP: i32 = 8;
+
+pub const PICKLE_BASE64: i32 = 0;
+pub const PICKLE_DECRYPTION: i32 = 1;
+pub const PICKLE_SERIALIZATION: i32 = 2;
+pub const PICKLE_VERSION: i32 = 3;
+pub const PICKLE_INVALID_NONCE: i32 = 4;
+pub const PICKLE_INVALID_ACCOUNT: i32 = 5;
+pub const PICKLE_INVALID_SESSION: i32 = 6;
+pub const PICKLE_INVALID_KDF_PARAMETERS: i32 = 7;
+
+pub const KEY_BASE64: i32 = 0;
+pub const KEY_INVALID_LENGTH: i32 = 1;
+pub const KEY_INVALID_MESSAGE: i32 = 2;
+pub const KEY_MISSING_ONE_TIME_KEY: i32 = 3;
+pub const KEY_MISMATCHED_IDENTITY_KEY: i32 = 4;
+pub const KEY_MISMATCHED_COMMITMENT: i32 = 5;
+
+pub const SIGNATURE_BASE64: i32 = 0;
+pub const SIGNATURE_INVALID: i32 = 1;
+
+/// The exception classes thrown by functions marked with `#[throws]`, they
+/// live in `com/github/fhilgers/vodozemac/` and share the constructor
+/// `(message: String, code: Int, messageIndex: Long)`.
+#[derive(Clone, Copy, Debug, Default)]
+pub enum ExceptionClass {
+    #[default]
+    Vodozemac,
+    Decryption,
+    Pickle,
+    Key,
+    Signature,
+}
+
+impl ExceptionClass {
+    pub const ALL: [ExceptionClass; 5] = [
+        ExceptionClass::Vodozemac,
+        ExceptionClass::Decryption,
+        ExceptionClass::Pickle,
+        ExceptionClass::Key,
+        ExceptionClass::Signature,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ExceptionClass::Vodozemac => "VodozemacException",
+            ExceptionClass::Decryption => "DecryptionException",
+            ExceptionClass::Pickle => "PickleException",
+            ExceptionClass::Key => "KeyException",
+            ExceptionClass::Signature => "SignatureException",
+        }
+    }
+}
+
+/// The structured part of an error, `message_index` is `-1` if the error does
+/// not refer to a message.
+#[derive(Clone, Copy, Debug)]
+pub struct ErrorDetails {
+    pub class: ExceptionClass,
+    pub code: i32,
+    pub message_index: i64,
+}
+
+impl Default for ErrorDetails {
+    fn default() -> Self {
+        Self::new(ExceptionClass::Vodozemac, 0)
+    }
+}
+
+impl ErrorDetails {
+    pub fn new(class: ExceptionClass, code: i32) -> Self {
+        Self {
+            class,
+            code,
+            message_index: -1,
+        }
+    }
+
+    pub fn with_message_index(mut self, message_index: impl Into<i64>) -> Self {
+        self.message_index = message_index.into();
+        self
+    }
+
+    pub fn of(error: &(dyn Error + 'static)) -> Self {
+        use ExceptionClass::*;
+
+        if let Some(error) = error.downcast_ref::<megolm::DecryptionError>() {
+            match *error {
+                megolm::DecryptionError::Signature(_) => {
+                    Self::new(Decryption, DECRYPTION_SIGNATURE)
+                }
+                megolm::DecryptionError::InvalidMAC(_) => {
+                    Self::new(Decryption, DECRYPTION_INVALID_MAC)
+                }
+                megolm::DecryptionError::InvalidMACLength(..) => {
+                    Self::new(Decryption, DECRYPTION_INVALID_MAC_LENGTH)
+                }
+                megolm::DecryptionError::InvalidPadding(_) => {
+                    Self::new(Decryption, DECRYPTION_INVALID_PADDING)
+                }
+                megolm::DecryptionError::UnknownMessageIndex(_, index) => {
+                    Self::new(Decryption, DECRYPTION_UNKNOWN_MESSAGE_INDEX)
+                        .with_message_index(index)
+                }
+            }
+        } else if let Some(error) = error.downcast_ref::<olm::DecryptionError>() {
+            match *error {
+                olm::DecryptionError::InvalidMAC(_) => {
+                    Self::new(Decryption, DECRYPTION_INVALID_MAC)
+                }
+                olm::DecryptionError::InvalidMACLength(..) => {
+                    Self::new(Decryption, DECRYPTION_INVALID_MAC_LENGTH)
+                }
+                olm::DecryptionError::InvalidPadding(_) => {
+                    Self::new(Decryption, DECRYPTION_INVALID_PADDING)
+                }
+                olm::DecryptionError::MissingMessageKey(index) => {
+                    Self::new(Decryption, DECRYPTION_MISSING_MESSAGE_KEY)
+                        .with_message_index(index as i64)
+                }
+                olm::DecryptionError::TooBigMessageGap(index, _) => {
+                    Self::new(Decryption, DECRYPTION_TOO_BIG_MESSAGE_GAP)
+                        .with_message_index(index as i64)
+                }
+            }
+        } else if let Some(error) = error.downcast_ref::<SessionCreationError>() {
+            match error {
+                SessionCreationError::MissingOneTimeKey(_) => {
+                    Self::new(Key, KEY_MISSING_ONE_TIME_KEY)
+                }
+                SessionCreationError::MismatchedIdentityKey(..) => {
+                    Self::new(Key, KEY_MISMATCHED_IDENTITY_KEY)
+                }
+                SessionCreationError::Decryption(error) => Self::of(error),
+            }
+        } else if let Some(error) = error.downcast_ref::<PickleError>() {
+            match error {
+                PickleError::Base64(_) => Self::new(Pickle, PICKLE_BASE64),
+                PickleError::Decryption(_) => Self::new(Pickle, PICKLE_DECRYPTION),
+                PickleError::Serialization(_) => Self::new(Pickle, PICKLE_SERIALIZATION),
+            }
+        } else if let Some(error) = error.downcast_ref::<LibolmPickleError>() {
+            match error {
+                LibolmPickleError::MissingVersion | LibolmPickleError::Version(..) => {
+                    Self::new(Pickle, PICKLE_VERSION)
+                }
+                LibolmPickleError::Base64(_) => Self::new(Pickle, PICKLE_BASE64),
+                LibolmPickleError::Decryption(_) => Self::new(Pickle, PICKLE_DECRYPTION),
+                LibolmPickleError::PublicKey(error) => Self::of(error),
+                LibolmPickleError::InvalidSession => Self::new(Pickle, PICKLE_INVALID_SESSION),
+                LibolmPickleError::Decode(_) | LibolmPickleError::Encode(_) => {
+                    Self::new(Pickle, PICKLE_SERIALIZATION)
+                }
+            }
+        } else if let Some(error) = error.downcast_ref::<DehydratedDeviceError>() {
+            match error {
+                DehydratedDeviceError::MissingVersion | DehydratedDeviceError::Version(..) => {
+                    Self::new(Pickle, PICKLE_VERSION)
+                }
+                DehydratedDeviceError::InvalidNonce => Self::new(Pickle, PICKLE_INVALID_NONCE),
+                DehydratedDeviceError::Base64(_) => Self::new(Pickle, PICKLE_BASE64),
+                DehydratedDeviceError::Decryption(_) => Self::new(Pickle, PICKLE_DECRYPTION),
+                DehydratedDeviceError::LibolmPickle(error) => Self::of(error),
+                DehydratedDeviceError::InvalidAccount => Self::new(Pickle, PICKLE_INVALID_ACCOUNT),
+            }
+        } else if let Some(DiffieHellmanError::InvalidKey(error)) = error.downcast_ref() {
+            Self::of(error)
+        } else if let Some(error) = error.downcast_ref::<KeyError>() {
+            match error {
+                KeyError::Signature(error) => Self::of(error),
+                KeyError::InvalidKeyLength { .. } => Self::new(Key, KEY_INVALID_LENGTH),
+                _ => Self::new(Key, KEY_BASE64),
+            }
+        } else if let Some(error) = error.downcast_ref::<SessionKeyDecodeError>() {
+            match error {
+                SessionKeyDecodeError::Signature(error) => Self::of(error),
+                SessionKeyDecodeError::PublicKey(error) => Self::of(error),
+                SessionKeyDecodeError::Base64(_) => Self::new(Key, KEY_BASE64),
+                _ => Self::new(Key, KEY_INVALID_MESSAGE),
+            }
+        } else if let Some(error) = error.downcast_ref::<DecodeError>() {
+            match error {
+                DecodeError::InvalidKey(error) => Self::of(error),
+                DecodeError::Signature(error) => Self::of(error),
+                _ => Self::new(Key, KEY_INVALID_MESSAGE),
+            }
+        } else if let Some(error) = error.downcast_ref::<DeviceDataError>() {
+            match error {
+                DeviceDataError::Json(_) | DeviceDataError::MissingNonce => {
+                    Self::new(Pickle, PICKLE_SERIALIZATION)
+                }
+                DeviceDataError::UnsupportedAlgorithm(_) => Self::new(Pickle, PICKLE_VERSION),
+            }
+        } else if let Some(error) = error.downcast_ref::<CommitmentError>() {
+            match error {
+                CommitmentError::Json(_) => Self::new(Key, KEY_INVALID_MESSAGE),
+                CommitmentError::Mismatch => Self::new(Key, KEY_MISMATCHED_COMMITMENT),
+            }
+        } else if error.is::<serde_json::Error>() {
+            Self::new(Pickle, PICKLE_SERIALIZATION)
+        } else if error.is::<KdfError>() {
+            Self::new(Pickle, PICKLE_INVALID_KDF_PARAMETERS)
+        } else if error.is::<Base64DecodeError>() {
+            Self::new(Key, KEY_BASE64)
+        } else if let Some(error) = error.downcast_ref::<SignatureError>() {
+            match error {
+                SignatureError::Base64(_) => Self::new(Signature, SIGNATURE_BASE64),
+                SignatureError::Signature(_) => Self::new(Signature, SIGNATURE_INVALID),
+            }
+        } else {
+            Self::default()
+        }
+    }
+}
+
+/// The exception classes of every prefix passed to [`register_jni`].
+static EXCEPTIONS: Mutex<Vec<(String, Vec<Option<GlobalRef>>)>> = Mutex::new(Vec::new());
+
+/// Looks up the exception classes under `prefix`, classes which can not be
+/// found are reported and replaced by `java/lang/RuntimeException`.
+///
+/// Registering the same prefix again replaces its classes, the classes of
+/// other prefixes are kept.
+pub fn register_jni(env: &mut JNIEnv, prefix: &str) -> Vec<String> {
+    let mut errors = Vec::new();
+
+    let classes = ExceptionClass::ALL
+        .iter()
+        .map(|class| {
+            let class_name = format!("{prefix}/{}", class.name());
+            let class = env
+                .find_class(&class_name)
+                .and_then(|class| env.new_global_ref(class));
+
+            class
+                .map_err(|error| {
+                    let _ = env.exception_clear();
+                    errors.push(format!("{class_name}: {error}"));
+                })
+                .ok()
+        })
+        .collect();
+
+    let mut exceptions = EXCEPTIONS.lock();
+    match exceptions
+        .iter_mut()
+        .find(|(registered, _)| registered == prefix)
+    {
+        Some((_, registered)) => *registered = classes,
+        None => exceptions.push((prefix.to_owned(), classes)),
+    }
+
+    errors
+}
+
+/// Finds the exception class for `class` of the bindings `caller` belongs to,
+/// the longest registered prefix of the caller's name wins.
+fn exception_class(
+    env: &mut JNIEnv,
+    caller: &JClass,
+    class: ExceptionClass,
+) -> jni::errors::Result<Option<GlobalRef>> {
+    let name = env
+        .call_method(caller, "getName", "()Ljava/lang/String;", &[])?
+        .l()?;
+    let name = String::from(env.get_string(&JString::from(name))?).replace('.', "/");
+
+    let exceptions = EXCEPTIONS.lock();
+    let classes = exceptions
+        .iter()
+        .filter(|(prefix, _)| {
+            name.strip_prefix(prefix.as_str())
+                .is_some_and(|rest| rest.starts_with('/'))
+        })
+        .max_by_key(|(prefix, _)| prefix.len())
+        .map(|(_, classes)| classes);
+
+    Ok(classes.and_then(|classes| classes[class as usize].clone()))
+}
+
+/// Frees `error` and throws it as the exception described by its details,
+/// using the exception classes registered for the bindings class `caller`.
+///
+/// Always returns [`jni::errors::Error::JavaException`] so that the caller
+/// can bail out with `?`.
+pub fn throw(env: &mut JNIEnv, caller: &JClass, error: CErrorStr) -> jni::errors::Result<()> {
+    let details = error.details();
+    let CSlice { ptr, len } = error.message;
+    let message = unsafe { Box::from_raw(slice_from_raw_parts_mut(ptr.as_ptr(), len)) };
+
+    let Some(class) = exception_class(env, caller, details.class)? else {
+        env.throw_new(
+            "java/lang/RuntimeException",
+            String::from_utf8_lossy(&message),
+        )?;
+        return Err(jni::errors::Error::JavaException);
+    };
+
+    let message = env.new_string(String::from_utf8_lossy(&message))?;
+    let exception = env.new_object(
+        &class,
+        "(Ljava/lang/String;IJ)V",
+        &[
+            (&message).into(),
+            details.code.into(),
+            details.message_index.into(),
+        ],
+    )?;
+
+    env.throw(JThrowable::from(exception))?;
+
+    Err(jni::errors::Error::JavaException)
+}
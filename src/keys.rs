@@ -2,31 +2,27 @@
 //
 // SPDX-License-Identifier: Apache-2.0
 
-use crate::slices::CErrorStr;
-use crate::{CResult, ZST, boxed, free};
-use jni::JNIEnv;
-use jni::objects::JClass;
+use crate::sas::verification;
+use crate::slices::{CErrorStr, CSlice};
+use crate::{CResult, NativeMethod, ZST, boxed, free};
 use macros::ffi;
 use std::ptr::NonNull;
 use vodozemac::{Curve25519PublicKey, Ed25519PublicKey, Ed25519Signature};
 
-pub fn register_jni(env: &mut JNIEnv, class: &JClass) -> jni::errors::Result<()> {
-    env.register_native_methods(
-        class,
-        &[
-            VODOZEMAC_ED25519_PUBLIC_KEY_FROM_BYTES_JNI.into(),
-            VODOZEMAC_ED25519_PUBLIC_KEY_TO_BYTES_JNI.into(),
-            VODOZEMAC_ED25519_PUBLIC_KEY_VERIFY_JNI.into(),
-            VODOZEMAC_ED25519_PUBLIC_KEY_FREE_JNI.into(),
-            VODOZEMAC_CURVE25519_PUBLIC_KEY_FROM_BYTES_JNI.into(),
-            VODOZEMAC_CURVE25519_PUBLIC_KEY_TO_BYTES_JNI.into(),
-            VODOZEMAC_CURVE25519_PUBLIC_KEY_FREE_JNI.into(),
-            VODOZEMAC_ED25519_SIGNATURE_FROM_BYTES_JNI.into(),
-            VODOZEMAC_ED25519_SIGNATURE_TO_BYTES_JNI.into(),
-            VODOZEMAC_ED25519_SIGNATURE_FREE_JNI.into(),
-        ],
-    )
-}
+pub const JNI_METHODS: &[NativeMethod] = &[
+    VODOZEMAC_ED25519_PUBLIC_KEY_FROM_BYTES_JNI,
+    VODOZEMAC_ED25519_PUBLIC_KEY_TO_BYTES_JNI,
+    VODOZEMAC_ED25519_PUBLIC_KEY_VERIFY_JNI,
+    VODOZEMAC_ED25519_PUBLIC_KEY_FREE_JNI,
+    VODOZEMAC_CURVE25519_PUBLIC_KEY_FROM_BYTES_JNI,
+    VODOZEMAC_CURVE25519_PUBLIC_KEY_TO_BYTES_JNI,
+    VODOZEMAC_CURVE25519_PUBLIC_KEY_CALCULATE_COMMITMENT_JNI,
+    VODOZEMAC_CURVE25519_PUBLIC_KEY_VERIFY_COMMITMENT_JNI,
+    VODOZEMAC_CURVE25519_PUBLIC_KEY_FREE_JNI,
+    VODOZEMAC_ED25519_SIGNATURE_FROM_BYTES_JNI,
+    VODOZEMAC_ED25519_SIGNATURE_TO_BYTES_JNI,
+    VODOZEMAC_ED25519_SIGNATURE_FREE_JNI,
+];
 
 #[ffi]
 pub fn vodozemac_ed25519_public_key_from_bytes(
@@ -42,6 +38,7 @@ pub fn vodozemac_ed25519_public_key_to_bytes(key: &Ed25519PublicKey, bytes: &mut
 
 #[ffi]
 #[sret]
+#[throws]
 pub fn vodozemac_ed25519_public_key_verify(
     key: &Ed25519PublicKey,
     #[expand] message: &[u8],
@@ -70,6 +67,35 @@ pub fn vodozemac_curve25519_public_key_to_bytes(key: &Curve25519PublicKey, bytes
     bytes.copy_from_slice(key.as_bytes())
 }
 
+/// The SAS commitment of the accepting side to `key` and the JSON of the
+/// `m.key.verification.start` content.
+#[ffi]
+#[sret]
+#[throws]
+pub fn vodozemac_curve25519_public_key_calculate_commitment(
+    key: &Curve25519PublicKey,
+    #[expand] start_content: &[u8],
+) -> CResult<CSlice<u8>, CErrorStr> {
+    verification::calculate_commitment(key, start_content)
+        .map(Into::into)
+        .map_err(Into::into)
+        .into()
+}
+
+#[ffi]
+#[sret]
+#[throws]
+pub fn vodozemac_curve25519_public_key_verify_commitment(
+    key: &Curve25519PublicKey,
+    #[expand] start_content: &[u8],
+    #[expand] commitment: &[u8],
+) -> CResult<ZST, CErrorStr> {
+    verification::verify_commitment(key, start_content, commitment)
+        .map(Into::into)
+        .map_err(Into::into)
+        .into()
+}
+
 #[ffi]
 pub fn vodozemac_curve25519_public_key_free(key: NonNull<Curve25519PublicKey>) {
     free(key)
@@ -6,150 +6,400 @@
 
 use ::macros::ffi;
 use jni::JNIEnv;
-use jni::objects::{JByteArray, JClass, JIntArray, JLongArray, JObject, JShortArray};
-use jni::sys::{jbyte, jint, jlong, jshort};
+use jni::objects::{
+    JByteArray, JByteBuffer, JClass, JIntArray, JLongArray, JObject, JShortArray, ReleaseMode,
+};
+use jni::sys::{jbyte, jint, jlong, jobject, jshort};
+use parking_lot::Mutex;
 use std::alloc::Layout;
-use std::ffi::c_void;
+use std::ffi::{CStr, c_char, c_void};
 use std::fmt::Debug;
 use std::mem::MaybeUninit;
 use std::ptr::{NonNull, slice_from_raw_parts};
 use std::{alloc, array, ptr};
+use zeroize::{Zeroize, Zeroizing};
 
-pub mod types;
-
-#[cfg(target_arch = "wasm32")]
+// On `wasm32-wasip1` getrandom uses the WASI `random_get` import, only the
+// browser build needs the `extern_rng` import provided by the JS glue.
+#[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
 pub mod wasm;
 
+#[cfg(feature = "deterministic-rng")]
+pub mod rng;
+
+/// Incremented whenever exported functions or `#[repr(C)]` types change in a
+/// way that requires new bindings.
+///
+/// - 2: secrets are returned as `CSecretSlice`, freed with
+///   [`vodozemac_secret_slice_free`].
+/// - 3: the SAS Diffie-Hellman functions return a `CResult`.
+/// - 4: batch decryption returns a `CResult`, `CErrorStr` carries the
+///   exception class, code and message index of the error,
+///   `vodozemac_rng_set_seed` reports whether the seed took effect, creating,
+///   cancelling and feeding events to a SAS verification return a `CResult`,
+///   so do the SAS MAC functions taking a `MAC_METHOD_*` constant. Megolm
+///   messages are authenticated by tracked inbound group sessions against the
+///   claimed Ed25519 key of the sending device, creating a tracked inbound
+///   group session, replenishing the keys of an account and calculating the
+///   libolm encoded SAS MAC return a `CResult`.
+pub const ABI_VERSION: u32 = 4;
+
+/// Bits of [`vodozemac_features`].
+pub const FEATURE_DETERMINISTIC_RNG: u32 = 1 << 0;
+pub const FEATURE_DANGEROUS_JSON_PICKLES: u32 = 1 << 1;
+
+/// The package under which the Kotlin bindings live, it can be changed at build
+/// time with the `VODOZEMAC_JNI_PREFIX` environment variable.
+pub const DEFAULT_JNI_PREFIX: &str = match option_env!("VODOZEMAC_JNI_PREFIX") {
+    Some(prefix) => prefix,
+    None => "com/github/fhilgers/vodozemac",
+};
+
+/// A `*BindingsKt` class, relative to `{prefix}/bindings/`, together with its
+/// native methods.
+pub struct JniModule {
+    pub class: &'static str,
+    pub methods: &'static [NativeMethod],
+}
+
+const JNI_MODULES: &[JniModule] = &[
+    JniModule {
+        class: "KeyBindingsKt",
+        methods: keys::JNI_METHODS,
+    },
+    JniModule {
+        class: "PickleBindingsKt",
+        methods: pickle::JNI_METHODS,
+    },
+    JniModule {
+        class: "SliceBindingsKt",
+        methods: SLICE_JNI_METHODS,
+    },
+];
+
+/// Every module registered by [`register_natives`].
+pub fn jni_modules() -> impl Iterator<Item = &'static JniModule> {
+    JNI_MODULES
+        .iter()
+        .chain(megolm::JNI_MODULES)
+        .chain(olm::JNI_MODULES)
+        .chain(sas::JNI_MODULES)
+}
+
+static JNI_REGISTRATION_ERRORS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+/// Registers the native methods of every module whose class can be found under
+/// `prefix`.
+///
+/// Missing classes, for example stripped by ProGuard, do not stop the other
+/// modules from being registered. The failures are returned and can be queried
+/// later with [`vodozemac_jni_registration_errors`].
+pub fn register_natives(env: &mut JNIEnv, prefix: &str) -> Vec<String> {
+    let mut errors = jni_modules()
+        .filter_map(|module| {
+            let class_name = format!("{prefix}/bindings/{}", module.class);
+            let methods = module.methods.iter().map(Into::into).collect::<Vec<_>>();
+            let result = env
+                .find_class(&class_name)
+                .and_then(|class| env.register_native_methods(&class, &methods));
+
+            let error = result.err()?;
+            let _ = env.exception_clear();
+            Some(format!("{class_name}: {error}"))
+        })
+        .collect::<Vec<_>>();
+
+    if let Err(error) = bindings_abi_version(env, prefix) {
+        errors.push(error);
+    }
+    errors.extend(exceptions::register_jni(env, prefix));
+
+    JNI_REGISTRATION_ERRORS.lock().clone_from(&errors);
+
+    errors
+}
+
+/// Reads the `ABI_VERSION` constant of the bindings, declared in
+/// `{prefix}/bindings/AbiKt` (the Kotlin file `Abi.kt`).
+///
+/// Kotlin inlines `const val`s, so the class has to be kept explicitly when
+/// the bindings are shrunk with R8.
+fn bindings_abi_version(env: &mut JNIEnv, prefix: &str) -> Result<u32, String> {
+    let class_name = format!("{prefix}/bindings/AbiKt");
+
+    env.get_static_field(class_name.as_str(), "ABI_VERSION", "I")
+        .and_then(|value| value.i())
+        .map(|version| version as u32)
+        .map_err(|error| {
+            let _ = env.exception_clear();
+            format!("{class_name}: {error}")
+        })
+}
+
+/// Compares [`ABI_VERSION`] with the `ABI_VERSION` constant of the bindings
+/// under `prefix`.
+///
+/// Bindings whose `AbiKt` can not be read, e.g. because R8 removed it, can not
+/// be checked and are accepted. [`register_natives`] reports the missing
+/// class.
+pub fn check_abi_version(env: &mut JNIEnv, prefix: &str) -> Result<(), String> {
+    match bindings_abi_version(env, prefix) {
+        Ok(version) if version != ABI_VERSION => Err(format!(
+            "the vodozemac bindings require ABI version {version}, but the loaded library is {}",
+            version_string()
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// Whether any class of the bindings can be found under `prefix`.
+pub fn has_bindings(env: &mut JNIEnv, prefix: &str) -> bool {
+    let classes = jni_modules().map(|module| module.class).chain(["AbiKt"]);
+
+    for class in classes {
+        let found = env.find_class(format!("{prefix}/bindings/{class}"));
+        let _ = env.exception_clear();
+        if let Ok(class) = found {
+            let _ = env.delete_local_ref(class);
+            return true;
+        }
+    }
+
+    false
+}
+
+/// Throws an `UnsatisfiedLinkError`, which `System.loadLibrary` rethrows to
+/// its caller.
+fn reject_bindings(env: &mut JNIEnv, message: String) -> jint {
+    let _ = env.throw_new("java/lang/UnsatisfiedLinkError", message);
+    jni::sys::JNI_ERR
+}
+
 #[unsafe(no_mangle)]
 pub extern "system" fn JNI_OnLoad(vm: jni::JavaVM, _: *mut c_void) -> jint {
-    fn inner(vm: jni::JavaVM) -> jni::errors::Result<()> {
-        let mut env = vm.get_env()?;
-
-        let class_name = |suffix: &str| format!("com/github/fhilgers/vodozemac/bindings/{suffix}");
-
-        let megolm_classes = megolm::MegolmJniClasses {
-            group_session: &env.find_class(class_name("megolm/GroupSessionBindingsKt"))?,
-            session_config: &env.find_class(class_name("megolm/SessionConfigBindingsKt"))?,
-            inbound_group_session: &env
-                .find_class(class_name("megolm/InboundGroupSessionBindingsKt"))?,
-            message: &env.find_class(class_name("megolm/MessageBindingsKt"))?,
-            session_key: &env.find_class(class_name("megolm/SessionKeyBindingsKt"))?,
-            exported_session_key: &env
-                .find_class(class_name("megolm/ExportedSessionKeyBindingsKt"))?,
-        };
+    let Ok(mut env) = vm.get_env() else {
+        return -1;
+    };
 
-        let sas_classes = sas::SasJniClasses {
-            established_sas: &env.find_class(class_name("sas/EstablishedSasBindingsKt"))?,
-            mac: &env.find_class(class_name("sas/MacBindingsKt"))?,
-            sas: &env.find_class(class_name("sas/SasBindingsKt"))?,
-            sas_bytes: &env.find_class(class_name("sas/SasBytesBindingsKt"))?,
-        };
+    // Hosts which embed the library under their own package register it with
+    // `vodozemac_register_natives`, the default package is neither checked
+    // nor registered then.
+    if !has_bindings(&mut env, DEFAULT_JNI_PREFIX) {
+        return jni::sys::JNI_VERSION_1_6;
+    }
 
-        let olm_classes = olm::OlmJniClasses {
-            account: &env.find_class(class_name("olm/AccountBindingsKt"))?,
-            message: &env.find_class(class_name("olm/MessageBindingsKt"))?,
-            session: &env.find_class(class_name("olm/SessionBindingsKt"))?,
-            session_config: &env.find_class(class_name("olm/SessionConfigBindingsKt"))?,
-            session_keys: &env.find_class(class_name("olm/SessionKeysBindingsKt"))?,
-        };
+    if let Err(message) = check_abi_version(&mut env, DEFAULT_JNI_PREFIX) {
+        return reject_bindings(&mut env, message);
+    }
+
+    register_natives(&mut env, DEFAULT_JNI_PREFIX);
+
+    jni::sys::JNI_VERSION_1_6
+}
+
+/// Registers the native methods under a different package than
+/// [`DEFAULT_JNI_PREFIX`], for hosts which embed the library under their own
+/// name, e.g. `org/example/crypto`.
+///
+/// Returns the number of classes which could not be found or registered, or
+/// -1 with a pending `UnsatisfiedLinkError` if the bindings have a different
+/// [`ABI_VERSION`].
+///
+/// # Safety
+///
+/// `env` must be a valid `JNIEnv` of the current thread and `prefix` a nul
+/// terminated string.
+#[unsafe(no_mangle)]
+pub unsafe extern "system" fn vodozemac_register_natives(
+    env: *mut jni::sys::JNIEnv,
+    prefix: *const c_char,
+) -> jint {
+    let Ok(mut env) = (unsafe { JNIEnv::from_raw(env) }) else {
+        return -1;
+    };
+    let Ok(prefix) = unsafe { CStr::from_ptr(prefix) }.to_str() else {
+        return -1;
+    };
 
-        let key_class = &env.find_class(class_name("KeyBindingsKt"))?;
+    if let Err(message) = check_abi_version(&mut env, prefix) {
+        return reject_bindings(&mut env, message);
+    }
 
-        megolm::register_jni(&mut env, &megolm_classes)?;
+    register_natives(&mut env, prefix).len() as jint
+}
 
-        sas::register_jni(&mut env, &sas_classes)?;
+#[ffi]
+#[sret]
+pub fn vodozemac_jni_registration_errors() -> CSlice<u8> {
+    JNI_REGISTRATION_ERRORS.lock().join("\n").into()
+}
 
-        olm::register_jni(&mut env, &olm_classes)?;
+#[ffi]
+pub fn vodozemac_abi_version() -> u32 {
+    ABI_VERSION
+}
 
-        keys::register_jni(&mut env, key_class)?;
+pub fn version_string() -> String {
+    format!(
+        "libvodozemac {} (vodozemac {}, ABI version {ABI_VERSION})",
+        env!("CARGO_PKG_VERSION"),
+        vodozemac::VERSION
+    )
+}
 
-        let slice_bindings = env.find_class(class_name("SliceBindingsKt"))?;
-        env.register_native_methods(
-            &slice_bindings,
-            &[
-                NativeMethod {
-                    name: "alloc",
-                    sig: "(II)J",
-                    fn_ptr: {
-                        fn wrapper(_: JNIEnv, _: JClass, size: jint, align: jint) -> jlong {
-                            alloc(size as _, align as _) as _
-                        }
-                        wrapper as _
-                    },
-                }
-                .into(),
-                NativeMethod {
-                    name: "dealloc",
-                    sig: "(JII)V",
-                    fn_ptr: {
-                        fn wrapper(_: JNIEnv, _: JClass, ptr: jlong, size: jint, align: jint) {
-                            dealloc(ptr as _, size as _, align as _)
+#[ffi]
+#[sret]
+pub fn vodozemac_version_string() -> CSlice<u8> {
+    version_string().into()
+}
+
+/// The optional features the library was built with, a combination of the
+/// `FEATURE_*` constants.
+#[ffi]
+pub fn vodozemac_features() -> u32 {
+    let mut features = 0;
+
+    if cfg!(feature = "deterministic-rng") {
+        features |= FEATURE_DETERMINISTIC_RNG;
+    }
+    if cfg!(feature = "dangerous-json-pickles") {
+        features |= FEATURE_DANGEROUS_JSON_PICKLES;
+    }
+
+    features
+}
+
+/// The layout of the structs returned through `#[sret]` as JSON, see
+/// [`abi::layout_json`].
+#[ffi]
+#[sret]
+pub fn vodozemac_abi_layout() -> CSlice<u8> {
+    abi::layout_json().into()
+}
+
+/// Converts a size passed from Java, negative sizes throw an
+/// `IllegalArgumentException`.
+fn jni_size(env: &mut JNIEnv, size: jint) -> Option<usize> {
+    let size = usize::try_from(size);
+    if let Err(error) = &size {
+        let _ = env.throw_new("java/lang/IllegalArgumentException", error.to_string());
+    }
+    size.ok()
+}
+
+const SLICE_JNI_METHODS: &[NativeMethod] = &[
+    NativeMethod {
+        name: "alloc",
+        sig: "(II)J",
+        params: &[jint::SIGNATURE, jint::SIGNATURE],
+        ret: jlong::SIGNATURE,
+        fn_ptr: {
+            fn wrapper(mut env: JNIEnv, _: JClass, size: jint, align: jint) -> jlong {
+                let (Some(size), Some(align)) =
+                    (jni_size(&mut env, size), jni_size(&mut env, align))
+                else {
+                    return 0;
+                };
+                alloc(size, align) as _
+            }
+            wrapper as _
+        },
+    },
+    NativeMethod {
+        name: "dealloc",
+        sig: "(JII)V",
+        params: &[jlong::SIGNATURE, jint::SIGNATURE, jint::SIGNATURE],
+        ret: <()>::SIGNATURE,
+        fn_ptr: {
+            fn wrapper(mut env: JNIEnv, _: JClass, ptr: jlong, size: jint, align: jint) {
+                let (Some(size), Some(align)) =
+                    (jni_size(&mut env, size), jni_size(&mut env, align))
+                else {
+                    return;
+                };
+                dealloc(ptr as _, size, align)
+            }
+            wrapper as _
+        },
+    },
+    NativeMethod {
+        name: "new_direct_byte_buffer",
+        sig: "(JI)Ljava/nio/ByteBuffer;",
+        params: &[jlong::SIGNATURE, jint::SIGNATURE],
+        ret: "Ljava/nio/ByteBuffer;",
+        fn_ptr: {
+            // Wraps a `CSlice` returned by the library without copying it.
+            // The buffer does not own the memory: JNI can not attach a
+            // `Cleaner` from native code, the bindings have to register one
+            // which calls `dealloc`, or `vodozemac_secret_slice_free` for
+            // secrets, once the buffer is unreachable. The generated
+            // wrappers themselves keep returning `CSlice`s.
+            fn wrapper(mut env: JNIEnv, _: JClass, ptr: jlong, size: jint) -> jobject {
+                let Some(size) = jni_size(&mut env, size) else {
+                    return ptr::null_mut();
+                };
+                match unsafe { env.new_direct_byte_buffer(ptr as _, size) } {
+                    Ok(buffer) => buffer.into_raw(),
+                    Err(e) => {
+                        if matches!(env.exception_check(), Ok(false)) {
+                            let _ = env.throw_new("java/lang/RuntimeException", e.to_string());
                         }
-                        wrapper as _
-                    },
+                        ptr::null_mut()
+                    }
                 }
-                .into(),
-                NativeMethod {
-                    name: "copy_nonoverlapping",
-                    sig: "(JLjava/lang/Object;I)V",
-                    fn_ptr: {
-                        // TODO: maybe other arrays?
-                        fn wrapper(
-                            mut env: JNIEnv,
-                            _: JClass,
-                            src: jlong,
-                            dest: JObject,
-                            size: jint,
-                        ) -> jni::errors::Result<()> {
-                            if env.is_instance_of(&dest, "[B")? {
-                                let src = unsafe {
-                                    &*slice_from_raw_parts(src as *mut jbyte, size as usize)
-                                };
-                                env.set_byte_array_region(JByteArray::from(dest), 0, src)?;
-                            } else if env.is_instance_of(&dest, "[S")? {
-                                let src = unsafe {
-                                    &*slice_from_raw_parts(src as *mut jshort, size as usize / 2)
-                                };
-                                env.set_short_array_region(JShortArray::from(dest), 0, src)?;
-                            } else if env.is_instance_of(&dest, "[I")? {
-                                let src = unsafe {
-                                    &*slice_from_raw_parts(src as *mut jint, size as usize / 4)
-                                };
-                                env.set_int_array_region(JIntArray::from(dest), 0, src)?;
-                            } else if env.is_instance_of(&dest, "[J")? {
-                                let src = unsafe {
-                                    &*slice_from_raw_parts(src as *mut jlong, size as usize / 8)
-                                };
-                                env.set_long_array_region(JLongArray::from(dest), 0, src)?;
-                            } else {
-                                env.throw_new(
-                                    "java/lang/IllegalArgumentException",
-                                    "invalid input",
-                                )?;
-                            }
-
-                            Ok(())
-                        }
-                        fn outer(
-                            env: JNIEnv,
-                            class: JClass,
-                            src: jlong,
-                            dest: JObject,
-                            size: jint,
-                        ) {
-                            let _ = wrapper(env, class, src, dest, size);
-                        }
-                        outer as _
-                    },
+            }
+            wrapper as _
+        },
+    },
+    NativeMethod {
+        name: "copy_nonoverlapping",
+        sig: "(JLjava/lang/Object;I)V",
+        params: &[jlong::SIGNATURE, JObject::SIGNATURE, jint::SIGNATURE],
+        ret: <()>::SIGNATURE,
+        fn_ptr: {
+            // TODO: maybe other arrays?
+            fn wrapper(
+                mut env: JNIEnv,
+                _: JClass,
+                src: jlong,
+                dest: JObject,
+                size: jint,
+            ) -> jni::errors::Result<()> {
+                let Some(size) = jni_size(&mut env, size) else {
+                    return Ok(());
+                };
+
+                if env.is_instance_of(&dest, "[B")? {
+                    let src = unsafe { &*slice_from_raw_parts(src as *mut jbyte, size) };
+                    env.set_byte_array_region(JByteArray::from(dest), 0, src)?;
+                } else if env.is_instance_of(&dest, "[S")? {
+                    let src = unsafe { &*slice_from_raw_parts(src as *mut jshort, size / 2) };
+                    env.set_short_array_region(JShortArray::from(dest), 0, src)?;
+                } else if env.is_instance_of(&dest, "[I")? {
+                    let src = unsafe { &*slice_from_raw_parts(src as *mut jint, size / 4) };
+                    env.set_int_array_region(JIntArray::from(dest), 0, src)?;
+                } else if env.is_instance_of(&dest, "[J")? {
+                    let src = unsafe { &*slice_from_raw_parts(src as *mut jlong, size / 8) };
+                    env.set_long_array_region(JLongArray::from(dest), 0, src)?;
+                } else {
+                    env.throw_new("java/lang/IllegalArgumentException", "invalid input")?;
                 }
-                .into(),
-            ],
-        )?;
-        Ok(())
-    }
 
-    inner(vm).map(|_| jni::sys::JNI_VERSION_1_6).unwrap_or(-1)
-}
+                Ok(())
+            }
+            fn outer(env: JNIEnv, class: JClass, src: jlong, dest: JObject, size: jint) {
+                let _ = wrapper(env, class, src, dest, size);
+            }
+            outer as _
+        },
+    },
+    VODOZEMAC_JNI_REGISTRATION_ERRORS_JNI,
+    VODOZEMAC_ABI_LAYOUT_JNI,
+    VODOZEMAC_ABI_VERSION_JNI,
+    VODOZEMAC_VERSION_STRING_JNI,
+    VODOZEMAC_FEATURES_JNI,
+    VODOZEMAC_SECRET_SLICE_FREE_JNI,
+];
 
 #[repr(C, usize)]
 #[derive(Clone, Copy, Debug)]
@@ -169,6 +419,8 @@ impl From<()> for ZST {
 }
 
 impl AsUsize for ZST {
+    const WORDS: usize = 0;
+
     type IntoIter = array::IntoIter<usize, 0>;
 
     fn as_usize(&self) -> Self::IntoIter {
@@ -207,6 +459,13 @@ pub fn dealloc(ptr: *mut u8, size: usize, align: usize) {
     unsafe { alloc::dealloc(ptr, layout) }
 }
 
+/// Wipes and frees a [`CSecretSlice`].
+#[ffi]
+pub fn vodozemac_secret_slice_free(ptr: NonNull<u8>, len: usize) {
+    let mut slice = unsafe { Box::from_raw(ptr::slice_from_raw_parts_mut(ptr.as_ptr(), len)) };
+    slice.zeroize();
+}
+
 #[ffi]
 pub fn copy_nonoverlapping(src: *const c_void, dest: *mut c_void, size: u32) {
     if size == 0 {
@@ -216,23 +475,33 @@ pub fn copy_nonoverlapping(src: *const c_void, dest: *mut c_void, size: u32) {
     unsafe { ptr::copy_nonoverlapping(src, dest, size as usize) }
 }
 
+pub mod abi;
 pub mod macros;
-use crate::slices::{CErrorStr, CSlice};
+use crate::slices::{CErrorStr, CSecretSlice, CSlice};
 pub use macros::{boxed, free};
 
+mod canonical_json;
+pub mod exceptions;
 pub mod keys;
 pub mod megolm;
 pub mod olm;
+pub mod pickle;
 pub mod sas;
 pub mod slices;
 
 pub trait AsUsize {
+    /// The most words `as_usize` yields, checked against the size of `#[sret]`
+    /// return types by [`abi::assert_words`].
+    const WORDS: usize;
+
     type IntoIter: Iterator<Item = usize>;
 
     fn as_usize(&self) -> Self::IntoIter;
 }
 
 impl<T> AsUsize for NonNull<T> {
+    const WORDS: usize = 1;
+
     type IntoIter = array::IntoIter<usize, 1>;
 
     fn as_usize(&self) -> Self::IntoIter {
@@ -241,6 +510,8 @@ impl<T> AsUsize for NonNull<T> {
 }
 
 impl<T> AsUsize for CSlice<T> {
+    const WORDS: usize = 2;
+
     type IntoIter = array::IntoIter<usize, 2>;
 
     fn as_usize(&self) -> Self::IntoIter {
@@ -249,12 +520,27 @@ impl<T> AsUsize for CSlice<T> {
 }
 
 impl AsUsize for CErrorStr {
+    const WORDS: usize = CSlice::<u8>::WORDS + 3;
+
+    type IntoIter = Chain<array::IntoIter<usize, 2>, array::IntoIter<usize, 3>>;
+
+    fn as_usize(&self) -> Self::IntoIter {
+        self.message
+            .as_usize()
+            .chain_exact([self.class, self.code, self.message_index].into_iter())
+    }
+}
+
+impl AsUsize for CSecretSlice {
+    const WORDS: usize = CSlice::<u8>::WORDS;
+
     type IntoIter = array::IntoIter<usize, 2>;
 
     fn as_usize(&self) -> Self::IntoIter {
         self.0.as_usize()
     }
 }
+
 pub enum Either<I1, I2> {
     Left(I1),
     Right(I2),
@@ -339,6 +625,12 @@ where
     T: AsUsize,
     E: AsUsize,
 {
+    const WORDS: usize = 1 + if T::WORDS > E::WORDS {
+        T::WORDS
+    } else {
+        E::WORDS
+    };
+
     type IntoIter = Chain<array::IntoIter<usize, 1>, Either<T::IntoIter, E::IntoIter>>;
 
     fn as_usize(&self) -> Self::IntoIter {
@@ -405,39 +697,272 @@ pub trait ChainExact: Iterator {
 impl<T: Debug + Default + Copy, E: ExactSizeIterator<Item = T>> CollectIntoArray<T> for E {}
 impl<E: Iterator> ChainExact for E {}
 
+/// Byte arrays may hold secrets, so the copy is wiped once it is dropped.
 pub fn get_byte_array_region(
     env: &mut JNIEnv,
     src: &JByteArray,
     offset: u32,
     length: u32,
-) -> jni::errors::Result<Box<[u8]>> {
+) -> jni::errors::Result<Zeroizing<Box<[u8]>>> {
     let mut dest = Box::<[u8]>::new_uninit_slice(length as usize);
     env.get_byte_array_region(src, offset as _, unsafe {
         &mut *(dest.as_mut() as *mut _ as *mut _)
     })?;
+    Ok(Zeroizing::new(unsafe { dest.assume_init() }))
+}
+
+/// A byte slice passed from Java without copying it.
+///
+/// Direct `java.nio.ByteBuffer`s are accessed through their address, starting
+/// at their position, the bytes up to their limit have to cover `length`. A
+/// `byte[]` is pinned with `GetPrimitiveArrayCritical` by [`JniBytes::pin`]
+/// and released on drop, no other JNI function may be called in between.
+///
+/// Pinning keeps the garbage collector from running, so arguments of long
+/// running functions are marked `#[copy]` and copied by [`JniBytes::copy`]
+/// instead.
+pub enum JniBytes<'local> {
+    Direct(*mut u8),
+    Array {
+        env: *mut jni::sys::JNIEnv,
+        array: JByteArray<'local>,
+        pinned: Option<(*mut u8, ReleaseMode)>,
+        copied: Option<(Zeroizing<Box<[u8]>>, ReleaseMode)>,
+    },
+}
+
+impl<'local> JniBytes<'local> {
+    pub fn new(
+        env: &mut JNIEnv,
+        object: JObject<'local>,
+        length: u32,
+    ) -> jni::errors::Result<Self> {
+        let available = if env.is_instance_of(&object, "java/nio/ByteBuffer")? {
+            let buffer = JByteBuffer::from(object);
+            let address = env.get_direct_buffer_address(&buffer)?;
+            let position = env.call_method(&buffer, "position", "()I", &[])?.i()?;
+            let limit = env.call_method(&buffer, "limit", "()I", &[])?.i()?;
+
+            // `0 <= position <= limit <= capacity` holds for every buffer.
+            let remaining = (limit - position) as usize;
+            if remaining >= length as usize {
+                return Ok(Self::Direct(unsafe { address.add(position as usize) }));
+            }
+            remaining
+        } else {
+            let array = JByteArray::from(object);
+            let capacity = env.get_array_length(&array)? as usize;
+            if capacity >= length as usize {
+                return Ok(Self::Array {
+                    env: env.get_raw(),
+                    array,
+                    pinned: None,
+                    copied: None,
+                });
+            }
+            capacity
+        };
+
+        env.throw_new(
+            "java/lang/IndexOutOfBoundsException",
+            format!("length {length} exceeds the {available} available bytes"),
+        )?;
+        Err(jni::errors::Error::JavaException)
+    }
+
+    /// # Safety
+    ///
+    /// No JNI function may be called until `self` is dropped.
+    pub unsafe fn pin(&mut self, mode: ReleaseMode) -> jni::errors::Result<*mut u8> {
+        match self {
+            JniBytes::Direct(ptr) => Ok(*ptr),
+            JniBytes::Array {
+                env, array, pinned, ..
+            } => {
+                let ptr = unsafe {
+                    let get = (**(*env)).GetPrimitiveArrayCritical.expect("jni 1.2");
+                    get(*env, array.as_raw(), ptr::null_mut())
+                };
+                if ptr.is_null() {
+                    return Err(jni::errors::Error::NullPtr("GetPrimitiveArrayCritical"));
+                }
+                *pinned = Some((ptr as _, mode));
+                Ok(ptr as _)
+            }
+        }
+    }
+
+    /// Copies the first `length` bytes of a `byte[]`, with
+    /// `ReleaseMode::CopyBack` they are written back on drop.
+    pub fn copy(
+        &mut self,
+        env: &mut JNIEnv,
+        length: u32,
+        mode: ReleaseMode,
+    ) -> jni::errors::Result<*mut u8> {
+        match self {
+            JniBytes::Direct(ptr) => Ok(*ptr),
+            JniBytes::Array { array, copied, .. } => {
+                let (bytes, _) =
+                    copied.insert((get_byte_array_region(env, array, 0, length)?, mode));
+                Ok(bytes.as_mut_ptr())
+            }
+        }
+    }
+}
+
+impl Drop for JniBytes<'_> {
+    fn drop(&mut self) {
+        match self {
+            JniBytes::Array {
+                env,
+                array,
+                pinned: Some((ptr, mode)),
+                ..
+            } => unsafe {
+                let release = (**(*env)).ReleasePrimitiveArrayCritical.expect("jni 1.2");
+                release(*env, array.as_raw(), *ptr as _, *mode as _);
+            },
+            JniBytes::Array {
+                env,
+                array,
+                copied: Some((bytes, ReleaseMode::CopyBack)),
+                ..
+            } => unsafe {
+                let set = (**(*env)).SetByteArrayRegion.expect("jni 1.1");
+                set(
+                    *env,
+                    array.as_raw(),
+                    0,
+                    bytes.len() as _,
+                    bytes.as_ptr() as _,
+                );
+            },
+            _ => {}
+        }
+    }
+}
+
+pub fn get_short_array_region(
+    env: &mut JNIEnv,
+    src: &JShortArray,
+    offset: u32,
+    length: u32,
+) -> jni::errors::Result<Box<[u16]>> {
+    let mut dest = Box::<[u16]>::new_uninit_slice(length as usize);
+    env.get_short_array_region(src, offset as _, unsafe {
+        &mut *(dest.as_mut() as *mut _ as *mut _)
+    })?;
+    Ok(unsafe { dest.assume_init() })
+}
+
+pub fn get_int_array_region(
+    env: &mut JNIEnv,
+    src: &JIntArray,
+    offset: u32,
+    length: u32,
+) -> jni::errors::Result<Box<[u32]>> {
+    let mut dest = Box::<[u32]>::new_uninit_slice(length as usize);
+    env.get_int_array_region(src, offset as _, unsafe {
+        &mut *(dest.as_mut() as *mut _ as *mut _)
+    })?;
     Ok(unsafe { dest.assume_init() })
 }
 
+pub fn get_long_array_region(
+    env: &mut JNIEnv,
+    src: &JLongArray,
+    offset: u32,
+    length: u32,
+) -> jni::errors::Result<Box<[u64]>> {
+    let mut dest = Box::<[u64]>::new_uninit_slice(length as usize);
+    env.get_long_array_region(src, offset as _, unsafe {
+        &mut *(dest.as_mut() as *mut _ as *mut _)
+    })?;
+    Ok(unsafe { dest.assume_init() })
+}
+
+/// Handles are passed from Java as `long[]`, but are only `usize` wide on
+/// 32 bit targets, so they can not be copied in place.
+pub fn get_ptr_array_region(
+    env: &mut JNIEnv,
+    src: &JLongArray,
+    offset: u32,
+    length: u32,
+) -> jni::errors::Result<Box<[usize]>> {
+    Ok(get_long_array_region(env, src, offset, length)?
+        .iter()
+        .map(|&handle| usize::try_from(handle).expect("handle fits into usize"))
+        .collect())
+}
+
+/// Used for pickle keys, so the copy is wiped once it is dropped.
 pub fn get_byte_array_region_const<const N: usize>(
     env: &mut JNIEnv,
     src: &JByteArray,
     offset: u32,
-) -> jni::errors::Result<[u8; N]> {
+) -> jni::errors::Result<Zeroizing<[u8; N]>> {
     let mut dest = [const { MaybeUninit::<u8>::uninit() }; N];
     env.get_byte_array_region(src, offset as _, unsafe {
         &mut *(dest.as_mut() as *mut _ as *mut _)
     })?;
+    Ok(Zeroizing::new(unsafe { transmute!(dest) }))
+}
+
+pub fn get_short_array_region_const<const N: usize>(
+    env: &mut JNIEnv,
+    src: &JShortArray,
+    offset: u32,
+) -> jni::errors::Result<[u16; N]> {
+    let mut dest = [const { MaybeUninit::<u16>::uninit() }; N];
+    env.get_short_array_region(src, offset as _, unsafe {
+        &mut *(dest.as_mut() as *mut _ as *mut _)
+    })?;
     Ok(unsafe { transmute!(dest) })
 }
 
+pub fn get_int_array_region_const<const N: usize>(
+    env: &mut JNIEnv,
+    src: &JIntArray,
+    offset: u32,
+) -> jni::errors::Result<[u32; N]> {
+    let mut dest = [const { MaybeUninit::<u32>::uninit() }; N];
+    env.get_int_array_region(src, offset as _, unsafe {
+        &mut *(dest.as_mut() as *mut _ as *mut _)
+    })?;
+    Ok(unsafe { transmute!(dest) })
+}
+
+/// A native method together with the JNI types of its wrapper, `params` and
+/// `ret` are derived from the Rust signature of `fn_ptr` and not from `sig`.
+#[derive(Clone, Copy)]
 pub struct NativeMethod {
-    name: &'static str,
-    sig: &'static str,
-    fn_ptr: *mut c_void,
+    pub name: &'static str,
+    pub sig: &'static str,
+    pub fn_ptr: *mut c_void,
+    pub params: &'static [&'static str],
+    pub ret: &'static str,
 }
 
-impl From<NativeMethod> for jni::NativeMethod {
-    fn from(value: NativeMethod) -> Self {
+impl NativeMethod {
+    /// Checks that `sig` is the signature the JVM has to use to call
+    /// `fn_ptr`, a mismatch would only be noticed when the method is called.
+    pub fn validate(&self) -> Result<(), String> {
+        let expected = format!("({}){}", self.params.concat(), self.ret);
+
+        if self.sig == expected {
+            Ok(())
+        } else {
+            Err(format!(
+                "{}: signature {} does not match the parameters {}",
+                self.name, self.sig, expected
+            ))
+        }
+    }
+}
+
+impl From<&NativeMethod> for jni::NativeMethod {
+    fn from(value: &NativeMethod) -> Self {
         jni::NativeMethod {
             name: value.name.into(),
             sig: value.sig.into(),
@@ -445,3 +970,51 @@ impl From<NativeMethod> for jni::NativeMethod {
         }
     }
 }
+
+/// The JNI type descriptor of a parameter or return type of a native method.
+///
+/// Arrays and buffers are declared as `Any` in the Kotlin bindings, so all
+/// objects share the descriptor of `java.lang.Object`. Handles are always
+/// passed as `Long`.
+pub trait JniType {
+    const SIGNATURE: &'static str;
+}
+
+macro_rules! jni_type {
+    ($signature:literal: $($ty:ty),*) => {
+        $(impl JniType for $ty {
+            const SIGNATURE: &'static str = $signature;
+        })*
+    };
+}
+
+jni_type!("V": ());
+jni_type!("B": u8, i8);
+jni_type!("S": u16, i16);
+jni_type!("I": u32, i32);
+jni_type!("J": u64, i64, usize, isize);
+jni_type!("Ljava/lang/Object;": JObject<'_>, JByteArray<'_>, JShortArray<'_>, JIntArray<'_>, JLongArray<'_>);
+
+impl<T> JniType for NonNull<T> {
+    const SIGNATURE: &'static str = "J";
+}
+
+impl<T> JniType for Option<NonNull<T>> {
+    const SIGNATURE: &'static str = "J";
+}
+
+impl<T> JniType for &T {
+    const SIGNATURE: &'static str = "J";
+}
+
+impl<T> JniType for Option<&T> {
+    const SIGNATURE: &'static str = "J";
+}
+
+impl<T> JniType for *const T {
+    const SIGNATURE: &'static str = "J";
+}
+
+impl<T> JniType for *mut T {
+    const SIGNATURE: &'static str = "J";
+}
@@ -18,35 +18,109 @@ pub fn free<T>(value: NonNull<T>) {
     drop(boxed);
 }
 
+/// Defines a `#[repr(C)]` type returned through `#[sret]`, checks that every
+/// field is made of pointer-sized words and describes it for
+/// [`crate::vodozemac_abi_layout`].
+///
+/// Enums get `#[repr(C, usize)]`, their variants need named fields.
 #[macro_export]
 macro_rules! define_and_assert_ptr_sized {
     (
         $(#[$meta:meta])*
         $vis:vis struct $name:ident {
             $(
-                $field:ident : $ty:ty
+                $(#[$field_meta:meta])*
+                $field_vis:vis $field:ident : $ty:ty
             ),* $(,)?
         }
     ) => {
         $(#[$meta])*
         #[repr(C)]
         $vis struct $name {
-            $( $field: $ty ),*
+            $(
+                $(#[$field_meta])*
+                $field_vis $field: $ty
+            ),*
         }
 
         const _: () = {
-            use std::mem::size_of;
-            const PTR_SIZE: usize = size_of::<*const ()>();
-            $(
-                const _: () = assert!(
-                    size_of::<$ty>() == PTR_SIZE,
-                    concat!(
-                        "Field `",
-                        stringify!($field),
-                        "` is not pointer-sized"
-                    )
-                );
-            )*
+            $crate::define_and_assert_ptr_sized!(@assert $($field: $ty),*);
         };
+
+        impl $crate::abi::Layout for $name {
+            const LAYOUT: $crate::abi::TypeLayout = $crate::abi::TypeLayout {
+                name: stringify!($name),
+                size: Some(::core::mem::size_of::<$name>()),
+                align: ::core::mem::align_of::<$name>(),
+                fields: $crate::abi::Fields::Struct(&[$(
+                    $crate::abi::FieldLayout {
+                        name: stringify!($field),
+                        ty: stringify!($ty),
+                        offset: ::core::mem::offset_of!($name, $field),
+                        size: Some(::core::mem::size_of::<$ty>()),
+                    }
+                ),*]),
+            };
+        }
+    };
+    (
+        $(#[$meta:meta])*
+        $vis:vis enum $name:ident $(<$lifetime:lifetime>)? {
+            $(
+                $variant:ident {
+                    $($field:ident : $ty:ty),* $(,)?
+                }
+            ),* $(,)?
+        }
+    ) => {
+        $(#[$meta])*
+        #[repr(C, usize)]
+        $vis enum $name $(<$lifetime>)? {
+            $(
+                $variant {
+                    $($field: $ty),*
+                }
+            ),*
+        }
+
+        impl $(<$lifetime>)? $crate::abi::Layout for $name $(<$lifetime>)? {
+            // The fields may borrow for `$lifetime`, so they are checked here
+            // instead of in a free constant.
+            const LAYOUT: $crate::abi::TypeLayout = {
+                $($crate::define_and_assert_ptr_sized!(@assert $($field: $ty),*);)*
+
+                let mut tag = 0;
+                $crate::abi::TypeLayout {
+                    name: stringify!($name),
+                    size: Some(::core::mem::size_of::<Self>()),
+                    align: ::core::mem::align_of::<Self>(),
+                    fields: $crate::abi::Fields::Enum(&[$(
+                        $crate::abi::VariantLayout {
+                            name: stringify!($variant),
+                            tag: {
+                                tag += 1;
+                                tag - 1
+                            },
+                            fields: &$crate::abi::variant_fields([$(
+                                (stringify!($field), stringify!($ty), ::core::mem::size_of::<$ty>())
+                            ),*]),
+                        }
+                    ),*]),
+                }
+            };
+        }
+    };
+    (@assert $($field:ident : $ty:ty),*) => {
+        $(
+            assert!(
+                ::core::mem::size_of::<$ty>().is_multiple_of(::core::mem::size_of::<*const ()>())
+                    && ::core::mem::align_of::<$ty>() == ::core::mem::align_of::<*const ()>(),
+                concat!(
+                    "Field `",
+                    stringify!($field),
+                    "` is not made of pointer-sized words"
+                )
+            );
+        )*
     };
 }
@@ -3,13 +3,14 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use crate::megolm::GroupSession;
+use crate::pickle::{self, RekeyedPickles};
+#[cfg(feature = "dangerous-json-pickles")]
+use crate::slices::CSecretSlice;
 use crate::slices::{CErrorStr, CSlice};
 use crate::{
     CResult::{self},
-    boxed, free,
+    NativeMethod, boxed, free,
 };
-use jni::JNIEnv;
-use jni::objects::JClass;
 use macros::ffi;
 use parking_lot::RwLock;
 use std::ptr::NonNull;
@@ -17,22 +18,22 @@ use std::str;
 use vodozemac::megolm;
 use vodozemac::megolm::{GroupSessionPickle, MegolmMessage, SessionConfig, SessionKey};
 
-pub fn register_jni(env: &mut JNIEnv, class: &JClass) -> jni::errors::Result<()> {
-    env.register_native_methods(
-        class,
-        &[
-            VODOZEMAC_MEGOLM_GROUP_SESSION_NEW_JNI.into(),
-            VODOZEMAC_MEGOLM_GROUP_SESSION_FREE_JNI.into(),
-            VODOZEMAC_MEGOLM_GROUP_SESSION_SESSION_ID_JNI.into(),
-            VODOZEMAC_MEGOLM_GROUP_SESSION_MESSAGE_INDEX_JNI.into(),
-            VODOZEMAC_MEGOLM_GROUP_SESSION_SESSION_CONFIG_JNI.into(),
-            VODOZEMAC_MEGOLM_GROUP_SESSION_ENCRYPT_JNI.into(),
-            VODOZEMAC_MEGOLM_GROUP_SESSION_SESSION_KEY_JNI.into(),
-            VODOZEMAC_MEGOLM_GROUP_SESSION_PICKLE_JNI.into(),
-            VODOZEMAC_MEGOLM_GROUP_SESSION_FROM_PICKLE_JNI.into(),
-        ],
-    )
-}
+pub const JNI_METHODS: &[NativeMethod] = &[
+    VODOZEMAC_MEGOLM_GROUP_SESSION_NEW_JNI,
+    VODOZEMAC_MEGOLM_GROUP_SESSION_FREE_JNI,
+    VODOZEMAC_MEGOLM_GROUP_SESSION_SESSION_ID_JNI,
+    VODOZEMAC_MEGOLM_GROUP_SESSION_MESSAGE_INDEX_JNI,
+    VODOZEMAC_MEGOLM_GROUP_SESSION_SESSION_CONFIG_JNI,
+    VODOZEMAC_MEGOLM_GROUP_SESSION_ENCRYPT_JNI,
+    VODOZEMAC_MEGOLM_GROUP_SESSION_SESSION_KEY_JNI,
+    VODOZEMAC_MEGOLM_GROUP_SESSION_PICKLE_JNI,
+    VODOZEMAC_MEGOLM_GROUP_SESSION_FROM_PICKLE_JNI,
+    VODOZEMAC_MEGOLM_GROUP_SESSION_REKEY_PICKLES_JNI,
+    #[cfg(feature = "dangerous-json-pickles")]
+    VODOZEMAC_MEGOLM_GROUP_SESSION_DANGEROUS_JSON_PICKLE_JNI,
+    #[cfg(feature = "dangerous-json-pickles")]
+    VODOZEMAC_MEGOLM_GROUP_SESSION_DANGEROUS_FROM_JSON_PICKLE_JNI,
+];
 
 #[ffi]
 pub fn vodozemac_megolm_group_session_new(config: &SessionConfig) -> NonNull<GroupSession> {
@@ -88,6 +89,7 @@ pub fn vodozemac_megolm_group_session_pickle(
 
 #[ffi]
 #[sret]
+#[throws]
 pub fn vodozemac_megolm_group_session_from_pickle(
     #[expand] ciphertext: &[u8],
     pickle_key: &[u8; 32],
@@ -101,3 +103,56 @@ pub fn vodozemac_megolm_group_session_from_pickle(
         .map_err(Into::into)
         .into()
 }
+
+/// Re-encrypts group session pickles with `new_key`, see [`pickle::rekey`].
+#[ffi]
+#[sret]
+#[throws]
+pub fn vodozemac_megolm_group_session_rekey_pickles(
+    #[expand]
+    #[copy]
+    pickles: &[u8],
+    #[expand] pickle_lengths: &[u32],
+    old_key: &[u8; 32],
+    new_key: &[u8; 32],
+) -> CResult<RekeyedPickles, CErrorStr> {
+    pickle::rekey(
+        pickles,
+        pickle_lengths,
+        old_key,
+        new_key,
+        GroupSessionPickle::from_encrypted,
+        GroupSessionPickle::encrypt,
+    )
+}
+
+/// Exports the group session as an unencrypted JSON pickle.
+///
+/// **Dangerous**: anyone holding the JSON can decrypt every future message of
+/// the session and sign messages in its name.
+#[cfg(feature = "dangerous-json-pickles")]
+#[ffi]
+#[sret]
+pub fn vodozemac_megolm_group_session_dangerous_json_pickle(
+    group_session: &GroupSession,
+) -> CSecretSlice {
+    serde_json::to_vec(&group_session.read().pickle())
+        .expect("pickles can be serialized")
+        .into()
+}
+
+/// Imports a pickle exported by [`vodozemac_megolm_group_session_dangerous_json_pickle`].
+#[cfg(feature = "dangerous-json-pickles")]
+#[ffi]
+#[sret]
+#[throws]
+pub fn vodozemac_megolm_group_session_dangerous_from_json_pickle(
+    #[expand] json: &[u8],
+) -> CResult<NonNull<GroupSession>, CErrorStr> {
+    serde_json::from_slice::<GroupSessionPickle>(json)
+        .map(megolm::GroupSession::from_pickle)
+        .map(RwLock::new)
+        .map(boxed)
+        .map_err(Into::into)
+        .into()
+}
@@ -2,43 +2,54 @@
 //
 // SPDX-License-Identifier: Apache-2.0
 
+use crate::exceptions::ExceptionClass::Decryption;
+use crate::exceptions::{
+    DECRYPTION_INVALID_MESSAGE, DECRYPTION_INVALID_SESSION_INDEX, ErrorDetails,
+};
 use crate::megolm::InboundGroupSession;
-use crate::slices::{CErrorStr, CSlice};
-use crate::{AsUsize, CResult, Chain, ChainExact, boxed, free};
-use jni::JNIEnv;
-use jni::objects::JClass;
+use crate::pickle::{self, RekeyedPickles};
+use crate::slices::{CErrorStr, CSecretSlice, CSlice, LengthError, split_concatenated};
+use crate::{
+    AsUsize, CResult, Chain, ChainExact, NativeMethod, boxed, free, vodozemac_secret_slice_free,
+};
 use macros::ffi;
 use parking_lot::{RwLock, RwLockWriteGuard};
-use std::ptr::NonNull;
+use std::ptr::{NonNull, slice_from_raw_parts_mut};
 use std::{array, str};
-use vodozemac::megolm;
 use vodozemac::megolm::{
     ExportedSessionKey, InboundGroupSessionPickle, MegolmMessage, SessionConfig, SessionKey,
     SessionOrdering,
 };
+use vodozemac::{Ed25519PublicKey, megolm};
 
-pub fn register_jni(env: &mut JNIEnv, class: &JClass) -> jni::errors::Result<()> {
-    env.register_native_methods(
-        class,
-        &[
-            VODOZEMAC_MEGOLM_INBOUND_GROUP_SESSION_NEW_JNI.into(),
-            VODOZEMAC_MEGOLM_INBOUND_GROUP_SESSION_FREE_JNI.into(),
-            VODOZEMAC_MEGOLM_INBOUND_GROUP_SESSION_IMPORT_JNI.into(),
-            VODOZEMAC_MEGOLM_INBOUND_GROUP_SESSION_SESSION_ID_JNI.into(),
-            VODOZEMAC_MEGOLM_INBOUND_GROUP_SESSION_CONNECTED_JNI.into(),
-            VODOZEMAC_MEGOLM_INBOUND_GROUP_SESSION_COMPARE_JNI.into(),
-            VODOZEMAC_MEGOLM_INBOUND_GROUP_SESSION_MERGE_JNI.into(),
-            VODOZEMAC_MEGOLM_INBOUND_GROUP_SESSION_FIRST_KNOWN_INDEX_JNI.into(),
-            VODOZEMAC_MEGOLM_INBOUND_GROUP_SESSION_ADVANCE_TO_JNI.into(),
-            VODOZEMAC_MEGOLM_INBOUND_GROUP_SESSION_DECRYPT_JNI.into(),
-            VODOZEMAC_MEGOLM_INBOUND_GROUP_SESSION_EXPORT_AT_JNI.into(),
-            VODOZEMAC_MEGOLM_INBOUND_GROUP_SESSION_EXPORT_AT_FIRST_KNOWN_INDEX_JNI.into(),
-            VODOZEMAC_MEGOLM_INBOUND_GROUP_SESSION_PICKLE_JNI.into(),
-            VODOZEMAC_MEGOLM_INBOUND_GROUP_SESSION_FROM_PICKLE_JNI.into(),
-        ],
-    )
-}
+pub const JNI_METHODS: &[NativeMethod] = &[
+    VODOZEMAC_MEGOLM_INBOUND_GROUP_SESSION_NEW_JNI,
+    VODOZEMAC_MEGOLM_INBOUND_GROUP_SESSION_FREE_JNI,
+    VODOZEMAC_MEGOLM_INBOUND_GROUP_SESSION_IMPORT_JNI,
+    VODOZEMAC_MEGOLM_INBOUND_GROUP_SESSION_SESSION_ID_JNI,
+    VODOZEMAC_MEGOLM_INBOUND_GROUP_SESSION_SIGNING_KEY_JNI,
+    VODOZEMAC_MEGOLM_INBOUND_GROUP_SESSION_CONNECTED_JNI,
+    VODOZEMAC_MEGOLM_INBOUND_GROUP_SESSION_COMPARE_JNI,
+    VODOZEMAC_MEGOLM_INBOUND_GROUP_SESSION_MERGE_JNI,
+    VODOZEMAC_MEGOLM_INBOUND_GROUP_SESSION_FIRST_KNOWN_INDEX_JNI,
+    VODOZEMAC_MEGOLM_INBOUND_GROUP_SESSION_ADVANCE_TO_JNI,
+    VODOZEMAC_MEGOLM_INBOUND_GROUP_SESSION_DECRYPT_JNI,
+    VODOZEMAC_MEGOLM_INBOUND_GROUP_SESSION_DECRYPT_BATCH_JNI,
+    VODOZEMAC_MEGOLM_INBOUND_GROUP_SESSION_DECRYPT_BATCH_FREE_JNI,
+    VODOZEMAC_MEGOLM_INBOUND_GROUP_SESSION_EXPORT_AT_JNI,
+    VODOZEMAC_MEGOLM_INBOUND_GROUP_SESSION_EXPORT_AT_FIRST_KNOWN_INDEX_JNI,
+    VODOZEMAC_MEGOLM_INBOUND_GROUP_SESSION_PICKLE_JNI,
+    VODOZEMAC_MEGOLM_INBOUND_GROUP_SESSION_FROM_PICKLE_JNI,
+    VODOZEMAC_MEGOLM_INBOUND_GROUP_SESSION_REKEY_PICKLES_JNI,
+    #[cfg(feature = "dangerous-json-pickles")]
+    VODOZEMAC_MEGOLM_INBOUND_GROUP_SESSION_DANGEROUS_JSON_PICKLE_JNI,
+    #[cfg(feature = "dangerous-json-pickles")]
+    VODOZEMAC_MEGOLM_INBOUND_GROUP_SESSION_DANGEROUS_FROM_JSON_PICKLE_JNI,
+];
 
+/// Messages encrypted with a `session_config` of another version fail to
+/// decrypt with `DECRYPTION_INVALID_MAC_LENGTH`, their MAC has the length of
+/// the other version.
 #[ffi]
 pub fn vodozemac_megolm_inbound_group_session_new(
     key: &SessionKey,
@@ -76,6 +87,20 @@ pub fn vodozemac_megolm_inbound_group_session_session_id(
     inbound_group_session.read().session_id().into()
 }
 
+/// The Ed25519 key every message of the session is signed with.
+#[ffi]
+pub fn vodozemac_megolm_inbound_group_session_signing_key(
+    inbound_group_session: &InboundGroupSession,
+) -> NonNull<Ed25519PublicKey> {
+    boxed(signing_key(&inbound_group_session.read()))
+}
+
+fn signing_key(inbound_group_session: &megolm::InboundGroupSession) -> Ed25519PublicKey {
+    // The session id is the encoded signing key.
+    Ed25519PublicKey::from_base64(&inbound_group_session.session_id())
+        .expect("the session id is a valid Ed25519 key")
+}
+
 #[ffi]
 pub fn vodozemac_megolm_inbound_group_session_connected(
     inbound_group_session: &InboundGroupSession,
@@ -103,7 +128,11 @@ pub fn vodozemac_megolm_inbound_group_session_compare(
     // TODO: fix vodozemac, this does not need to be mutable
     let (mut this, mut other) = lock_ordered(inbound_group_session, other);
 
-    match this.compare(&mut other) {
+    ordering_code(this.compare(&mut other))
+}
+
+pub(super) fn ordering_code(ordering: SessionOrdering) -> u32 {
+    match ordering {
         SessionOrdering::Equal => 0,
         SessionOrdering::Better => 1,
         SessionOrdering::Worse => 2,
@@ -143,14 +172,17 @@ pub fn vodozemac_megolm_inbound_group_session_advance_to(
     inbound_group_session.write().advance_to(index).into()
 }
 
-#[repr(C)]
-pub struct DecryptedMessage {
-    plaintext: CSlice<u8>,
-    message_index: usize,
+crate::define_and_assert_ptr_sized! {
+    pub struct DecryptedMessage {
+        plaintext: CSecretSlice,
+        message_index: usize,
+    }
 }
 
 impl AsUsize for DecryptedMessage {
-    type IntoIter = Chain<<CSlice<u8> as AsUsize>::IntoIter, array::IntoIter<usize, 1>>;
+    const WORDS: usize = CSecretSlice::WORDS + 1;
+
+    type IntoIter = Chain<<CSecretSlice as AsUsize>::IntoIter, array::IntoIter<usize, 1>>;
 
     fn as_usize(&self) -> Self::IntoIter {
         self.plaintext
@@ -170,6 +202,7 @@ impl From<megolm::DecryptedMessage> for DecryptedMessage {
 
 #[ffi]
 #[sret]
+#[throws]
 pub fn vodozemac_megolm_inbound_group_session_decrypt(
     inbound_group_session: &InboundGroupSession,
     message: &MegolmMessage,
@@ -182,6 +215,104 @@ pub fn vodozemac_megolm_inbound_group_session_decrypt(
         .into()
 }
 
+/// An entry of [`vodozemac_megolm_inbound_group_session_decrypt_batch`], the
+/// error carries the index found in the message unless it could not be parsed.
+pub type BatchDecryptionResult = CResult<DecryptedMessage, CErrorStr>;
+
+/// Decrypts many serialized messages in a single call.
+///
+/// `messages` contains the concatenated serialized messages, their lengths are
+/// given by `message_lengths`. The message at position `i` is decrypted with
+/// `sessions[session_indices[i]]`. The returned results must be freed with
+/// [`vodozemac_megolm_inbound_group_session_decrypt_batch_free`], which also
+/// wipes the plaintexts.
+///
+/// Fails as a whole if `session_indices` and `message_lengths` differ in
+/// length or the lengths do not add up to the size of `messages`.
+#[ffi]
+#[sret]
+#[throws]
+pub fn vodozemac_megolm_inbound_group_session_decrypt_batch(
+    #[expand] sessions: &[&InboundGroupSession],
+    #[expand] session_indices: &[u32],
+    #[expand]
+    #[copy]
+    messages: &[u8],
+    #[expand] message_lengths: &[u32],
+) -> CResult<CSlice<BatchDecryptionResult>, CErrorStr> {
+    if session_indices.len() != message_lengths.len() {
+        return CResult::Err(
+            LengthError::Count {
+                expected: message_lengths.len(),
+                actual: session_indices.len(),
+            }
+            .into(),
+        );
+    }
+
+    let messages = match split_concatenated(messages, message_lengths) {
+        Ok(messages) => messages,
+        Err(error) => return CResult::Err(error.into()),
+    };
+
+    let results = session_indices
+        .iter()
+        .zip(messages)
+        .map(|(&session_index, bytes)| {
+            let message = MegolmMessage::from_bytes(bytes).map_err(|e| {
+                CErrorStr::new(
+                    e.to_string(),
+                    ErrorDetails::new(Decryption, DECRYPTION_INVALID_MESSAGE),
+                )
+            })?;
+
+            let message_index = message.message_index();
+
+            let Some(session) = sessions.get(session_index as usize) else {
+                return Err(CErrorStr::new(
+                    format!("invalid session index {session_index}"),
+                    ErrorDetails::new(Decryption, DECRYPTION_INVALID_SESSION_INDEX)
+                        .with_message_index(message_index),
+                ));
+            };
+
+            session
+                .write()
+                .decrypt(&message)
+                .map(Into::into)
+                .map_err(|e| {
+                    let details = ErrorDetails::of(&e).with_message_index(message_index);
+                    CErrorStr::new(e.to_string(), details)
+                })
+        })
+        .map(CResult::from)
+        .collect::<Vec<_>>();
+
+    CResult::Ok(results.into())
+}
+
+#[ffi]
+pub fn vodozemac_megolm_inbound_group_session_decrypt_batch_free(
+    results: NonNull<BatchDecryptionResult>,
+    length: u32,
+) {
+    let results =
+        unsafe { Box::from_raw(slice_from_raw_parts_mut(results.as_ptr(), length as usize)) };
+
+    for result in results {
+        match result {
+            CResult::Ok(DecryptedMessage {
+                plaintext: CSecretSlice(CSlice { ptr, len }),
+                ..
+            }) => vodozemac_secret_slice_free(ptr, len),
+            CResult::Err(error) => {
+                let CSlice { ptr, len } = error.message;
+                drop(unsafe { Box::from_raw(slice_from_raw_parts_mut(ptr.as_ptr(), len)) });
+            }
+        }
+    }
+}
+
 #[ffi]
 pub fn vodozemac_megolm_inbound_group_session_export_at(
     inbound_group_session: &InboundGroupSession,
@@ -212,6 +343,7 @@ pub fn vodozemac_megolm_inbound_group_session_pickle(
 
 #[ffi]
 #[sret]
+#[throws]
 pub fn vodozemac_megolm_inbound_group_session_from_pickle(
     #[expand] ciphertext: &[u8],
     pickle_key: &[u8; 32],
@@ -226,7 +358,60 @@ pub fn vodozemac_megolm_inbound_group_session_from_pickle(
         .into()
 }
 
-fn lock_ordered<'a, T>(
+/// Re-encrypts inbound group session pickles with `new_key`, see [`pickle::rekey`].
+#[ffi]
+#[sret]
+#[throws]
+pub fn vodozemac_megolm_inbound_group_session_rekey_pickles(
+    #[expand]
+    #[copy]
+    pickles: &[u8],
+    #[expand] pickle_lengths: &[u32],
+    old_key: &[u8; 32],
+    new_key: &[u8; 32],
+) -> CResult<RekeyedPickles, CErrorStr> {
+    pickle::rekey(
+        pickles,
+        pickle_lengths,
+        old_key,
+        new_key,
+        InboundGroupSessionPickle::from_encrypted,
+        InboundGroupSessionPickle::encrypt,
+    )
+}
+
+/// Exports the inbound group session as an unencrypted JSON pickle.
+///
+/// **Dangerous**: the JSON contains the ratchet in plain text, it decrypts
+/// every message from the first known index on.
+#[cfg(feature = "dangerous-json-pickles")]
+#[ffi]
+#[sret]
+pub fn vodozemac_megolm_inbound_group_session_dangerous_json_pickle(
+    inbound_group_session: &InboundGroupSession,
+) -> CSecretSlice {
+    serde_json::to_vec(&inbound_group_session.read().pickle())
+        .expect("pickles can be serialized")
+        .into()
+}
+
+/// Imports a pickle exported by [`vodozemac_megolm_inbound_group_session_dangerous_json_pickle`].
+#[cfg(feature = "dangerous-json-pickles")]
+#[ffi]
+#[sret]
+#[throws]
+pub fn vodozemac_megolm_inbound_group_session_dangerous_from_json_pickle(
+    #[expand] json: &[u8],
+) -> CResult<NonNull<InboundGroupSession>, CErrorStr> {
+    serde_json::from_slice::<InboundGroupSessionPickle>(json)
+        .map(megolm::InboundGroupSession::from_pickle)
+        .map(RwLock::new)
+        .map(boxed)
+        .map_err(Into::into)
+        .into()
+}
+
+pub(super) fn lock_ordered<'a, T>(
     a: &'a RwLock<T>,
     b: &'a RwLock<T>,
 ) -> (RwLockWriteGuard<'a, T>, RwLockWriteGuard<'a, T>) {
@@ -3,28 +3,21 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use crate::slices::{CErrorStr, CSlice};
-use crate::{CResult, boxed, free};
-use jni::JNIEnv;
-use jni::objects::JClass;
+use crate::{CResult, NativeMethod, boxed, free};
 use macros::ffi;
 use std::ptr::NonNull;
 use vodozemac::Ed25519Signature;
 use vodozemac::megolm::MegolmMessage;
 
-pub fn register_jni(env: &mut JNIEnv, class: &JClass) -> jni::errors::Result<()> {
-    env.register_native_methods(
-        class,
-        &[
-            VODOZEMAC_MEGOLM_MESSAGE_FREE_JNI.into(),
-            VODOZEMAC_MEGOLM_MESSAGE_CIPHERTEXT_JNI.into(),
-            VODOZEMAC_MEGOLM_MESSAGE_INDEX_JNI.into(),
-            VODOZEMAC_MEGOLM_MESSAGE_MAC_JNI.into(),
-            VODOZEMAC_MEGOLM_MESSAGE_SIGNATURE_JNI.into(),
-            VODOZEMAC_MEGOLM_MESSAGE_TO_BYTES_JNI.into(),
-            VODOZEMAC_MEGOLM_MESSAGE_FROM_BYTES_JNI.into(),
-        ],
-    )
-}
+pub const JNI_METHODS: &[NativeMethod] = &[
+    VODOZEMAC_MEGOLM_MESSAGE_FREE_JNI,
+    VODOZEMAC_MEGOLM_MESSAGE_CIPHERTEXT_JNI,
+    VODOZEMAC_MEGOLM_MESSAGE_INDEX_JNI,
+    VODOZEMAC_MEGOLM_MESSAGE_MAC_JNI,
+    VODOZEMAC_MEGOLM_MESSAGE_SIGNATURE_JNI,
+    VODOZEMAC_MEGOLM_MESSAGE_TO_BYTES_JNI,
+    VODOZEMAC_MEGOLM_MESSAGE_FROM_BYTES_JNI,
+];
 
 #[ffi]
 pub fn vodozemac_megolm_message_free(message: NonNull<MegolmMessage>) {
@@ -61,6 +54,7 @@ pub fn vodozemac_megolm_message_to_bytes(message: &MegolmMessage) -> CSlice<u8>
 
 #[ffi]
 #[sret]
+#[throws]
 pub fn vodozemac_megolm_message_from_bytes(
     #[expand] bytes: &[u8],
 ) -> CResult<NonNull<MegolmMessage>, CErrorStr> {
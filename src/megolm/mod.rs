@@ -2,47 +2,49 @@
 //
 // SPDX-License-Identifier: Apache-2.0
 
-use jni::JNIEnv;
-use jni::objects::JClass;
+use crate::JniModule;
 use parking_lot::RwLock;
 use vodozemac::megolm;
 
 mod exported_session_key;
 mod group_session;
-mod inbound_group_session;
+pub(crate) mod inbound_group_session;
 mod message;
-mod session_config;
+pub mod session_config;
 mod session_key;
+pub(crate) mod tracked_inbound_group_session;
 
 pub type GroupSession = RwLock<megolm::GroupSession>;
 pub type InboundGroupSession = RwLock<megolm::InboundGroupSession>;
+pub type TrackedInboundGroupSession = RwLock<tracked_inbound_group_session::TrackedSession>;
 
-pub struct MegolmJniClasses<'local, 'a> {
-    pub group_session: &'a JClass<'local>,
-    pub inbound_group_session: &'a JClass<'local>,
-    pub message: &'a JClass<'local>,
-    pub session_config: &'a JClass<'local>,
-    pub session_key: &'a JClass<'local>,
-    pub exported_session_key: &'a JClass<'local>,
-}
-
-pub fn register_jni(
-    env: &mut JNIEnv,
-    MegolmJniClasses {
-        group_session,
-        inbound_group_session,
-        message,
-        session_config,
-        session_key,
-        exported_session_key,
-    }: &MegolmJniClasses,
-) -> jni::errors::Result<()> {
-    group_session::register_jni(env, group_session)?;
-    inbound_group_session::register_jni(env, inbound_group_session)?;
-    message::register_jni(env, message)?;
-    session_config::register_jni(env, session_config)?;
-    session_key::register_jni(env, session_key)?;
-    exported_session_key::register_jni(env, exported_session_key)?;
-
-    Ok(())
-}
+pub const JNI_MODULES: &[JniModule] = &[
+    JniModule {
+        class: "megolm/GroupSessionBindingsKt",
+        methods: group_session::JNI_METHODS,
+    },
+    JniModule {
+        class: "megolm/SessionConfigBindingsKt",
+        methods: session_config::JNI_METHODS,
+    },
+    JniModule {
+        class: "megolm/InboundGroupSessionBindingsKt",
+        methods: inbound_group_session::JNI_METHODS,
+    },
+    JniModule {
+        class: "megolm/TrackedInboundGroupSessionBindingsKt",
+        methods: tracked_inbound_group_session::JNI_METHODS,
+    },
+    JniModule {
+        class: "megolm/MessageBindingsKt",
+        methods: message::JNI_METHODS,
+    },
+    JniModule {
+        class: "megolm/SessionKeyBindingsKt",
+        methods: session_key::JNI_METHODS,
+    },
+    JniModule {
+        class: "megolm/ExportedSessionKeyBindingsKt",
+        methods: exported_session_key::JNI_METHODS,
+    },
+];
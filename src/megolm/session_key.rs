@@ -2,24 +2,17 @@
 //
 // SPDX-License-Identifier: Apache-2.0
 
-use crate::slices::{CErrorStr, CSlice};
-use crate::{CResult, boxed, free};
-use jni::JNIEnv;
-use jni::objects::JClass;
+use crate::slices::{CErrorStr, CSecretSlice};
+use crate::{CResult, NativeMethod, boxed, free};
 use macros::ffi;
 use std::ptr::NonNull;
 use vodozemac::megolm::SessionKey;
 
-pub fn register_jni(env: &mut JNIEnv, class: &JClass) -> jni::errors::Result<()> {
-    env.register_native_methods(
-        class,
-        &[
-            VODOZEMAC_MEGOLM_SESSION_KEY_FREE_JNI.into(),
-            VODOZEMAC_MEGOLM_SESSION_KEY_TO_BYTES_JNI.into(),
-            VODOZEMAC_MEGOLM_SESSION_KEY_FROM_BYTES_JNI.into(),
-        ],
-    )
-}
+pub const JNI_METHODS: &[NativeMethod] = &[
+    VODOZEMAC_MEGOLM_SESSION_KEY_FREE_JNI,
+    VODOZEMAC_MEGOLM_SESSION_KEY_TO_BYTES_JNI,
+    VODOZEMAC_MEGOLM_SESSION_KEY_FROM_BYTES_JNI,
+];
 
 #[ffi]
 pub fn vodozemac_megolm_session_key_free(session_key: NonNull<SessionKey>) {
@@ -28,12 +21,13 @@ pub fn vodozemac_megolm_session_key_free(session_key: NonNull<SessionKey>) {
 
 #[ffi]
 #[sret]
-pub fn vodozemac_megolm_session_key_to_bytes(session_key: &SessionKey) -> CSlice<u8> {
+pub fn vodozemac_megolm_session_key_to_bytes(session_key: &SessionKey) -> CSecretSlice {
     session_key.to_bytes().into()
 }
 
 #[ffi]
 #[sret]
+#[throws]
 pub fn vodozemac_megolm_session_key_from_bytes(
     #[expand] bytes: &[u8],
 ) -> CResult<NonNull<SessionKey>, CErrorStr> {
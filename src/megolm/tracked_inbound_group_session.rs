@@ -0,0 +1,400 @@
+// SPDX-FileCopyrightText: 2025 Felix Hilgers <contact@fhilgers.com>
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! An inbound group session together with what is known about where it came
+//! from.
+//!
+//! Clients decide how much to trust a decrypted message based on how its
+//! session arrived: directly from the sending device in an `m.room_key`, or
+//! forwarded, restored from a backup or imported from a file. The plain
+//! [`InboundGroupSession`] pickle loses this, the pickle of a
+//! [`TrackedInboundGroupSession`] keeps it next to the ratchet.
+
+use crate::megolm::inbound_group_session::{DecryptedMessage, lock_ordered, ordering_code};
+use crate::megolm::{InboundGroupSession, TrackedInboundGroupSession};
+use crate::pickle::{self, RekeyedPickles};
+use crate::slices::{CErrorStr, CSecretSlice, CSlice};
+use crate::{AsUsize, CResult, Chain, ChainExact, NativeMethod, boxed, free};
+use macros::ffi;
+use parking_lot::RwLock;
+use serde::de::Error as _;
+use serde::{Deserialize, Deserializer, Serialize};
+use std::ptr::NonNull;
+use std::{array, str};
+use vodozemac::megolm::{self, InboundGroupSessionPickle, MegolmMessage};
+use vodozemac::{Curve25519PublicKey, Ed25519PublicKey};
+
+pub const JNI_METHODS: &[NativeMethod] = &[
+    VODOZEMAC_MEGOLM_TRACKED_INBOUND_GROUP_SESSION_NEW_JNI,
+    VODOZEMAC_MEGOLM_TRACKED_INBOUND_GROUP_SESSION_FREE_JNI,
+    VODOZEMAC_MEGOLM_TRACKED_INBOUND_GROUP_SESSION_SESSION_JNI,
+    VODOZEMAC_MEGOLM_TRACKED_INBOUND_GROUP_SESSION_SENDER_KEY_JNI,
+    VODOZEMAC_MEGOLM_TRACKED_INBOUND_GROUP_SESSION_CLAIMED_ED25519_KEY_JNI,
+    VODOZEMAC_MEGOLM_TRACKED_INBOUND_GROUP_SESSION_FORWARDING_CHAIN_JNI,
+    VODOZEMAC_MEGOLM_TRACKED_INBOUND_GROUP_SESSION_ROOM_ID_JNI,
+    VODOZEMAC_MEGOLM_TRACKED_INBOUND_GROUP_SESSION_IMPORTED_JNI,
+    VODOZEMAC_MEGOLM_TRACKED_INBOUND_GROUP_SESSION_DECRYPT_JNI,
+    VODOZEMAC_MEGOLM_TRACKED_INBOUND_GROUP_SESSION_DECRYPT_AND_AUTHENTICATE_JNI,
+    VODOZEMAC_MEGOLM_TRACKED_INBOUND_GROUP_SESSION_COMPARE_JNI,
+    VODOZEMAC_MEGOLM_TRACKED_INBOUND_GROUP_SESSION_MERGE_JNI,
+    VODOZEMAC_MEGOLM_TRACKED_INBOUND_GROUP_SESSION_PICKLE_JNI,
+    VODOZEMAC_MEGOLM_TRACKED_INBOUND_GROUP_SESSION_FROM_PICKLE_JNI,
+    VODOZEMAC_MEGOLM_TRACKED_INBOUND_GROUP_SESSION_REKEY_PICKLES_JNI,
+];
+
+/// Values of [`AuthenticatedMessage::status`].
+pub const AUTHENTICATION_VERIFIED: u32 = 0;
+pub const AUTHENTICATION_UNKNOWN_DEVICE: u32 = 1;
+pub const AUTHENTICATION_MISMATCHED: u32 = 2;
+
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+pub struct SessionMetadata {
+    /// The Curve25519 key of the device that created the session.
+    sender_key: Curve25519PublicKey,
+    /// The Ed25519 key the creating device claimed in the room key event.
+    claimed_ed25519_key: Ed25519PublicKey,
+    /// The Curve25519 keys of the devices which forwarded the session, empty
+    /// if it was received from the creating device.
+    forwarding_chain: Vec<Curve25519PublicKey>,
+    room_id: String,
+    /// Whether the session came from a backup or a key export file.
+    imported: bool,
+}
+
+impl SessionMetadata {
+    /// Sessions received directly are preferred over imported ones, shorter
+    /// forwarding chains over longer ones.
+    fn is_more_trusted_than(&self, other: &SessionMetadata) -> bool {
+        match (self.imported, other.imported) {
+            (false, true) => true,
+            (true, false) => false,
+            _ => self.forwarding_chain.len() < other.forwarding_chain.len(),
+        }
+    }
+}
+
+pub struct TrackedSession {
+    session: megolm::InboundGroupSession,
+    metadata: SessionMetadata,
+}
+
+/// Incremented whenever [`TrackedSessionPickle`] changes, pickles of other
+/// versions fail to deserialize.
+pub const PICKLE_VERSION: u32 = 1;
+
+fn pickle_version<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u32, D::Error> {
+    let version = u32::deserialize(deserializer)?;
+    if version != PICKLE_VERSION {
+        return Err(D::Error::custom(format!(
+            "unsupported tracked session pickle version {version}"
+        )));
+    }
+    Ok(version)
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct TrackedSessionPickle {
+    #[serde(deserialize_with = "pickle_version")]
+    version: u32,
+    session: InboundGroupSessionPickle,
+    metadata: SessionMetadata,
+}
+
+impl From<&TrackedSession> for TrackedSessionPickle {
+    fn from(value: &TrackedSession) -> Self {
+        Self {
+            version: PICKLE_VERSION,
+            session: value.session.pickle(),
+            metadata: value.metadata.clone(),
+        }
+    }
+}
+
+impl From<TrackedSessionPickle> for TrackedSession {
+    fn from(value: TrackedSessionPickle) -> Self {
+        Self {
+            session: megolm::InboundGroupSession::from_pickle(value.session),
+            metadata: value.metadata,
+        }
+    }
+}
+
+/// Copies `session` and attaches the given origin to it.
+///
+/// `imported` is non-zero for sessions restored from a backup or a key export
+/// file, `forwarding_chain` is empty for sessions received in an `m.room_key`
+/// from the creating device.
+#[ffi]
+#[sret]
+#[throws]
+pub fn vodozemac_megolm_tracked_inbound_group_session_new(
+    session: &InboundGroupSession,
+    sender_key: &Curve25519PublicKey,
+    claimed_ed25519_key: &Ed25519PublicKey,
+    #[expand] forwarding_chain: &[&Curve25519PublicKey],
+    #[expand] room_id: &[u8],
+    imported: u32,
+) -> CResult<NonNull<TrackedInboundGroupSession>, CErrorStr> {
+    let room_id = match str::from_utf8(room_id) {
+        Ok(room_id) => room_id,
+        Err(error) => return CResult::Err(error.into()),
+    };
+
+    CResult::Ok(boxed(RwLock::new(TrackedSession {
+        session: megolm::InboundGroupSession::from_pickle(session.read().pickle()),
+        metadata: SessionMetadata {
+            sender_key: *sender_key,
+            claimed_ed25519_key: *claimed_ed25519_key,
+            forwarding_chain: forwarding_chain.iter().map(|&&key| key).collect(),
+            room_id: room_id.to_owned(),
+            imported: imported != 0,
+        },
+    })))
+}
+
+#[ffi]
+pub fn vodozemac_megolm_tracked_inbound_group_session_free(
+    tracked: NonNull<TrackedInboundGroupSession>,
+) {
+    free(tracked)
+}
+
+/// A copy of the wrapped session, changes to it are not reflected in
+/// `tracked`.
+#[ffi]
+pub fn vodozemac_megolm_tracked_inbound_group_session_session(
+    tracked: &TrackedInboundGroupSession,
+) -> NonNull<InboundGroupSession> {
+    let session = megolm::InboundGroupSession::from_pickle(tracked.read().session.pickle());
+
+    boxed(RwLock::new(session))
+}
+
+#[ffi]
+pub fn vodozemac_megolm_tracked_inbound_group_session_sender_key(
+    tracked: &TrackedInboundGroupSession,
+) -> NonNull<Curve25519PublicKey> {
+    boxed(tracked.read().metadata.sender_key)
+}
+
+#[ffi]
+pub fn vodozemac_megolm_tracked_inbound_group_session_claimed_ed25519_key(
+    tracked: &TrackedInboundGroupSession,
+) -> NonNull<Ed25519PublicKey> {
+    boxed(tracked.read().metadata.claimed_ed25519_key)
+}
+
+#[ffi]
+#[sret]
+pub fn vodozemac_megolm_tracked_inbound_group_session_forwarding_chain(
+    tracked: &TrackedInboundGroupSession,
+) -> CSlice<NonNull<Curve25519PublicKey>> {
+    tracked
+        .read()
+        .metadata
+        .forwarding_chain
+        .iter()
+        .copied()
+        .map(boxed)
+        .collect::<Vec<_>>()
+        .into()
+}
+
+#[ffi]
+#[sret]
+pub fn vodozemac_megolm_tracked_inbound_group_session_room_id(
+    tracked: &TrackedInboundGroupSession,
+) -> CSlice<u8> {
+    tracked.read().metadata.room_id.clone().into()
+}
+
+#[ffi]
+pub fn vodozemac_megolm_tracked_inbound_group_session_imported(
+    tracked: &TrackedInboundGroupSession,
+) -> u32 {
+    tracked.read().metadata.imported.into()
+}
+
+#[ffi]
+#[sret]
+#[throws]
+pub fn vodozemac_megolm_tracked_inbound_group_session_decrypt(
+    tracked: &TrackedInboundGroupSession,
+    message: &MegolmMessage,
+) -> CResult<DecryptedMessage, CErrorStr> {
+    tracked
+        .write()
+        .session
+        .decrypt(message)
+        .map(Into::into)
+        .map_err(Into::into)
+        .into()
+}
+
+crate::define_and_assert_ptr_sized! {
+    /// A message decrypted by
+    /// [`vodozemac_megolm_tracked_inbound_group_session_decrypt_and_authenticate`],
+    /// `status` is one of the `AUTHENTICATION_*` constants.
+    pub struct AuthenticatedMessage {
+        plaintext: CSecretSlice,
+        message_index: usize,
+        status: usize,
+    }
+}
+
+impl AsUsize for AuthenticatedMessage {
+    const WORDS: usize = CSecretSlice::WORDS + 2;
+
+    type IntoIter = Chain<<CSecretSlice as AsUsize>::IntoIter, array::IntoIter<usize, 2>>;
+
+    fn as_usize(&self) -> Self::IntoIter {
+        self.plaintext
+            .as_usize()
+            .chain_exact([self.message_index, self.status].into_iter())
+    }
+}
+
+/// Decrypts `message` and checks that the session was claimed by the device
+/// the client believes sent it.
+///
+/// `device_key` is the Ed25519 key the client knows for the sending device,
+/// null if the device is not known. It is compared with the claimed Ed25519
+/// key of the room key, the message signature itself is checked against the
+/// signing key of the session and a failure there is thrown like for
+/// [`vodozemac_megolm_tracked_inbound_group_session_decrypt`]. Forwarded and
+/// imported sessions only carry the key their forwarder claimed, check
+/// [`vodozemac_megolm_tracked_inbound_group_session_forwarding_chain`] and
+/// [`vodozemac_megolm_tracked_inbound_group_session_imported`] as well.
+#[ffi]
+#[sret]
+#[throws]
+pub fn vodozemac_megolm_tracked_inbound_group_session_decrypt_and_authenticate(
+    tracked: &TrackedInboundGroupSession,
+    message: &MegolmMessage,
+    device_key: Option<&Ed25519PublicKey>,
+) -> CResult<AuthenticatedMessage, CErrorStr> {
+    let mut tracked = tracked.write();
+
+    let status = match device_key {
+        None => AUTHENTICATION_UNKNOWN_DEVICE,
+        Some(key) if *key == tracked.metadata.claimed_ed25519_key => AUTHENTICATION_VERIFIED,
+        Some(_) => AUTHENTICATION_MISMATCHED,
+    };
+
+    tracked
+        .session
+        .decrypt(message)
+        .map(|decrypted| AuthenticatedMessage {
+            plaintext: decrypted.plaintext.into(),
+            message_index: decrypted.message_index as usize,
+            status: status as usize,
+        })
+        .map_err(Into::into)
+        .into()
+}
+
+/// Compares the ratchets like
+/// [`vodozemac_megolm_inbound_group_session_compare`](crate::megolm::inbound_group_session::vodozemac_megolm_inbound_group_session_compare),
+/// the metadata is not taken into account.
+#[ffi]
+pub fn vodozemac_megolm_tracked_inbound_group_session_compare(
+    tracked: &TrackedInboundGroupSession,
+    other: &TrackedInboundGroupSession,
+) -> u32 {
+    if (&raw const *tracked) == (&raw const *other) {
+        return 0; // Equal
+    }
+
+    let (mut this, mut other) = lock_ordered(tracked, other);
+
+    ordering_code(this.session.compare(&mut other.session))
+}
+
+/// Merges the ratchets of two copies of the same session.
+///
+/// The result keeps the metadata of the more trusted copy: one received
+/// directly is preferred over an imported one, a shorter forwarding chain over
+/// a longer one. Null if the ratchets are not connected or the copies disagree
+/// on the sender key, the claimed Ed25519 key or the room.
+#[ffi]
+pub fn vodozemac_megolm_tracked_inbound_group_session_merge(
+    tracked: &TrackedInboundGroupSession,
+    other: &TrackedInboundGroupSession,
+) -> Option<NonNull<TrackedInboundGroupSession>> {
+    if (&raw const *tracked) == (&raw const *other) {
+        let pickle = TrackedSessionPickle::from(&*tracked.read());
+        return Some(boxed(RwLock::new(pickle.into())));
+    }
+
+    let (mut this, mut other) = lock_ordered(tracked, other);
+
+    if this.metadata.sender_key != other.metadata.sender_key
+        || this.metadata.claimed_ed25519_key != other.metadata.claimed_ed25519_key
+        || this.metadata.room_id != other.metadata.room_id
+    {
+        return None;
+    }
+
+    let metadata = if other.metadata.is_more_trusted_than(&this.metadata) {
+        other.metadata.clone()
+    } else {
+        this.metadata.clone()
+    };
+
+    this.session
+        .merge(&mut other.session)
+        .map(|session| TrackedSession { session, metadata })
+        .map(RwLock::new)
+        .map(boxed)
+}
+
+#[ffi]
+#[sret]
+pub fn vodozemac_megolm_tracked_inbound_group_session_pickle(
+    tracked: &TrackedInboundGroupSession,
+    pickle_key: &[u8; 32],
+) -> CSlice<u8> {
+    pickle::encrypt(&TrackedSessionPickle::from(&*tracked.read()), pickle_key).into()
+}
+
+#[ffi]
+#[sret]
+#[throws]
+pub fn vodozemac_megolm_tracked_inbound_group_session_from_pickle(
+    #[expand] ciphertext: &[u8],
+    pickle_key: &[u8; 32],
+) -> CResult<NonNull<TrackedInboundGroupSession>, CErrorStr> {
+    // Pickles are base64, anything else fails to decode.
+    let ciphertext = String::from_utf8_lossy(ciphertext);
+
+    pickle::decrypt::<TrackedSessionPickle>(&ciphertext, pickle_key)
+        .map(TrackedSession::from)
+        .map(RwLock::new)
+        .map(boxed)
+        .map_err(Into::into)
+        .into()
+}
+
+/// Re-encrypts tracked inbound group session pickles with `new_key`, see
+/// [`pickle::rekey`].
+#[ffi]
+#[sret]
+#[throws]
+pub fn vodozemac_megolm_tracked_inbound_group_session_rekey_pickles(
+    #[expand]
+    #[copy]
+    pickles: &[u8],
+    #[expand] pickle_lengths: &[u32],
+    old_key: &[u8; 32],
+    new_key: &[u8; 32],
+) -> CResult<RekeyedPickles, CErrorStr> {
+    pickle::rekey(
+        pickles,
+        pickle_lengths,
+        old_key,
+        new_key,
+        pickle::decrypt::<TrackedSessionPickle>,
+        |pickle, key| pickle::encrypt(&pickle, key),
+    )
+}
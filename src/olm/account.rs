@@ -3,10 +3,9 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use crate::olm::{Account, Session};
-use crate::slices::{CErrorStr, CSlice};
-use crate::{AsUsize, CResult, Chain, ChainExact, boxed, free};
-use jni::JNIEnv;
-use jni::objects::JClass;
+use crate::pickle::{self, RekeyedPickles};
+use crate::slices::{CErrorStr, CSecretSlice, CSlice};
+use crate::{AsUsize, CResult, Chain, ChainExact, NativeMethod, boxed, free};
 use macros::ffi;
 use parking_lot::RwLock;
 use std::ptr::NonNull;
@@ -14,56 +13,60 @@ use std::{array, str};
 use vodozemac::olm::{AccountPickle, Message, PreKeyMessage, SessionConfig, SessionKeys};
 use vodozemac::{Curve25519PublicKey, Ed25519PublicKey, Ed25519Signature, olm};
 
-pub fn register_jni(env: &mut JNIEnv, class: &JClass) -> jni::errors::Result<()> {
-    env.register_native_methods(
-        class,
-        &[
-            VODOZEMAC_OLM_ACCOUNT_NEW_JNI.into(),
-            VODOZEMAC_OLM_ACCOUNT_FREE_JNI.into(),
-            VODOZEMAC_OLM_ACCOUNT_IDENTITY_KEYS_JNI.into(),
-            VODOZEMAC_OLM_ACCOUNT_ED25519_KEY_JNI.into(),
-            VODOZEMAC_OLM_ACCOUNT_CURVE25519_KEY_JNI.into(),
-            VODOZEMAC_OLM_ACCOUNT_SIGN_JNI.into(),
-            VODOZEMAC_OLM_ACCOUNT_MAX_NUMBER_OF_ONE_TIME_KEYS_JNI.into(),
-            VODOZEMAC_OLM_ACCOUNT_CREATE_OUTBOUND_SESSION_JNI.into(),
-            VODOZEMAC_OLM_ACCOUNT_CREATE_INBOUND_SESSION_JNI.into(),
-            VODOZEMAC_OLM_ACCOUNT_GENERATE_ONE_TIME_KEYS_JNI.into(),
-            VODOZEMAC_OLM_ACCOUNT_STORED_ONE_TIME_KEY_COUNT_JNI.into(),
-            VODOZEMAC_OLM_ACCOUNT_ONE_TIME_KEYS_JNI.into(),
-            VODOZEMAC_OLM_ACCOUNT_GENERATE_FALLBACK_KEY_JNI.into(),
-            VODOZEMAC_OLM_ACCOUNT_FALLBACK_KEY_JNI.into(),
-            VODOZEMAC_OLM_ACCOUNT_FORGET_FALLBACK_KEY_JNI.into(),
-            VODOZEMAC_OLM_ACCOUNT_MARK_KEYS_AS_PUBLISHED_JNI.into(),
-            VODOZEMAC_OLM_ACCOUNT_PICKLE_JNI.into(),
-            VODOZEMAC_OLM_ACCOUNT_FROM_PICKLE_JNI.into(),
-            VODOZEMAC_OLM_ACCOUNT_TO_DEHYDRATED_DEVICE_JNI.into(),
-            VODOZEMAC_OLM_ACCOUNT_FROM_DEHYDRATED_DEVICE_JNI.into(),
-        ],
-    )
-}
-
-#[repr(C)]
-pub struct IdentityKeys {
-    ed25519: NonNull<Ed25519PublicKey>,
-    curve25519: NonNull<Curve25519PublicKey>,
+pub const JNI_METHODS: &[NativeMethod] = &[
+    VODOZEMAC_OLM_ACCOUNT_NEW_JNI,
+    VODOZEMAC_OLM_ACCOUNT_FREE_JNI,
+    VODOZEMAC_OLM_ACCOUNT_IDENTITY_KEYS_JNI,
+    VODOZEMAC_OLM_ACCOUNT_ED25519_KEY_JNI,
+    VODOZEMAC_OLM_ACCOUNT_CURVE25519_KEY_JNI,
+    VODOZEMAC_OLM_ACCOUNT_SIGN_JNI,
+    VODOZEMAC_OLM_ACCOUNT_MAX_NUMBER_OF_ONE_TIME_KEYS_JNI,
+    VODOZEMAC_OLM_ACCOUNT_CREATE_OUTBOUND_SESSION_JNI,
+    VODOZEMAC_OLM_ACCOUNT_CREATE_INBOUND_SESSION_JNI,
+    VODOZEMAC_OLM_ACCOUNT_GENERATE_ONE_TIME_KEYS_JNI,
+    VODOZEMAC_OLM_ACCOUNT_STORED_ONE_TIME_KEY_COUNT_JNI,
+    VODOZEMAC_OLM_ACCOUNT_ONE_TIME_KEYS_JNI,
+    VODOZEMAC_OLM_ACCOUNT_GENERATE_FALLBACK_KEY_JNI,
+    VODOZEMAC_OLM_ACCOUNT_FALLBACK_KEY_JNI,
+    VODOZEMAC_OLM_ACCOUNT_FORGET_FALLBACK_KEY_JNI,
+    VODOZEMAC_OLM_ACCOUNT_MARK_KEYS_AS_PUBLISHED_JNI,
+    VODOZEMAC_OLM_ACCOUNT_PICKLE_JNI,
+    VODOZEMAC_OLM_ACCOUNT_FROM_PICKLE_JNI,
+    VODOZEMAC_OLM_ACCOUNT_REKEY_PICKLES_JNI,
+    #[cfg(feature = "dangerous-json-pickles")]
+    VODOZEMAC_OLM_ACCOUNT_DANGEROUS_JSON_PICKLE_JNI,
+    #[cfg(feature = "dangerous-json-pickles")]
+    VODOZEMAC_OLM_ACCOUNT_DANGEROUS_FROM_JSON_PICKLE_JNI,
+    VODOZEMAC_OLM_ACCOUNT_TO_DEHYDRATED_DEVICE_JNI,
+    VODOZEMAC_OLM_ACCOUNT_FROM_DEHYDRATED_DEVICE_JNI,
+];
+
+crate::define_and_assert_ptr_sized! {
+    pub struct IdentityKeys {
+        ed25519: NonNull<Ed25519PublicKey>,
+        curve25519: NonNull<Curve25519PublicKey>,
+    }
 }
 
-#[repr(C)]
-pub struct InboundCreationResult {
-    plaintext: CSlice<u8>,
-    session: NonNull<Session>,
+crate::define_and_assert_ptr_sized! {
+    pub struct InboundCreationResult {
+        plaintext: CSecretSlice,
+        session: NonNull<Session>,
+    }
 }
 
-#[repr(C)]
-pub struct OneTimeKeyGenerationResult {
-    created: CSlice<NonNull<Curve25519PublicKey>>,
-    removed: CSlice<NonNull<Curve25519PublicKey>>,
+crate::define_and_assert_ptr_sized! {
+    pub struct OneTimeKeyGenerationResult {
+        created: CSlice<NonNull<Curve25519PublicKey>>,
+        removed: CSlice<NonNull<Curve25519PublicKey>>,
+    }
 }
 
-#[repr(C)]
-pub struct DehydratedDeviceResult {
-    ciphertext: CSlice<u8>,
-    nonce: CSlice<u8>,
+crate::define_and_assert_ptr_sized! {
+    pub struct DehydratedDeviceResult {
+        ciphertext: CSlice<u8>,
+        nonce: CSlice<u8>,
+    }
 }
 
 impl From<olm::IdentityKeys> for IdentityKeys {
@@ -98,6 +101,8 @@ impl From<olm::OneTimeKeyGenerationResult> for OneTimeKeyGenerationResult {
 }
 
 impl AsUsize for IdentityKeys {
+    const WORDS: usize = 2;
+
     type IntoIter = Chain<array::IntoIter<usize, 1>, array::IntoIter<usize, 1>>;
 
     fn as_usize(&self) -> Self::IntoIter {
@@ -108,7 +113,9 @@ impl AsUsize for IdentityKeys {
 }
 
 impl AsUsize for InboundCreationResult {
-    type IntoIter = Chain<<CSlice<u8> as AsUsize>::IntoIter, array::IntoIter<usize, 1>>;
+    const WORDS: usize = CSecretSlice::WORDS + 1;
+
+    type IntoIter = Chain<<CSecretSlice as AsUsize>::IntoIter, array::IntoIter<usize, 1>>;
 
     fn as_usize(&self) -> Self::IntoIter {
         self.plaintext
@@ -118,6 +125,8 @@ impl AsUsize for InboundCreationResult {
 }
 
 impl AsUsize for OneTimeKeyGenerationResult {
+    const WORDS: usize = 2 * CSlice::<u8>::WORDS;
+
     type IntoIter = Chain<array::IntoIter<usize, 2>, array::IntoIter<usize, 2>>;
 
     fn as_usize(&self) -> Self::IntoIter {
@@ -126,6 +135,8 @@ impl AsUsize for OneTimeKeyGenerationResult {
 }
 
 impl AsUsize for DehydratedDeviceResult {
+    const WORDS: usize = 2 * CSlice::<u8>::WORDS;
+
     type IntoIter = Chain<array::IntoIter<usize, 2>, array::IntoIter<usize, 2>>;
 
     fn as_usize(&self) -> Self::IntoIter {
@@ -191,6 +202,7 @@ pub fn vodozemac_olm_account_create_outbound_session(
 
 #[ffi]
 #[sret]
+#[throws]
 pub fn vodozemac_olm_account_create_inbound_session(
     account: &Account,
     their_identity_key: &Curve25519PublicKey,
@@ -275,6 +287,7 @@ pub fn vodozemac_olm_account_pickle(account: &Account, pickle_key: &[u8; 32]) ->
 
 #[ffi]
 #[sret]
+#[throws]
 pub fn vodozemac_olm_account_from_pickle(
     #[expand] ciphertext: &[u8],
     pickle_key: &[u8; 32],
@@ -289,8 +302,61 @@ pub fn vodozemac_olm_account_from_pickle(
         .into()
 }
 
+/// Re-encrypts account pickles with `new_key`, see [`pickle::rekey`].
+#[ffi]
+#[sret]
+#[throws]
+pub fn vodozemac_olm_account_rekey_pickles(
+    #[expand]
+    #[copy]
+    pickles: &[u8],
+    #[expand] pickle_lengths: &[u32],
+    old_key: &[u8; 32],
+    new_key: &[u8; 32],
+) -> CResult<RekeyedPickles, CErrorStr> {
+    pickle::rekey(
+        pickles,
+        pickle_lengths,
+        old_key,
+        new_key,
+        AccountPickle::from_encrypted,
+        AccountPickle::encrypt,
+    )
+}
+
+/// Exports the account as an unencrypted JSON pickle.
+///
+/// **Dangerous**: the JSON contains the private identity and one-time keys in
+/// plain text. Only meant for debugging and migration tooling, the function is
+/// not part of default builds.
+#[cfg(feature = "dangerous-json-pickles")]
+#[ffi]
+#[sret]
+pub fn vodozemac_olm_account_dangerous_json_pickle(account: &Account) -> CSecretSlice {
+    serde_json::to_vec(&account.read().pickle())
+        .expect("pickles can be serialized")
+        .into()
+}
+
+/// Imports a pickle exported by [`vodozemac_olm_account_dangerous_json_pickle`].
+#[cfg(feature = "dangerous-json-pickles")]
+#[ffi]
+#[sret]
+#[throws]
+pub fn vodozemac_olm_account_dangerous_from_json_pickle(
+    #[expand] json: &[u8],
+) -> CResult<NonNull<Account>, CErrorStr> {
+    serde_json::from_slice::<AccountPickle>(json)
+        .map(olm::Account::from_pickle)
+        .map(RwLock::new)
+        .map(boxed)
+        .map_err(Into::into)
+        .into()
+}
+
 #[ffi]
 #[sret]
+#[throws]
 pub fn vodozemac_olm_account_to_dehydrated_device(
     account: &Account,
     key: &[u8; 32],
@@ -308,6 +374,7 @@ pub fn vodozemac_olm_account_to_dehydrated_device(
 
 #[ffi]
 #[sret]
+#[throws]
 pub fn vodozemac_olm_account_from_dehydrated_device(
     #[expand] ciphertext: &[u8],
     #[expand] nonce: &[u8],
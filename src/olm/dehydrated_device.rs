@@ -0,0 +1,397 @@
+// SPDX-FileCopyrightText: 2025 Felix Hilgers <contact@fhilgers.com>
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! The [MSC3814] dehydrated device flow around
+//! [`olm::Account::to_dehydrated_device`].
+//!
+//! A fresh account is uploaded with `PUT /dehydrated_device`, the body is
+//! built by [`vodozemac_olm_account_dehydrated_device_request`]. Once another
+//! device of the user rehydrates it, the to-device events which were sent to
+//! the dehydrated device are decrypted by
+//! [`vodozemac_olm_account_process_dehydrated_events`].
+//!
+//! [MSC3814]: https://github.com/matrix-org/matrix-spec-proposals/pull/3814
+
+use crate::canonical_json::canonical_json;
+use crate::megolm::session_config::ALGORITHM_V1 as MEGOLM_ALGORITHM;
+use crate::olm::Account;
+use crate::olm::session_config::ALGORITHM_V1 as OLM_ALGORITHM;
+use crate::slices::{CErrorStr, CSecretSlice, CSlice};
+use crate::{CResult, NativeMethod, boxed};
+use macros::ffi;
+use parking_lot::RwLock;
+use serde::Deserialize;
+use serde_json::{Map, Value, json};
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::ptr::NonNull;
+use std::str;
+use vodozemac::megolm::{InboundGroupSession, SessionConfig, SessionKey};
+use vodozemac::olm::{OlmMessage, Session};
+use vodozemac::{Curve25519PublicKey, olm};
+use zeroize::Zeroizing;
+
+pub const JNI_METHODS: &[NativeMethod] = &[
+    VODOZEMAC_OLM_ACCOUNT_NEW_DEHYDRATED_JNI,
+    VODOZEMAC_OLM_ACCOUNT_DEHYDRATED_DEVICE_REQUEST_JNI,
+    VODOZEMAC_OLM_ACCOUNT_FROM_DEHYDRATED_DEVICE_DATA_JNI,
+    VODOZEMAC_OLM_ACCOUNT_PROCESS_DEHYDRATED_EVENTS_JNI,
+];
+
+/// The algorithm of the `device_data` envelope, a vodozemac dehydrated device
+/// in `device_pickle` together with its `nonce`.
+pub const DEHYDRATION_ALGORITHM: &str = "org.matrix.msc3814.v2";
+/// The earlier envelope which only holds a libolm account pickle in
+/// `device_pickle`, still accepted when rehydrating.
+pub const DEHYDRATION_ALGORITHM_V1: &str = "org.matrix.msc3814.v1.olm";
+
+#[derive(Debug)]
+pub enum DeviceDataError {
+    Json(serde_json::Error),
+    UnsupportedAlgorithm(String),
+    MissingNonce,
+}
+
+impl Display for DeviceDataError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DeviceDataError::Json(error) => write!(f, "invalid device data: {error}"),
+            DeviceDataError::UnsupportedAlgorithm(algorithm) => {
+                write!(f, "unsupported dehydrated device algorithm {algorithm}")
+            }
+            DeviceDataError::MissingNonce => write!(f, "the device data has no nonce"),
+        }
+    }
+}
+
+impl Error for DeviceDataError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            DeviceDataError::Json(error) => Some(error),
+            DeviceDataError::UnsupportedAlgorithm(_) | DeviceDataError::MissingNonce => None,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct DeviceData {
+    algorithm: String,
+    device_pickle: String,
+    nonce: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ToDeviceEvent {
+    sender: String,
+    content: EncryptedContent,
+}
+
+#[derive(Deserialize)]
+struct EncryptedContent {
+    algorithm: String,
+    sender_key: String,
+    ciphertext: HashMap<String, OlmMessage>,
+}
+
+#[derive(Deserialize)]
+struct Ed25519Keys {
+    ed25519: String,
+}
+
+#[derive(Deserialize)]
+struct DecryptedEvent {
+    #[serde(rename = "type")]
+    event_type: String,
+    sender: String,
+    recipient: String,
+    recipient_keys: Ed25519Keys,
+    keys: Ed25519Keys,
+    content: Value,
+}
+
+#[derive(Deserialize)]
+struct RoomKeyContent {
+    algorithm: String,
+    room_id: String,
+    session_id: String,
+    session_key: String,
+}
+
+fn sign(account: &olm::Account, object: &mut Value, user_id: &str, device_id: &str) {
+    let signature = account.sign(canonical_json(object));
+    object["signatures"] = json!({
+        user_id: { format!("ed25519:{device_id}"): signature.to_base64() }
+    });
+}
+
+pub(super) fn signed_keys(
+    account: &olm::Account,
+    keys: HashMap<vodozemac::KeyId, Curve25519PublicKey>,
+    fallback: bool,
+    user_id: &str,
+    device_id: &str,
+) -> Map<String, Value> {
+    keys.into_iter()
+        .map(|(key_id, key)| {
+            let mut object = json!({ "key": key.to_base64() });
+            if fallback {
+                object["fallback"] = Value::Bool(true);
+            }
+            sign(account, &mut object, user_id, device_id);
+            (format!("signed_curve25519:{}", key_id.to_base64()), object)
+        })
+        .collect()
+}
+
+/// Creates an account to be dehydrated, with `one_time_keys` one-time keys
+/// and a fallback key.
+#[ffi]
+pub fn vodozemac_olm_account_new_dehydrated(one_time_keys: u32) -> NonNull<Account> {
+    let mut account = olm::Account::new();
+    let count = account
+        .max_number_of_one_time_keys()
+        .min(one_time_keys as usize);
+    account.generate_one_time_keys(count);
+    account.generate_fallback_key();
+
+    boxed(RwLock::new(account))
+}
+
+/// Builds the JSON body of `PUT /dehydrated_device`: the encrypted
+/// `device_data`, the signed device keys and the signed one-time and fallback
+/// keys. The keys are marked as published.
+///
+/// MSC3814 clients use the base64 encoded Curve25519 key as the `device_id`.
+#[ffi]
+#[sret]
+#[throws]
+pub fn vodozemac_olm_account_dehydrated_device_request(
+    account: &Account,
+    #[expand] user_id: &[u8],
+    #[expand] device_id: &[u8],
+    #[expand] display_name: &[u8],
+    pickle_key: &[u8; 32],
+) -> CResult<CSlice<u8>, CErrorStr> {
+    dehydrated_device_request(account, user_id, device_id, display_name, pickle_key)
+        .map(Into::into)
+        .into()
+}
+
+fn dehydrated_device_request(
+    account: &Account,
+    user_id: &[u8],
+    device_id: &[u8],
+    display_name: &[u8],
+    pickle_key: &[u8; 32],
+) -> Result<String, CErrorStr> {
+    let user_id = str::from_utf8(user_id)?;
+    let device_id = str::from_utf8(device_id)?;
+    let display_name = str::from_utf8(display_name)?;
+
+    let mut account = account.write();
+    let identity_keys = account.identity_keys();
+
+    let mut device_keys = json!({
+        "algorithms": [OLM_ALGORITHM, MEGOLM_ALGORITHM],
+        "dehydrated": true,
+        "device_id": device_id,
+        "keys": {
+            format!("curve25519:{device_id}"): identity_keys.curve25519.to_base64(),
+            format!("ed25519:{device_id}"): identity_keys.ed25519.to_base64(),
+        },
+        "user_id": user_id,
+    });
+    sign(&account, &mut device_keys, user_id, device_id);
+
+    let one_time_keys = signed_keys(&account, account.one_time_keys(), false, user_id, device_id);
+    let fallback_keys = signed_keys(&account, account.fallback_key(), true, user_id, device_id);
+
+    account.mark_keys_as_published();
+
+    let dehydrated = account.to_dehydrated_device(pickle_key)?;
+
+    let body = json!({
+        "device_id": device_id,
+        "initial_device_display_name": display_name,
+        "device_data": {
+            "algorithm": DEHYDRATION_ALGORITHM,
+            "device_pickle": dehydrated.ciphertext,
+            "nonce": dehydrated.nonce,
+        },
+        "device_keys": device_keys,
+        "one_time_keys": one_time_keys,
+        "fallback_keys": fallback_keys,
+    });
+
+    Ok(body.to_string())
+}
+
+/// Rehydrates the account from the `device_data` returned by
+/// `GET /dehydrated_device`, in either the [`DEHYDRATION_ALGORITHM`] or the
+/// [`DEHYDRATION_ALGORITHM_V1`] format.
+#[ffi]
+#[sret]
+#[throws]
+pub fn vodozemac_olm_account_from_dehydrated_device_data(
+    #[expand] device_data: &[u8],
+    pickle_key: &[u8; 32],
+) -> CResult<NonNull<Account>, CErrorStr> {
+    let device_data = match serde_json::from_slice::<DeviceData>(device_data) {
+        Ok(device_data) => device_data,
+        Err(error) => return CResult::Err(DeviceDataError::Json(error).into()),
+    };
+
+    let account: Result<_, CErrorStr> = match (device_data.algorithm.as_str(), &device_data.nonce) {
+        (DEHYDRATION_ALGORITHM, Some(nonce)) => {
+            olm::Account::from_dehydrated_device(&device_data.device_pickle, nonce, pickle_key)
+                .map_err(Into::into)
+        }
+        (DEHYDRATION_ALGORITHM, None) => Err(DeviceDataError::MissingNonce.into()),
+        (DEHYDRATION_ALGORITHM_V1, _) => {
+            olm::Account::from_libolm_pickle(&device_data.device_pickle, pickle_key)
+                .map_err(Into::into)
+        }
+        _ => Err(DeviceDataError::UnsupportedAlgorithm(device_data.algorithm).into()),
+    };
+
+    account.map(RwLock::new).map(boxed).into()
+}
+
+/// Decrypts one to-device event, sessions created by pre-key messages are
+/// kept to decrypt later events of the same sender.
+fn process_event(
+    account: &mut olm::Account,
+    sessions: &mut Vec<(Curve25519PublicKey, Session)>,
+    user_id: &str,
+    event: &Value,
+) -> Result<Option<Value>, String> {
+    let event = ToDeviceEvent::deserialize(event).map_err(|e| e.to_string())?;
+    let content = event.content;
+    if content.algorithm != OLM_ALGORITHM {
+        return Err(format!("unsupported algorithm {}", content.algorithm));
+    }
+    let sender_key =
+        Curve25519PublicKey::from_base64(&content.sender_key).map_err(|e| e.to_string())?;
+
+    let identity_keys = account.identity_keys();
+    let message = content
+        .ciphertext
+        .get(&identity_keys.curve25519.to_base64())
+        .ok_or("the event was not encrypted for this device")?;
+
+    let existing = sessions
+        .iter_mut()
+        .filter(|(key, _)| *key == sender_key)
+        .map(|(_, session)| session)
+        .find(|session| match message {
+            OlmMessage::PreKey(message) => session.session_id() == message.session_id(),
+            OlmMessage::Normal(_) => true,
+        });
+
+    let plaintext = match (existing, message) {
+        (Some(session), message) => session.decrypt(message).map_err(|e| e.to_string())?,
+        (None, OlmMessage::PreKey(message)) => {
+            let created = account
+                .create_inbound_session(sender_key, message)
+                .map_err(|e| e.to_string())?;
+            sessions.push((sender_key, created.session));
+            created.plaintext
+        }
+        (None, OlmMessage::Normal(_)) => return Err("no session for the message".to_owned()),
+    };
+    let plaintext = Zeroizing::new(plaintext);
+
+    let decrypted =
+        serde_json::from_slice::<DecryptedEvent>(&plaintext).map_err(|e| e.to_string())?;
+    if decrypted.sender != event.sender {
+        return Err("the decrypted sender does not match the event".to_owned());
+    }
+    if decrypted.recipient != user_id
+        || decrypted.recipient_keys.ed25519 != identity_keys.ed25519.to_base64()
+    {
+        return Err("the event was meant for another recipient".to_owned());
+    }
+    if decrypted.event_type != "m.room_key" {
+        return Ok(None);
+    }
+
+    let room_key = RoomKeyContent::deserialize(&decrypted.content).map_err(|e| e.to_string())?;
+    if room_key.algorithm != MEGOLM_ALGORITHM {
+        return Err(format!(
+            "unsupported room key algorithm {}",
+            room_key.algorithm
+        ));
+    }
+
+    // Make sure the key belongs to the session it claims to be for.
+    let session_key = SessionKey::from_base64(&room_key.session_key).map_err(|e| e.to_string())?;
+    let session = InboundGroupSession::new(&session_key, SessionConfig::version_1());
+    if session.session_id() != room_key.session_id {
+        return Err("the session key does not match the session id".to_owned());
+    }
+
+    Ok(Some(json!({
+        "algorithm": room_key.algorithm,
+        "room_id": room_key.room_id,
+        "session_id": room_key.session_id,
+        "session_key": room_key.session_key,
+        "sender": decrypted.sender,
+        "sender_key": content.sender_key,
+        "sender_claimed_keys": { "ed25519": decrypted.keys.ed25519 },
+    })))
+}
+
+/// Decrypts the to-device events sent to a rehydrated device and returns the
+/// room keys they contain.
+///
+/// `events` is the JSON array of `events` returned by
+/// `POST /dehydrated_device/{device_id}/events`. The result is a JSON object:
+///
+/// ```json
+/// {"room_keys":[{"algorithm":"m.megolm.v1.aes-sha2","room_id":"...",
+///   "session_id":"...","session_key":"...","sender":"@alice:example.org",
+///   "sender_key":"...","sender_claimed_keys":{"ed25519":"..."}}],
+///  "failures":[{"index":3,"error":"..."}]}
+/// ```
+///
+/// Events which are not room keys are skipped, events which can not be
+/// decrypted are listed in `failures` by their index.
+#[ffi]
+#[sret]
+#[throws]
+pub fn vodozemac_olm_account_process_dehydrated_events(
+    account: &Account,
+    #[expand] user_id: &[u8],
+    #[expand] events: &[u8],
+) -> CResult<CSecretSlice, CErrorStr> {
+    let user_id = match str::from_utf8(user_id) {
+        Ok(user_id) => user_id,
+        Err(error) => return CResult::Err(error.into()),
+    };
+    let events = match serde_json::from_slice::<Vec<Value>>(events) {
+        Ok(events) => events,
+        Err(error) => return CResult::Err(error.into()),
+    };
+
+    let mut account = account.write();
+    let mut sessions = Vec::new();
+    let mut room_keys = Vec::new();
+    let mut failures = Vec::new();
+
+    for (index, event) in events.iter().enumerate() {
+        match process_event(&mut account, &mut sessions, user_id, event) {
+            Ok(Some(room_key)) => room_keys.push(room_key),
+            Ok(None) => {}
+            Err(error) => failures.push(json!({ "index": index, "error": error })),
+        }
+    }
+
+    let result = json!({ "room_keys": room_keys, "failures": failures });
+    CResult::Ok(
+        serde_json::to_vec(&result)
+            .expect("values can be serialized")
+            .into(),
+    )
+}
@@ -0,0 +1,192 @@
+// SPDX-FileCopyrightText: 2025 Felix Hilgers <contact@fhilgers.com>
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Keeps the one-time and fallback keys on the server topped up.
+//!
+//! After every sync the client passes the counts the server reported in
+//! `device_one_time_keys_count` and `device_unused_fallback_key_types`, and
+//! gets back the keys to send with `POST /keys/upload`.
+
+use crate::olm::Account;
+use crate::olm::dehydrated_device::signed_keys;
+use crate::slices::{CErrorStr, CSlice};
+use crate::{CResult, NativeMethod};
+use macros::ffi;
+use serde_json::json;
+use std::str;
+
+pub const JNI_METHODS: &[NativeMethod] = &[
+    VODOZEMAC_OLM_ACCOUNT_ONE_TIME_KEY_TARGET_JNI,
+    VODOZEMAC_OLM_ACCOUNT_REPLENISH_KEYS_JNI,
+];
+
+/// How long the previous fallback key is kept after a rotation, messages
+/// encrypted with it may still be on their way.
+pub const FALLBACK_KEY_GRACE_PERIOD_MS: u64 = 60 * 60 * 1000;
+
+/// The number of one-time keys to keep on the server. Only half of what the
+/// account can store, so that keys claimed but not yet used by other devices
+/// are not pushed out by new ones.
+pub fn one_time_key_target(max_number_of_one_time_keys: usize) -> usize {
+    max_number_of_one_time_keys / 2
+}
+
+/// The number of one-time keys to generate, given the count the server
+/// reported and the keys generated before but not uploaded yet.
+pub fn one_time_keys_to_generate(
+    max_number_of_one_time_keys: usize,
+    server_count: usize,
+    unpublished: usize,
+) -> usize {
+    one_time_key_target(max_number_of_one_time_keys).saturating_sub(server_count + unpublished)
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct FallbackKeyPlan {
+    /// Generate a new fallback key, the current one becomes the previous one.
+    pub rotate: bool,
+    /// Forget the previous fallback key.
+    pub forget_previous: bool,
+}
+
+/// Decides what happens to the fallback key.
+///
+/// A new key is generated once the server no longer reports an unused one,
+/// unless a new key is already waiting to be uploaded. The previous key is
+/// forgotten [`FALLBACK_KEY_GRACE_PERIOD_MS`] after `rotated_at`, zero if the
+/// key was never rotated.
+pub fn plan_fallback_key(
+    server_has_unused_key: bool,
+    unpublished_key: bool,
+    rotated_at: u64,
+    now: u64,
+) -> FallbackKeyPlan {
+    let rotate = !server_has_unused_key && !unpublished_key;
+    let forget_previous = !rotate
+        && rotated_at != 0
+        && now.saturating_sub(rotated_at) >= FALLBACK_KEY_GRACE_PERIOD_MS;
+
+    FallbackKeyPlan {
+        rotate,
+        forget_previous,
+    }
+}
+
+#[ffi]
+pub fn vodozemac_olm_account_one_time_key_target(account: &Account) -> u32 {
+    one_time_key_target(account.read().max_number_of_one_time_keys()) as u32
+}
+
+/// Generates the keys needed to get back to the targets and returns the
+/// signed keys to upload as JSON:
+///
+/// ```json
+/// {"one_time_keys": {"signed_curve25519:AAAAAQ": {...}},
+///  "fallback_keys": {"signed_curve25519:AAAAAg": {...}},
+///  "fallback_key_rotated_at": 1700000000000}
+/// ```
+///
+/// `server_count` is the `signed_curve25519` count of
+/// `device_one_time_keys_count`, `fallback_key_unused` whether
+/// `signed_curve25519` is listed in `device_unused_fallback_key_types`.
+/// `fallback_key_rotated_at` is the value returned by the previous call, the
+/// caller has to store it, zero initially.
+///
+/// The keys are not marked as published, call
+/// [`vodozemac_olm_account_mark_keys_as_published`](crate::olm::account::vodozemac_olm_account_mark_keys_as_published)
+/// once the upload succeeded. Until then the same keys are returned again.
+#[ffi]
+#[sret]
+#[throws]
+pub fn vodozemac_olm_account_replenish_keys(
+    account: &Account,
+    #[expand] user_id: &[u8],
+    #[expand] device_id: &[u8],
+    server_count: u32,
+    fallback_key_unused: u32,
+    fallback_key_rotated_at: u64,
+    now: u64,
+) -> CResult<CSlice<u8>, CErrorStr> {
+    let (user_id, device_id) = match (str::from_utf8(user_id), str::from_utf8(device_id)) {
+        (Ok(user_id), Ok(device_id)) => (user_id, device_id),
+        (Err(error), _) | (_, Err(error)) => return CResult::Err(error.into()),
+    };
+
+    let mut account = account.write();
+
+    let count = one_time_keys_to_generate(
+        account.max_number_of_one_time_keys(),
+        server_count as usize,
+        account.one_time_keys().len(),
+    );
+    if count > 0 {
+        account.generate_one_time_keys(count);
+    }
+
+    let plan = plan_fallback_key(
+        fallback_key_unused != 0,
+        !account.fallback_key().is_empty(),
+        fallback_key_rotated_at,
+        now,
+    );
+    let mut rotated_at = fallback_key_rotated_at;
+    if plan.rotate {
+        account.generate_fallback_key();
+        rotated_at = now;
+    }
+    if plan.forget_previous {
+        account.forget_fallback_key();
+    }
+
+    let one_time_keys = signed_keys(&account, account.one_time_keys(), false, user_id, device_id);
+    let fallback_keys = signed_keys(&account, account.fallback_key(), true, user_id, device_id);
+
+    let body = json!({
+        "one_time_keys": one_time_keys,
+        "fallback_keys": fallback_keys,
+        "fallback_key_rotated_at": rotated_at,
+    });
+    CResult::Ok(body.to_string().into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn one_time_key_policy() {
+        // The pool is filled up to half of the maximum, counting keys which
+        // still wait for their upload.
+        assert_eq!(one_time_keys_to_generate(50, 0, 0), 25);
+        assert_eq!(one_time_keys_to_generate(50, 20, 0), 5);
+        assert_eq!(one_time_keys_to_generate(50, 20, 5), 0);
+        assert_eq!(one_time_keys_to_generate(50, 25, 0), 0);
+        assert_eq!(one_time_keys_to_generate(50, 40, 3), 0);
+    }
+
+    #[test]
+    fn fallback_key_policy() {
+        let plan = |rotate, forget_previous| FallbackKeyPlan {
+            rotate,
+            forget_previous,
+        };
+        let grace = FALLBACK_KEY_GRACE_PERIOD_MS;
+
+        // A used key is rotated, unless its replacement was not uploaded yet.
+        assert_eq!(plan_fallback_key(false, false, 0, 1), plan(true, false));
+        assert_eq!(plan_fallback_key(false, true, 1, 2), plan(false, false));
+        assert_eq!(plan_fallback_key(true, false, 0, 1), plan(false, false));
+
+        // The previous key is kept for the grace period after the rotation.
+        assert_eq!(plan_fallback_key(true, false, 1, grace), plan(false, false));
+        assert_eq!(
+            plan_fallback_key(true, false, 1, 1 + grace),
+            plan(false, true)
+        );
+        assert_eq!(
+            plan_fallback_key(false, false, 1, 1 + grace),
+            plan(true, false)
+        );
+    }
+}
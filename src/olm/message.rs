@@ -4,29 +4,22 @@
 
 use crate::olm::session::OlmMessage;
 use crate::slices::{CErrorStr, CSlice};
-use crate::{CResult, boxed, free};
-use jni::JNIEnv;
-use jni::objects::JClass;
+use crate::{CResult, NativeMethod, boxed, free};
 use macros::ffi;
 use std::ptr::NonNull;
 use vodozemac::olm::{Message, SessionKeys};
 use vodozemac::{Curve25519PublicKey, olm};
 
-pub fn register_jni(env: &mut JNIEnv, class: &JClass) -> jni::errors::Result<()> {
-    env.register_native_methods(
-        class,
-        &[
-            VODOZEMAC_OLM_MESSAGE_FREE_JNI.into(),
-            VODOZEMAC_OLM_MESSAGE_RATCHET_KEY_JNI.into(),
-            VODOZEMAC_OLM_MESSAGE_CHAIN_INDEX_JNI.into(),
-            VODOZEMAC_OLM_MESSAGE_CIPHERTEXT_JNI.into(),
-            VODOZEMAC_OLM_MESSAGE_VERSION_JNI.into(),
-            VODOZEMAC_OLM_MESSAGE_MAC_TRUNCATED_JNI.into(),
-            VODOZEMAC_OLM_MESSAGE_TO_BYTES_JNI.into(),
-            VODOZEMAC_OLM_MESSAGE_FROM_BYTES_JNI.into(),
-        ],
-    )
-}
+pub const JNI_METHODS: &[NativeMethod] = &[
+    VODOZEMAC_OLM_MESSAGE_FREE_JNI,
+    VODOZEMAC_OLM_MESSAGE_RATCHET_KEY_JNI,
+    VODOZEMAC_OLM_MESSAGE_CHAIN_INDEX_JNI,
+    VODOZEMAC_OLM_MESSAGE_CIPHERTEXT_JNI,
+    VODOZEMAC_OLM_MESSAGE_VERSION_JNI,
+    VODOZEMAC_OLM_MESSAGE_MAC_TRUNCATED_JNI,
+    VODOZEMAC_OLM_MESSAGE_TO_BYTES_JNI,
+    VODOZEMAC_OLM_MESSAGE_FROM_BYTES_JNI,
+];
 
 #[ffi]
 pub fn vodozemac_olm_message_free(message: NonNull<Message>) {
@@ -73,6 +66,7 @@ pub fn vodozemac_olm_message_to_bytes(
 
 #[ffi]
 #[sret]
+#[throws]
 pub fn vodozemac_olm_message_from_bytes(
     message_type: u32,
     #[expand] bytes: &[u8],
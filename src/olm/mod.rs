@@ -2,42 +2,53 @@
 //
 // SPDX-License-Identifier: Apache-2.0
 
-use jni::JNIEnv;
-use jni::objects::JClass;
+use crate::JniModule;
 use parking_lot::RwLock;
 
 pub mod account;
+pub mod dehydrated_device;
+pub mod key_pool;
 pub mod message;
 pub mod session;
 pub mod session_config;
 pub mod session_keys;
+pub mod tracked_session;
 
 pub type Account = RwLock<vodozemac::olm::Account>;
 pub type Session = RwLock<vodozemac::olm::Session>;
+pub type TrackedSession = RwLock<tracked_session::TrackedOlmSession>;
 
-pub struct OlmJniClasses<'local, 'a> {
-    pub account: &'a JClass<'local>,
-    pub message: &'a JClass<'local>,
-    pub session: &'a JClass<'local>,
-    pub session_config: &'a JClass<'local>,
-    pub session_keys: &'a JClass<'local>,
-}
-
-pub fn register_jni(
-    env: &mut JNIEnv,
-    OlmJniClasses {
-        account,
-        message,
-        session,
-        session_config,
-        session_keys,
-    }: &OlmJniClasses,
-) -> jni::errors::Result<()> {
-    account::register_jni(env, account)?;
-    message::register_jni(env, message)?;
-    session::register_jni(env, session)?;
-    session_config::register_jni(env, session_config)?;
-    session_keys::register_jni(env, session_keys)?;
-
-    Ok(())
-}
+pub const JNI_MODULES: &[JniModule] = &[
+    JniModule {
+        class: "olm/AccountBindingsKt",
+        methods: account::JNI_METHODS,
+    },
+    JniModule {
+        class: "olm/DehydratedDeviceBindingsKt",
+        methods: dehydrated_device::JNI_METHODS,
+    },
+    JniModule {
+        class: "olm/KeyPoolBindingsKt",
+        methods: key_pool::JNI_METHODS,
+    },
+    JniModule {
+        class: "olm/MessageBindingsKt",
+        methods: message::JNI_METHODS,
+    },
+    JniModule {
+        class: "olm/SessionBindingsKt",
+        methods: session::JNI_METHODS,
+    },
+    JniModule {
+        class: "olm/SessionConfigBindingsKt",
+        methods: session_config::JNI_METHODS,
+    },
+    JniModule {
+        class: "olm/SessionKeysBindingsKt",
+        methods: session_keys::JNI_METHODS,
+    },
+    JniModule {
+        class: "olm/TrackedSessionBindingsKt",
+        methods: tracked_session::JNI_METHODS,
+    },
+];
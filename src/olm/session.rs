@@ -3,10 +3,9 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use crate::olm::Session;
-use crate::slices::{CErrorStr, CSlice};
-use crate::{AsUsize, CResult, Either, boxed, free};
-use jni::JNIEnv;
-use jni::objects::JClass;
+use crate::pickle::{self, RekeyedPickles};
+use crate::slices::{CErrorStr, CSecretSlice, CSlice};
+use crate::{AsUsize, CResult, Either, NativeMethod, boxed, free};
 use macros::ffi;
 use parking_lot::RwLock;
 use std::ptr::NonNull;
@@ -14,33 +13,34 @@ use std::{array, str};
 use vodozemac::olm;
 use vodozemac::olm::{Message, PreKeyMessage, SessionConfig, SessionKeys, SessionPickle};
 
-pub fn register_jni(env: &mut JNIEnv, class: &JClass) -> jni::errors::Result<()> {
-    env.register_native_methods(
-        class,
-        &[
-            VODOZEMAC_OLM_SESSION_FREE_JNI.into(),
-            VODOZEMAC_OLM_SESSION_SESSION_ID_JNI.into(),
-            VODOZEMAC_OLM_SESSION_HAS_RECEIVED_MESSAGE_JNI.into(),
-            VODOZEMAC_OLM_SESSION_ENCRYPT_JNI.into(),
-            VODOZEMAC_OLM_SESSION_SESSION_KEYS_JNI.into(),
-            VODOZEMAC_OLM_SESSION_SESSION_CONFIG_JNI.into(),
-            VODOZEMAC_OLM_SESSION_DECRYPT_JNI.into(),
-            VODOZEMAC_OLM_SESSION_PICKLE_JNI.into(),
-            VODOZEMAC_OLM_SESSION_FROM_PICKLE_JNI.into(),
-        ],
-    )
-}
-
-#[repr(C, usize)]
-#[derive(Clone, Copy)]
-pub enum OlmMessage<'a> {
-    Normal {
-        message: &'a Message,
-    },
-    PreKey {
-        message: &'a Message,
-        session_keys: &'a SessionKeys,
-    },
+pub const JNI_METHODS: &[NativeMethod] = &[
+    VODOZEMAC_OLM_SESSION_FREE_JNI,
+    VODOZEMAC_OLM_SESSION_SESSION_ID_JNI,
+    VODOZEMAC_OLM_SESSION_HAS_RECEIVED_MESSAGE_JNI,
+    VODOZEMAC_OLM_SESSION_ENCRYPT_JNI,
+    VODOZEMAC_OLM_SESSION_SESSION_KEYS_JNI,
+    VODOZEMAC_OLM_SESSION_SESSION_CONFIG_JNI,
+    VODOZEMAC_OLM_SESSION_DECRYPT_JNI,
+    VODOZEMAC_OLM_SESSION_PICKLE_JNI,
+    VODOZEMAC_OLM_SESSION_FROM_PICKLE_JNI,
+    VODOZEMAC_OLM_SESSION_REKEY_PICKLES_JNI,
+    #[cfg(feature = "dangerous-json-pickles")]
+    VODOZEMAC_OLM_SESSION_DANGEROUS_JSON_PICKLE_JNI,
+    #[cfg(feature = "dangerous-json-pickles")]
+    VODOZEMAC_OLM_SESSION_DANGEROUS_FROM_JSON_PICKLE_JNI,
+];
+
+crate::define_and_assert_ptr_sized! {
+    #[derive(Clone, Copy)]
+    pub enum OlmMessage<'a> {
+        Normal {
+            message: &'a Message,
+        },
+        PreKey {
+            message: &'a Message,
+            session_keys: &'a SessionKeys,
+        },
+    }
 }
 
 impl<'a> OlmMessage<'a> {
@@ -56,6 +56,8 @@ impl<'a> OlmMessage<'a> {
 }
 
 impl AsUsize for OlmMessage<'_> {
+    const WORDS: usize = 3;
+
     type IntoIter = Either<array::IntoIter<usize, 2>, array::IntoIter<usize, 3>>;
 
     fn as_usize(&self) -> Self::IntoIter {
@@ -139,11 +141,12 @@ pub fn vodozemac_olm_session_session_config(session: &Session) -> NonNull<Sessio
 
 #[ffi]
 #[sret]
+#[throws]
 pub fn vodozemac_olm_session_decrypt(
     session: &Session,
     message: &Message,
     session_keys: Option<&SessionKeys>,
-) -> CResult<CSlice<u8>, CErrorStr> {
+) -> CResult<CSecretSlice, CErrorStr> {
     // TODO: zero copy?
     let message = message.clone();
 
@@ -168,6 +171,7 @@ pub fn vodozemac_olm_session_pickle(session: &Session, pickle_key: &[u8; 32]) ->
 
 #[ffi]
 #[sret]
+#[throws]
 pub fn vodozemac_olm_session_from_pickle(
     #[expand] ciphertext: &[u8],
     pickle_key: &[u8; 32],
@@ -181,3 +185,54 @@ pub fn vodozemac_olm_session_from_pickle(
         .map_err(Into::into)
         .into()
 }
+
+/// Re-encrypts session pickles with `new_key`, see [`pickle::rekey`].
+#[ffi]
+#[sret]
+#[throws]
+pub fn vodozemac_olm_session_rekey_pickles(
+    #[expand]
+    #[copy]
+    pickles: &[u8],
+    #[expand] pickle_lengths: &[u32],
+    old_key: &[u8; 32],
+    new_key: &[u8; 32],
+) -> CResult<RekeyedPickles, CErrorStr> {
+    pickle::rekey(
+        pickles,
+        pickle_lengths,
+        old_key,
+        new_key,
+        SessionPickle::from_encrypted,
+        SessionPickle::encrypt,
+    )
+}
+
+/// Exports the session as an unencrypted JSON pickle.
+///
+/// **Dangerous**: the ratchet keys end up in plain text, see
+/// [`crate::olm::account::vodozemac_olm_account_dangerous_json_pickle`].
+#[cfg(feature = "dangerous-json-pickles")]
+#[ffi]
+#[sret]
+pub fn vodozemac_olm_session_dangerous_json_pickle(session: &Session) -> CSecretSlice {
+    serde_json::to_vec(&session.read().pickle())
+        .expect("pickles can be serialized")
+        .into()
+}
+
+/// Imports a pickle exported by [`vodozemac_olm_session_dangerous_json_pickle`].
+#[cfg(feature = "dangerous-json-pickles")]
+#[ffi]
+#[sret]
+#[throws]
+pub fn vodozemac_olm_session_dangerous_from_json_pickle(
+    #[expand] json: &[u8],
+) -> CResult<NonNull<Session>, CErrorStr> {
+    serde_json::from_slice::<SessionPickle>(json)
+        .map(olm::Session::from_pickle)
+        .map(RwLock::new)
+        .map(boxed)
+        .map_err(Into::into)
+        .into()
+}
@@ -2,24 +2,26 @@
 //
 // SPDX-License-Identifier: Apache-2.0
 
-use crate::{boxed, free};
-use jni::JNIEnv;
-use jni::objects::JClass;
+use crate::slices::CSlice;
+use crate::{NativeMethod, boxed, free};
 use macros::ffi;
 use std::ptr::NonNull;
 use vodozemac::olm::SessionConfig;
 
-pub fn register_jni(env: &mut JNIEnv, class: &JClass) -> jni::errors::Result<()> {
-    env.register_native_methods(
-        class,
-        &[
-            VODOZEMAC_OLM_SESSION_CONFIG_VERSION_1_JNI.into(),
-            VODOZEMAC_OLM_SESSION_CONFIG_VERSION_2_JNI.into(),
-            VODOZEMAC_OLM_SESSION_CONFIG_VERSION_JNI.into(),
-            VODOZEMAC_OLM_SESSION_CONFIG_FREE_JNI.into(),
-        ],
-    )
-}
+pub const JNI_METHODS: &[NativeMethod] = &[
+    VODOZEMAC_OLM_SESSION_CONFIG_VERSION_1_JNI,
+    VODOZEMAC_OLM_SESSION_CONFIG_VERSION_2_JNI,
+    VODOZEMAC_OLM_SESSION_CONFIG_VERSION_JNI,
+    VODOZEMAC_OLM_SESSION_CONFIG_FROM_ALGORITHM_JNI,
+    VODOZEMAC_OLM_SESSION_CONFIG_ALGORITHM_JNI,
+    VODOZEMAC_OLM_SESSION_CONFIG_EQUALS_JNI,
+    VODOZEMAC_OLM_SESSION_CONFIG_SUPPORTS_FULL_MAC_JNI,
+    VODOZEMAC_OLM_SESSION_CONFIG_FREE_JNI,
+];
+
+pub const ALGORITHM_V1: &str = "m.olm.v1.curve25519-aes-sha2";
+/// Unstable, see [`crate::megolm::session_config::ALGORITHM_V2`].
+pub const ALGORITHM_V2: &str = "org.matrix.vodozemac.olm.v2";
 
 #[ffi]
 pub fn vodozemac_olm_session_config_version_1() -> NonNull<SessionConfig> {
@@ -36,6 +38,44 @@ pub fn vodozemac_olm_session_config_version(session_config: &SessionConfig) -> u
     session_config.version() as u32
 }
 
+/// The config for a negotiated `algorithm` identifier, null for algorithms
+/// other than [`ALGORITHM_V1`] and [`ALGORITHM_V2`].
+#[ffi]
+pub fn vodozemac_olm_session_config_from_algorithm(
+    #[expand] algorithm: &[u8],
+) -> Option<NonNull<SessionConfig>> {
+    match algorithm {
+        a if a == ALGORITHM_V1.as_bytes() => Some(boxed(SessionConfig::version_1())),
+        a if a == ALGORITHM_V2.as_bytes() => Some(boxed(SessionConfig::version_2())),
+        _ => None,
+    }
+}
+
+#[ffi]
+#[sret]
+pub fn vodozemac_olm_session_config_algorithm(session_config: &SessionConfig) -> CSlice<u8> {
+    if *session_config == SessionConfig::version_1() {
+        ALGORITHM_V1.to_owned().into()
+    } else {
+        ALGORITHM_V2.to_owned().into()
+    }
+}
+
+#[ffi]
+pub fn vodozemac_olm_session_config_equals(
+    session_config: &SessionConfig,
+    other: &SessionConfig,
+) -> u32 {
+    (session_config == other).into()
+}
+
+/// Whether messages carry the full MAC instead of the truncated MAC of
+/// version 1, true for version 2.
+#[ffi]
+pub fn vodozemac_olm_session_config_supports_full_mac(session_config: &SessionConfig) -> u32 {
+    (session_config.version() == 2).into()
+}
+
 #[ffi]
 pub fn vodozemac_olm_session_config_free(session_config: NonNull<SessionConfig>) {
     free(session_config)
@@ -3,26 +3,19 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use crate::slices::CSlice;
-use crate::{boxed, free};
-use jni::JNIEnv;
-use jni::objects::JClass;
+use crate::{NativeMethod, boxed, free};
 use macros::ffi;
 use std::ptr::NonNull;
 use vodozemac::Curve25519PublicKey;
 use vodozemac::olm::SessionKeys;
 
-pub fn register_jni(env: &mut JNIEnv, class: &JClass) -> jni::errors::Result<()> {
-    env.register_native_methods(
-        class,
-        &[
-            VODOZEMAC_OLM_SESSION_KEYS_FREE_JNI.into(),
-            VODOZEMAC_OLM_SESSION_KEYS_IDENTITY_KEY_JNI.into(),
-            VODOZEMAC_OLM_SESSION_KEYS_BASE_KEY_JNI.into(),
-            VODOZEMAC_OLM_SESSION_KEYS_ONE_TIME_KEY_JNI.into(),
-            VODOZEMAC_OLM_SESSION_KEYS_SESSION_ID_JNI.into(),
-        ],
-    )
-}
+pub const JNI_METHODS: &[NativeMethod] = &[
+    VODOZEMAC_OLM_SESSION_KEYS_FREE_JNI,
+    VODOZEMAC_OLM_SESSION_KEYS_IDENTITY_KEY_JNI,
+    VODOZEMAC_OLM_SESSION_KEYS_BASE_KEY_JNI,
+    VODOZEMAC_OLM_SESSION_KEYS_ONE_TIME_KEY_JNI,
+    VODOZEMAC_OLM_SESSION_KEYS_SESSION_ID_JNI,
+];
 
 #[ffi]
 pub fn vodozemac_olm_session_keys_free(session_keys: NonNull<SessionKeys>) {
@@ -0,0 +1,297 @@
+// SPDX-FileCopyrightText: 2025 Felix Hilgers <contact@fhilgers.com>
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! An Olm session which remembers when it was used and whether decrypting with
+//! it keeps failing.
+//!
+//! A session is wedged when the other side lost its state, every message it
+//! sends from then on fails to decrypt. The spec recommends establishing a new
+//! session and sending an `m.dummy` event over it, rate limited so that two
+//! devices do not keep replacing each other's sessions.
+//!
+//! Timestamps are milliseconds since the Unix epoch as passed in by the
+//! caller, the library has no clock on every target.
+
+use crate::olm::session::OlmMessage;
+use crate::olm::{Session, TrackedSession};
+use crate::pickle::{self, RekeyedPickles};
+use crate::slices::{CErrorStr, CSecretSlice, CSlice};
+use crate::{CResult, NativeMethod, boxed, free};
+use macros::ffi;
+use parking_lot::RwLock;
+use serde::de::Error as _;
+use serde::{Deserialize, Deserializer, Serialize};
+use std::ptr::NonNull;
+use vodozemac::olm::{self, DecryptionError, Message, PreKeyMessage, SessionKeys, SessionPickle};
+
+pub const JNI_METHODS: &[NativeMethod] = &[
+    VODOZEMAC_OLM_TRACKED_SESSION_NEW_JNI,
+    VODOZEMAC_OLM_TRACKED_SESSION_FREE_JNI,
+    VODOZEMAC_OLM_TRACKED_SESSION_SESSION_JNI,
+    VODOZEMAC_OLM_TRACKED_SESSION_ENCRYPT_JNI,
+    VODOZEMAC_OLM_TRACKED_SESSION_DECRYPT_JNI,
+    VODOZEMAC_OLM_TRACKED_SESSION_RECORD_FAILURE_JNI,
+    VODOZEMAC_OLM_TRACKED_SESSION_CREATED_AT_JNI,
+    VODOZEMAC_OLM_TRACKED_SESSION_LAST_ENCRYPTED_AT_JNI,
+    VODOZEMAC_OLM_TRACKED_SESSION_LAST_DECRYPTED_AT_JNI,
+    VODOZEMAC_OLM_TRACKED_SESSION_CONSECUTIVE_FAILURES_JNI,
+    VODOZEMAC_OLM_TRACKED_SESSION_IS_WEDGED_JNI,
+    VODOZEMAC_OLM_TRACKED_SESSION_SHOULD_REPLACE_JNI,
+    VODOZEMAC_OLM_TRACKED_SESSION_PICKLE_JNI,
+    VODOZEMAC_OLM_TRACKED_SESSION_FROM_PICKLE_JNI,
+    VODOZEMAC_OLM_TRACKED_SESSION_REKEY_PICKLES_JNI,
+];
+
+/// Failed decryptions in a row after which a session counts as wedged.
+pub const WEDGE_FAILURE_THRESHOLD: u32 = 3;
+
+/// A wedged session is only replaced if it is older than this, so a
+/// replacement that fails as well is not replaced right away again.
+pub const UNWEDGE_INTERVAL_MS: u64 = 60 * 60 * 1000;
+
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct SessionHistory {
+    created_at: u64,
+    /// Zero until the first message is encrypted.
+    last_encrypted_at: u64,
+    /// Zero until the first message is decrypted.
+    last_decrypted_at: u64,
+    consecutive_failures: u32,
+}
+
+impl SessionHistory {
+    fn is_wedged(&self) -> bool {
+        self.consecutive_failures >= WEDGE_FAILURE_THRESHOLD
+    }
+
+    fn should_replace(&self, now: u64) -> bool {
+        self.is_wedged() && now.saturating_sub(self.created_at) >= UNWEDGE_INTERVAL_MS
+    }
+}
+
+pub struct TrackedOlmSession {
+    session: olm::Session,
+    history: SessionHistory,
+}
+
+/// Incremented whenever [`TrackedOlmSessionPickle`] changes, pickles of other
+/// versions fail to deserialize.
+pub const PICKLE_VERSION: u32 = 1;
+
+fn pickle_version<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u32, D::Error> {
+    let version = u32::deserialize(deserializer)?;
+    if version != PICKLE_VERSION {
+        return Err(D::Error::custom(format!(
+            "unsupported tracked Olm session pickle version {version}"
+        )));
+    }
+    Ok(version)
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct TrackedOlmSessionPickle {
+    #[serde(deserialize_with = "pickle_version")]
+    version: u32,
+    session: SessionPickle,
+    history: SessionHistory,
+}
+
+impl From<&TrackedOlmSession> for TrackedOlmSessionPickle {
+    fn from(value: &TrackedOlmSession) -> Self {
+        Self {
+            version: PICKLE_VERSION,
+            session: value.session.pickle(),
+            history: value.history.clone(),
+        }
+    }
+}
+
+impl From<TrackedOlmSessionPickle> for TrackedOlmSession {
+    fn from(value: TrackedOlmSessionPickle) -> Self {
+        Self {
+            session: olm::Session::from_pickle(value.session),
+            history: value.history,
+        }
+    }
+}
+
+/// Copies `session`, `created_at` is when it was established.
+#[ffi]
+pub fn vodozemac_olm_tracked_session_new(
+    session: &Session,
+    created_at: u64,
+) -> NonNull<TrackedSession> {
+    boxed(RwLock::new(TrackedOlmSession {
+        session: olm::Session::from_pickle(session.read().pickle()),
+        history: SessionHistory {
+            created_at,
+            ..Default::default()
+        },
+    }))
+}
+
+#[ffi]
+pub fn vodozemac_olm_tracked_session_free(tracked: NonNull<TrackedSession>) {
+    free(tracked)
+}
+
+/// A copy of the wrapped session, changes to it are not reflected in
+/// `tracked`.
+#[ffi]
+pub fn vodozemac_olm_tracked_session_session(tracked: &TrackedSession) -> NonNull<Session> {
+    let session = olm::Session::from_pickle(tracked.read().session.pickle());
+
+    boxed(RwLock::new(session))
+}
+
+#[ffi]
+#[sret]
+pub fn vodozemac_olm_tracked_session_encrypt(
+    tracked: &TrackedSession,
+    #[expand] plaintext: &[u8],
+    now: u64,
+) -> OlmMessage<'static> {
+    let mut tracked = tracked.write();
+    tracked.history.last_encrypted_at = now;
+
+    tracked.session.encrypt(plaintext).into()
+}
+
+/// Decrypts like [`vodozemac_olm_session_decrypt`](crate::olm::session::vodozemac_olm_session_decrypt)
+/// and records the outcome.
+///
+/// A success resets the failure count. Failures only count for pre-key
+/// messages of this session, a normal message does not say which session it
+/// belongs to and usually fails because it was meant for another one, see
+/// [`vodozemac_olm_tracked_session_record_failure`]. A message whose key was
+/// already used up is a replay and not a sign of a wedged session, it is not
+/// counted either.
+#[ffi]
+#[sret]
+#[throws]
+pub fn vodozemac_olm_tracked_session_decrypt(
+    tracked: &TrackedSession,
+    message: &Message,
+    session_keys: Option<&SessionKeys>,
+    now: u64,
+) -> CResult<CSecretSlice, CErrorStr> {
+    let message = message.clone();
+
+    let mut tracked = tracked.write();
+
+    let (olm_message, ours) = match session_keys {
+        Some(keys) => (
+            olm::OlmMessage::PreKey(PreKeyMessage::wrap(*keys, message)),
+            keys.session_id() == tracked.session.session_id(),
+        ),
+        None => (olm::OlmMessage::Normal(message), false),
+    };
+
+    let result = tracked.session.decrypt(&olm_message);
+
+    match &result {
+        Ok(_) => {
+            tracked.history.last_decrypted_at = now;
+            tracked.history.consecutive_failures = 0;
+        }
+        Err(DecryptionError::MissingMessageKey(_)) => {}
+        Err(_) if ours => {
+            tracked.history.consecutive_failures =
+                tracked.history.consecutive_failures.saturating_add(1)
+        }
+        Err(_) => {}
+    }
+
+    result.map(Into::into).map_err(Into::into).into()
+}
+
+/// Counts a failed decryption, for a normal message from the other device
+/// which none of the sessions with it could decrypt.
+#[ffi]
+pub fn vodozemac_olm_tracked_session_record_failure(tracked: &TrackedSession) {
+    let history = &mut tracked.write().history;
+    history.consecutive_failures = history.consecutive_failures.saturating_add(1);
+}
+
+#[ffi]
+pub fn vodozemac_olm_tracked_session_created_at(tracked: &TrackedSession) -> u64 {
+    tracked.read().history.created_at
+}
+
+#[ffi]
+pub fn vodozemac_olm_tracked_session_last_encrypted_at(tracked: &TrackedSession) -> u64 {
+    tracked.read().history.last_encrypted_at
+}
+
+#[ffi]
+pub fn vodozemac_olm_tracked_session_last_decrypted_at(tracked: &TrackedSession) -> u64 {
+    tracked.read().history.last_decrypted_at
+}
+
+#[ffi]
+pub fn vodozemac_olm_tracked_session_consecutive_failures(tracked: &TrackedSession) -> u32 {
+    tracked.read().history.consecutive_failures
+}
+
+/// Whether the last [`WEDGE_FAILURE_THRESHOLD`] messages failed to decrypt.
+#[ffi]
+pub fn vodozemac_olm_tracked_session_is_wedged(tracked: &TrackedSession) -> u32 {
+    tracked.read().history.is_wedged().into()
+}
+
+/// Whether the session is wedged and old enough to be replaced by a new one,
+/// see [`UNWEDGE_INTERVAL_MS`].
+#[ffi]
+pub fn vodozemac_olm_tracked_session_should_replace(tracked: &TrackedSession, now: u64) -> u32 {
+    tracked.read().history.should_replace(now).into()
+}
+
+#[ffi]
+#[sret]
+pub fn vodozemac_olm_tracked_session_pickle(
+    tracked: &TrackedSession,
+    pickle_key: &[u8; 32],
+) -> CSlice<u8> {
+    pickle::encrypt(&TrackedOlmSessionPickle::from(&*tracked.read()), pickle_key).into()
+}
+
+#[ffi]
+#[sret]
+#[throws]
+pub fn vodozemac_olm_tracked_session_from_pickle(
+    #[expand] ciphertext: &[u8],
+    pickle_key: &[u8; 32],
+) -> CResult<NonNull<TrackedSession>, CErrorStr> {
+    // Pickles are base64, anything else fails to decode.
+    let ciphertext = String::from_utf8_lossy(ciphertext);
+
+    pickle::decrypt::<TrackedOlmSessionPickle>(&ciphertext, pickle_key)
+        .map(TrackedOlmSession::from)
+        .map(RwLock::new)
+        .map(boxed)
+        .map_err(Into::into)
+        .into()
+}
+
+/// Re-encrypts tracked session pickles with `new_key`, see [`pickle::rekey`].
+#[ffi]
+#[sret]
+#[throws]
+pub fn vodozemac_olm_tracked_session_rekey_pickles(
+    #[expand]
+    #[copy]
+    pickles: &[u8],
+    #[expand] pickle_lengths: &[u32],
+    old_key: &[u8; 32],
+    new_key: &[u8; 32],
+) -> CResult<RekeyedPickles, CErrorStr> {
+    pickle::rekey(
+        pickles,
+        pickle_lengths,
+        old_key,
+        new_key,
+        pickle::decrypt::<TrackedOlmSessionPickle>,
+        |pickle, key| pickle::encrypt(&pickle, key),
+    )
+}
@@ -0,0 +1,259 @@
+// SPDX-FileCopyrightText: 2025 Felix Hilgers <contact@fhilgers.com>
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! Derivation of pickle keys from passphrases and re-encryption of pickles
+//! with a new key.
+
+use crate::exceptions::ErrorDetails;
+use crate::slices::{CErrorStr, CSecretSlice, CSlice, split_concatenated};
+use crate::{AsUsize, CResult, Chain, ChainExact, NativeMethod};
+use argon2::{Algorithm, Argon2, Params, Version};
+use macros::ffi;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use sha2::Sha256;
+use std::array;
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use vodozemac::hazmat::Cipher;
+use vodozemac::{PickleError, base64_decode, base64_encode};
+use zeroize::{Zeroize, Zeroizing};
+
+pub const JNI_METHODS: &[NativeMethod] = &[
+    VODOZEMAC_PICKLE_KEY_PBKDF2_SHA256_JNI,
+    VODOZEMAC_PICKLE_KEY_ARGON2ID_JNI,
+];
+
+pub const PICKLE_KEY_LENGTH: usize = 32;
+
+/// Salts have to be at least as long as recommended by NIST SP 800-132.
+pub const MIN_SALT_LENGTH: usize = 16;
+
+/// OWASP recommends 600 000 iterations for PBKDF2-HMAC-SHA256, this is only
+/// the floor below which keys are refused.
+pub const PBKDF2_MIN_ITERATIONS: u32 = 100_000;
+
+/// The smallest Argon2id configuration OWASP recommends, 19 MiB of memory and
+/// two iterations.
+pub const ARGON2_MIN_MEMORY_KIB: u32 = 19 * 1024;
+pub const ARGON2_MIN_ITERATIONS: u32 = 2;
+
+/// Passphrases are derived on phones as well, more than 1 GiB would only get
+/// the process killed.
+pub const ARGON2_MAX_MEMORY_KIB: u32 = 1024 * 1024;
+
+#[derive(Debug)]
+pub enum KdfError {
+    SaltTooShort(usize),
+    TooFewIterations { iterations: u32, minimum: u32 },
+    MemoryOutOfRange(u32),
+    Argon2(argon2::Error),
+}
+
+impl Display for KdfError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            KdfError::SaltTooShort(length) => write!(
+                f,
+                "the salt has to be at least {MIN_SALT_LENGTH} bytes long, got {length}"
+            ),
+            KdfError::TooFewIterations {
+                iterations,
+                minimum,
+            } => write!(
+                f,
+                "at least {minimum} iterations are required, got {iterations}"
+            ),
+            KdfError::MemoryOutOfRange(memory_kib) => write!(
+                f,
+                "the memory has to be between {ARGON2_MIN_MEMORY_KIB} and \
+                 {ARGON2_MAX_MEMORY_KIB} KiB, got {memory_kib}"
+            ),
+            KdfError::Argon2(error) => write!(f, "invalid Argon2 parameters: {error}"),
+        }
+    }
+}
+
+impl Error for KdfError {}
+
+fn check_salt(salt: &[u8]) -> Result<(), KdfError> {
+    if salt.len() < MIN_SALT_LENGTH {
+        return Err(KdfError::SaltTooShort(salt.len()));
+    }
+    Ok(())
+}
+
+pub fn pbkdf2_sha256(
+    passphrase: &[u8],
+    salt: &[u8],
+    iterations: u32,
+) -> Result<Zeroizing<[u8; PICKLE_KEY_LENGTH]>, KdfError> {
+    check_salt(salt)?;
+    if iterations < PBKDF2_MIN_ITERATIONS {
+        return Err(KdfError::TooFewIterations {
+            iterations,
+            minimum: PBKDF2_MIN_ITERATIONS,
+        });
+    }
+
+    let mut key = Zeroizing::new([0; PICKLE_KEY_LENGTH]);
+    pbkdf2::pbkdf2_hmac::<Sha256>(passphrase, salt, iterations, key.as_mut());
+    Ok(key)
+}
+
+/// `memory_kib`, `iterations` and `parallelism` are the `m`, `t` and `p`
+/// parameters of RFC 9106, `memory_kib` and `iterations` are checked against
+/// [`ARGON2_MIN_MEMORY_KIB`], [`ARGON2_MAX_MEMORY_KIB`] and
+/// [`ARGON2_MIN_ITERATIONS`].
+pub fn argon2id(
+    passphrase: &[u8],
+    salt: &[u8],
+    memory_kib: u32,
+    iterations: u32,
+    parallelism: u32,
+) -> Result<Zeroizing<[u8; PICKLE_KEY_LENGTH]>, KdfError> {
+    check_salt(salt)?;
+    if !(ARGON2_MIN_MEMORY_KIB..=ARGON2_MAX_MEMORY_KIB).contains(&memory_kib) {
+        return Err(KdfError::MemoryOutOfRange(memory_kib));
+    }
+    if iterations < ARGON2_MIN_ITERATIONS {
+        return Err(KdfError::TooFewIterations {
+            iterations,
+            minimum: ARGON2_MIN_ITERATIONS,
+        });
+    }
+
+    let params = Params::new(memory_kib, iterations, parallelism, Some(PICKLE_KEY_LENGTH))
+        .map_err(KdfError::Argon2)?;
+
+    let mut key = Zeroizing::new([0; PICKLE_KEY_LENGTH]);
+    Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+        .hash_password_into(passphrase, salt, key.as_mut())
+        .map_err(KdfError::Argon2)?;
+    Ok(key)
+}
+
+/// Derives a pickle key from `passphrase` with PBKDF2-HMAC-SHA256.
+#[ffi]
+#[sret]
+#[throws]
+pub fn vodozemac_pickle_key_pbkdf2_sha256(
+    #[expand]
+    #[copy]
+    passphrase: &[u8],
+    #[expand]
+    #[copy]
+    salt: &[u8],
+    iterations: u32,
+) -> CResult<CSecretSlice, CErrorStr> {
+    pbkdf2_sha256(passphrase, salt, iterations)
+        .map(|key| key.to_vec().into())
+        .map_err(Into::into)
+        .into()
+}
+
+/// Derives a pickle key from `passphrase` with Argon2id, see [`argon2id`].
+#[ffi]
+#[sret]
+#[throws]
+pub fn vodozemac_pickle_key_argon2id(
+    #[expand]
+    #[copy]
+    passphrase: &[u8],
+    #[expand]
+    #[copy]
+    salt: &[u8],
+    memory_kib: u32,
+    iterations: u32,
+    parallelism: u32,
+) -> CResult<CSecretSlice, CErrorStr> {
+    argon2id(passphrase, salt, memory_kib, iterations, parallelism)
+        .map(|key| key.to_vec().into())
+        .map_err(Into::into)
+        .into()
+}
+
+/// Encrypts `value` the same way vodozemac encrypts its own pickles, for the
+/// types this crate adds on top of them.
+pub fn encrypt<T: Serialize>(value: &T, pickle_key: &[u8; 32]) -> String {
+    let mut json = serde_json::to_vec(value).expect("pickles can be serialized");
+    let ciphertext = Cipher::new_pickle(pickle_key).encrypt_pickle(&json);
+    json.zeroize();
+
+    base64_encode(ciphertext)
+}
+
+/// Decrypts a pickle produced by [`encrypt`].
+pub fn decrypt<T: DeserializeOwned>(
+    ciphertext: &str,
+    pickle_key: &[u8; 32],
+) -> Result<T, PickleError> {
+    let decoded = base64_decode(ciphertext)?;
+    let json = Zeroizing::new(Cipher::new_pickle(pickle_key).decrypt_pickle(&decoded)?);
+
+    Ok(serde_json::from_slice(&json)?)
+}
+
+crate::define_and_assert_ptr_sized! {
+    /// The concatenated re-encrypted pickles, `lengths[i]` is the length of
+    /// the `i`th pickle.
+    pub struct RekeyedPickles {
+        pickles: CSlice<u8>,
+        lengths: CSlice<u32>,
+    }
+}
+
+impl AsUsize for RekeyedPickles {
+    const WORDS: usize = CSlice::<u8>::WORDS + CSlice::<u32>::WORDS;
+
+    type IntoIter = Chain<array::IntoIter<usize, 2>, array::IntoIter<usize, 2>>;
+
+    fn as_usize(&self) -> Self::IntoIter {
+        self.pickles.as_usize().chain_exact(self.lengths.as_usize())
+    }
+}
+
+/// Decrypts every pickle with `old_key` and encrypts it again with `new_key`.
+///
+/// `pickles` contains the concatenated encrypted pickles, their lengths are
+/// given by `pickle_lengths` and have to add up to the size of `pickles`.
+/// Nothing is returned unless every pickle could be decrypted, the error names
+/// the index of the first one that could not.
+pub fn rekey<P>(
+    pickles: &[u8],
+    pickle_lengths: &[u32],
+    old_key: &[u8; 32],
+    new_key: &[u8; 32],
+    decrypt: impl Fn(&str, &[u8; 32]) -> Result<P, PickleError>,
+    encrypt: impl Fn(P, &[u8; 32]) -> String,
+) -> CResult<RekeyedPickles, CErrorStr> {
+    let pickles = match split_concatenated(pickles, pickle_lengths) {
+        Ok(pickles) => pickles,
+        Err(error) => return CResult::Err(error.into()),
+    };
+
+    let mut rekeyed = String::new();
+    let mut lengths = Vec::with_capacity(pickles.len());
+
+    for (index, pickle) in pickles.into_iter().enumerate() {
+        // Pickles are base64, anything else fails to decode below.
+        let pickle = String::from_utf8_lossy(pickle);
+
+        let pickle = match decrypt(&pickle, old_key) {
+            Ok(pickle) => encrypt(pickle, new_key),
+            Err(error) => {
+                let details = ErrorDetails::of(&error);
+                return CResult::Err(CErrorStr::new(format!("pickle {index}: {error}"), details));
+            }
+        };
+
+        rekeyed.push_str(&pickle);
+        lengths.push(pickle.len() as u32);
+    }
+
+    CResult::Ok(RekeyedPickles {
+        pickles: rekeyed.into(),
+        lengths: lengths.into(),
+    })
+}
@@ -0,0 +1,66 @@
+// SPDX-FileCopyrightText: 2025 Felix Hilgers <contact@fhilgers.com>
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! A seedable RNG for generating reproducible test vectors, enabled by the
+//! `deterministic-rng` feature. Never enable it in a release build.
+//!
+//! vodozemac draws all randomness from `rand::thread_rng`, which seeds itself
+//! through getrandom. getrandom only lets us replace its backend on targets
+//! without a native source of randomness, so the seed reaches vodozemac only
+//! on `wasm32-unknown-unknown`. The feature builds on every target,
+//! [`vodozemac_rng_set_seed`] reports whether the seed took effect.
+
+use crate::ffi;
+use parking_lot::Mutex;
+use rand::RngCore;
+use rand_chacha::ChaCha20Rng;
+use rand_chacha::rand_core::SeedableRng;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Whether getrandom draws from [`fill`] on this target.
+pub const SEEDABLE: bool = cfg!(all(target_arch = "wasm32", target_os = "unknown"));
+
+static RNG: Mutex<Option<ChaCha20Rng>> = Mutex::new(None);
+
+/// Set whenever getrandom draws from [`RNG`].
+static DRAWN: AtomicBool = AtomicBool::new(false);
+
+pub fn fill(buf: &mut [u8]) {
+    DRAWN.store(true, Ordering::Relaxed);
+    RNG.lock()
+        .get_or_insert_with(|| ChaCha20Rng::from_seed([0; 32]))
+        .fill_bytes(buf)
+}
+
+/// Makes `thread_rng` seed itself from [`RNG`] again.
+///
+/// `thread_rng` only draws a new seed once it has produced 64 KiB, so it is
+/// drained word by word until it does. A fresh `thread_rng` draws its seed on
+/// creation instead. Either way exactly one word of the new stream is
+/// consumed, which keeps everything generated afterwards independent of what
+/// was generated before the seed was set.
+fn reseed_thread_rng() {
+    DRAWN.store(false, Ordering::Relaxed);
+    let mut rng = rand::thread_rng();
+
+    if DRAWN.load(Ordering::Relaxed) {
+        rng.next_u32();
+    }
+    while !DRAWN.load(Ordering::Relaxed) {
+        rng.next_u32();
+    }
+}
+
+/// Seeds the RNG and reseeds `thread_rng` from it, returns whether the seed
+/// reaches vodozemac, see [`SEEDABLE`].
+#[ffi]
+pub fn vodozemac_rng_set_seed(seed: &[u8; 32]) -> u32 {
+    *RNG.lock() = Some(ChaCha20Rng::from_seed(*seed));
+
+    if SEEDABLE {
+        reseed_thread_rng();
+    }
+
+    SEEDABLE.into()
+}
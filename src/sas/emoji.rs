@@ -0,0 +1,58 @@
+// SPDX-FileCopyrightText: 2025 Felix Hilgers <contact@fhilgers.com>
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! The SAS emoji table of the Matrix specification.
+//!
+//! `sas-emoji.json` has the format of `data-definitions/sas-emoji.json` from
+//! the specification repository. Besides English it carries German, Spanish,
+//! French, Italian, Dutch and Portuguese descriptions, copying a newer upstream
+//! file over adds more languages without any code changes.
+
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::sync::OnceLock;
+
+const TABLE: &str = include_str!("sas-emoji.json");
+
+#[derive(Deserialize)]
+pub struct Emoji {
+    pub number: u8,
+    pub emoji: String,
+    pub description: String,
+    pub translated_descriptions: HashMap<String, String>,
+}
+
+impl Emoji {
+    /// The description in `locale`, falling back to the language without the
+    /// region and then to English.
+    ///
+    /// Locales are matched like the keys of the table, `pt_BR` and `pt-BR` are
+    /// the same.
+    pub fn description(&self, locale: &str) -> &str {
+        let locale = locale.replace('-', "_");
+        let language = locale.split('_').next().unwrap_or_default();
+
+        self.translated_descriptions
+            .get(&locale)
+            .or_else(|| self.translated_descriptions.get(language))
+            .unwrap_or(&self.description)
+    }
+}
+
+pub fn table() -> &'static [Emoji] {
+    static EMOJIS: OnceLock<Vec<Emoji>> = OnceLock::new();
+
+    EMOJIS.get_or_init(|| {
+        let emojis: Vec<Emoji> = serde_json::from_str(TABLE).expect("the emoji table is valid");
+        assert!(
+            emojis
+                .iter()
+                .enumerate()
+                .all(|(i, e)| e.number as usize == i)
+                && emojis.len() == 64,
+            "the emoji table has 64 entries in order"
+        );
+        emojis
+    })
+}
@@ -2,28 +2,29 @@
 //
 // SPDX-License-Identifier: Apache-2.0
 
-use crate::{boxed, free};
-use jni::JNIEnv;
-use jni::objects::JClass;
+use crate::sas::mac::{MacError, MacMethod};
+use crate::sas::verification;
+use crate::slices::{CErrorStr, CSlice};
+use crate::{CResult, NativeMethod, ZST, boxed, free};
 use macros::ffi;
 use std::ptr::NonNull;
 use std::str;
 use vodozemac::Curve25519PublicKey;
 use vodozemac::sas::{EstablishedSas, Mac, SasBytes};
 
-pub fn register_jni(env: &mut JNIEnv, class: &JClass) -> jni::errors::Result<()> {
-    env.register_native_methods(
-        class,
-        &[
-            VODOZEMAC_SAS_ESTABLISHED_SAS_FREE_JNI.into(),
-            VODOZEMAC_SAS_ESTABLISHED_SAS_BYTES_JNI.into(),
-            VODOZEMAC_SAS_ESTABLISHED_SAS_CALCULATE_MAC_JNI.into(),
-            VODOZEMAC_SAS_ESTABLISHED_SAS_VERIFY_MAC_JNI.into(),
-            VODOZEMAC_SAS_ESTABLISHED_SAS_OUR_PUBLIC_KEY_JNI.into(),
-            VODOZEMAC_SAS_ESTABLISHED_SAS_THEIR_PUBLIC_KEY_JNI.into(),
-        ],
-    )
-}
+pub const JNI_METHODS: &[NativeMethod] = &[
+    VODOZEMAC_SAS_ESTABLISHED_SAS_FREE_JNI,
+    VODOZEMAC_SAS_ESTABLISHED_SAS_BYTES_JNI,
+    VODOZEMAC_SAS_ESTABLISHED_SAS_CALCULATE_MAC_JNI,
+    VODOZEMAC_SAS_ESTABLISHED_SAS_VERIFY_MAC_JNI,
+    VODOZEMAC_SAS_ESTABLISHED_SAS_CALCULATE_MAC_INVALID_BASE64_JNI,
+    VODOZEMAC_SAS_ESTABLISHED_SAS_CALCULATE_MAC_WITH_METHOD_JNI,
+    VODOZEMAC_SAS_ESTABLISHED_SAS_VERIFY_MAC_WITH_METHOD_JNI,
+    VODOZEMAC_SAS_ESTABLISHED_SAS_OUR_PUBLIC_KEY_JNI,
+    VODOZEMAC_SAS_ESTABLISHED_SAS_THEIR_PUBLIC_KEY_JNI,
+    VODOZEMAC_SAS_ESTABLISHED_SAS_CALCULATE_COMMITMENT_JNI,
+    VODOZEMAC_SAS_ESTABLISHED_SAS_VERIFY_COMMITMENT_JNI,
+];
 
 #[ffi]
 pub fn vodozemac_sas_established_sas_free(sas: NonNull<EstablishedSas>) {
@@ -63,6 +64,87 @@ pub fn vodozemac_sas_established_sas_verify_mac(
     sas.verify_mac(input, info, tag).is_ok().into()
 }
 
+/// The MAC of the `hkdf-hmac-sha256` method as libolm encoded it, only use
+/// this to interoperate with clients that do not support
+/// `hkdf-hmac-sha256.v2`.
+#[ffi]
+#[sret]
+#[throws]
+pub fn vodozemac_sas_established_sas_calculate_mac_invalid_base64(
+    sas: &EstablishedSas,
+    #[expand] input: &[u8],
+    #[expand] info: &[u8],
+) -> CResult<CSlice<u8>, CErrorStr> {
+    fn calculate(sas: &EstablishedSas, input: &[u8], info: &[u8]) -> Result<String, CErrorStr> {
+        let input = str::from_utf8(input)?;
+        let info = str::from_utf8(info)?;
+
+        Ok(sas.calculate_mac_invalid_base64(input, info))
+    }
+
+    calculate(sas, input, info).map(Into::into).into()
+}
+
+/// Calculates the base64 encoded MAC with one of the `MAC_METHOD_*`
+/// constants.
+#[ffi]
+#[sret]
+#[throws]
+pub fn vodozemac_sas_established_sas_calculate_mac_with_method(
+    sas: &EstablishedSas,
+    method: u32,
+    #[expand] input: &[u8],
+    #[expand] info: &[u8],
+) -> CResult<CSlice<u8>, CErrorStr> {
+    fn calculate(
+        sas: &EstablishedSas,
+        method: u32,
+        input: &[u8],
+        info: &[u8],
+    ) -> Result<String, CErrorStr> {
+        let method = MacMethod::from_u32(method)?;
+        let input = str::from_utf8(input)?;
+        let info = str::from_utf8(info)?;
+
+        Ok(method.calculate(sas, input, info))
+    }
+
+    calculate(sas, method, input, info).map(Into::into).into()
+}
+
+/// Verifies the base64 encoded `tag` with one of the `MAC_METHOD_*`
+/// constants, fails if the MAC does not match.
+#[ffi]
+#[sret]
+#[throws]
+pub fn vodozemac_sas_established_sas_verify_mac_with_method(
+    sas: &EstablishedSas,
+    method: u32,
+    #[expand] input: &[u8],
+    #[expand] info: &[u8],
+    #[expand] tag: &[u8],
+) -> CResult<ZST, CErrorStr> {
+    fn verify(
+        sas: &EstablishedSas,
+        method: u32,
+        input: &[u8],
+        info: &[u8],
+        tag: &[u8],
+    ) -> Result<(), CErrorStr> {
+        let method = MacMethod::from_u32(method)?;
+        let input = str::from_utf8(input)?;
+        let info = str::from_utf8(info)?;
+        let tag = str::from_utf8(tag)?;
+
+        if !method.verify(sas, input, info, tag) {
+            return Err(MacError::Mismatch.into());
+        }
+        Ok(())
+    }
+
+    verify(sas, method, input, info, tag).map(Into::into).into()
+}
+
 #[ffi]
 pub fn vodozemac_sas_established_sas_our_public_key(
     sas: &EstablishedSas,
@@ -76,3 +158,34 @@ pub fn vodozemac_sas_established_sas_their_public_key(
 ) -> NonNull<Curve25519PublicKey> {
     boxed(sas.their_public_key())
 }
+
+/// Commits to our public key, like
+/// [`crate::keys::vodozemac_curve25519_public_key_calculate_commitment`].
+#[ffi]
+#[sret]
+#[throws]
+pub fn vodozemac_sas_established_sas_calculate_commitment(
+    sas: &EstablishedSas,
+    #[expand] start_content: &[u8],
+) -> CResult<CSlice<u8>, CErrorStr> {
+    verification::calculate_commitment(&sas.our_public_key(), start_content)
+        .map(Into::into)
+        .map_err(Into::into)
+        .into()
+}
+
+/// Checks the commitment the other side sent in its `m.key.verification.accept`
+/// content against their public key.
+#[ffi]
+#[sret]
+#[throws]
+pub fn vodozemac_sas_established_sas_verify_commitment(
+    sas: &EstablishedSas,
+    #[expand] start_content: &[u8],
+    #[expand] commitment: &[u8],
+) -> CResult<ZST, CErrorStr> {
+    verification::verify_commitment(&sas.their_public_key(), start_content, commitment)
+        .map(Into::into)
+        .map_err(Into::into)
+        .into()
+}
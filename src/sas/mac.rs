@@ -2,22 +2,96 @@
 //
 // SPDX-License-Identifier: Apache-2.0
 
-use crate::{boxed, free};
-use jni::JNIEnv;
-use jni::objects::JClass;
+use crate::{NativeMethod, boxed, free};
 use macros::ffi;
+use std::error::Error;
+use std::fmt::{Display, Formatter};
 use std::ptr::NonNull;
-use vodozemac::sas::Mac;
-
-pub fn register_jni(env: &mut JNIEnv, class: &JClass) -> jni::errors::Result<()> {
-    env.register_native_methods(
-        class,
-        &[
-            VODOZEMAC_SAS_MAC_FREE_JNI.into(),
-            VODOZEMAC_SAS_MAC_AS_BYTES_JNI.into(),
-            VODOZEMAC_SAS_MAC_FROM_SLICE_JNI.into(),
-        ],
-    )
+use vodozemac::sas::{EstablishedSas, Mac};
+
+pub const JNI_METHODS: &[NativeMethod] = &[
+    VODOZEMAC_SAS_MAC_FREE_JNI,
+    VODOZEMAC_SAS_MAC_AS_BYTES_JNI,
+    VODOZEMAC_SAS_MAC_FROM_SLICE_JNI,
+];
+
+pub const MAC_METHOD_HKDF_HMAC_SHA256_V2: u32 = 0;
+/// The MAC method of libolm, which encodes its MACs as invalid base64.
+pub const MAC_METHOD_HKDF_HMAC_SHA256: u32 = 1;
+
+#[derive(Debug)]
+pub enum MacError {
+    UnknownMethod(u32),
+    Mismatch,
+}
+
+impl Display for MacError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MacError::UnknownMethod(method) => write!(f, "unknown MAC method {method}"),
+            MacError::Mismatch => write!(f, "the MAC does not match"),
+        }
+    }
+}
+
+impl Error for MacError {}
+
+/// The `message_authentication_code` methods of `m.sas.v1`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MacMethod {
+    HkdfHmacSha256V2,
+    HkdfHmacSha256,
+}
+
+impl MacMethod {
+    /// In order of preference.
+    pub const ALL: [MacMethod; 2] = [MacMethod::HkdfHmacSha256V2, MacMethod::HkdfHmacSha256];
+
+    pub fn from_u32(method: u32) -> Result<Self, MacError> {
+        match method {
+            MAC_METHOD_HKDF_HMAC_SHA256_V2 => Ok(MacMethod::HkdfHmacSha256V2),
+            MAC_METHOD_HKDF_HMAC_SHA256 => Ok(MacMethod::HkdfHmacSha256),
+            _ => Err(MacError::UnknownMethod(method)),
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|method| method.name() == name)
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            MacMethod::HkdfHmacSha256V2 => "hkdf-hmac-sha256.v2",
+            MacMethod::HkdfHmacSha256 => "hkdf-hmac-sha256",
+        }
+    }
+
+    /// The base64 encoded MAC, as it is sent in `m.key.verification.mac`.
+    pub fn calculate(self, sas: &EstablishedSas, input: &str, info: &str) -> String {
+        match self {
+            MacMethod::HkdfHmacSha256V2 => sas.calculate_mac(input, info).to_base64(),
+            MacMethod::HkdfHmacSha256 => sas.calculate_mac_invalid_base64(input, info),
+        }
+    }
+
+    pub fn verify(self, sas: &EstablishedSas, input: &str, info: &str, tag: &str) -> bool {
+        match self {
+            MacMethod::HkdfHmacSha256V2 => {
+                Mac::from_base64(tag).is_ok_and(|tag| sas.verify_mac(input, info, &tag).is_ok())
+            }
+            MacMethod::HkdfHmacSha256 => {
+                // The invalid base64 can not be decoded, compare the encoded
+                // MACs without branching on their contents instead.
+                let expected = sas.calculate_mac_invalid_base64(input, info);
+                expected.len() == tag.len()
+                    && expected
+                        .bytes()
+                        .zip(tag.bytes())
+                        .fold(0, |difference, (a, b)| difference | (a ^ b))
+                        == 0
+            }
+        }
+    }
 }
 
 #[ffi]
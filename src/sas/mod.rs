@@ -2,39 +2,40 @@
 //
 // SPDX-License-Identifier: Apache-2.0
 
-use jni::JNIEnv;
-use jni::objects::JClass;
+use crate::JniModule;
 use parking_lot::RwLock;
 
+pub mod emoji;
 pub mod established_sas;
 pub mod mac;
 
 #[allow(clippy::module_inception)]
 pub mod sas;
 pub mod sas_bytes;
+pub mod verification;
 
-pub type Sas = RwLock<Option<vodozemac::sas::Sas>>;
+pub type Sas = RwLock<sas::SasState>;
+pub type Verification = RwLock<verification::SasVerification>;
 
-pub struct SasJniClasses<'local, 'a> {
-    pub established_sas: &'a JClass<'local>,
-    pub mac: &'a JClass<'local>,
-    pub sas: &'a JClass<'local>,
-    pub sas_bytes: &'a JClass<'local>,
-}
-
-pub fn register_jni(
-    env: &mut JNIEnv,
-    SasJniClasses {
-        established_sas,
-        mac,
-        sas,
-        sas_bytes,
-    }: &SasJniClasses,
-) -> jni::errors::Result<()> {
-    established_sas::register_jni(env, established_sas)?;
-    mac::register_jni(env, mac)?;
-    sas::register_jni(env, sas)?;
-    sas_bytes::register_jni(env, sas_bytes)?;
-
-    Ok(())
-}
+pub const JNI_MODULES: &[JniModule] = &[
+    JniModule {
+        class: "sas/EstablishedSasBindingsKt",
+        methods: established_sas::JNI_METHODS,
+    },
+    JniModule {
+        class: "sas/MacBindingsKt",
+        methods: mac::JNI_METHODS,
+    },
+    JniModule {
+        class: "sas/SasBindingsKt",
+        methods: sas::JNI_METHODS,
+    },
+    JniModule {
+        class: "sas/SasBytesBindingsKt",
+        methods: sas_bytes::JNI_METHODS,
+    },
+    JniModule {
+        class: "sas/VerificationBindingsKt",
+        methods: verification::JNI_METHODS,
+    },
+];
@@ -3,49 +3,141 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use crate::sas::Sas;
-use crate::{boxed, free};
-use jni::JNIEnv;
-use jni::objects::JClass;
+use crate::slices::CErrorStr;
+use crate::{CResult, NativeMethod, boxed, free};
 use macros::ffi;
 use parking_lot::RwLock;
+use std::error::Error;
+use std::fmt::{Display, Formatter};
 use std::ptr::NonNull;
 use vodozemac::sas::EstablishedSas;
-use vodozemac::{Curve25519PublicKey, sas};
+use vodozemac::{Curve25519PublicKey, Curve25519SecretKey, KeyError, sas};
 
-pub fn register_jni(env: &mut JNIEnv, class: &JClass) -> jni::errors::Result<()> {
-    env.register_native_methods(
-        class,
-        &[
-            VODOZEMAC_SAS_SAS_NEW_JNI.into(),
-            VODOZEMAC_SAS_SAS_PUBLIC_KEY_JNI.into(),
-            VODOZEMAC_SAS_SAS_DIFFIE_HELLMAN_JNI.into(),
-            VODOZEMAC_SAS_SAS_FREE_JNI.into(),
-        ],
-    )
+pub const JNI_METHODS: &[NativeMethod] = &[
+    VODOZEMAC_SAS_SAS_NEW_JNI,
+    VODOZEMAC_SAS_SAS_PUBLIC_KEY_JNI,
+    VODOZEMAC_SAS_SAS_IS_USED_JNI,
+    VODOZEMAC_SAS_SAS_DIFFIE_HELLMAN_JNI,
+    VODOZEMAC_SAS_SAS_DIFFIE_HELLMAN_WITH_RAW_JNI,
+    VODOZEMAC_SAS_SAS_FREE_JNI,
+];
+
+#[derive(Debug)]
+pub enum DiffieHellmanError {
+    AlreadyUsed,
+    InvalidKey(KeyError),
+}
+
+impl Display for DiffieHellmanError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DiffieHellmanError::AlreadyUsed => {
+                write!(f, "the Diffie-Hellman key exchange already happened")
+            }
+            DiffieHellmanError::InvalidKey(error) => write!(f, "{error}"),
+        }
+    }
+}
+
+impl Error for DiffieHellmanError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            DiffieHellmanError::AlreadyUsed => None,
+            DiffieHellmanError::InvalidKey(error) => Some(error),
+        }
+    }
+}
+
+/// The ephemeral secret is consumed by the key exchange, the public key stays
+/// readable afterwards.
+pub struct SasState {
+    sas: Option<sas::Sas>,
+    public_key: Curve25519PublicKey,
+}
+
+impl SasState {
+    fn new() -> Self {
+        let sas = sas::Sas::new();
+        Self {
+            public_key: sas.public_key(),
+            sas: Some(sas),
+        }
+    }
+
+    fn diffie_hellman(
+        &mut self,
+        their_public_key: Curve25519PublicKey,
+    ) -> Result<EstablishedSas, DiffieHellmanError> {
+        if self.sas.is_none() {
+            return Err(DiffieHellmanError::AlreadyUsed);
+        }
+
+        // Only keys of small order make the exchange non-contributory, and
+        // they do so for every secret. Checking with a throwaway secret keeps
+        // ours around, so a rejected key can be retried with another one.
+        if !Curve25519SecretKey::new()
+            .diffie_hellman(&their_public_key)
+            .was_contributory()
+        {
+            return Err(DiffieHellmanError::InvalidKey(KeyError::NonContributoryKey));
+        }
+
+        let sas = self.sas.take().expect("the secret was checked above");
+        sas.diffie_hellman(their_public_key)
+            .map_err(DiffieHellmanError::InvalidKey)
+    }
 }
 
 #[ffi]
 pub fn vodozemac_sas_sas_new() -> NonNull<Sas> {
-    boxed(RwLock::new(Some(sas::Sas::new())))
+    boxed(RwLock::new(SasState::new()))
 }
 
 #[ffi]
 pub fn vodozemac_sas_sas_public_key(sas: &Sas) -> NonNull<Curve25519PublicKey> {
-    boxed(sas.read().as_ref().expect("sas is not used").public_key())
+    boxed(sas.read().public_key)
+}
+
+/// Whether the key exchange already happened, after which
+/// [`vodozemac_sas_sas_diffie_hellman`] fails.
+#[ffi]
+pub fn vodozemac_sas_sas_is_used(sas: &Sas) -> u32 {
+    sas.read().sas.is_none().into()
 }
 
+/// Establishes the shared secret with their public key. A rejected key does
+/// not use up `sas`, the exchange can be retried with another key.
 #[ffi]
+#[sret]
+#[throws]
 pub fn vodozemac_sas_sas_diffie_hellman(
     sas: &Sas,
     their_public_key: &Curve25519PublicKey,
-) -> Option<NonNull<EstablishedSas>> {
-    Some(boxed(
-        sas.write()
-            .take()
-            .expect("not used")
-            .diffie_hellman(*their_public_key)
-            .ok()?,
-    ))
+) -> CResult<NonNull<EstablishedSas>, CErrorStr> {
+    sas.write()
+        .diffie_hellman(*their_public_key)
+        .map(boxed)
+        .map_err(Into::into)
+        .into()
+}
+
+/// Like [`vodozemac_sas_sas_diffie_hellman`], with the base64 encoded key of
+/// the `m.key.verification.key` content.
+#[ffi]
+#[sret]
+#[throws]
+pub fn vodozemac_sas_sas_diffie_hellman_with_raw(
+    sas: &Sas,
+    #[expand] their_public_key: &[u8],
+) -> CResult<NonNull<EstablishedSas>, CErrorStr> {
+    let their_public_key = String::from_utf8_lossy(their_public_key);
+
+    Curve25519PublicKey::from_base64(&their_public_key)
+        .map_err(DiffieHellmanError::InvalidKey)
+        .and_then(|key| sas.write().diffie_hellman(key))
+        .map(boxed)
+        .map_err(Into::into)
+        .into()
 }
 
 #[ffi]
@@ -2,24 +2,22 @@
 //
 // SPDX-License-Identifier: Apache-2.0
 
-use crate::free;
-use jni::JNIEnv;
-use jni::objects::JClass;
+use crate::sas::emoji;
+use crate::slices::CSlice;
+use crate::{NativeMethod, free};
 use macros::ffi;
+use serde_json::{Value, json};
 use std::ptr::NonNull;
+use std::str;
 use vodozemac::sas::SasBytes;
 
-pub fn register_jni(env: &mut JNIEnv, class: &JClass) -> jni::errors::Result<()> {
-    env.register_native_methods(
-        class,
-        &[
-            VODOZEMAC_SAS_SAS_BYTES_FREE_JNI.into(),
-            VODOZEMAC_SAS_SAS_BYTES_EMOJI_INDICES_JNI.into(),
-            VODOZEMAC_SAS_SAS_BYTES_DECIMALS_JNI.into(),
-            VODOZEMAC_SAS_SAS_BYTES_AS_BYTES_JNI.into(),
-        ],
-    )
-}
+pub const JNI_METHODS: &[NativeMethod] = &[
+    VODOZEMAC_SAS_SAS_BYTES_FREE_JNI,
+    VODOZEMAC_SAS_SAS_BYTES_EMOJI_INDICES_JNI,
+    VODOZEMAC_SAS_SAS_BYTES_EMOJIS_JNI,
+    VODOZEMAC_SAS_SAS_BYTES_DECIMALS_JNI,
+    VODOZEMAC_SAS_SAS_BYTES_AS_BYTES_JNI,
+];
 
 #[ffi]
 pub fn vodozemac_sas_sas_bytes_free(sas_bytes: NonNull<SasBytes>) {
@@ -34,6 +32,27 @@ pub fn vodozemac_sas_sas_bytes_emoji_indices(
     emoji_indices_out.copy_from_slice(&sas_bytes.emoji_indices())
 }
 
+/// The seven emojis of the short authentication string as a JSON array of
+/// `{"emoji": "🐶", "description": "Dog"}` objects, the descriptions are in
+/// `locale` if the specification has a translation for it and in English
+/// otherwise. A `locale` that isn't valid UTF-8 is treated as English.
+#[ffi]
+#[sret]
+pub fn vodozemac_sas_sas_bytes_emojis(sas_bytes: &SasBytes, #[expand] locale: &[u8]) -> CSlice<u8> {
+    let locale = str::from_utf8(locale).unwrap_or("en");
+    let table = emoji::table();
+
+    let emojis: Vec<_> = sas_bytes
+        .emoji_indices()
+        .into_iter()
+        .map(|index| {
+            let emoji = &table[index as usize];
+            json!({ "emoji": emoji.emoji, "description": emoji.description(locale) })
+        })
+        .collect();
+    Value::from(emojis).to_string().into()
+}
+
 #[ffi]
 pub fn vodozemac_sas_sas_bytes_decimals(sas_bytes: &SasBytes, decimals_out: &mut [u16; 3]) {
     let decimals = sas_bytes.decimals();
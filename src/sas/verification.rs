@@ -0,0 +1,857 @@
+// SPDX-FileCopyrightText: 2025 Felix Hilgers <contact@fhilgers.com>
+//
+// SPDX-License-Identifier: Apache-2.0
+
+//! The `m.sas.v1` interactive verification flow.
+//!
+//! A [`SasVerification`] consumes the contents of the `m.key.verification.*`
+//! events sent by the other device and produces the contents we have to send
+//! back, every function returns them as a JSON array of
+//! `{"type": "m.key.verification.key", "content": {...}}` objects. Protocol
+//! violations do not surface as errors, they cancel the verification and the
+//! `m.key.verification.cancel` event is returned like any other reply.
+
+use crate::canonical_json::canonical_json;
+use crate::sas::Verification;
+use crate::sas::mac::MacMethod;
+use crate::slices::{CErrorStr, CSlice};
+use crate::{CResult, NativeMethod, boxed, free};
+use macros::ffi;
+use parking_lot::RwLock;
+use serde::Deserialize;
+use serde::de::DeserializeOwned;
+use serde_json::{Map, Value, json};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::mem;
+use std::ptr::NonNull;
+use std::str::{self, Utf8Error};
+use vodozemac::sas::{EstablishedSas, Sas, SasBytes};
+use vodozemac::{Curve25519PublicKey, base64_encode};
+
+pub const JNI_METHODS: &[NativeMethod] = &[
+    VODOZEMAC_SAS_VERIFICATION_NEW_JNI,
+    VODOZEMAC_SAS_VERIFICATION_FREE_JNI,
+    VODOZEMAC_SAS_VERIFICATION_STATE_JNI,
+    VODOZEMAC_SAS_VERIFICATION_START_JNI,
+    VODOZEMAC_SAS_VERIFICATION_ACCEPT_JNI,
+    VODOZEMAC_SAS_VERIFICATION_RECEIVE_JNI,
+    VODOZEMAC_SAS_VERIFICATION_SHORT_AUTH_STRING_JNI,
+    VODOZEMAC_SAS_VERIFICATION_SUPPORTS_EMOJI_JNI,
+    VODOZEMAC_SAS_VERIFICATION_CONFIRM_JNI,
+    VODOZEMAC_SAS_VERIFICATION_CANCEL_JNI,
+    VODOZEMAC_SAS_VERIFICATION_CANCEL_CODE_JNI,
+    VODOZEMAC_SAS_VERIFICATION_VERIFIED_KEYS_JNI,
+];
+
+pub const METHOD: &str = "m.sas.v1";
+pub const KEY_AGREEMENT_PROTOCOL: &str = "curve25519-hkdf-sha256";
+pub const HASH: &str = "sha256";
+pub const DECIMAL: &str = "decimal";
+pub const EMOJI: &str = "emoji";
+
+pub const START: &str = "m.key.verification.start";
+pub const ACCEPT: &str = "m.key.verification.accept";
+pub const KEY: &str = "m.key.verification.key";
+pub const MAC: &str = "m.key.verification.mac";
+pub const DONE: &str = "m.key.verification.done";
+pub const CANCEL: &str = "m.key.verification.cancel";
+
+pub const CANCEL_USER: &str = "m.user";
+pub const CANCEL_UNKNOWN_TRANSACTION: &str = "m.unknown_transaction";
+pub const CANCEL_UNKNOWN_METHOD: &str = "m.unknown_method";
+pub const CANCEL_UNEXPECTED_MESSAGE: &str = "m.unexpected_message";
+pub const CANCEL_KEY_MISMATCH: &str = "m.key_mismatch";
+pub const CANCEL_INVALID_MESSAGE: &str = "m.invalid_message";
+pub const CANCEL_MISMATCHED_COMMITMENT: &str = "m.mismatched_commitment";
+pub const CANCEL_MISMATCHED_SAS: &str = "m.mismatched_sas";
+
+/// Nothing was sent or received yet.
+pub const STATE_CREATED: u32 = 0;
+/// The `start` event was sent or received.
+pub const STATE_STARTED: u32 = 1;
+/// The `accept` event was sent or received, the keys are being exchanged.
+pub const STATE_ACCEPTED: u32 = 2;
+/// The short authentication string can be shown to the user.
+pub const STATE_KEYS_EXCHANGED: u32 = 3;
+/// The user confirmed the short authentication string, our MAC was sent.
+pub const STATE_CONFIRMED: u32 = 4;
+/// Their MAC was verified, we are waiting for their `done` event.
+pub const STATE_MAC_VERIFIED: u32 = 5;
+pub const STATE_DONE: u32 = 6;
+pub const STATE_CANCELLED: u32 = 7;
+
+#[derive(Debug)]
+pub enum VerificationError {
+    UnexpectedState(&'static str),
+    InvalidKeys(serde_json::Error),
+}
+
+impl Display for VerificationError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            VerificationError::UnexpectedState(action) => {
+                write!(f, "the verification can not {action} in its current state")
+            }
+            VerificationError::InvalidKeys(error) => write!(f, "invalid keys: {error}"),
+        }
+    }
+}
+
+impl Error for VerificationError {}
+
+#[derive(Debug)]
+pub enum CommitmentError {
+    Json(serde_json::Error),
+    Mismatch,
+}
+
+impl Display for CommitmentError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CommitmentError::Json(error) => write!(f, "invalid start content: {error}"),
+            CommitmentError::Mismatch => write!(f, "the commitment does not match"),
+        }
+    }
+}
+
+impl Error for CommitmentError {}
+
+struct Cancellation {
+    code: String,
+    reason: String,
+}
+
+impl Cancellation {
+    fn new(code: &str, reason: impl Into<String>) -> Self {
+        Self {
+            code: code.to_owned(),
+            reason: reason.into(),
+        }
+    }
+}
+
+struct Party {
+    user_id: String,
+    device_id: String,
+}
+
+/// The `start` content of the verification, the accepting side commits to
+/// its public key and this content.
+struct Commitment {
+    commitment: String,
+    start: Value,
+}
+
+enum State {
+    Created,
+    Started {
+        sas: Sas,
+        start: Value,
+    },
+    Accepted {
+        sas: Sas,
+        // Only the starting side has to check a commitment.
+        commitment: Option<Commitment>,
+    },
+    KeysExchanged {
+        established: EstablishedSas,
+        their_mac: Option<MacContent>,
+    },
+    Confirmed {
+        established: EstablishedSas,
+        their_keys: BTreeMap<String, String>,
+    },
+    MacVerified {
+        established: EstablishedSas,
+    },
+    Done,
+    Cancelled {
+        code: String,
+    },
+}
+
+#[derive(Deserialize)]
+struct StartContent {
+    method: String,
+    key_agreement_protocols: Vec<String>,
+    hashes: Vec<String>,
+    message_authentication_codes: Vec<String>,
+    short_authentication_string: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct AcceptContent {
+    method: String,
+    key_agreement_protocol: String,
+    hash: String,
+    message_authentication_code: String,
+    short_authentication_string: Vec<String>,
+    commitment: String,
+}
+
+#[derive(Deserialize)]
+struct KeyContent {
+    key: String,
+}
+
+#[derive(Deserialize)]
+struct MacContent {
+    mac: BTreeMap<String, String>,
+    keys: String,
+}
+
+#[derive(Deserialize)]
+struct CancelContent {
+    code: String,
+}
+
+type Events = Vec<(&'static str, Value)>;
+
+/// `base64(SHA256(public_key || canonical_json(start)))`.
+fn commitment(public_key: &str, start: &Value) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(public_key);
+    hasher.update(canonical_json(start));
+    base64_encode(hasher.finalize())
+}
+
+/// Commits to `public_key` and the `start` content, the JSON is
+/// canonicalized first so both sides hash the same bytes.
+pub fn calculate_commitment(
+    public_key: &Curve25519PublicKey,
+    start: &[u8],
+) -> Result<String, CommitmentError> {
+    let start: Value = serde_json::from_slice(start).map_err(CommitmentError::Json)?;
+    Ok(commitment(&public_key.to_base64(), &start))
+}
+
+pub fn verify_commitment(
+    public_key: &Curve25519PublicKey,
+    start: &[u8],
+    expected: &[u8],
+) -> Result<(), CommitmentError> {
+    if calculate_commitment(public_key, start)?.as_bytes() == expected {
+        Ok(())
+    } else {
+        Err(CommitmentError::Mismatch)
+    }
+}
+
+fn parse<T: DeserializeOwned>(content: Value) -> Result<T, Cancellation> {
+    serde_json::from_value(content)
+        .map_err(|e| Cancellation::new(CANCEL_INVALID_MESSAGE, e.to_string()))
+}
+
+fn unexpected(event_type: &str) -> Cancellation {
+    Cancellation::new(
+        CANCEL_UNEXPECTED_MESSAGE,
+        format!("unexpected {event_type} event"),
+    )
+}
+
+pub struct SasVerification {
+    our: Party,
+    their: Party,
+    transaction_id: String,
+    we_started: bool,
+    emoji: bool,
+    mac_method: MacMethod,
+    verified_keys: Vec<String>,
+    state: State,
+}
+
+impl SasVerification {
+    fn state(&self) -> u32 {
+        match self.state {
+            State::Created => STATE_CREATED,
+            State::Started { .. } => STATE_STARTED,
+            State::Accepted { .. } => STATE_ACCEPTED,
+            State::KeysExchanged { .. } => STATE_KEYS_EXCHANGED,
+            State::Confirmed { .. } => STATE_CONFIRMED,
+            State::MacVerified { .. } => STATE_MAC_VERIFIED,
+            State::Done => STATE_DONE,
+            State::Cancelled { .. } => STATE_CANCELLED,
+        }
+    }
+
+    fn content(&self, mut content: Value) -> Value {
+        content["transaction_id"] = self.transaction_id.clone().into();
+        content
+    }
+
+    fn start(&mut self) -> Result<Events, VerificationError> {
+        if !matches!(self.state, State::Created) {
+            return Err(VerificationError::UnexpectedState("start"));
+        }
+
+        let start = self.content(json!({
+            "from_device": self.our.device_id,
+            "method": METHOD,
+            "key_agreement_protocols": [KEY_AGREEMENT_PROTOCOL],
+            "hashes": [HASH],
+            "message_authentication_codes": MacMethod::ALL.map(MacMethod::name),
+            "short_authentication_string": [DECIMAL, EMOJI],
+        }));
+        self.we_started = true;
+        self.state = State::Started {
+            sas: Sas::new(),
+            start: start.clone(),
+        };
+
+        Ok(vec![(START, start)])
+    }
+
+    fn accept(&mut self) -> Result<Events, VerificationError> {
+        let State::Started { sas, start } = mem::replace(&mut self.state, State::Created) else {
+            return Err(VerificationError::UnexpectedState("accept"));
+        };
+        if self.we_started {
+            self.state = State::Started { sas, start };
+            return Err(VerificationError::UnexpectedState("accept"));
+        }
+
+        let methods: Vec<_> = [DECIMAL, EMOJI]
+            .into_iter()
+            .filter(|method| *method == DECIMAL || self.emoji)
+            .collect();
+        let accept = self.content(json!({
+            "method": METHOD,
+            "key_agreement_protocol": KEY_AGREEMENT_PROTOCOL,
+            "hash": HASH,
+            "message_authentication_code": self.mac_method.name(),
+            "short_authentication_string": methods,
+            "commitment": commitment(&sas.public_key().to_base64(), &start),
+        }));
+        self.state = State::Accepted {
+            sas,
+            commitment: None,
+        };
+
+        Ok(vec![(ACCEPT, accept)])
+    }
+
+    fn receive(&mut self, event_type: &str, content: Value) -> Events {
+        if matches!(self.state, State::Done | State::Cancelled { .. }) {
+            return Vec::new();
+        }
+        // Events of other transactions are not ours to cancel.
+        if content["transaction_id"] != self.transaction_id.as_str() {
+            return Vec::new();
+        }
+
+        match self.handle(event_type, content) {
+            Ok(events) => events,
+            Err(cancellation) => self.cancel(cancellation),
+        }
+    }
+
+    fn handle(&mut self, event_type: &str, content: Value) -> Result<Events, Cancellation> {
+        if event_type == CANCEL {
+            let cancel: CancelContent = parse(content)?;
+            self.state = State::Cancelled { code: cancel.code };
+            return Ok(Vec::new());
+        }
+
+        match (mem::replace(&mut self.state, State::Created), event_type) {
+            (State::Created, START) => {
+                let start: StartContent = parse(content.clone())?;
+                let mac_method = MacMethod::ALL.into_iter().find(|method| {
+                    start
+                        .message_authentication_codes
+                        .iter()
+                        .any(|m| m == method.name())
+                });
+                let Some(mac_method) = mac_method else {
+                    return Err(Cancellation::new(
+                        CANCEL_UNKNOWN_METHOD,
+                        "none of the offered MAC methods are supported",
+                    ));
+                };
+                if start.method != METHOD
+                    || !start
+                        .key_agreement_protocols
+                        .iter()
+                        .any(|p| p == KEY_AGREEMENT_PROTOCOL)
+                    || !start.hashes.iter().any(|h| h == HASH)
+                    || !start
+                        .short_authentication_string
+                        .iter()
+                        .any(|s| s == DECIMAL)
+                {
+                    return Err(Cancellation::new(
+                        CANCEL_UNKNOWN_METHOD,
+                        "none of the offered methods are supported",
+                    ));
+                }
+
+                self.emoji = start.short_authentication_string.iter().any(|s| s == EMOJI);
+                self.mac_method = mac_method;
+                self.state = State::Started {
+                    sas: Sas::new(),
+                    start: content,
+                };
+                Ok(Vec::new())
+            }
+            (State::Started { sas, start }, ACCEPT) if self.we_started => {
+                let accept: AcceptContent = parse(content)?;
+                let mac_method = MacMethod::from_name(&accept.message_authentication_code);
+                if accept.method != METHOD
+                    || accept.key_agreement_protocol != KEY_AGREEMENT_PROTOCOL
+                    || accept.hash != HASH
+                    || mac_method.is_none()
+                    || !accept
+                        .short_authentication_string
+                        .iter()
+                        .any(|s| s == DECIMAL)
+                {
+                    return Err(Cancellation::new(
+                        CANCEL_UNKNOWN_METHOD,
+                        "the accepted method was not offered",
+                    ));
+                }
+
+                self.emoji = accept
+                    .short_authentication_string
+                    .iter()
+                    .any(|s| s == EMOJI);
+                self.mac_method = mac_method.expect("the MAC method was checked");
+                let key = self.content(json!({ "key": sas.public_key().to_base64() }));
+                self.state = State::Accepted {
+                    sas,
+                    commitment: Some(Commitment {
+                        commitment: accept.commitment,
+                        start,
+                    }),
+                };
+                Ok(vec![(KEY, key)])
+            }
+            (
+                State::Accepted {
+                    sas,
+                    commitment: expected,
+                },
+                KEY,
+            ) => {
+                let key: KeyContent = parse(content)?;
+                if let Some(Commitment {
+                    commitment: expected,
+                    start,
+                }) = &expected
+                    && commitment(&key.key, start) != *expected
+                {
+                    return Err(Cancellation::new(
+                        CANCEL_MISMATCHED_COMMITMENT,
+                        "the key does not match the commitment",
+                    ));
+                }
+
+                let their_key = Curve25519PublicKey::from_base64(&key.key)
+                    .map_err(|e| Cancellation::new(CANCEL_INVALID_MESSAGE, e.to_string()))?;
+                let our_key = sas.public_key().to_base64();
+                let established = sas
+                    .diffie_hellman(their_key)
+                    .map_err(|e| Cancellation::new(CANCEL_KEY_MISMATCH, e.to_string()))?;
+                self.state = State::KeysExchanged {
+                    established,
+                    their_mac: None,
+                };
+
+                // The accepting side sends its key only after it received the
+                // key of the starting side.
+                if expected.is_some() {
+                    Ok(Vec::new())
+                } else {
+                    Ok(vec![(KEY, self.content(json!({ "key": our_key })))])
+                }
+            }
+            (
+                State::KeysExchanged {
+                    established,
+                    their_mac: None,
+                },
+                MAC,
+            ) => {
+                // The user has not compared the short authentication string
+                // yet, the MAC is checked once they confirm it.
+                self.state = State::KeysExchanged {
+                    established,
+                    their_mac: Some(parse(content)?),
+                };
+                Ok(Vec::new())
+            }
+            (
+                State::Confirmed {
+                    established,
+                    their_keys,
+                },
+                MAC,
+            ) => {
+                self.verify_mac(&established, &their_keys, &parse(content)?)?;
+                self.state = State::MacVerified { established };
+                Ok(vec![(DONE, self.content(json!({})))])
+            }
+            (State::MacVerified { .. }, DONE) => {
+                self.state = State::Done;
+                Ok(Vec::new())
+            }
+            _ => Err(unexpected(event_type)),
+        }
+    }
+
+    fn sas_info(&self, established: &EstablishedSas) -> String {
+        let ours = (&self.our, established.our_public_key().to_base64());
+        let theirs = (&self.their, established.their_public_key().to_base64());
+        let ((starter, starter_key), (accepter, accepter_key)) = if self.we_started {
+            (ours, theirs)
+        } else {
+            (theirs, ours)
+        };
+
+        format!(
+            "MATRIX_KEY_VERIFICATION_SAS|{}|{}|{starter_key}|{}|{}|{accepter_key}|{}",
+            starter.user_id,
+            starter.device_id,
+            accepter.user_id,
+            accepter.device_id,
+            self.transaction_id,
+        )
+    }
+
+    fn short_auth_string(&self) -> Option<SasBytes> {
+        match &self.state {
+            State::KeysExchanged { established, .. }
+            | State::Confirmed { established, .. }
+            | State::MacVerified { established } => {
+                Some(established.bytes(&self.sas_info(established)))
+            }
+            _ => None,
+        }
+    }
+
+    fn mac_info(&self, sender: &Party, receiver: &Party) -> String {
+        format!(
+            "MATRIX_KEY_VERIFICATION_MAC{}{}{}{}{}",
+            sender.user_id,
+            sender.device_id,
+            receiver.user_id,
+            receiver.device_id,
+            self.transaction_id,
+        )
+    }
+
+    fn mac(&self, established: &EstablishedSas, our_keys: &BTreeMap<String, String>) -> Value {
+        let info = self.mac_info(&self.our, &self.their);
+        let mac: Map<String, Value> = our_keys
+            .iter()
+            .map(|(key_id, key)| {
+                let mac = self
+                    .mac_method
+                    .calculate(established, key, &format!("{info}{key_id}"));
+                (key_id.clone(), mac.into())
+            })
+            .collect();
+        let key_ids = our_keys.keys().cloned().collect::<Vec<_>>().join(",");
+        let keys = self
+            .mac_method
+            .calculate(established, &key_ids, &format!("{info}KEY_IDS"));
+
+        self.content(json!({ "mac": mac, "keys": keys }))
+    }
+
+    /// Checks the MAC of the key ids first, then the MAC of every key we know.
+    /// Keys we do not know are ignored, but at least one has to be verified.
+    fn verify_mac(
+        &mut self,
+        established: &EstablishedSas,
+        their_keys: &BTreeMap<String, String>,
+        content: &MacContent,
+    ) -> Result<(), Cancellation> {
+        let info = self.mac_info(&self.their, &self.our);
+        let check = |input: &str, info: String, mac: &str| {
+            if self.mac_method.verify(established, input, &info, mac) {
+                Ok(())
+            } else {
+                Err(Cancellation::new(
+                    CANCEL_KEY_MISMATCH,
+                    "the MAC does not match",
+                ))
+            }
+        };
+
+        let key_ids = content.mac.keys().cloned().collect::<Vec<_>>().join(",");
+        check(&key_ids, format!("{info}KEY_IDS"), &content.keys)?;
+
+        let mut verified = Vec::new();
+        for (key_id, mac) in &content.mac {
+            if let Some(key) = their_keys.get(key_id) {
+                check(key, format!("{info}{key_id}"), mac)?;
+                verified.push(key_id.clone());
+            }
+        }
+        if verified.is_empty() {
+            return Err(Cancellation::new(
+                CANCEL_KEY_MISMATCH,
+                "none of the keys in the MAC are known",
+            ));
+        }
+
+        self.verified_keys = verified;
+        Ok(())
+    }
+
+    fn confirm(
+        &mut self,
+        our_keys: BTreeMap<String, String>,
+        their_keys: BTreeMap<String, String>,
+    ) -> Result<Events, VerificationError> {
+        let State::KeysExchanged {
+            established,
+            their_mac,
+        } = mem::replace(&mut self.state, State::Created)
+        else {
+            return Err(VerificationError::UnexpectedState("confirm"));
+        };
+
+        let mac = self.mac(&established, &our_keys);
+        let Some(their_mac) = their_mac else {
+            self.state = State::Confirmed {
+                established,
+                their_keys,
+            };
+            return Ok(vec![(MAC, mac)]);
+        };
+
+        match self.verify_mac(&established, &their_keys, &their_mac) {
+            Ok(()) => {
+                self.state = State::MacVerified { established };
+                Ok(vec![(MAC, mac), (DONE, self.content(json!({})))])
+            }
+            Err(cancellation) => Ok(self.cancel(cancellation)),
+        }
+    }
+
+    fn cancel(&mut self, cancellation: Cancellation) -> Events {
+        if matches!(self.state, State::Done | State::Cancelled { .. }) {
+            return Vec::new();
+        }
+
+        let cancel = self.content(json!({
+            "code": cancellation.code,
+            "reason": cancellation.reason,
+        }));
+        self.state = State::Cancelled {
+            code: cancellation.code,
+        };
+        vec![(CANCEL, cancel)]
+    }
+}
+
+fn to_json(events: Events) -> CSlice<u8> {
+    let events: Vec<_> = events
+        .into_iter()
+        .map(|(event_type, content)| json!({ "type": event_type, "content": content }))
+        .collect();
+    Value::from(events).to_string().into()
+}
+
+fn parse_keys(keys: &[u8]) -> Result<BTreeMap<String, String>, VerificationError> {
+    serde_json::from_slice(keys).map_err(VerificationError::InvalidKeys)
+}
+
+/// Creates the verification with the device `their_device_id` of
+/// `their_user_id`, `transaction_id` has to be unique for the pair of devices.
+#[ffi]
+#[sret]
+#[throws]
+pub fn vodozemac_sas_verification_new(
+    #[expand] our_user_id: &[u8],
+    #[expand] our_device_id: &[u8],
+    #[expand] their_user_id: &[u8],
+    #[expand] their_device_id: &[u8],
+    #[expand] transaction_id: &[u8],
+) -> CResult<NonNull<Verification>, CErrorStr> {
+    let string = |bytes| str::from_utf8(bytes).map(str::to_owned);
+    let strings = || -> Result<_, Utf8Error> {
+        Ok((
+            Party {
+                user_id: string(our_user_id)?,
+                device_id: string(our_device_id)?,
+            },
+            Party {
+                user_id: string(their_user_id)?,
+                device_id: string(their_device_id)?,
+            },
+            string(transaction_id)?,
+        ))
+    };
+    let (our, their, transaction_id) = match strings() {
+        Ok(strings) => strings,
+        Err(error) => return CResult::Err(error.into()),
+    };
+
+    CResult::Ok(boxed(RwLock::new(SasVerification {
+        our,
+        their,
+        transaction_id,
+        we_started: false,
+        emoji: true,
+        mac_method: MacMethod::HkdfHmacSha256V2,
+        verified_keys: Vec::new(),
+        state: State::Created,
+    })))
+}
+
+#[ffi]
+pub fn vodozemac_sas_verification_free(verification: NonNull<Verification>) {
+    free(verification)
+}
+
+/// One of the `STATE_*` constants.
+#[ffi]
+pub fn vodozemac_sas_verification_state(verification: &Verification) -> u32 {
+    verification.read().state()
+}
+
+/// Returns the `m.key.verification.start` event which begins the
+/// verification.
+#[ffi]
+#[sret]
+#[throws]
+pub fn vodozemac_sas_verification_start(
+    verification: &Verification,
+) -> CResult<CSlice<u8>, CErrorStr> {
+    verification
+        .write()
+        .start()
+        .map(to_json)
+        .map_err(Into::into)
+        .into()
+}
+
+/// Accepts a verification started by the other device, returning the
+/// `m.key.verification.accept` event.
+#[ffi]
+#[sret]
+#[throws]
+pub fn vodozemac_sas_verification_accept(
+    verification: &Verification,
+) -> CResult<CSlice<u8>, CErrorStr> {
+    verification
+        .write()
+        .accept()
+        .map(to_json)
+        .map_err(Into::into)
+        .into()
+}
+
+/// Processes the `content` of an event of type `event_type` which was sent by
+/// the other device and returns the events to send in reply.
+///
+/// Events of other transactions are ignored.
+#[ffi]
+#[sret]
+#[throws]
+pub fn vodozemac_sas_verification_receive(
+    verification: &Verification,
+    #[expand] event_type: &[u8],
+    #[expand] content: &[u8],
+) -> CResult<CSlice<u8>, CErrorStr> {
+    let event_type = match str::from_utf8(event_type) {
+        Ok(event_type) => event_type,
+        Err(error) => return CResult::Err(error.into()),
+    };
+    let mut verification = verification.write();
+
+    let events = match serde_json::from_slice(content) {
+        Ok(content @ Value::Object(_)) => verification.receive(event_type, content),
+        _ => verification.cancel(Cancellation::new(
+            CANCEL_INVALID_MESSAGE,
+            "the content is not a JSON object",
+        )),
+    };
+    CResult::Ok(to_json(events))
+}
+
+/// The short authentication string, null until both keys were exchanged.
+#[ffi]
+pub fn vodozemac_sas_verification_short_auth_string(
+    verification: &Verification,
+) -> Option<NonNull<SasBytes>> {
+    verification.read().short_auth_string().map(boxed)
+}
+
+/// Whether both sides agreed on showing emojis, decimals are always
+/// supported.
+#[ffi]
+pub fn vodozemac_sas_verification_supports_emoji(verification: &Verification) -> u32 {
+    verification.read().emoji.into()
+}
+
+/// Confirms that the short authentication strings match and returns the
+/// `m.key.verification.mac` event.
+///
+/// `our_keys` and `their_keys` are JSON objects mapping key ids like
+/// `ed25519:DEVICEID` to the base64 encoded keys. Our keys are MACed, their
+/// keys are checked against the MAC of the other device as soon as it
+/// arrives.
+#[ffi]
+#[sret]
+#[throws]
+pub fn vodozemac_sas_verification_confirm(
+    verification: &Verification,
+    #[expand] our_keys: &[u8],
+    #[expand] their_keys: &[u8],
+) -> CResult<CSlice<u8>, CErrorStr> {
+    let keys = parse_keys(our_keys).and_then(|our| Ok((our, parse_keys(their_keys)?)));
+
+    keys.and_then(|(our_keys, their_keys)| verification.write().confirm(our_keys, their_keys))
+        .map(to_json)
+        .map_err(Into::into)
+        .into()
+}
+
+/// Cancels the verification with `code`, e.g. `m.user` or `m.mismatched_sas`,
+/// returning the `m.key.verification.cancel` event.
+#[ffi]
+#[sret]
+#[throws]
+pub fn vodozemac_sas_verification_cancel(
+    verification: &Verification,
+    #[expand] code: &[u8],
+) -> CResult<CSlice<u8>, CErrorStr> {
+    let code = match str::from_utf8(code) {
+        Ok(code) => code,
+        Err(error) => return CResult::Err(error.into()),
+    };
+    let reason = match code {
+        CANCEL_USER => "the user cancelled the verification",
+        CANCEL_MISMATCHED_SAS => "the short authentication strings do not match",
+        _ => "the verification was cancelled",
+    };
+
+    CResult::Ok(to_json(
+        verification.write().cancel(Cancellation::new(code, reason)),
+    ))
+}
+
+/// The code the verification was cancelled with, empty unless it was.
+#[ffi]
+#[sret]
+pub fn vodozemac_sas_verification_cancel_code(verification: &Verification) -> CSlice<u8> {
+    match &verification.read().state {
+        State::Cancelled { code } => code.clone().into(),
+        _ => String::new().into(),
+    }
+}
+
+/// The JSON array of the ids of their keys whose MAC was verified.
+#[ffi]
+#[sret]
+pub fn vodozemac_sas_verification_verified_keys(verification: &Verification) -> CSlice<u8> {
+    Value::from(verification.read().verified_keys.clone())
+        .to_string()
+        .into()
+}
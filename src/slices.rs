@@ -2,11 +2,14 @@
 //
 // SPDX-License-Identifier: Apache-2.0
 
+use crate::exceptions::{ErrorDetails, ExceptionClass};
 use crate::free;
 use macros::ffi;
 use std::error::Error;
 use std::ffi::c_void;
+use std::fmt::{Display, Formatter};
 use std::ptr::{NonNull, slice_from_raw_parts_mut};
+use zeroize::Zeroize;
 
 pub type Slice<T> = Box<[T]>;
 pub type ByteSlice = Slice<u8>;
@@ -72,9 +75,32 @@ pub struct CSlice<T> {
     pub len: usize,
 }
 
+/// The [`CErrorStr::message_index`] of errors which do not refer to a message.
+pub const NO_MESSAGE_INDEX: usize = usize::MAX;
+
+crate::define_and_assert_ptr_sized! {
+    /// An error returned inside a [`crate::CResult`], `message` is freed with
+    /// `dealloc`.
+    ///
+    /// `class` is the index of an [`ExceptionClass`], `code` one of the
+    /// constants of [`crate::exceptions`] for that class and `message_index`
+    /// the index of the message the error refers to, [`NO_MESSAGE_INDEX`] if
+    /// there is none.
+    #[derive(Copy, Clone, Debug)]
+    pub struct CErrorStr {
+        pub message: CSlice<u8>,
+        pub class: usize,
+        pub code: usize,
+        pub message_index: usize,
+    }
+}
+
+/// Bytes which must not linger in freed memory, like plaintexts and exported
+/// session keys. They have the layout of a `CSlice<u8>`, but have to be freed
+/// with [`crate::vodozemac_secret_slice_free`] instead of `dealloc`.
 #[repr(transparent)]
 #[derive(Copy, Clone, Debug)]
-pub struct CErrorStr(pub CSlice<u8>);
+pub struct CSecretSlice(pub CSlice<u8>);
 
 impl<T> From<Vec<T>> for CSlice<T> {
     fn from(value: Vec<T>) -> Self {
@@ -86,14 +112,113 @@ impl<T> From<Vec<T>> for CSlice<T> {
     }
 }
 
+impl From<Vec<u8>> for CSecretSlice {
+    fn from(mut value: Vec<u8>) -> Self {
+        // Shrinking the vector would leave a copy in the old allocation.
+        if value.len() == value.capacity() {
+            return CSecretSlice(value.into());
+        }
+
+        let slice = value.as_slice().to_vec();
+        value.zeroize();
+        CSecretSlice(slice.into())
+    }
+}
+
 impl From<String> for CSlice<u8> {
     fn from(value: String) -> Self {
         value.into_bytes().into()
     }
 }
 
-impl<T: Error> From<T> for CErrorStr {
+impl CErrorStr {
+    pub fn new(message: String, details: ErrorDetails) -> Self {
+        Self {
+            message: message.into(),
+            class: details.class as usize,
+            code: details.code as usize,
+            message_index: usize::try_from(details.message_index).unwrap_or(NO_MESSAGE_INDEX),
+        }
+    }
+
+    pub fn details(&self) -> ErrorDetails {
+        let class = ExceptionClass::ALL
+            .get(self.class)
+            .copied()
+            .unwrap_or_default();
+        let message_index = match self.message_index {
+            NO_MESSAGE_INDEX => -1,
+            index => index as i64,
+        };
+
+        ErrorDetails::new(class, self.code as i32).with_message_index(message_index)
+    }
+}
+
+impl<T: Error + 'static> From<T> for CErrorStr {
     fn from(value: T) -> Self {
-        CErrorStr(value.to_string().into())
+        CErrorStr::new(value.to_string(), ErrorDetails::of(&value))
+    }
+}
+
+/// The lengths passed together with a buffer of concatenated items do not
+/// describe that buffer.
+#[derive(Debug)]
+pub enum LengthError {
+    /// A parallel array does not have an entry for every item.
+    Count { expected: usize, actual: usize },
+    /// The lengths do not add up to the size of the buffer, `lengths` is
+    /// `None` if their sum overflows.
+    Total {
+        lengths: Option<usize>,
+        buffer: usize,
+    },
+}
+
+impl Display for LengthError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LengthError::Count { expected, actual } => {
+                write!(f, "expected {expected} entries, got {actual}")
+            }
+            LengthError::Total {
+                lengths: Some(lengths),
+                buffer,
+            } => write!(f, "the lengths add up to {lengths} bytes, got {buffer}"),
+            LengthError::Total {
+                lengths: None,
+                buffer,
+            } => write!(f, "the lengths overflow, got {buffer} bytes"),
+        }
     }
 }
+
+impl Error for LengthError {}
+
+/// Splits `buffer` into consecutive items of the given lengths, which have to
+/// cover it exactly.
+pub fn split_concatenated<'a>(
+    buffer: &'a [u8],
+    lengths: &[u32],
+) -> Result<Vec<&'a [u8]>, LengthError> {
+    let total = lengths
+        .iter()
+        .try_fold(0usize, |sum, &length| sum.checked_add(length as usize));
+
+    if total != Some(buffer.len()) {
+        return Err(LengthError::Total {
+            lengths: total,
+            buffer: buffer.len(),
+        });
+    }
+
+    let mut rest = buffer;
+    Ok(lengths
+        .iter()
+        .map(|&length| {
+            let (item, tail) = rest.split_at(length as usize);
+            rest = tail;
+            item
+        })
+        .collect())
+}
@@ -2,6 +2,13 @@
 //
 // SPDX-License-Identifier: Apache-2.0
 
+#[cfg(feature = "deterministic-rng")]
+fn custom_rng(buf: &mut [u8]) -> Result<(), getrandom::Error> {
+    crate::rng::fill(buf);
+    Ok(())
+}
+
+#[cfg(not(feature = "deterministic-rng"))]
 fn custom_rng(buf: &mut [u8]) -> Result<(), getrandom::Error> {
     let (buf, len) = (buf.as_mut_ptr(), buf.len());
     let res = unsafe { extern_rng(buf, len) };
@@ -15,6 +22,7 @@ fn custom_rng(buf: &mut [u8]) -> Result<(), getrandom::Error> {
 
 getrandom::register_custom_getrandom!(custom_rng);
 
+#[cfg(not(feature = "deterministic-rng"))]
 unsafe extern "C" {
     fn extern_rng(buf: *mut u8, len: usize) -> u32;
 }